@@ -33,7 +33,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let outline = dt_analyzer::new::stage1::analyze_file(&file, &text, &diag);
     let includes = &[]; // TODO
-    let analyzed2 = dt_analyzer::new::stage2::compute(&outline, includes, &diag);
+    let analyzed2 =
+        dt_analyzer::new::stage2::compute(&path.as_str().into(), &outline, includes, &diag);
     println!(
         "{}={:#?}",
         "macro defs".cyan(),
@@ -0,0 +1,428 @@
+//! Evaluation of cell expressions like `(GIC_SPI + 3)` or `(1 << SHIFT)` after expanding the
+//! macros in them, e.g. for showing the value of a property in editors.
+//!
+//! # Example
+//!
+//! ```
+//! use dt_analyzer::eval::evaluate;
+//!
+//! assert_eq!(evaluate("(1 << 4 | 0x3)"), Ok(0x13));
+//! assert_eq!(evaluate("(2 > 1 ? 'a' : 0)"), Ok(97));
+//! ```
+
+use rustc_hash::FxHashSet;
+
+use crate::resolved_prop::MacroResolver;
+
+/// How many times a macro's output is expanded again, like nested macros in a header
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// Expands the macros in `input`, recursively, like the C preprocessor.
+///
+/// Macros which aren't defined and function-like macros without arguments are kept as they are, as
+/// are macros in their own expansion.
+#[must_use]
+pub fn expand_macros(input: &str, resolver: &impl MacroResolver) -> String {
+    expand(input, resolver, &mut FxHashSet::default(), 0)
+}
+
+fn expand<'r>(
+    input: &str,
+    resolver: &'r impl MacroResolver,
+    expanding: &mut FxHashSet<&'r str>,
+    depth: usize,
+) -> String {
+    let mut output = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find(is_ident_start) {
+        let (before, ident_start) = rest.split_at(start);
+        output.push_str(before);
+        // Skip numbers like `0x1f` and suffixes like `1U` without expanding them
+        if before.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            let end = ident_start
+                .find(|c: char| !is_ident_continue(c))
+                .unwrap_or(ident_start.len());
+            output.push_str(&ident_start[..end]);
+            rest = &ident_start[end..];
+            continue;
+        }
+        let end = ident_start
+            .find(|c: char| !is_ident_continue(c))
+            .unwrap_or(ident_start.len());
+        let (ident, after) = ident_start.split_at(end);
+        rest = after;
+
+        let definition = resolver.resolve(ident).filter(|definition| {
+            depth < MAX_EXPANSION_DEPTH && !expanding.contains(&*definition.name)
+        });
+        let Some(definition) = definition else {
+            output.push_str(ident);
+            continue;
+        };
+        let arguments = if definition.params.is_empty() {
+            Vec::new()
+        } else {
+            let Some((arguments, after)) = arguments(rest) else {
+                output.push_str(ident);
+                continue;
+            };
+            rest = after;
+            // Arguments are expanded before substitution, unless they are stringified or pasted
+            arguments
+                .iter()
+                .enumerate()
+                .map(|(index, argument)| {
+                    if definition.dont_prescan_indices.contains(&index) {
+                        argument.trim().to_owned()
+                    } else {
+                        expand(argument, resolver, expanding, depth + 1)
+                            .trim()
+                            .to_owned()
+                    }
+                })
+                .collect()
+        };
+        if arguments.len() != definition.params.len() {
+            output.push_str(ident);
+            continue;
+        }
+        let substituted = definition.substitute(&arguments).1;
+        expanding.insert(&definition.name);
+        output.push_str(&expand(&substituted, resolver, expanding, depth + 1));
+        expanding.remove(&*definition.name);
+    }
+    output.push_str(rest);
+    output
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Splits the parenthesized, comma-separated arguments off the start of `input`.
+fn arguments(input: &str) -> Option<(Vec<&str>, &str)> {
+    let input = input.trim_start();
+    let inner = input.strip_prefix('(')?;
+    let mut arguments = Vec::new();
+    let mut level = 0usize;
+    let mut start = 0;
+    for (index, c) in inner.char_indices() {
+        match c {
+            '(' => level += 1,
+            ')' if level == 0 => {
+                let last = &inner[start..index];
+                if !(arguments.is_empty() && last.trim().is_empty()) {
+                    arguments.push(last);
+                }
+                return Some((arguments, &inner[index + 1..]));
+            }
+            ')' => level -= 1,
+            ',' if level == 0 => {
+                arguments.push(&inner[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Errors from [`evaluate`]
+#[derive(thiserror::Error, Debug, displaydoc::Display, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// unexpected `{0}`
+    Unexpected(String),
+    /// unexpected end of expression
+    UnexpectedEnd,
+    /// invalid number `{0}`
+    InvalidNumber(String),
+    /// unknown identifier `{0}`
+    UnknownIdentifier(String),
+    /// division by zero
+    DivisionByZero,
+}
+
+/// Evaluates an integer expression with C's operators, like dtc does for cells.
+///
+/// The arithmetic is done on 64-bit unsigned integers, which wrap around.
+///
+/// # Errors
+///
+/// Returns an error if `input` isn't a valid expression, contains identifiers, e.g. macros which
+/// weren't [expanded](expand_macros), or divides by zero.
+pub fn evaluate(input: &str) -> Result<u64, EvalError> {
+    let mut parser = ExprParser {
+        tokens: tokenize(input)?,
+        position: 0,
+    };
+    let value = parser.ternary()?;
+    match parser.tokens.get(parser.position) {
+        Some(token) => Err(EvalError::Unexpected(token.to_string())),
+        None => Ok(value),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ExprToken {
+    Number(u64),
+    Operator(&'static str),
+}
+
+impl std::fmt::Display for ExprToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(value) => write!(f, "{value}"),
+            Self::Operator(operator) => f.write_str(operator),
+        }
+    }
+}
+
+/// Operators, longest first so `<<` isn't lexed as `<`
+const OPERATORS: &[&str] = &[
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "&", "|", "^", "~",
+    "!", "<", ">", "?", ":", "(", ")",
+];
+
+fn tokenize(input: &str) -> Result<Vec<ExprToken>, EvalError> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while !rest.is_empty() {
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(ExprToken::Number(parse_number(&rest[..end])?));
+            rest = &rest[end..];
+        } else if let Some(char_literal) = rest.strip_prefix('\'') {
+            let end = char_literal
+                .find('\'')
+                .ok_or_else(|| EvalError::InvalidNumber(rest.to_owned()))?;
+            let value =
+                crate::string::interpret_escaped_string(&format!("\"{}\"", &char_literal[..end]))
+                    .ok()
+                    .and_then(|text| {
+                        let mut chars = text.chars();
+                        chars.next().filter(|_| chars.next().is_none())
+                    })
+                    .ok_or_else(|| EvalError::InvalidNumber(rest[..end + 2].to_owned()))?;
+            tokens.push(ExprToken::Number(value.into()));
+            rest = &char_literal[end + 1..];
+        } else if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(ExprToken::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !is_ident_continue(c))
+                .filter(|&end| end > 0)
+                .unwrap_or(
+                    rest.len()
+                        .min(rest.chars().next().map_or(0, char::len_utf8)),
+                );
+            return Err(if rest.starts_with(is_ident_start) {
+                EvalError::UnknownIdentifier(rest[..end].to_owned())
+            } else {
+                EvalError::Unexpected(rest[..end].to_owned())
+            });
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Parses a C integer literal, e.g. `0x1f`, `010`, `0b11` or `4096UL`.
+fn parse_number(text: &str) -> Result<u64, EvalError> {
+    let digits = text.trim_end_matches(['u', 'U', 'l', 'L']);
+    let (digits, radix) = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        (hex, 16)
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        (binary, 2)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (&digits[1..], 8)
+    } else {
+        (digits, 10)
+    };
+    u64::from_str_radix(digits, radix).map_err(|_| EvalError::InvalidNumber(text.to_owned()))
+}
+
+/// A precedence climbing parser which evaluates while parsing
+struct ExprParser {
+    tokens: Vec<ExprToken>,
+    position: usize,
+}
+
+/// Binary operators from the lowest to the highest precedence
+const BINARY_PRECEDENCE: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl ExprParser {
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(ExprToken::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), EvalError> {
+        match self.tokens.get(self.position) {
+            Some(ExprToken::Operator(found)) if *found == operator => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(token) => Err(EvalError::Unexpected(token.to_string())),
+            None => Err(EvalError::UnexpectedEnd),
+        }
+    }
+
+    fn ternary(&mut self) -> Result<u64, EvalError> {
+        let condition = self.binary(0)?;
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+        self.position += 1;
+        let then = self.ternary()?;
+        self.expect(":")?;
+        let otherwise = self.ternary()?;
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<u64, EvalError> {
+        let Some(operators) = BINARY_PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some(operator) = self
+            .peek_operator()
+            .filter(|operator| operators.contains(operator))
+        {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = match operator {
+                "||" => u64::from(left != 0 || right != 0),
+                "&&" => u64::from(left != 0 && right != 0),
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "==" => u64::from(left == right),
+                "!=" => u64::from(left != right),
+                "<" => u64::from(left < right),
+                ">" => u64::from(left > right),
+                "<=" => u64::from(left <= right),
+                ">=" => u64::from(left >= right),
+                // Shifts by 64 or more give 0 like dtc's 64-bit arithmetic on most targets
+                "<<" => u32::try_from(right)
+                    .ok()
+                    .and_then(|right| left.checked_shl(right))
+                    .unwrap_or(0),
+                ">>" => u32::try_from(right)
+                    .ok()
+                    .and_then(|right| left.checked_shr(right))
+                    .unwrap_or(0),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" => left.checked_div(right).ok_or(EvalError::DivisionByZero)?,
+                "%" => left.checked_rem(right).ok_or(EvalError::DivisionByZero)?,
+                _ => unreachable!("operator from the precedence table"),
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<u64, EvalError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(EvalError::UnexpectedEnd)?;
+        self.position += 1;
+        match token {
+            ExprToken::Number(value) => Ok(value),
+            ExprToken::Operator("-") => Ok(self.unary()?.wrapping_neg()),
+            ExprToken::Operator("+") => self.unary(),
+            ExprToken::Operator("~") => Ok(!self.unary()?),
+            ExprToken::Operator("!") => Ok(u64::from(self.unary()? == 0)),
+            ExprToken::Operator("(") => {
+                let value = self.ternary()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            ExprToken::Operator(operator) => Err(EvalError::Unexpected(operator.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap;
+
+    use super::*;
+    use crate::macros::MacroDefinition;
+
+    #[test]
+    fn operators() {
+        assert_eq!(evaluate("0x10"), Ok(16));
+        assert_eq!(evaluate("(1 + 2 * 3)"), Ok(7));
+        assert_eq!(evaluate("((1 + 2) * 3)"), Ok(9));
+        assert_eq!(evaluate("(1 << 4 | 1 << 1)"), Ok(0x12));
+        assert_eq!(evaluate("(0 - 1)"), Ok(u64::MAX));
+        assert_eq!(evaluate("(~0 & 0xff)"), Ok(0xff));
+        assert_eq!(evaluate("(1 ? 2 : 3)"), Ok(2));
+        assert_eq!(evaluate("(010 + 0b11 + 5U)"), Ok(16));
+        assert_eq!(evaluate("'a'"), Ok(97));
+        assert_eq!(evaluate("(1 / 0)"), Err(EvalError::DivisionByZero));
+        assert_eq!(
+            evaluate("(FOO + 1)"),
+            Err(EvalError::UnknownIdentifier("FOO".to_owned()))
+        );
+        assert_eq!(evaluate("(1 +"), Err(EvalError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn macros() {
+        let definitions = [
+            "#define GIC_SPI 0",
+            "#define BIT(n) (1 << (n))",
+            "#define IRQ_TYPE_LEVEL_HIGH 4",
+            "#define FLAGS (BIT(1) | IRQ_TYPE_LEVEL_HIGH)",
+            "#define SELF SELF",
+        ]
+        .map(|definition| MacroDefinition::parse(definition).unwrap());
+        let macros = definitions
+            .iter()
+            .map(|definition| {
+                (
+                    definition.name.clone(),
+                    (dt_parser::TextRange::new(0, 0), definition),
+                )
+            })
+            .collect::<FxHashMap<_, _>>();
+
+        assert_eq!(
+            expand_macros("BIT(3)", &macros).replace(' ', ""),
+            "(1<<(3))"
+        );
+        assert_eq!(evaluate(&expand_macros("FLAGS", &macros)), Ok(6));
+        assert_eq!(evaluate(&expand_macros("(BIT(BIT(2)))", &macros)), Ok(16));
+        assert_eq!(expand_macros("SELF 0x1UL BIT", &macros), "SELF 0x1UL BIT");
+    }
+}
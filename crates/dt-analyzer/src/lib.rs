@@ -46,6 +46,7 @@ pub use prop::{
 };
 pub use string::StringParseError;
 
+pub mod eval;
 mod macros;
 pub mod new;
 mod prop;
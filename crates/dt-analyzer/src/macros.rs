@@ -62,7 +62,7 @@ struct Parser<'params, 'input> {
     params: &'params [String],
     /// List of (already) processed tokens
     body_tokens: Vec<MacroToken>,
-    dont_prescan_indices: Vec<usize>,
+    pub(crate) dont_prescan_indices: Vec<usize>,
     expect_stringify_param: bool,
     /// If expecting a concat param, the concat token's range is stored here
     expect_concat_param: Option<TextRange>,
@@ -373,11 +373,11 @@ pub struct MacroDefinition {
     /// Macro name
     pub name: String,
     /// Parameter names
-    params: Vec<String>,
+    pub(crate) params: Vec<String>,
     /// Parsed macro body tokens, used for substitution
     body_tokens: Vec<MacroToken>,
     /// Parameter indices not to prescan.
-    dont_prescan_indices: Vec<usize>,
+    pub(crate) dont_prescan_indices: Vec<usize>,
 }
 impl MacroDefinition {
     pub(crate) fn parse(input: &str) -> Result<Self, MacroDefinitionParseError> {
@@ -457,7 +457,7 @@ impl MacroDefinition {
         })
     }
     #[expect(clippy::too_many_lines, reason = "Hard to make this shorter")]
-    fn substitute(&self, arguments: &[String]) -> (Vec<TextRangeMap>, String) {
+    pub(crate) fn substitute(&self, arguments: &[String]) -> (Vec<TextRangeMap>, String) {
         let mut s = String::new();
         let mut iter = self.body_tokens.iter().peekable();
         let mut push_ws = false;
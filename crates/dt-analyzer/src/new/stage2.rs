@@ -8,10 +8,12 @@ use std::borrow::Cow;
 
 use dt_diagnostic::{Diagnostic, DiagnosticCollector, MultiSpan, Severity, SpanLabel};
 use dt_parser::{
-    ast::{self, AstNode, AstNodeOrToken, AstToken, HasMacroInvocation, HasName},
+    ast::{self, AstNode, AstNodeOrToken, AstToken, HasLabel, HasMacroInvocation, HasName},
+    cst::GreenItem,
+    lexer::TokenKind,
     match_ast,
     parser::Entrypoint,
-    TextRange,
+    SourceId, TextRange,
 };
 use enum_as_inner::EnumAsInner;
 use rustc_hash::FxHashMap;
@@ -21,29 +23,36 @@ use crate::{
     resolved_prop::Value,
 };
 
-use super::stage1::{AnalyzedToplevel, LabelDef};
+use super::stage1::AnalyzedToplevel;
 
+/// How many `#include`s are followed in a row, so include cycles terminate
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// A file included by an `#include` or `/include/` in the computed file or its includes
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ResolvedInclude<'a> {
+    /// The file containing the include
+    pub includer: SourceId,
+    /// The range of the include in [`includer`](Self::includer)
     pub text_range: TextRange,
-    /// Reference to the analyzed file of the include.
-    ///
-    /// This is used to detect duplicates.
-    pub analyzed: &'a Stage2File,
-    /// Map of labels defined in the included file.
-    pub labels: FxHashMap<String, LabelDef>,
+    /// The included file
+    pub source_id: SourceId,
+    /// Stage 1 toplevels of the included file
+    pub outline: &'a [AnalyzedToplevel],
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Stage2File {
+    /// The computed file, which its includes were merged into
+    pub source_id: SourceId,
     pub root_node: Stage2Node,
 }
 
 #[derive(derive_more::Debug, Default, Clone, PartialEq, Eq)]
 #[debug("Stage2Node {children:#?}")]
 pub struct Stage2Node {
-    /// List of ASTs this node was merged from
-    pub asts: Vec<ast::DtNode>,
+    /// List of ASTs this node was merged from, with the files they are in
+    pub asts: Vec<(SourceId, ast::DtNode)>,
     pub children: FxHashMap<String, Stage2Tree>,
 }
 
@@ -54,31 +63,114 @@ impl Stage2Node {
     ///
     /// * Node doesn't have any ASTs ([`Stage2File::root_node`] when there are no root nodes)
     /// * There is no name ([`Stage2Node`'s children](`Stage2Node::children`) always have a name)
-    fn name_text_range(&self) -> Option<TextRange> {
-        Some(self.asts.last()?.name()?.syntax().text_range())
+    ///
+    /// For extensions, this is the range of the reference, e.g. `&label`.
+    fn name_text_range(&self) -> Option<(&SourceId, TextRange)> {
+        let (source_id, ast) = self.asts.last()?;
+        if let Some(phandle) = ast.extension_name() {
+            return Some((source_id, phandle.syntax().text_range()));
+        }
+        Some((source_id, ast.name()?.syntax().text_range()))
+    }
+
+    /// Converts the node and its children to JSON in the shape of `dtc -O yaml`, which dt-schema
+    /// validates.
+    ///
+    /// Flags become `true` and other properties become arrays of their values.
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        self.to_json_with_spans().0
+    }
+
+    /// Like [`Stage2Node::to_json`], but also returns where each JSON value came from.
+    #[must_use]
+    pub fn to_json_with_spans(&self) -> (serde_json::Value, JsonSpans) {
+        let mut spans = JsonSpans::default();
+        let json = self.json_with_spans(&mut String::new(), &mut spans);
+        (json, spans)
     }
 
-    // TODO: remove once sum stage is done
-    #[cfg(test)]
-    pub(crate) fn into_json(self) -> serde_json::Value {
-        use serde_json::Value as JValue;
+    fn json_with_spans(&self, pointer: &mut String, spans: &mut JsonSpans) -> serde_json::Value {
+        if let Some(span) = self.name_span() {
+            spans.insert(pointer.clone(), span);
+        }
         let mut map = serde_json::Map::new();
-        for (name, tree) in self.children {
-            map.insert(
-                name,
-                match tree {
-                    Stage2Tree::Prop(prop) => {
-                        if prop.values.is_empty() {
-                            JValue::Bool(true)
-                        } else {
-                            JValue::Array(prop.values.into_iter().map(Value::into_json).collect())
-                        }
-                    }
-                    Stage2Tree::Node(node) => node.into_json(),
-                },
-            );
+        for (name, tree) in &self.children {
+            let len = pointer.len();
+            pointer.push('/');
+            pointer.push_str(&name.replace('~', "~0").replace('/', "~1"));
+            let json = match tree {
+                Stage2Tree::Prop(prop) => prop.json_with_spans(pointer, spans),
+                Stage2Tree::Node(node) => node.json_with_spans(pointer, spans),
+            };
+            pointer.truncate(len);
+            map.insert(name.clone(), json);
+        }
+        serde_json::Value::Object(map)
+    }
+
+    /// Returns the text range of the name including the unit address, the `/` of a root node or
+    /// the reference of an extension.
+    fn name_span(&self) -> Option<(SourceId, TextRange)> {
+        let (source_id, ast) = self.asts.last()?;
+        if let Some(phandle) = ast.extension_name() {
+            return Some((source_id.clone(), phandle.syntax().text_range()));
+        }
+        let range = match ast.name() {
+            Some(name) if !name.syntax().text().is_empty() => {
+                let name = name.syntax().text_range();
+                let end = ast
+                    .unit_address()
+                    .map_or(name.end, |unit| unit.syntax().text_range().end);
+                TextRange::new(name.start, end)
+            }
+            _ => ast
+                .syntax()
+                .child_tokens()
+                .find(|token| token.green.kind == TokenKind::Slash)?
+                .text_range(),
+        };
+        Some((source_id.clone(), range))
+    }
+}
+
+/// Source locations of the values in [`Stage2Node::to_json`]'s output, keyed by [JSON pointer][1].
+///
+/// Nodes point to their names, properties to the whole property, and values and cells to
+/// themselves. For example, `/soc/serial@1000/reg/0/1` points to the second cell of the first value
+/// of `reg`.
+///
+/// When nodes are merged, the last definition wins, just like for the values. Each span is in the
+/// file its definition is in, which may be an included file.
+///
+/// [1]: https://datatracker.ietf.org/doc/html/rfc6901
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct JsonSpans {
+    spans: FxHashMap<String, (SourceId, TextRange)>,
+}
+impl JsonSpans {
+    /// Returns the file and span of the value at `pointer`.
+    #[must_use]
+    pub fn get(&self, pointer: &str) -> Option<&(SourceId, TextRange)> {
+        self.spans.get(pointer)
+    }
+
+    /// Sets the file and span of the value at `pointer`, for when the JSON is reshaped.
+    pub fn insert(&mut self, pointer: String, span: (SourceId, TextRange)) {
+        self.spans.insert(pointer, span);
+    }
+
+    /// Returns the file and span of the value at `pointer` or its closest ancestor with a span.
+    ///
+    /// This is useful for values which aren't from the source, like `$nodename` in dt-schema.
+    #[must_use]
+    pub fn find(&self, mut pointer: &str) -> Option<&(SourceId, TextRange)> {
+        loop {
+            if let Some(span) = self.get(pointer) {
+                return Some(span);
+            }
+            pointer = &pointer[..pointer.rfind('/')?];
         }
-        JValue::Object(map)
     }
 }
 
@@ -90,10 +182,12 @@ pub enum Stage2Tree {
     #[debug("{_0:?}")]
     Node(Stage2Node),
 }
-
 #[derive(derive_more::Debug, Clone, PartialEq, Eq)]
 #[debug("{values:?}")]
 pub struct Stage2Property {
+    /// The file [`ast`](Self::ast) is in
+    #[debug(skip)]
+    pub source_id: SourceId,
     #[debug(skip)]
     pub ast: ast::DtProperty,
     pub values: Vec<Value>,
@@ -103,8 +197,31 @@ impl Stage2Property {
     ///
     /// This returns `None` when there is no name
     /// ([`Stage2Node`'s children](`Stage2Node::children`) always have a name)
-    fn name_text_range(&self) -> Option<TextRange> {
-        Some(self.ast.name()?.syntax().text_range())
+    fn name_text_range(&self) -> Option<(&SourceId, TextRange)> {
+        Some((&self.source_id, self.ast.name()?.syntax().text_range()))
+    }
+
+    fn json_with_spans(&self, pointer: &str, spans: &mut JsonSpans) -> serde_json::Value {
+        let span = |range| (self.source_id.clone(), range);
+        spans.insert(pointer.to_owned(), span(self.ast.syntax().text_range()));
+        if self.values.is_empty() {
+            return serde_json::Value::Bool(true);
+        }
+
+        // Values are only computed if every value AST is valid, so they line up
+        for (i, value_ast) in self.ast.values().enumerate() {
+            let value_pointer = format!("{pointer}/{i}");
+            if let ast::PropValue::CellList(cell_list) = &value_ast {
+                for (j, cell) in cell_list.cells().enumerate() {
+                    spans.insert(
+                        format!("{value_pointer}/{j}"),
+                        span(cell.syntax().text_range()),
+                    );
+                }
+            }
+            spans.insert(value_pointer, span(value_ast.syntax().text_range()));
+        }
+        serde_json::Value::Array(self.values.iter().cloned().map(Value::into_json).collect())
     }
 }
 
@@ -112,37 +229,157 @@ impl Stage2Property {
 // Phandle values can be before the label definition
 // Extensions must be defined after the label definition
 
+/// Merges the nodes of a file and its includes into one tree.
+///
+/// Included files are merged where they are included, like the preprocessor would.
+///
 /// # Parameters
 ///
+/// * `source_id`: The file of `outline`
 /// * `outline`: Stage 1 toplevels
 /// * `includes`: List of (potentially transitive) includes
-/// * `diag`: Single-file diagnostic collector
+/// * `diag`: Single-file diagnostic collector, which only gets the diagnostics of `outline`
 pub fn compute(
+    source_id: &SourceId,
     outline: &[AnalyzedToplevel],
-    _includes: &[ResolvedInclude],
+    includes: &[ResolvedInclude],
     diag: &impl DiagnosticCollector,
 ) -> Stage2File {
-    let mut root_node = Stage2Node::default();
-    let macro_db: FxHashMap<_, _> = outline
+    // Macros from every file, since headers are included for their macros
+    let macro_db: FxHashMap<_, _> = includes
         .iter()
+        .flat_map(|include| include.outline)
+        .chain(outline)
         .filter_map(AnalyzedToplevel::as_macro_definition)
         .map(|(tr, macro_def)| (macro_def.name.clone(), (*tr, macro_def)))
         .collect();
+    let mut cx = Context {
+        source_id,
+        includes,
+        diag,
+        macro_db,
+        labels: FxHashMap::default(),
+        root_node: Stage2Node::default(),
+    };
+    cx.merge_file(source_id, outline, 0);
+    Stage2File {
+        source_id: source_id.clone(),
+        root_node: cx.root_node,
+    }
+}
 
-    for stage1_node in outline.iter().filter_map(AnalyzedToplevel::as_node) {
-        if stage1_node.is_extension {
-            // TODO: cache path in LabelDef
-        } else {
-            merge_root_node(&stage1_node.ast, diag, &mut root_node, &macro_db);
+struct Context<'a, 'i, D> {
+    /// The computed file
+    source_id: &'a SourceId,
+    includes: &'a [ResolvedInclude<'i>],
+    diag: &'a D,
+    macro_db: FxHashMap<String, (TextRange, &'a MacroDefinition)>,
+    /// Paths of the labeled nodes merged so far, for extensions
+    labels: FxHashMap<String, Vec<String>>,
+    root_node: Stage2Node,
+}
+
+impl<D: DiagnosticCollector> Context<'_, '_, D> {
+    fn merge_file(&mut self, source_id: &SourceId, outline: &[AnalyzedToplevel], depth: usize) {
+        for toplevel in outline {
+            match toplevel {
+                AnalyzedToplevel::Node(stage1_node) => {
+                    let diag: &dyn DiagnosticCollector = if source_id == self.source_id {
+                        self.diag
+                    } else {
+                        &IgnoredDiagnostics
+                    };
+                    let mut path = if stage1_node.is_extension {
+                        // Like in DTC, the label must be defined before the extension
+                        let Some(path) = stage1_node
+                            .ast
+                            .extension_name()
+                            .and_then(|phandle| self.extension_path(&phandle))
+                        else {
+                            continue;
+                        };
+                        path
+                    } else {
+                        Vec::new()
+                    };
+                    let Some(node) = self.root_node.node_at_mut(&path) else {
+                        continue;
+                    };
+                    merge_root_node(
+                        source_id,
+                        &stage1_node.ast,
+                        diag,
+                        node,
+                        &self.macro_db,
+                        &mut path,
+                        &mut self.labels,
+                    );
+                }
+                AnalyzedToplevel::Include(include) if depth < MAX_INCLUDE_DEPTH => {
+                    if let Some(resolved) = self.includes.iter().find(|resolved| {
+                        resolved.includer == *source_id && resolved.text_range == include.text_range
+                    }) {
+                        self.merge_file(&resolved.source_id, resolved.outline, depth + 1);
+                    }
+                }
+                _ => {}
+            }
         }
     }
-    Stage2File { root_node }
+}
+
+impl<D> Context<'_, '_, D> {
+    /// Returns the path of the node `phandle` points to, e.g. `&label` or `&{/soc/serial@1000}`.
+    fn extension_path(&self, phandle: &ast::DtPhandle) -> Option<Vec<String>> {
+        if phandle.is_label() {
+            return self
+                .labels
+                .get(phandle.name()?.syntax().text().as_str())
+                .cloned();
+        }
+        let mut path = String::new();
+        let mut in_path = false;
+        for item in &phandle.syntax().green.children {
+            match item {
+                GreenItem::Token(token) if token.kind == TokenKind::LCurly => in_path = true,
+                GreenItem::Token(token) if token.kind == TokenKind::RCurly => break,
+                GreenItem::Token(token) if in_path => path.push_str(token.text.as_str()),
+                // Unit addresses
+                GreenItem::Node(node) if in_path => {
+                    path.extend(node.child_tokens().map(|token| token.text.as_str()));
+                }
+                _ => {}
+            }
+        }
+        let path = path.trim().strip_prefix('/')?;
+        Some(
+            path.split('/')
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        )
+    }
+}
+
+impl Stage2Node {
+    /// Returns the descendant node at `path`.
+    fn node_at_mut(&mut self, path: &[String]) -> Option<&mut Stage2Node> {
+        path.iter().try_fold(self, |node, name| {
+            node.children.get_mut(name)?.as_node_mut()
+        })
+    }
+}
+
+/// Drops the diagnostics of included files, which are reported when they are checked themselves
+struct IgnoredDiagnostics;
+impl DiagnosticCollector for IgnoredDiagnostics {
+    fn emit(&self, _diag: Diagnostic) {}
 }
 
 fn get_node_prop_name(
     plain_name: Option<&str>,
     ast: &impl HasMacroInvocation,
-    diag: &impl DiagnosticCollector,
+    diag: &dyn DiagnosticCollector,
     macro_db: &FxHashMap<String, (TextRange, &MacroDefinition)>,
 ) -> Option<String> {
     let (macro_ast, macro_def) = match plain_name {
@@ -186,12 +423,18 @@ fn get_node_prop_name(
 }
 
 fn merge_root_node(
+    source_id: &SourceId,
     ast: &ast::DtNode,
-    diag: &impl DiagnosticCollector,
+    diag: &dyn DiagnosticCollector,
     stage2: &mut Stage2Node,
     macro_db: &FxHashMap<String, (TextRange, &MacroDefinition)>,
+    path: &mut Vec<String>,
+    labels: &mut FxHashMap<String, Vec<String>>,
 ) {
-    stage2.asts.push(ast.clone());
+    stage2.asts.push((source_id.clone(), ast.clone()));
+    if let Some(label) = ast.label().and_then(|label| label.name()) {
+        labels.insert(label.syntax().text().to_owned(), path.clone());
+    }
 
     //for (name, child) in ast.syntax().child_nodes().filter_map(|node| {
     for syntax in ast.syntax().child_nodes() {
@@ -205,32 +448,31 @@ fn merge_root_node(
                         continue
                     };
 
+                    path.push(name.clone());
                     match stage2.children.get_mut(&name) {
                         Some(Stage2Tree::Prop(other)) => {
                             // can't mix
                             diag.emit(Diagnostic {
                                 span: MultiSpan {
                                     primary_spans: vec![child_ast.syntax().text_range()],
-                                    span_labels: vec![SpanLabel {
-                                        span: other.name_text_range().expect("Must have a name"),
-                                        msg: Cow::Owned(format!("previous definition of `{name}` here")),
-                                    }],
+                                    span_labels: previous_definition(source_id, other.name_text_range(), &name),
                                 },
                                 msg: Cow::Owned(format!("`{name}` is defined multiple times")),
                                 severity: Severity::Error,
+                                suggestions: Vec::new(),
                             });
-                            continue
                         }
                         Some(Stage2Tree::Node(other)) => {
                             // merge
-                            merge_root_node(&child_ast, diag, other, macro_db);
+                            merge_root_node(source_id, &child_ast, diag, other, macro_db, path, labels);
                         }
                         None => {
                             let mut child_node = Stage2Node::default();
-                            merge_root_node(&child_ast, diag, &mut child_node, macro_db);
+                            merge_root_node(source_id, &child_ast, diag, &mut child_node, macro_db, path, labels);
                             stage2.children.insert(name.clone(), Stage2Tree::Node(child_node));
                         }
                     }
+                    path.pop();
                     // TODO: what to do with unit address?, $nodename (jsonschema property)?
                     // Kernel 6.10:
                     // Documentation/devicetree/bindings/thermal/thermal-zones.yaml#L41
@@ -250,13 +492,11 @@ fn merge_root_node(
                         diag.emit(Diagnostic {
                             span: MultiSpan {
                                 primary_spans: vec![name_ast.syntax().text_range()],
-                                span_labels: vec![SpanLabel {
-                                    span: other.name_text_range().expect("Must have a name"),
-                                    msg: Cow::Owned(format!("previous definition of `{name}` here")),
-                                }],
+                                span_labels: previous_definition(source_id, other.name_text_range(), name),
                             },
                             msg: Cow::Owned(format!("`{name}` is defined multiple times")),
                             severity: Severity::Error,
+                            suggestions: Vec::new(),
                         });
                         continue
                     }
@@ -275,6 +515,7 @@ fn merge_root_node(
                         }
                     ).collect::<Result<Vec<_>, ()>>() {
                         let prop = Stage2Property {
+                            source_id: source_id.clone(),
                             ast: prop_ast,
                             values
                         };
@@ -286,3 +527,20 @@ fn merge_root_node(
         }
     }
 }
+
+/// Returns a label for the previous definition of `name`, unless it's in another file than
+/// `source_id`.
+fn previous_definition(
+    source_id: &SourceId,
+    previous: Option<(&SourceId, TextRange)>,
+    name: &str,
+) -> Vec<SpanLabel> {
+    let (previous_source_id, span) = previous.expect("Must have a name");
+    if previous_source_id != source_id {
+        return Vec::new();
+    }
+    vec![SpanLabel {
+        span,
+        msg: Cow::Owned(format!("previous definition of `{name}` here")),
+    }]
+}
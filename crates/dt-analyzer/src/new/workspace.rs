@@ -1 +1,130 @@
 //! File resolver, etc.
+
+use std::path::{Path, PathBuf};
+
+use dt_parser::{ast::SourceFile, SourceId, TextRange};
+
+use super::{stage1::AnalyzedToplevel, stage2::ResolvedInclude};
+
+/// A file read from disk and analyzed by stage 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedFile {
+    /// The path's display string
+    pub source_id: SourceId,
+    pub path: PathBuf,
+    pub text: String,
+    pub outline: Vec<AnalyzedToplevel>,
+}
+
+impl LoadedFile {
+    /// Reads and analyzes the file at `path`, ignoring any errors in it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::new(path, std::fs::read_to_string(path)?))
+    }
+
+    /// Analyzes `text` as the contents of the file at `path`, ignoring any errors in it.
+    #[must_use]
+    pub fn new(path: &Path, text: String) -> Self {
+        let file = SourceFile::parse(&text).source_file();
+        let outline = super::stage1::analyze_file(&file, &text, &std::sync::Mutex::new(Vec::new()));
+        Self {
+            source_id: SourceId::from(path.display().to_string()),
+            path: path.to_owned(),
+            text,
+            outline,
+        }
+    }
+}
+
+/// A file and the files it includes, transitively, for [`compute`](super::stage2::compute).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludedFiles {
+    /// The main file comes first
+    files: Vec<LoadedFile>,
+    /// Each include's file, range and the index of the included file
+    includes: Vec<(SourceId, TextRange, usize)>,
+}
+
+impl IncludedFiles {
+    /// Loads the file at `path` and the files it includes, which are found like
+    /// [`AnalyzedInclude::find_file`](super::stage1::AnalyzedInclude::find_file) does.
+    ///
+    /// Includes which aren't found or can't be read are skipped, like the other files' errors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file at `path` can't be read.
+    pub fn load<P: AsRef<Path>>(path: &Path, include_dirs: &[P]) -> std::io::Result<Self> {
+        Ok(Self::with_main_file(LoadedFile::load(path)?, include_dirs))
+    }
+
+    /// Loads the files included by `main_file`, like [`load`](Self::load) does.
+    #[must_use]
+    pub fn with_main_file<P: AsRef<Path>>(main_file: LoadedFile, include_dirs: &[P]) -> Self {
+        let mut this = Self {
+            files: vec![main_file],
+            includes: Vec::new(),
+        };
+        let mut i = 0;
+        while let Some(file) = this.files.get(i) {
+            let parent = file.path.parent().unwrap_or(Path::new(""));
+            let found = file
+                .outline
+                .iter()
+                .filter_map(AnalyzedToplevel::as_include)
+                .filter_map(|include| {
+                    Some((include.text_range, include.find_file(parent, include_dirs)?))
+                })
+                .collect::<Vec<_>>();
+            let includer = file.source_id.clone();
+            for (text_range, path) in found {
+                let index =
+                    if let Some(index) = this.files.iter().position(|file| file.path == path) {
+                        index
+                    } else {
+                        let Ok(file) = LoadedFile::load(&path) else {
+                            continue;
+                        };
+                        this.files.push(file);
+                        this.files.len() - 1
+                    };
+                this.includes.push((includer.clone(), text_range, index));
+            }
+            i += 1;
+        }
+        this
+    }
+
+    /// Returns the file which was loaded first.
+    #[must_use]
+    pub fn main_file(&self) -> &LoadedFile {
+        &self.files[0]
+    }
+
+    /// Returns the loaded file with the ID `source_id`.
+    #[must_use]
+    pub fn get(&self, source_id: &str) -> Option<&LoadedFile> {
+        self.files.iter().find(|file| &*file.source_id == source_id)
+    }
+
+    /// Returns every include for [`compute`](super::stage2::compute).
+    #[must_use]
+    pub fn resolved_includes(&self) -> Vec<ResolvedInclude<'_>> {
+        self.includes
+            .iter()
+            .map(|(includer, text_range, index)| {
+                let file = &self.files[*index];
+                ResolvedInclude {
+                    includer: includer.clone(),
+                    text_range: *text_range,
+                    source_id: file.source_id.clone(),
+                    outline: &file.outline,
+                }
+            })
+            .collect()
+    }
+}
@@ -1,4 +1,6 @@
-use dt_parser::ast::SourceFile;
+use dt_parser::{ast::SourceFile, SourceId};
+
+use crate::new::workspace::IncludedFiles;
 
 struct PanickingDiagnosticCollector;
 impl dt_diagnostic::DiagnosticCollector for PanickingDiagnosticCollector {
@@ -19,18 +21,18 @@ fn dts_to_json(filename: &str) -> serde_json::Value {
 
     let analyzed = crate::new::stage1::analyze_file(file, src, diag);
 
-    let includes = analyzed
-        .iter()
-        .filter_map(|a| a.as_include())
-        .collect::<Vec<_>>();
-    assert!(
-        includes.is_empty(),
-        "Nonzero number of includes: {includes:#?}"
+    let files = IncludedFiles::load(filename.as_ref(), &[] as &[&str]).unwrap();
+    let includes = files.resolved_includes();
+    assert_eq!(
+        includes.len(),
+        analyzed.iter().filter(|a| a.as_include().is_some()).count(),
+        "Includes not found: {analyzed:#?}"
     );
 
-    let stage2 = crate::new::stage2::compute(&analyzed, &[], diag);
+    let stage2 =
+        crate::new::stage2::compute(&files.main_file().source_id, &analyzed, &includes, diag);
 
-    stage2.root_node.into_json()
+    stage2.root_node.to_json()
 }
 
 macro_rules! define_tests {
@@ -51,6 +53,100 @@ macro_rules! define_tests {
 
 define_tests! {
     test_1_basic, "1";
-    // test_2_include, "2"; // FIXME: nonzero number of includes
+    test_2_include, "2";
     // test_3_macros, "3"; // FIXME: macros
 }
+
+#[test]
+fn json_spans() {
+    let src = "/dts-v1/;\n/ {\n\tserial@1000 { reg = <0x1000 0x100>, <0x2000 0x10>; compatible = \"a\", \"b\"; };\n};\n/ { serial@1000 { status; }; };";
+    let file = SourceFile::parse(src).source_file();
+    let diag = &PanickingDiagnosticCollector;
+    let analyzed = crate::new::stage1::analyze_file(&file, src, diag);
+    let stage2 = crate::new::stage2::compute(&SourceId::from("test.dts"), &analyzed, &[], diag);
+    let (json, spans) = stage2.root_node.to_json_with_spans();
+    assert_eq!(json, stage2.root_node.to_json());
+
+    let text = |pointer| spans.get(pointer).and_then(|(_, span)| span.text(src));
+    // The last definition of a merged node wins
+    assert_eq!(text(""), Some("/"));
+    assert_eq!(text("/serial@1000"), Some("serial@1000"));
+    assert_eq!(
+        spans.get("/serial@1000").unwrap().1.start,
+        src.rfind("serial").unwrap()
+    );
+    assert_eq!(
+        text("/serial@1000/reg"),
+        Some("reg = <0x1000 0x100>, <0x2000 0x10>;")
+    );
+    assert_eq!(text("/serial@1000/reg/1"), Some("<0x2000 0x10>"));
+    assert_eq!(text("/serial@1000/reg/1/0"), Some("0x2000"));
+    assert_eq!(text("/serial@1000/compatible/1"), Some("\"b\""));
+    assert_eq!(text("/serial@1000/status"), Some("status;"));
+    assert_eq!(text("/serial@1000/reg/2"), None);
+
+    let find = |pointer| spans.find(pointer).and_then(|(_, span)| span.text(src));
+    assert_eq!(find("/serial@1000/$nodename"), Some("serial@1000"));
+    assert_eq!(find("/serial@1000/reg/1/0"), Some("0x2000"));
+}
+
+#[test]
+fn json_spans_with_includes() {
+    let dtsi = "/ {\n\tserial@1000 { reg = <0x1000 0x100>; status = \"disabled\"; };\n};\n";
+    let src = "/dts-v1/;\n#include \"soc.dtsi\"\n/ { serial@1000 { status = \"okay\"; }; };\n";
+    let diag = &PanickingDiagnosticCollector;
+    let dtsi_file = SourceFile::parse(dtsi).source_file();
+    let dtsi_outline = crate::new::stage1::analyze_file(&dtsi_file, dtsi, diag);
+    let file = SourceFile::parse(src).source_file();
+    let outline = crate::new::stage1::analyze_file(&file, src, diag);
+
+    let (board, soc) = (SourceId::from("board.dts"), SourceId::from("soc.dtsi"));
+    let include = crate::new::stage2::ResolvedInclude {
+        includer: board.clone(),
+        text_range: outline[0].text_range(),
+        source_id: soc.clone(),
+        outline: &dtsi_outline,
+    };
+    let stage2 = crate::new::stage2::compute(&board, &outline, &[include], diag);
+    let (json, spans) = stage2.root_node.to_json_with_spans();
+    assert_eq!(
+        json,
+        serde_json::json!({ "serial@1000": { "reg": [[0x1000, 0x100]], "status": ["okay"] } })
+    );
+
+    let text = |pointer| {
+        let (source_id, span) = spans.get(pointer)?;
+        span.text(if *source_id == board { src } else { dtsi })
+            .map(|text| (source_id.as_ref(), text))
+    };
+    assert_eq!(text("/serial@1000/reg/0/1"), Some(("soc.dtsi", "0x100")));
+    assert_eq!(
+        text("/serial@1000/status"),
+        Some(("board.dts", "status = \"okay\";"))
+    );
+    assert_eq!(text("/serial@1000"), Some(("board.dts", "serial@1000")));
+}
+
+#[test]
+fn extensions() {
+    let src = "/dts-v1/;\n/ { soc { uart: serial@1000 { status = \"disabled\"; }; }; };\n&uart { status = \"okay\"; extra: child {}; };\n&extra { a; };\n&{/soc/serial@1000} { b; };\n&missing { c; };\n";
+    let file = SourceFile::parse(src).source_file();
+    let diag = &PanickingDiagnosticCollector;
+    let analyzed = crate::new::stage1::analyze_file(&file, src, diag);
+    let stage2 = crate::new::stage2::compute(&SourceId::from("test.dts"), &analyzed, &[], diag);
+    let (json, spans) = stage2.root_node.to_json_with_spans();
+    assert_eq!(
+        json,
+        serde_json::json!({ "soc": { "serial@1000": {
+            "status": ["okay"],
+            "child": { "a": true },
+            "b": true,
+        } } })
+    );
+    assert_eq!(
+        spans
+            .get("/soc/serial@1000")
+            .and_then(|(_, span)| span.text(src)),
+        Some("&{/soc/serial@1000}")
+    );
+}
@@ -0,0 +1,817 @@
+//! Validating whole devicetrees against a collection of binding schemas.
+
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use dt_analyzer::new::stage2::{Stage2File, Stage2Node, Stage2Tree};
+use dt_diagnostic::{Diagnostic, MultiSpan, Severity};
+use dt_parser::{SourceId, TextRange};
+use dt_workspace::Workspace;
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use serde_json::Value as JValue;
+use serde_yaml::Mapping;
+
+use crate::{
+    cache::{content_hash, default_cache_dir, CachedSchema, SchemaCache},
+    get_compatible_items,
+    zephyr::{self, ZephyrBindings},
+    Binding, BindingDoc, BindingSchema, CompatibleIndex, NodeSkeleton, PropertyTypes, SchemaIndex,
+    ZephyrBinding,
+};
+
+/// A binding schema which failed to load
+#[derive(Debug)]
+pub struct BindingLoadError {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+impl std::fmt::Display for BindingLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:#}", self.path.display(), self.error)
+    }
+}
+
+/// A collection of binding schemas, indexed by the compatibles they select, and of
+/// [`ZephyrBinding`]s.
+///
+/// This is the equivalent of `make dtbs_check`: see [`Bindings::validate`].
+#[derive(Debug, Default)]
+pub struct Bindings {
+    schemas: Vec<BindingSchema>,
+    zephyr: ZephyrBindings,
+    /// Schemas selecting nodes by `compatible`, which are most of them
+    by_compatible: FxHashMap<String, Vec<usize>>,
+    /// Schemas with a custom `select`, which must be tried on every node
+    by_select: Vec<usize>,
+    /// Every documented compatible, shared with lint passes
+    compatibles: Arc<CompatibleIndex>,
+    types: PropertyTypes,
+}
+
+impl Bindings {
+    /// Creates an empty collection.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles every `.yaml` file in `bindings_dirs` and their subdirectories in parallel, along
+    /// with the core schemas of the dt-schema checkout in `dt_schema_dir`.
+    ///
+    /// `$ref`s are resolved offline with a [`SchemaIndex`] of all of these files, including
+    /// dt-schema's meta-schemas. Schemas which fail to compile are returned as errors alongside the
+    /// loaded ones.
+    ///
+    /// Files without an `$id` are loaded as [`ZephyrBinding`]s instead.
+    #[must_use]
+    pub fn load_dirs(
+        bindings_dirs: &[impl AsRef<Path>],
+        dt_schema_dir: Option<&Path>,
+    ) -> (Self, Vec<BindingLoadError>) {
+        Self::load(bindings_dirs, dt_schema_dir, None)
+    }
+
+    /// Like [`Bindings::load_dirs`], but reuses the schemas processed by earlier runs from a cache
+    /// in `cache_dir`, e.g. [`default_cache_dir`](crate::default_cache_dir).
+    ///
+    /// Schemas are reprocessed when their files or dt-schema's meta-schemas change. The validators
+    /// of cached schemas are compiled on first use, so they don't report compilation errors here.
+    #[must_use]
+    pub fn load_dirs_cached(
+        bindings_dirs: &[impl AsRef<Path>],
+        dt_schema_dir: Option<&Path>,
+        cache_dir: &Path,
+    ) -> (Self, Vec<BindingLoadError>) {
+        Self::load(bindings_dirs, dt_schema_dir, Some(cache_dir))
+    }
+
+    fn load(
+        bindings_dirs: &[impl AsRef<Path>],
+        dt_schema_dir: Option<&Path>,
+        cache_dir: Option<&Path>,
+    ) -> (Self, Vec<BindingLoadError>) {
+        let mut errors = Vec::new();
+        let mut paths = Vec::new();
+        for dir in bindings_dirs {
+            collect_yaml_files(dir.as_ref(), &mut paths, &mut errors);
+        }
+        let mut dt_schema_paths = Vec::new();
+        if let Some(dir) = dt_schema_dir {
+            collect_yaml_files(dir, &mut dt_schema_paths, &mut errors);
+        }
+        paths.sort_unstable();
+        dt_schema_paths.sort_unstable();
+
+        let (index, index_errors) =
+            SchemaIndex::from_paths(&[paths.as_slice(), &dt_schema_paths].concat());
+        errors.extend(index_errors);
+
+        // Meta-schemas and tests aren't bindings
+        paths.extend(dt_schema_paths.into_iter().filter(|path| {
+            dt_schema_dir
+                .and_then(|dir| path.strip_prefix(dir).ok())
+                .is_some_and(|path| path.components().any(|c| c.as_os_str() == "schemas"))
+        }));
+
+        let meta_schemas = index.meta_schemas_hash();
+        let cache_path = cache_dir.map(|dir| SchemaCache::path(dir, bindings_dirs, dt_schema_dir));
+        let cache = cache_path
+            .as_deref()
+            .and_then(|path| SchemaCache::load(path, &meta_schemas))
+            .unwrap_or_default();
+
+        let results = paths
+            .into_par_iter()
+            .map(|path| load_file(path, &cache, &index))
+            .collect::<Vec<_>>();
+
+        let mut bindings = Self::new();
+        let mut hashes = Vec::new();
+        let mut cache_hits = 0;
+        let mut zephyr_files = Vec::new();
+        for result in results {
+            match result {
+                Ok(Loaded::Schema(schema, hash, cached)) => {
+                    cache_hits += usize::from(cached);
+                    hashes.push(hash);
+                    bindings.push(*schema);
+                }
+                Ok(Loaded::Zephyr(path, map)) => zephyr_files.push((path, map)),
+                Err(error) => errors.push(error),
+            }
+        }
+        let (zephyr_bindings, zephyr_errors) = zephyr::load(&zephyr_files);
+        errors.extend(zephyr_errors);
+        for binding in zephyr_bindings {
+            bindings.insert_zephyr(binding);
+        }
+
+        // The compatible index is reused if every schema is unchanged. Schemas are in the same order
+        // because paths are sorted.
+        if cache_hits == hashes.len() && cache_hits == cache.schemas.len() {
+            bindings.by_compatible = cache.by_compatible;
+            bindings.by_select = cache.by_select;
+            return (bindings, errors);
+        }
+        for i in 0..bindings.schemas.len() {
+            bindings.index_schema(i);
+        }
+        if let Some(cache_path) = cache_path {
+            let schemas = bindings
+                .schemas
+                .iter()
+                .zip(hashes)
+                .map(|(schema, hash)| CachedSchema {
+                    path: schema.path.clone(),
+                    hash,
+                    raw_schema: schema.raw_schema.clone(),
+                    custom_select: schema.custom_select,
+                })
+                .collect();
+            let cache = SchemaCache::new(
+                meta_schemas,
+                schemas,
+                bindings.by_compatible.clone(),
+                bindings.by_select.clone(),
+            );
+            if let Err(error) = cache.save(&cache_path) {
+                errors.push(BindingLoadError {
+                    path: cache_path,
+                    error: error.context("Failed to write the schema cache"),
+                });
+            }
+        }
+        (bindings, errors)
+    }
+
+    /// Loads the bindings configured in `workspace`, see [`Bindings::load_dirs`].
+    ///
+    /// Schemas are cached in the workspace's `cache-dir`, or [`default_cache_dir`] if it's not set.
+    ///
+    /// [`default_cache_dir`]: crate::default_cache_dir
+    #[must_use]
+    pub fn load_workspace(workspace: &Workspace) -> (Self, Vec<BindingLoadError>) {
+        let cache_dir = workspace
+            .config
+            .cache_dir()
+            .map(Path::to_owned)
+            .or_else(default_cache_dir);
+        Self::load(
+            workspace.config.bindings_dirs(),
+            workspace.config.dt_schema_dir(),
+            cache_dir.as_deref(),
+        )
+    }
+
+    /// Adds a schema to the collection.
+    pub fn insert(&mut self, schema: BindingSchema) {
+        self.push(schema);
+        self.index_schema(self.schemas.len() - 1);
+    }
+
+    /// Adds a Zephyr binding to the collection.
+    pub fn insert_zephyr(&mut self, binding: ZephyrBinding) {
+        if let Some(compatible) = &binding.compatible {
+            Arc::make_mut(&mut self.compatibles).insert(compatible, &binding.path);
+        }
+        self.zephyr.push(binding);
+    }
+
+    /// Adds a schema without adding it to the compatible index.
+    fn push(&mut self, schema: BindingSchema) {
+        self.types.insert_schema(&schema.raw_schema);
+        Arc::make_mut(&mut self.compatibles).insert_schema(
+            &schema.path,
+            &schema.raw_schema,
+            &schema.index,
+        );
+        self.schemas.push(schema);
+    }
+
+    /// Adds the schema at `index` to the compatible index.
+    fn index_schema(&mut self, index: usize) {
+        let schema = &self.schemas[index];
+        let compatibles = get_compatible_items(&schema.raw_schema);
+        if schema.custom_select || compatibles.is_empty() {
+            self.by_select.push(index);
+        } else {
+            for compatible in compatibles {
+                self.by_compatible
+                    .entry(compatible)
+                    .or_default()
+                    .push(index);
+            }
+        }
+    }
+
+    /// Returns all schemas in the collection.
+    pub fn schemas(&self) -> impl Iterator<Item = &BindingSchema> {
+        self.schemas.iter()
+    }
+
+    /// Returns all Zephyr bindings in the collection.
+    pub fn zephyr_bindings(&self) -> impl Iterator<Item = &ZephyrBinding> {
+        self.zephyr.iter()
+    }
+
+    /// Returns the number of schemas and Zephyr bindings.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.schemas.len() + self.zephyr.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the property types from the core properties and all schemas in the collection.
+    #[must_use]
+    pub fn property_types(&self) -> &PropertyTypes {
+        &self.types
+    }
+
+    /// Returns every compatible documented by the schemas in the collection.
+    #[must_use]
+    pub fn compatibles(&self) -> &Arc<CompatibleIndex> {
+        &self.compatibles
+    }
+
+    /// Returns the first schema documenting `compatible`.
+    #[must_use]
+    pub fn find_compatible(&self, compatible: &str) -> Option<&BindingSchema> {
+        if let Some(index) = self
+            .by_compatible
+            .get(compatible)
+            .and_then(|list| list.first())
+        {
+            return Some(&self.schemas[*index]);
+        }
+        // Schemas with a custom `select` or compatible patterns
+        let path = self.compatibles.schemas(compatible).next()?;
+        self.schemas.iter().find(|schema| schema.path == path)
+    }
+
+    /// Creates a node for `compatible` from the first schema documenting it, see
+    /// [`NodeSkeleton::from_schema`].
+    #[must_use]
+    pub fn node_skeleton(&self, compatible: &str) -> Option<NodeSkeleton> {
+        let schema = self.find_compatible(compatible)?;
+        Some(NodeSkeleton::from_schema(schema, compatible, &self.types))
+    }
+
+    /// Returns the documentation of the first binding documenting `compatible`, preferring
+    /// dt-schema bindings over Zephyr ones.
+    #[must_use]
+    pub fn binding_doc(&self, compatible: &str) -> Option<BindingDoc> {
+        if let Some(schema) = self.find_compatible(compatible) {
+            return Some(BindingDoc::from_schema(schema, &self.types));
+        }
+        self.zephyr
+            .iter()
+            .find(|binding| binding.compatible.as_deref() == Some(compatible))
+            .map(BindingDoc::from_zephyr)
+    }
+
+    /// Returns the schemas selecting the node converted to `json`.
+    pub fn matching<'a>(&'a self, json: &'a JValue) -> impl Iterator<Item = &'a BindingSchema> {
+        let mut candidates = json
+            .get("compatible")
+            .and_then(JValue::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(JValue::as_str)
+            .filter_map(|compatible| self.by_compatible.get(compatible))
+            .flatten()
+            .chain(&self.by_select)
+            .copied()
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        candidates
+            .into_iter()
+            .map(|index| &self.schemas[index])
+            .filter(|schema| {
+                schema
+                    .select_validator()
+                    .is_ok_and(|validator| validator.is_valid(json))
+            })
+    }
+
+    /// Validates every node in `file` against each schema selecting it and its Zephyr binding.
+    ///
+    /// Property values are first decoded with their types, see [`PropertyTypes::coerce`].
+    ///
+    /// Errors point to the value, cell, property or node which failed validation. Errors about
+    /// missing properties point to the node name. Each error is returned with the file it points
+    /// to, which is an included file for nodes and properties defined there.
+    #[must_use]
+    pub fn validate(&self, file: &Stage2File) -> Vec<(SourceId, Diagnostic)> {
+        let (mut root_json, mut spans) = file.root_node.to_json_with_spans();
+        self.types
+            .coerce(&file.root_node, &mut root_json, &mut spans);
+        add_nodenames(&mut root_json, "/");
+
+        let mut nodes = Vec::new();
+        self.collect_nodes(String::new(), &file.root_node, &root_json, None, &mut nodes);
+
+        let find = |pointer: &str| {
+            spans
+                .find(pointer)
+                .cloned()
+                .unwrap_or_else(|| (file.source_id.clone(), TextRange::new(0, 0)))
+        };
+        let mut diagnostics = nodes
+            .into_par_iter()
+            .flat_map_iter(|node| {
+                let bindings = self
+                    .matching(node.json)
+                    .map(|schema| schema as &dyn Binding)
+                    .chain(node.zephyr.map(|binding| binding as &dyn Binding));
+                let mut diagnostics = Vec::new();
+                for binding in bindings {
+                    let origin = binding.origin();
+                    for error in binding.validate(node.node, node.json) {
+                        let spans = error
+                            .pointers
+                            .iter()
+                            .map(|pointer| find(&format!("{}{pointer}", node.pointer)))
+                            .collect::<Vec<_>>();
+                        // Only the spans in one file are kept, preferably in the computed file
+                        let source_id = spans
+                            .iter()
+                            .find(|(source_id, _)| *source_id == file.source_id)
+                            .or(spans.first())
+                            .map_or_else(
+                                || file.source_id.clone(),
+                                |(source_id, _)| source_id.clone(),
+                            );
+                        let mut primary_spans = spans
+                            .into_iter()
+                            .filter(|(span_source_id, _)| *span_source_id == source_id)
+                            .map(|(_, span)| span)
+                            .collect::<Vec<_>>();
+                        primary_spans.sort_unstable();
+                        let mut msg = error.msg;
+                        if let Some(origin) = &origin {
+                            msg.push_str("\nfrom ");
+                            msg.push_str(origin);
+                        }
+                        diagnostics.push((
+                            source_id,
+                            Diagnostic {
+                                span: MultiSpan {
+                                    primary_spans,
+                                    span_labels: Vec::new(),
+                                },
+                                msg: Cow::Owned(msg),
+                                severity: Severity::Error,
+                                suggestions: Vec::new(),
+                            },
+                        ));
+                    }
+                }
+                diagnostics
+            })
+            .collect::<Vec<_>>();
+        diagnostics.sort_by(|(a_source_id, a), (b_source_id, b)| {
+            (a_source_id, a.span.primary_spans.first())
+                .cmp(&(b_source_id, b.span.primary_spans.first()))
+        });
+        diagnostics
+    }
+
+    /// Collects every node below `node` with its JSON pointer and Zephyr binding, whose parent has
+    /// the binding `parent`.
+    fn collect_nodes<'a>(
+        &'a self,
+        pointer: String,
+        node: &'a Stage2Node,
+        json: &'a JValue,
+        parent: Option<&'a ZephyrBinding>,
+        out: &mut Vec<NodeToValidate<'a>>,
+    ) {
+        let compatibles = json
+            .get("compatible")
+            .and_then(JValue::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(JValue::as_str);
+        let zephyr = self.zephyr.find(compatibles, parent);
+        for (name, child) in &node.children {
+            if let (Stage2Tree::Node(child), Some(child_json)) = (child, json.get(name)) {
+                let pointer = format!("{pointer}/{}", escape(name));
+                self.collect_nodes(pointer, child, child_json, zephyr, out);
+            }
+        }
+        out.push(NodeToValidate {
+            pointer,
+            node,
+            json,
+            zephyr,
+        });
+    }
+}
+
+/// A file loaded by [`Bindings::load`]
+enum Loaded {
+    /// A dt-schema binding with the hash of its file and whether it's from the cache
+    Schema(Box<BindingSchema>, String, bool),
+    /// A Zephyr binding, whose `include:`s are merged once every file is loaded
+    Zephyr(PathBuf, Mapping),
+}
+
+/// Loads the binding at `path`, from `cache` if it's a dt-schema binding which hasn't changed.
+fn load_file(
+    path: PathBuf,
+    cache: &SchemaCache,
+    index: &SchemaIndex,
+) -> Result<Loaded, BindingLoadError> {
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            return Err(BindingLoadError {
+                path,
+                error: err.into(),
+            })
+        }
+    };
+    if let Some(map) = zephyr::parse_binding(&text) {
+        return Ok(Loaded::Zephyr(path, map));
+    }
+    let hash = content_hash(&text);
+    if let Some(cached) = cache.get(&path, &hash) {
+        let schema =
+            BindingSchema::from_fixed(path, cached.raw_schema.clone(), cached.custom_select, index);
+        return Ok(Loaded::Schema(Box::new(schema), hash, true));
+    }
+    match BindingSchema::compile_text(&path, &text, index) {
+        Ok(schema) => Ok(Loaded::Schema(Box::new(schema), hash, false)),
+        Err(error) => Err(BindingLoadError { path, error }),
+    }
+}
+
+/// A node found by [`Bindings::collect_nodes`]
+struct NodeToValidate<'a> {
+    pointer: String,
+    node: &'a Stage2Node,
+    json: &'a JValue,
+    zephyr: Option<&'a ZephyrBinding>,
+}
+
+pub(crate) fn collect_yaml_files(
+    dir: &Path,
+    paths: &mut Vec<PathBuf>,
+    errors: &mut Vec<BindingLoadError>,
+) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            errors.push(BindingLoadError {
+                path: dir.to_owned(),
+                error: err.into(),
+            });
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_yaml_files(&path, paths, errors);
+        } else if path.extension().is_some_and(|ext| ext == "yaml") {
+            paths.push(path);
+        }
+    }
+}
+
+/// Adds `$nodename` to `json` and every subnode, like dt-schema.
+fn add_nodenames(json: &mut JValue, name: &str) {
+    let JValue::Object(map) = json else {
+        return;
+    };
+    for (child_name, child) in map.iter_mut() {
+        add_nodenames(child, child_name);
+    }
+    map.insert("$nodename".to_owned(), JValue::Array(vec![name.into()]));
+}
+
+/// Escapes a JSON pointer segment.
+pub(crate) fn escape(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use dt_analyzer::new::workspace::IncludedFiles;
+    use dt_parser::ast::SourceFile;
+
+    use super::*;
+
+    const BINDING: &str = include_str!("../test_data/bindings/misc/acme,foo.yaml");
+
+    fn bindings() -> Bindings {
+        let (bindings, errors) = Bindings::load_dirs(&["test_data/bindings"], None);
+        assert_eq!(bindings.len(), 1, "{errors:?}");
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].path.ends_with("broken.yaml"));
+        bindings
+    }
+
+    fn validate(bindings: &Bindings, src: &str) -> Vec<(String, String)> {
+        let file = SourceFile::parse(src).source_file();
+        let diag = std::sync::Mutex::new(Vec::new());
+        let outline = dt_analyzer::new::stage1::analyze_file(&file, src, &diag);
+        let stage2 =
+            dt_analyzer::new::stage2::compute(&SourceId::from("test.dts"), &outline, &[], &diag);
+        bindings
+            .validate(&stage2)
+            .into_iter()
+            .map(|(_, diagnostic)| {
+                let spans = diagnostic.span.primary_spans.iter();
+                (
+                    spans
+                        .map(|span| span.text(src).unwrap())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    diagnostic.msg.lines().next().unwrap().to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn validate_tree() {
+        let bindings = bindings();
+        let src = r#"/dts-v1/;
+/ {
+    good { compatible = "acme,foo"; power-domains = <1>; };
+    missing { compatible = "acme,foo"; };
+    soc {
+        bad { compatible = "acme,foo"; power-domains = <1>; acme,mode = "medium"; };
+        cells { compatible = "acme,foo"; power-domains = <1>; acme,channels = <0 1>, <4 1>; };
+        unknown { compatible = "acme,foo"; power-domains = <1>; foo; bar = <1>; };
+        clocks { compatible = "acme,foo"; power-domains = <1>; clocks = <&clk 1 &clk 2 &clk 3>; };
+    };
+    unrelated { compatible = "acme,bar"; };
+    clk: clock-controller { #clock-cells = <1>; };
+};"#;
+        assert_eq!(
+            validate(&bindings, src),
+            [
+                (
+                    "missing".to_owned(),
+                    r#""power-domains" is a required property"#.to_owned()
+                ),
+                (
+                    r#""medium""#.to_owned(),
+                    r#""medium" is not one of ["fast","slow"]"#.to_owned()
+                ),
+                (
+                    "4".to_owned(),
+                    "4 is greater than the maximum of 3".to_owned()
+                ),
+                (
+                    "foo;, bar = <1>;".to_owned(),
+                    "Additional properties are not allowed ('bar', 'foo' were unexpected)"
+                        .to_owned()
+                ),
+                // Split into a row for each clock with `#clock-cells`
+                (
+                    "clocks = <&clk 1 &clk 2 &clk 3>;".to_owned(),
+                    "[[1,1],[1,2],[1,3]] has more than 2 items".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_includes() {
+        let bindings = bindings();
+        let files =
+            IncludedFiles::load(Path::new("test_data/include/board.dts"), &[] as &[&Path]).unwrap();
+        let main_file = files.main_file();
+        let stage2 = dt_analyzer::new::stage2::compute(
+            &main_file.source_id,
+            &main_file.outline,
+            &files.resolved_includes(),
+            &std::sync::Mutex::new(Vec::new()),
+        );
+        let mut errors = bindings
+            .validate(&stage2)
+            .into_iter()
+            .map(|(source_id, diagnostic)| {
+                let file = files.get(&source_id).unwrap();
+                (
+                    file.path.file_name().unwrap().to_str().unwrap().to_owned(),
+                    diagnostic.span.primary_spans[0]
+                        .text(&file.text)
+                        .unwrap()
+                        .to_owned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        errors.sort();
+        // `power-domains` from the `&foo` extension satisfies `required`
+        assert_eq!(
+            errors,
+            [
+                ("board.dts".to_owned(), r#""medium""#.to_owned()),
+                ("soc.dtsi".to_owned(), "4".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn binding_doc() {
+        let (bindings, errors) =
+            Bindings::load_dirs(&["test_data/uart", "test_data/schemas"], None);
+        assert!(errors.is_empty(), "{errors:?}");
+
+        let doc = bindings.binding_doc("acme,uart").unwrap();
+        assert_eq!(doc.title.as_deref(), Some("Acme UART"));
+        assert_eq!(doc.description.as_deref(), Some("A UART."));
+        assert_eq!(doc.maintainers, ["Jane Doe <jane@example.com>"]);
+        let names = doc
+            .properties
+            .iter()
+            .map(|property| property.name.as_str())
+            .collect::<Vec<_>>();
+        // Fixups add the properties every node may have, like `status`
+        assert_eq!(names[..2], ["compatible", "reg"]);
+        assert_eq!(names.last(), Some(&"acme,parity"));
+        assert!(names.contains(&"current-speed") && names.contains(&"status"));
+        assert!(!names.contains(&"$nodename"));
+        let speed = doc.property("current-speed").unwrap();
+        assert_eq!(
+            speed.description.as_deref(),
+            Some("The current active speed of the UART.")
+        );
+        assert_eq!(speed.type_name.as_deref(), Some("uint32"));
+        assert!(doc.property("reg").unwrap().required);
+        assert_eq!(
+            doc.property("reg").unwrap().type_name.as_deref(),
+            Some("uint32-matrix")
+        );
+        assert_eq!(
+            doc.property("acme,parity").unwrap().values,
+            [r#""even""#, r#""odd""#]
+        );
+        assert_eq!(bindings.binding_doc("acme,other"), None);
+    }
+
+    #[test]
+    fn validate_zephyr() {
+        // Alongside dt-schema bindings
+        let (bindings, errors) =
+            Bindings::load_dirs(&["test_data/zephyr", "test_data/bindings/misc"], None);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!((bindings.len(), bindings.zephyr_bindings().count()), (3, 2));
+        assert!(bindings.compatibles().is_documented("acme,sensor"));
+
+        let src = r#"/dts-v1/;
+/ {
+    i2c {
+        compatible = "acme,i2c";
+        status = "broken";
+        #gpio-cells = <3>;
+        sensor { compatible = "acme,sensor"; channel { acme,gain = <3>; }; };
+    };
+    sensor { compatible = "acme,sensor"; };
+    foo { compatible = "acme,foo"; };
+    i2c2 { compatible = "acme,i2c"; clock-frequency = "fast"; };
+};"#;
+        assert_eq!(
+            validate(&bindings, src),
+            [
+                (
+                    "i2c".to_owned(),
+                    r#""clock-frequency" is a required property"#.to_owned()
+                ),
+                (
+                    r#""broken""#.to_owned(),
+                    r#""broken" is not one of ["okay","disabled"]"#.to_owned()
+                ),
+                (
+                    "3".to_owned(),
+                    r##""#gpio-cells" should be 2 for the specifier cells [pin, flags]"##
+                        .to_owned()
+                ),
+                // Only on the I2C bus
+                (
+                    "sensor".to_owned(),
+                    r#""reg" is a required property"#.to_owned()
+                ),
+                ("3".to_owned(), "3 is not one of [1,2,4]".to_owned()),
+                // Alongside dt-schema bindings
+                (
+                    "foo".to_owned(),
+                    r#""power-domains" is a required property"#.to_owned()
+                ),
+                (
+                    r#"clock-frequency = "fast";"#.to_owned(),
+                    r#""clock-frequency" should have type int"#.to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let bindings_dir = dir.path().join("bindings");
+        let cache_dir = dir.path().join("cache");
+        std::fs::create_dir_all(&bindings_dir).unwrap();
+        for name in ["misc/acme,foo.yaml", "broken.yaml"] {
+            let fixture = Path::new("test_data/bindings").join(name);
+            std::fs::copy(&fixture, bindings_dir.join(fixture.file_name().unwrap())).unwrap();
+        }
+        let src = r#"/ { foo { compatible = "acme,foo"; acme,mode = "medium"; }; };"#;
+
+        let (bindings, errors) = Bindings::load_dirs_cached(&[&bindings_dir], None, &cache_dir);
+        assert_eq!((bindings.len(), errors.len()), (1, 1), "{errors:?}");
+        let cache_path = SchemaCache::path(&cache_dir, &[&bindings_dir], None);
+        assert!(cache_path.is_file());
+        let expected = validate(&bindings, src);
+        assert_eq!(expected.len(), 2);
+
+        // From the cache, with the compatible index and lazily compiled validators
+        let (bindings, errors) = Bindings::load_dirs_cached(&[&bindings_dir], None, &cache_dir);
+        assert_eq!((bindings.len(), errors.len()), (1, 1), "{errors:?}");
+        assert!(bindings.by_compatible.contains_key("acme,foo"));
+        assert_eq!(validate(&bindings, src), expected);
+
+        // Changed files are reprocessed
+        std::fs::write(
+            bindings_dir.join("acme,foo.yaml"),
+            BINDING.replace("- power-domains\n", ""),
+        )
+        .unwrap();
+        let (bindings, _) = Bindings::load_dirs_cached(&[&bindings_dir], None, &cache_dir);
+        assert_eq!(validate(&bindings, src), expected[1..]);
+
+        // Caches from other versions are ignored
+        let cache = std::fs::read_to_string(&cache_path).unwrap();
+        std::fs::write(
+            &cache_path,
+            cache.replace(env!("CARGO_PKG_VERSION"), "0.0.0-old"),
+        )
+        .unwrap();
+        assert!(SchemaCache::load(&cache_path, &bindings_meta_schemas(&bindings_dir)).is_none());
+
+        // So are caches from other formats of the same version
+        std::fs::write(&cache_path, cache.replace("\"format\":1,", "\"format\":0,")).unwrap();
+        assert!(SchemaCache::load(&cache_path, &bindings_meta_schemas(&bindings_dir)).is_none());
+        std::fs::write(&cache_path, cache).unwrap();
+        assert!(SchemaCache::load(&cache_path, &bindings_meta_schemas(&bindings_dir)).is_some());
+    }
+
+    fn bindings_meta_schemas(dir: &Path) -> String {
+        SchemaIndex::build(&[dir]).0.meta_schemas_hash()
+    }
+}
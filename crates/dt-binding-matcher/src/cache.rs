@@ -0,0 +1,142 @@
+//! A cache of processed binding schemas on disk, so large trees like Linux's load quickly.
+
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Mapping;
+use sha2::{Digest as _, Sha256};
+
+/// Version of the cache's format and of the fixups applied to the cached schemas. Bump it when
+/// either changes, since development builds share the crate version.
+const FORMAT_VERSION: u32 = 1;
+
+/// Returns the default directory for caches, `$XDG_CACHE_HOME/dt-tools` or `~/.cache/dt-tools`.
+#[must_use]
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::home_dir().map(|home| home.join(".cache")))?;
+    Some(cache_home.join("dt-tools"))
+}
+
+/// Binding schemas after fixups and the compatible index of [`Bindings`](crate::Bindings),
+/// keyed by the contents of their files.
+///
+/// Schemas from the cache skip parsing, meta-schema validation and fixups, and their validators are
+/// compiled on first use.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct SchemaCache {
+    /// The dt-tools version which wrote the cache, since fixups change between versions
+    pub version: String,
+    /// The [`FORMAT_VERSION`] of the cache
+    pub format: u32,
+    /// Hash of the meta-schemas, i.e. the dt-schema version, which decide whether schemas are
+    /// valid
+    pub meta_schemas: String,
+    /// Schemas in load order
+    pub schemas: Vec<CachedSchema>,
+    pub by_compatible: FxHashMap<String, Vec<usize>>,
+    pub by_select: Vec<usize>,
+    /// Index of each schema in `schemas` by path
+    #[serde(skip)]
+    by_path: FxHashMap<PathBuf, usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CachedSchema {
+    pub path: PathBuf,
+    /// Hash of the file's contents
+    pub hash: String,
+    pub raw_schema: Mapping,
+    pub custom_select: bool,
+}
+
+impl SchemaCache {
+    pub fn new(
+        meta_schemas: String,
+        schemas: Vec<CachedSchema>,
+        by_compatible: FxHashMap<String, Vec<usize>>,
+        by_select: Vec<usize>,
+    ) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            format: FORMAT_VERSION,
+            meta_schemas,
+            schemas,
+            by_compatible,
+            by_select,
+            by_path: FxHashMap::default(),
+        }
+    }
+
+    /// Returns the path of the cache file in `cache_dir` for schemas loaded from these directories.
+    pub fn path(
+        cache_dir: &Path,
+        bindings_dirs: &[impl AsRef<Path>],
+        dt_schema_dir: Option<&Path>,
+    ) -> PathBuf {
+        let mut hasher = Sha256::new();
+        for dir in bindings_dirs {
+            hasher.update(dir.as_ref().as_os_str().as_encoded_bytes());
+            hasher.update([0]);
+        }
+        // Paths can't contain NUL, so this separates the dt-schema checkout from the bindings
+        if let Some(dir) = dt_schema_dir {
+            hasher.update([0]);
+            hasher.update(dir.as_os_str().as_encoded_bytes());
+        }
+        let hash = hex(&hasher.finalize());
+        cache_dir.join(format!("bindings-{}.json", &hash[..16]))
+    }
+
+    /// Reads the cache at `path`, unless it's missing, invalid or stale.
+    pub fn load(path: &Path, meta_schemas: &str) -> Option<Self> {
+        let mut cache: Self = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+        if cache.version != env!("CARGO_PKG_VERSION")
+            || cache.format != FORMAT_VERSION
+            || cache.meta_schemas != meta_schemas
+        {
+            return None;
+        }
+        cache.by_path = (cache.schemas.iter().enumerate())
+            .map(|(i, schema)| (schema.path.clone(), i))
+            .collect();
+        Some(cache)
+    }
+
+    /// Writes the cache to `path` atomically, so concurrent readers never see half of it.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        std::fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Returns the cached schema for `path` if the file's contents have the hash `hash`.
+    pub fn get(&self, path: &Path, hash: &str) -> Option<&CachedSchema> {
+        let schema = &self.schemas[*self.by_path.get(path)?];
+        (schema.hash == hash).then_some(schema)
+    }
+}
+
+/// Hashes the contents of a file for the cache with SHA-256, which is the same across platforms
+/// and versions.
+pub(crate) fn content_hash(text: &str) -> String {
+    hex(&Sha256::digest(text))
+}
+
+/// Formats a digest as lowercase hex.
+pub(crate) fn hex(digest: &[u8]) -> String {
+    digest.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
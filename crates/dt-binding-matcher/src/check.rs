@@ -0,0 +1,300 @@
+//! Checking binding schemas against dt-schema's meta-schemas, like `make dt_binding_check`.
+
+use std::path::Path;
+
+use jsonschema::error::ValidationErrorKind;
+use rustc_hash::FxHashMap;
+use serde_json::Value as JValue;
+use yaml_rust2::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
+
+use crate::{bindings::escape, BindingSchema, SchemaIndex};
+
+/// A problem in a binding schema file, found by [`check_binding`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    /// [JSON pointer](https://datatracker.ietf.org/doc/html/rfc6901) to the offending value
+    pub pointer: String,
+    /// 1-based line of the value, or of its key in a mapping
+    pub line: usize,
+    /// 1-based column of the value, or of its key in a mapping
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Checks the binding schema `text`, read from `path`, like `make dt_binding_check`.
+///
+/// The schema is validated against the meta-schema in its `$schema`, which restricts the keywords
+/// and property names bindings may use. Its `$id` must match `path`, and it must compile with its
+/// `$ref`s resolved by `index`. Compilation is only tried if the other checks pass.
+#[must_use]
+pub fn check_binding(path: &Path, text: &str, index: &SchemaIndex) -> Vec<SchemaError> {
+    let yaml: serde_yaml::Value = match serde_yaml::from_str(text) {
+        Ok(yaml) => yaml,
+        Err(err) => {
+            let (line, column) = err
+                .location()
+                .map_or((1, 1), |location| (location.line(), location.column()));
+            return vec![SchemaError {
+                pointer: String::new(),
+                line,
+                column,
+                message: err.to_string(),
+            }];
+        }
+    };
+    let positions = YamlPositions::new(text);
+    let json = match serde_json::to_value(&yaml) {
+        Ok(json) => json,
+        Err(err) => return vec![positions.error(String::new(), err.to_string())],
+    };
+
+    let mut errors = meta_schema_errors(&json, index)
+        .into_iter()
+        .map(|(pointer, message)| positions.error(pointer, message))
+        .collect::<Vec<_>>();
+
+    if let Some(id) = json.get("$id").and_then(JValue::as_str) {
+        if let Some(relative) = id.strip_prefix("http://devicetree.org/schemas/") {
+            let relative = relative.trim_end_matches('#');
+            if !path.ends_with(relative) {
+                errors.push(positions.error(
+                    "/$id".to_owned(),
+                    format!(
+                        "`$id` doesn't match the file path: `{relative}` isn't the end of {}",
+                        path.display()
+                    ),
+                ));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        if let Err(err) = BindingSchema::compile_unchecked(path, yaml, index) {
+            errors.push(positions.error(String::new(), format!("{err:#}")));
+        }
+    }
+    errors.sort_by_key(|error| (error.line, error.column));
+    errors
+}
+
+/// Validates a binding schema against the meta-schema in its `$schema`.
+///
+/// Returns the JSON pointer and message of each error.
+pub(crate) fn meta_schema_errors(json: &JValue, index: &SchemaIndex) -> Vec<(String, String)> {
+    let Some(meta_schema) = json.get("$schema").and_then(JValue::as_str) else {
+        return vec![(String::new(), "`$schema` is missing".to_owned())];
+    };
+    let validator = match index.meta_validator(meta_schema) {
+        Ok(validator) => validator,
+        Err(err) => return vec![("/$schema".to_owned(), format!("{err:#}"))],
+    };
+
+    let mut errors = Vec::new();
+    for error in validator.iter_errors(json) {
+        let pointer = error.instance_path.to_string();
+        match &error.kind {
+            // Point to each unexpected key, e.g. a keyword outside dt-schema's vocabulary
+            ValidationErrorKind::AdditionalProperties { unexpected }
+            | ValidationErrorKind::UnevaluatedProperties { unexpected } => {
+                for name in unexpected {
+                    errors.push((format!("{pointer}/{}", escape(name)), error.to_string()));
+                }
+            }
+            // Point to the key which doesn't match, e.g. a property name with uppercase letters
+            ValidationErrorKind::PropertyNames { error: name_error } => {
+                let name = name_error.instance.as_str().unwrap_or_default();
+                errors.push((format!("{pointer}/{}", escape(name)), error.to_string()));
+            }
+            _ => errors.push((pointer, error.to_string())),
+        }
+    }
+    errors
+}
+
+/// Positions of the values in a YAML document by JSON pointer.
+///
+/// Values in mappings are at their keys, which is where YAML errors are usually reported.
+pub(crate) struct YamlPositions {
+    positions: FxHashMap<String, (usize, usize)>,
+}
+
+impl YamlPositions {
+    pub(crate) fn new(text: &str) -> Self {
+        let mut receiver = PositionReceiver {
+            stack: Vec::new(),
+            positions: FxHashMap::default(),
+        };
+        // Syntax errors are reported by serde_yaml
+        let _ = Parser::new_from_str(text).load(&mut receiver, false);
+        Self {
+            positions: receiver.positions,
+        }
+    }
+
+    /// Returns the 1-based line and column of the value at `pointer`.
+    pub(crate) fn get(&self, pointer: &str) -> Option<(usize, usize)> {
+        self.positions.get(pointer).copied()
+    }
+
+    /// Creates an error at the value at `pointer` or its closest ancestor.
+    pub(crate) fn error(&self, pointer: String, message: String) -> SchemaError {
+        let mut ancestor = pointer.as_str();
+        let (line, column) = loop {
+            if let Some(position) = self.positions.get(ancestor) {
+                break *position;
+            }
+            match ancestor.rfind('/') {
+                Some(slash) => ancestor = &ancestor[..slash],
+                None => break (1, 1),
+            }
+        };
+        SchemaError {
+            pointer,
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+enum Container {
+    /// The key of the current entry, or `None` when a key is expected next
+    Mapping(Option<String>),
+    /// The index of the next item
+    Sequence(usize),
+}
+
+struct PositionReceiver {
+    /// Open containers and their pointers
+    stack: Vec<(Container, String)>,
+    positions: FxHashMap<String, (usize, usize)>,
+}
+
+impl PositionReceiver {
+    /// Records the position of a node and returns its pointer.
+    fn node(&mut self, scalar: Option<&str>, mark: Marker) -> String {
+        let position = (mark.line(), mark.col() + 1);
+        let Some((container, pointer)) = self.stack.last_mut() else {
+            // The root is at the start of the file, not at its first key
+            self.positions.insert(String::new(), (1, 1));
+            return String::new();
+        };
+        match container {
+            Container::Mapping(key @ None) => {
+                // Complex keys aren't supported, but they aren't valid JSON either
+                let name = scalar.unwrap_or_default().to_owned();
+                let key_pointer = format!("{pointer}/{}", escape(&name));
+                self.positions.insert(key_pointer, position);
+                *key = Some(name);
+                String::new()
+            }
+            Container::Mapping(key @ Some(_)) => {
+                let name = key.take().unwrap_or_default();
+                format!("{pointer}/{}", escape(&name))
+            }
+            Container::Sequence(index) => {
+                let item_pointer = format!("{pointer}/{index}");
+                *index += 1;
+                self.positions.insert(item_pointer.clone(), position);
+                item_pointer
+            }
+        }
+    }
+}
+
+impl MarkedEventReceiver for PositionReceiver {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, ..) => {
+                self.node(Some(&value), mark);
+            }
+            Event::Alias(_) => {
+                self.node(None, mark);
+            }
+            Event::MappingStart(..) => {
+                let pointer = self.node(None, mark);
+                self.stack.push((Container::Mapping(None), pointer));
+            }
+            Event::SequenceStart(..) => {
+                let pointer = self.node(None, mark);
+                self.stack.push((Container::Sequence(0), pointer));
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks `text` as if it were the binding `bindings/<name>`, which isn't read.
+    fn check(name: &str, text: &str, index: &SchemaIndex) -> Vec<String> {
+        check_binding(&Path::new("bindings").join(name), text, index)
+            .iter()
+            .map(|error| format!("{} {error}", error.pointer))
+            .collect()
+    }
+
+    #[test]
+    fn check_bindings() {
+        let (index, errors) = SchemaIndex::build(&["test_data/meta-schemas"]);
+        assert!(errors.is_empty(), "{errors:?}");
+
+        assert_eq!(
+            check(
+                "good.yaml",
+                "$id: http://devicetree.org/schemas/good.yaml#\n\
+                $schema: http://devicetree.org/meta-schemas/core.yaml#\n\
+                title: Good\n\
+                properties:\n  acme,foo: true\n",
+                &index,
+            ),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            check(
+                "bad.yaml",
+                "$id: http://devicetree.org/schemas/misc/bad.yaml#\n\
+                $schema: http://devicetree.org/meta-schemas/core.yaml#\n\
+                properties:\n  acme,foo: true\n  Acme_Bar: true\n\
+                descripton: typo\n",
+                &index,
+            ),
+            [
+                r#" 1:1: "title" is a required property"#.to_owned(),
+                "/$id 1:1: `$id` doesn't match the file path: `misc/bad.yaml` isn't the end of "
+                    .to_owned()
+                    + &Path::new("bindings/bad.yaml").display().to_string(),
+                r#"/properties/Acme_Bar 5:3: "Acme_Bar" does not match "^[a-z][a-z0-9,-]*$""#
+                    .to_owned(),
+                "/descripton 6:1: Additional properties are not allowed ('descripton' was \
+                unexpected)"
+                    .to_owned(),
+            ]
+        );
+        assert_eq!(
+            check("syntax.yaml", "title: [unclosed\n", &index),
+            [
+                " 2:1: did not find expected ',' or ']' at line 2 column 1, while parsing a flow \
+            sequence at line 1 column 8"
+            ]
+        );
+        assert_eq!(
+            check("no-schema.yaml", "title: No schema\n", &index),
+            [" 1:1: `$schema` is missing"]
+        );
+    }
+}
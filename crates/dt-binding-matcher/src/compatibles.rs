@@ -0,0 +1,339 @@
+//! Finding the compatibles documented by binding schemas.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use rustc_hash::FxHashMap;
+use serde_yaml::{Mapping, Value};
+use url::Url;
+
+use crate::{types::resolve_pointer, SchemaIndex};
+
+/// How many `$ref`s are followed in a row, so reference cycles terminate
+const MAX_REF_DEPTH: usize = 16;
+
+/// Returns the compatibles accepted by the `compatible` property of a schema, or an empty set if
+/// they can't all be listed, e.g. because of a `pattern`.
+///
+/// Works with both plain and fixed up schemas, where `compatible` is wrapped in `items`. Local
+/// `$ref`s like `#/$defs/compatibles` are followed.
+#[must_use]
+pub fn get_compatible_items(map: &Mapping) -> HashSet<String> {
+    let mut compatibles = Compatibles::new(map, None);
+    if !compatibles.collect_property(map) {
+        compatibles.names.clear();
+    }
+    compatibles.names
+}
+
+/// The compatibles and compatible patterns found in a schema.
+struct Compatibles<'a> {
+    /// The whole schema, which local `$ref`s point into
+    root: &'a Mapping,
+    /// The schemas which `$ref`s to other files are resolved with
+    index: Option<&'a SchemaIndex>,
+    names: HashSet<String>,
+    patterns: Vec<String>,
+}
+
+impl<'a> Compatibles<'a> {
+    fn new(root: &'a Mapping, index: Option<&'a SchemaIndex>) -> Self {
+        Self {
+            root,
+            index,
+            names: HashSet::new(),
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Collects the compatibles of the `compatible` property of `map`. Returns `true` if every
+    /// value it accepts has one of them.
+    fn collect_property(&mut self, map: &Mapping) -> bool {
+        map.get("properties")
+            .and_then(Value::as_mapping)
+            .and_then(|properties| properties.get("compatible"))
+            .is_some_and(|compatible| self.collect(compatible, 0))
+    }
+
+    /// Collects the compatibles in `schema`. Returns `true` if every value `schema` accepts has one
+    /// of them.
+    fn collect(&mut self, schema: &Value, depth: usize) -> bool {
+        let Some(schema) = schema.as_mapping() else {
+            return false;
+        };
+        let mut listed = false;
+        if let Some(item) = schema.get("const").and_then(Value::as_str) {
+            self.names.insert(item.to_owned());
+            listed = true;
+        }
+        if let Some(items) = schema.get("enum").and_then(Value::as_sequence) {
+            self.names
+                .extend(items.iter().filter_map(Value::as_str).map(str::to_owned));
+            listed = true;
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            self.patterns.push(pattern.to_owned());
+        }
+        // One listed item is enough, since it must be in every compatible list
+        match schema.get("items") {
+            Some(Value::Sequence(items)) => {
+                for item in items {
+                    listed |= self.collect(item, depth);
+                }
+            }
+            Some(item) => listed |= self.collect(item, depth),
+            None => {}
+        }
+        if let Some(contains) = schema.get("contains") {
+            listed |= self.collect(contains, depth);
+        }
+        if let Some(branches) = schema.get("allOf").and_then(Value::as_sequence) {
+            for branch in branches {
+                listed |= self.collect(branch, depth);
+            }
+        }
+        for key in ["oneOf", "anyOf"] {
+            if let Some(branches) = schema.get(key).and_then(Value::as_sequence) {
+                let mut all_listed = true;
+                for branch in branches {
+                    all_listed &= self.collect(branch, depth);
+                }
+                listed |= all_listed;
+            }
+        }
+        if let Some(reference) =
+            (schema.get("$ref").and_then(Value::as_str)).filter(|_| depth < MAX_REF_DEPTH)
+        {
+            listed |= match reference.strip_prefix('#') {
+                Some(pointer) => resolve_pointer(self.root, pointer)
+                    .is_some_and(|target| self.collect(target, depth + 1)),
+                None => self.collect_external(reference, depth + 1),
+            };
+        }
+        listed
+    }
+
+    /// Collects the compatibles in another schema, e.g. `/schemas/acme/common.yaml#/$defs/soc`,
+    /// resolved against the `$id` of the schema. Returns `false` if it isn't in the index.
+    fn collect_external(&mut self, reference: &str, depth: usize) -> bool {
+        let Some(index) = self.index else {
+            return false;
+        };
+        let Some(url) = (self.root.get("$id").and_then(Value::as_str))
+            .and_then(|id| Url::parse(id).ok())
+            .and_then(|id| id.join(reference).ok())
+        else {
+            return false;
+        };
+        let Some(Ok(schema)) = index.schema(url.as_str()).map(serde_yaml::to_value) else {
+            return false;
+        };
+        let Value::Mapping(root) = &schema else {
+            return false;
+        };
+        let mut compatibles = Compatibles {
+            root,
+            index: Some(index),
+            names: std::mem::take(&mut self.names),
+            patterns: std::mem::take(&mut self.patterns),
+        };
+        let listed = match url.fragment().unwrap_or_default() {
+            "" => compatibles.collect(&schema, depth),
+            pointer => resolve_pointer(root, pointer)
+                .is_some_and(|target| compatibles.collect(target, depth)),
+        };
+        self.names = compatibles.names;
+        self.patterns = compatibles.patterns;
+        listed
+    }
+}
+
+/// Every compatible documented by a collection of binding schemas, with the schemas documenting
+/// them.
+///
+/// Unlike the index used to select schemas, this includes compatibles of schemas with a custom
+/// `select` and those documented only by a `pattern`, like `checkpatch.pl` checks for.
+#[derive(Debug, Default, Clone)]
+pub struct CompatibleIndex {
+    names: FxHashMap<String, Vec<PathBuf>>,
+    /// Compatible patterns, e.g. `^acme,foo-[0-9]+$`
+    patterns: Vec<(Regex, PathBuf)>,
+}
+
+impl CompatibleIndex {
+    /// Creates an empty index.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the compatibles documented by a binding schema read from `path`, following `$ref`s to
+    /// other schemas in `index`.
+    ///
+    /// Patterns which aren't valid regular expressions are ignored.
+    pub fn insert_schema(&mut self, path: &Path, schema: &Mapping, index: &SchemaIndex) {
+        let mut compatibles = Compatibles::new(schema, Some(index));
+        compatibles.collect_property(schema);
+        for name in compatibles.names {
+            self.names.entry(name).or_default().push(path.to_owned());
+        }
+        for pattern in compatibles.patterns {
+            if let Ok(regex) = Regex::new(&pattern) {
+                self.patterns.push((regex, path.to_owned()));
+            }
+        }
+    }
+
+    /// Adds a compatible documented by the binding read from `path`, e.g. a [`ZephyrBinding`].
+    ///
+    /// [`ZephyrBinding`]: crate::ZephyrBinding
+    pub fn insert(&mut self, compatible: &str, path: &Path) {
+        self.names
+            .entry(compatible.to_owned())
+            .or_default()
+            .push(path.to_owned());
+    }
+
+    /// Returns `true` if any schema documents `compatible`.
+    #[must_use]
+    pub fn is_documented(&self, compatible: &str) -> bool {
+        self.names.contains_key(compatible)
+            || self
+                .patterns
+                .iter()
+                .any(|(regex, _)| regex.is_match(compatible))
+    }
+
+    /// Returns the paths of the schemas documenting `compatible`.
+    pub fn schemas<'a>(&'a self, compatible: &'a str) -> impl Iterator<Item = &'a Path> {
+        let by_name = self.names.get(compatible).into_iter().flatten();
+        let by_pattern = self
+            .patterns
+            .iter()
+            .filter(move |(regex, _)| regex.is_match(compatible))
+            .map(|(_, path)| path);
+        by_name.chain(by_pattern).map(PathBuf::as_path)
+    }
+
+    /// Returns the number of documented compatibles, excluding patterns.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns `true` if no compatibles or patterns are documented.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.patterns.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn sorted(set: HashSet<String>) -> Vec<String> {
+        let mut list = set.into_iter().collect::<Vec<_>>();
+        list.sort_unstable();
+        list
+    }
+
+    #[test]
+    fn nested_and_referenced() {
+        let schema = mapping(
+            r"
+properties:
+  compatible:
+    oneOf:
+      - items:
+          - enum: ['acme,foo-v2', 'acme,foo-v3']
+          - const: acme,foo
+      - $ref: '#/$defs/legacy'
+$defs:
+  legacy:
+    allOf:
+      - const: acme,foo-legacy
+",
+        );
+        assert_eq!(
+            sorted(get_compatible_items(&schema)),
+            ["acme,foo", "acme,foo-legacy", "acme,foo-v2", "acme,foo-v3"]
+        );
+    }
+
+    #[test]
+    fn incomplete() {
+        let schema = mapping(
+            r"
+properties:
+  compatible:
+    oneOf:
+      - const: acme,foo
+      - pattern: '^acme,foo-[0-9]+$'
+",
+        );
+        assert!(get_compatible_items(&schema).is_empty());
+
+        let mut index = CompatibleIndex::new();
+        index.insert_schema(Path::new("acme,foo.yaml"), &schema, &SchemaIndex::default());
+        assert!(index.is_documented("acme,foo"));
+        assert!(index.is_documented("acme,foo-12"));
+        assert!(!index.is_documented("acme,bar"));
+        assert_eq!(
+            index.schemas("acme,foo-12").collect::<Vec<_>>(),
+            [Path::new("acme,foo.yaml")]
+        );
+    }
+
+    #[test]
+    fn other_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("common.yaml"),
+            r"
+$id: http://devicetree.org/schemas/acme/common.yaml#
+$defs:
+  compatibles:
+    enum: ['acme,foo-v2', 'acme,foo-v3']
+",
+        )
+        .unwrap();
+        let (schemas, errors) = SchemaIndex::build(&[dir.path()]);
+        assert!(errors.is_empty(), "{errors:?}");
+        let schema = mapping(
+            r"
+$id: http://devicetree.org/schemas/acme/foo.yaml#
+properties:
+  compatible:
+    oneOf:
+      - $ref: common.yaml#/$defs/compatibles
+      - $ref: /schemas/acme/missing.yaml#/$defs/compatibles
+",
+        );
+
+        let mut index = CompatibleIndex::new();
+        index.insert_schema(Path::new("acme,foo.yaml"), &schema, &schemas);
+        assert!(index.is_documented("acme,foo-v2"));
+        assert!(index.is_documented("acme,foo-v3"));
+        assert!(!index.is_documented("acme,foo"));
+    }
+
+    #[test]
+    fn reference_cycle() {
+        let schema = mapping(
+            r"
+properties:
+  compatible:
+    $ref: '#/properties/compatible'
+",
+        );
+        assert!(get_compatible_items(&schema).is_empty());
+    }
+}
@@ -0,0 +1,340 @@
+//! Documentation of bindings, e.g. for completion and hover in editors.
+
+use std::path::PathBuf;
+
+use serde_json::Value as JValue;
+
+use crate::{
+    skeleton::property_order, BindingSchema, PropertyType, PropertyTypes, SchemaIndex,
+    ZephyrBinding,
+};
+
+/// How many `$ref`s to other schemas are followed in a row, so reference cycles terminate
+const MAX_REF_DEPTH: usize = 8;
+
+/// The documentation of a binding, see [`Bindings::binding_doc`].
+///
+/// [`Bindings::binding_doc`]: crate::Bindings::binding_doc
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingDoc {
+    /// The path of the YAML file
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub maintainers: Vec<String>,
+    /// The documented properties, including those of the schemas the binding references, in the
+    /// order of the devicetree coding style
+    pub properties: Vec<PropertyDoc>,
+}
+
+/// A property of a [`BindingDoc`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyDoc {
+    pub name: String,
+    pub description: Option<String>,
+    /// The type, e.g. `uint32` or `phandle-array`
+    pub type_name: Option<String>,
+    pub required: bool,
+    pub deprecated: bool,
+    /// The values allowed by `const`s and `enum`s in DTS syntax, e.g. `"okay"` or `<1>`
+    pub values: Vec<String>,
+}
+
+impl BindingDoc {
+    /// Returns the documentation of the property `name`.
+    #[must_use]
+    pub fn property(&self, name: &str) -> Option<&PropertyDoc> {
+        self.properties
+            .iter()
+            .find(|property| property.name == name)
+    }
+
+    /// Collects the documentation of a dt-schema binding, following `$ref`s to other schemas in
+    /// `index`, e.g. to `serial.yaml` or `spi-peripheral-props.yaml`.
+    ///
+    /// Properties of the binding itself take precedence over referenced ones.
+    pub(crate) fn from_schema(schema: &BindingSchema, types: &PropertyTypes) -> Self {
+        let root = serde_json::to_value(&schema.raw_schema).unwrap_or_default();
+        let string = |key: &str| root.get(key).and_then(JValue::as_str).map(str::to_owned);
+        let mut doc = Self {
+            path: schema.path.clone(),
+            title: string("title"),
+            description: string("description").map(|text| text.trim().to_owned()),
+            maintainers: root
+                .get("maintainers")
+                .and_then(JValue::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(JValue::as_str)
+                .map(str::to_owned)
+                .collect(),
+            properties: Vec::new(),
+        };
+        let id = string("$id").unwrap_or_default();
+        doc.collect(&root, &id, &schema.index, 0);
+
+        for property in &mut doc.properties {
+            if property.type_name.is_none() {
+                property.type_name = types.get(&property.name).map(PropertyType::name);
+            }
+        }
+        doc.properties
+            .sort_by_key(|property| (property_order(&property.name), property.name.clone()));
+        doc
+    }
+
+    /// Collects the properties of `schema`, its `allOf` branches and the schemas it references.
+    ///
+    /// `id` is the `$id` of the file `schema` is in, which relative `$ref`s are resolved against.
+    fn collect(&mut self, schema: &JValue, id: &str, index: &SchemaIndex, depth: usize) {
+        let required = schema
+            .get("required")
+            .and_then(JValue::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(JValue::as_str)
+            .collect::<Vec<_>>();
+        if let Some(properties) = schema.get("properties").and_then(JValue::as_object) {
+            // `$nodename` is the node name
+            for (name, property) in properties.iter().filter(|(name, _)| !name.starts_with('$')) {
+                self.merge(PropertyDoc::from_schema(name, property));
+            }
+        }
+        for name in required {
+            if let Some(property) = self.properties.iter_mut().find(|p| p.name == name) {
+                property.required = true;
+            } else {
+                self.properties.push(PropertyDoc {
+                    required: true,
+                    ..PropertyDoc::new(name)
+                });
+            }
+        }
+
+        for branch in schema
+            .get("allOf")
+            .and_then(JValue::as_array)
+            .into_iter()
+            .flatten()
+        {
+            self.collect(branch, id, index, depth);
+        }
+        let reference = schema.get("$ref").and_then(JValue::as_str);
+        if let Some(reference) = reference.filter(|_| depth < MAX_REF_DEPTH) {
+            let target_id = resolve_reference(id, reference);
+            // Local references are only used for definitions, not properties
+            if target_id.is_empty() || target_id == id.split('#').next().unwrap_or(id) {
+                return;
+            }
+            let Ok(uri) = jsonschema::Uri::parse(target_id.clone()) else {
+                return;
+            };
+            if let Ok(target) = jsonschema::Retrieve::retrieve(index, &uri) {
+                self.collect(&target, &target_id, index, depth + 1);
+            }
+        }
+    }
+
+    /// Adds `property`, filling in what an earlier schema didn't document about it.
+    fn merge(&mut self, property: PropertyDoc) {
+        let Some(existing) = self.properties.iter_mut().find(|p| p.name == property.name) else {
+            self.properties.push(property);
+            return;
+        };
+        existing.description = existing.description.take().or(property.description);
+        existing.type_name = existing.type_name.take().or(property.type_name);
+        existing.deprecated |= property.deprecated;
+        if existing.values.is_empty() {
+            existing.values = property.values;
+        }
+    }
+
+    /// Collects the documentation of a Zephyr binding.
+    pub(crate) fn from_zephyr(binding: &ZephyrBinding) -> Self {
+        let mut properties = binding
+            .properties
+            .iter()
+            .map(|(name, property)| PropertyDoc {
+                description: property
+                    .description
+                    .as_ref()
+                    .map(|text| text.trim().to_owned()),
+                type_name: Some(property.ty.name().to_owned()),
+                required: property.required,
+                values: property
+                    .const_value
+                    .iter()
+                    .chain(property.enum_values.iter().flatten())
+                    .filter_map(dts_value)
+                    .collect(),
+                ..PropertyDoc::new(name)
+            })
+            .collect::<Vec<_>>();
+        properties.sort_by_key(|property| (property_order(&property.name), property.name.clone()));
+        Self {
+            path: binding.path.clone(),
+            title: None,
+            description: binding
+                .description
+                .as_ref()
+                .map(|text| text.trim().to_owned()),
+            maintainers: Vec::new(),
+            properties,
+        }
+    }
+}
+
+impl PropertyDoc {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            description: None,
+            type_name: None,
+            required: false,
+            deprecated: false,
+            values: Vec::new(),
+        }
+    }
+
+    fn from_schema(name: &str, schema: &JValue) -> Self {
+        let mut values = Vec::new();
+        collect_values(schema, &mut values, 0);
+        values.dedup();
+        Self {
+            description: schema
+                .get("description")
+                .and_then(JValue::as_str)
+                .map(|text| text.trim().to_owned()),
+            type_name: type_name(schema),
+            deprecated: schema.get("deprecated").and_then(JValue::as_bool) == Some(true),
+            values,
+            ..Self::new(name)
+        }
+    }
+}
+
+/// Returns the type from a `$ref` to `types.yaml`, e.g. `uint32` from
+/// `$ref: /schemas/types.yaml#/definitions/uint32`, also inside `allOf`.
+fn type_name(schema: &JValue) -> Option<String> {
+    let from_ref = |schema: &JValue| {
+        let reference = schema.get("$ref")?.as_str()?;
+        let (file, definition) = reference.split_once("#/definitions/")?;
+        file.ends_with("types.yaml").then(|| definition.to_owned())
+    };
+    from_ref(schema).or_else(|| schema.get("allOf")?.as_array()?.iter().find_map(from_ref))
+}
+
+/// Collects the `const`s and `enum`s of a property schema, also in `items` and branches, since
+/// fixups wrap them in `items`.
+fn collect_values(schema: &JValue, values: &mut Vec<String>, depth: usize) {
+    if depth > 3 {
+        return;
+    }
+    values.extend(schema.get("const").and_then(dts_value));
+    values.extend(
+        schema
+            .get("enum")
+            .and_then(JValue::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(dts_value),
+    );
+    match schema.get("items") {
+        Some(JValue::Array(items)) => {
+            for item in items {
+                collect_values(item, values, depth + 1);
+            }
+        }
+        Some(item) => collect_values(item, values, depth + 1),
+        None => {}
+    }
+    for key in ["oneOf", "anyOf", "allOf"] {
+        for branch in schema
+            .get(key)
+            .and_then(JValue::as_array)
+            .into_iter()
+            .flatten()
+        {
+            collect_values(branch, values, depth + 1);
+        }
+    }
+}
+
+/// Converts a `const` or `enum` value to DTS syntax.
+fn dts_value(value: &JValue) -> Option<String> {
+    match value {
+        JValue::String(string) => Some(format!("\"{string}\"")),
+        JValue::Number(number) => Some(format!("<{number}>")),
+        _ => None,
+    }
+}
+
+/// Resolves a `$ref` against the `$id` of the schema containing it, like a URI, ignoring the
+/// fragment.
+fn resolve_reference(id: &str, reference: &str) -> String {
+    let reference = reference.split('#').next().unwrap_or_default();
+    if reference.is_empty() || reference.contains("://") {
+        return reference.to_owned();
+    }
+    let Some((scheme, rest)) = id.split_once("://") else {
+        return reference.to_owned();
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    if reference.starts_with('/') {
+        return format!("{scheme}://{host}{reference}");
+    }
+    let dir = id.rsplit_once('/').map_or(id, |(dir, _)| dir);
+    let mut segments = dir.split('/').collect::<Vec<_>>();
+    for segment in reference.split('/') {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references() {
+        assert_eq!(
+            resolve_reference(
+                "http://devicetree.org/schemas/serial/acme,uart.yaml#",
+                "serial.yaml#"
+            ),
+            "http://devicetree.org/schemas/serial/serial.yaml"
+        );
+        assert_eq!(
+            resolve_reference(
+                "http://devicetree.org/schemas/serial/acme,uart.yaml#",
+                "/schemas/spi/spi-peripheral-props.yaml#"
+            ),
+            "http://devicetree.org/schemas/spi/spi-peripheral-props.yaml"
+        );
+        assert_eq!(
+            resolve_reference("http://devicetree.org/schemas/a/b.yaml#", "../c.yaml"),
+            "http://devicetree.org/schemas/c.yaml"
+        );
+    }
+
+    #[test]
+    fn property_values() {
+        let property = PropertyDoc::from_schema(
+            "acme,mode",
+            &serde_json::json!({
+                "description": "  The mode\n",
+                "$ref": "/schemas/types.yaml#/definitions/uint32",
+                "items": [{ "enum": [1, 2] }],
+            }),
+        );
+        assert_eq!(property.description.as_deref(), Some("The mode"));
+        assert_eq!(property.type_name.as_deref(), Some("uint32"));
+        assert_eq!(property.values, ["<1>", "<2>"]);
+    }
+}
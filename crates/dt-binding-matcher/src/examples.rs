@@ -0,0 +1,304 @@
+//! Running the `examples:` of binding schemas as tests, like `make dt_binding_check`.
+
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::Context as _;
+use dt_parser::{ast::SourceFile, SourceId};
+
+use crate::{check::YamlPositions, BindingSchema, Bindings, SchemaError, SchemaIndex};
+
+/// Checks each of the `examples:` of the binding schema `text`, read from `path`, against the
+/// schema itself.
+///
+/// Like dt-schema, examples are wrapped in a root node with `#address-cells` and `#size-cells` of
+/// 1, unless they have their own root node. Examples with C preprocessor directives, like
+/// `#include <dt-bindings/gpio/gpio.h>`, are preprocessed with `cpp` using `include_paths`.
+///
+/// Errors are at the line and column of the offending value in the YAML file and their messages
+/// start with the index of the example. The schema should pass [`check_binding`] first.
+///
+/// [`check_binding`]: crate::check_binding
+#[must_use]
+pub fn check_examples(
+    path: &Path,
+    text: &str,
+    index: &SchemaIndex,
+    include_paths: &[PathBuf],
+) -> Vec<SchemaError> {
+    let positions = YamlPositions::new(text);
+    let schema = serde_yaml::from_str(text)
+        .map_err(anyhow::Error::from)
+        .and_then(|yaml| BindingSchema::compile_unchecked(path, yaml, index));
+    let schema = match schema {
+        Ok(schema) => schema,
+        Err(err) => return vec![positions.error(String::new(), format!("{err:#}"))],
+    };
+    let examples = schema
+        .raw_schema
+        .get("examples")
+        .and_then(serde_yaml::Value::as_sequence)
+        .cloned()
+        .unwrap_or_default();
+    // Only the example's own schema is validated against, with the core property types
+    let mut bindings = Bindings::new();
+    bindings.insert(schema);
+
+    let mut errors = Vec::new();
+    for (i, example) in examples.iter().enumerate() {
+        let pointer = format!("/examples/{i}");
+        let Some(example) = example.as_str() else {
+            errors.push(positions.error(pointer, format!("example {i}: must be a string")));
+            continue;
+        };
+        let mut example = BindingExample::new(i, example, text, positions.get(&pointer));
+        let results = if example.has_directives() {
+            example.preprocess(include_paths)
+        } else {
+            Ok(())
+        };
+        let results = match results {
+            Ok(()) => example.check(&bindings),
+            Err(err) => vec![(None, format!("{err:#}"))],
+        };
+        for (position, message) in results {
+            let (line, column) = position.unwrap_or(example.position);
+            errors.push(SchemaError {
+                pointer: pointer.clone(),
+                line,
+                column,
+                message: format!("example {i}: {message}"),
+            });
+        }
+    }
+    errors
+}
+
+/// An example wrapped in a devicetree, with the YAML positions of its lines.
+struct BindingExample {
+    source: String,
+    /// The 1-based YAML line and 0-based column of each line in `source`, or `None` for lines
+    /// added by wrapping or preprocessing
+    origins: Vec<Option<(usize, usize)>>,
+    /// The 1-based line and column of the example in the YAML file
+    position: (usize, usize),
+}
+
+impl BindingExample {
+    fn new(index: usize, example: &str, yaml: &str, position: Option<(usize, usize)>) -> Self {
+        let position = position.unwrap_or((1, 1));
+        // Block scalars are positioned at their first line. Other scalars are folded or escaped, so
+        // only their first line can be mapped.
+        let first_line = example.lines().next().unwrap_or_default();
+        let is_mapped = yaml
+            .lines()
+            .nth(position.0 - 1)
+            .and_then(|line| line.get(position.1 - 1..))
+            .is_some_and(|line| !first_line.is_empty() && line.starts_with(first_line));
+        let indent = position.1 - 1;
+
+        let mut this = Self {
+            source: String::new(),
+            origins: Vec::new(),
+            position,
+        };
+        // Examples like simple-framebuffer.yaml have their own root nodes
+        let has_root = example
+            .lines()
+            .any(|line| line.trim_start().starts_with("/ {"));
+        if !has_root {
+            for line in [
+                "/dts-v1/;",
+                "",
+                "/ {",
+                "    #address-cells = <1>;",
+                "    #size-cells = <1>;",
+                "",
+                &format!("    example-{index} {{"),
+                "        #address-cells = <1>;",
+                "        #size-cells = <1>;",
+                "",
+            ] {
+                this.push_line(line, None);
+            }
+        }
+        for (i, line) in example.lines().enumerate() {
+            this.push_line(line, is_mapped.then_some((position.0 + i, indent)));
+        }
+        if !has_root {
+            this.push_line("    };", None);
+            this.push_line("};", None);
+        }
+        this
+    }
+
+    fn push_line(&mut self, line: &str, origin: Option<(usize, usize)>) {
+        self.source.push_str(line);
+        self.source.push('\n');
+        self.origins.push(origin);
+    }
+
+    fn has_directives(&self) -> bool {
+        self.source.lines().any(|line| {
+            let line = line.trim_start();
+            ["#include", "#define", "#undef", "#if"]
+                .iter()
+                .any(|directive| line.starts_with(directive))
+        })
+    }
+
+    /// Runs the source through the C preprocessor like the Linux kernel does for devicetrees.
+    fn preprocess(&mut self, include_paths: &[PathBuf]) -> anyhow::Result<()> {
+        let mut command = Command::new("cpp");
+        command.args([
+            "-nostdinc",
+            "-undef",
+            "-D__DTS__",
+            "-x",
+            "assembler-with-cpp",
+        ]);
+        for path in include_paths {
+            command.arg("-I").arg(path);
+        }
+        let mut child = command
+            .arg("-")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run `cpp`")?;
+        child
+            .stdin
+            .take()
+            .expect("stdin should be piped")
+            .write_all(self.source.as_bytes())?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "`cpp` failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let old_origins = std::mem::take(&mut self.origins);
+        self.source.clear();
+        // The line in the input, if the current line is from the input and not an included file
+        let mut input_line = Some(0);
+        for line in String::from_utf8(output.stdout)?.lines() {
+            // Line markers: # <line> "<file>" <flags>
+            if let Some(marker) = line.strip_prefix("# ") {
+                let (line, file) = marker.split_once(' ').unwrap_or((marker, ""));
+                if let Ok(line) = line.parse::<usize>() {
+                    input_line = file
+                        .starts_with("\"<stdin>\"")
+                        .then(|| line.saturating_sub(1));
+                    continue;
+                }
+            }
+            let origin = input_line.and_then(|i| old_origins.get(i).copied().flatten());
+            self.push_line(line, origin);
+            input_line = input_line.map(|i| i + 1);
+        }
+        Ok(())
+    }
+
+    /// Analyzes the example and validates it against `bindings`.
+    ///
+    /// Returns the YAML position and message of each error.
+    fn check(&self, bindings: &Bindings) -> Vec<(Option<(usize, usize)>, String)> {
+        let parse = SourceFile::parse(&self.source);
+        if !parse.lex_errors.is_empty() || !parse.errors.is_empty() {
+            return parse
+                .lex_errors
+                .iter()
+                .map(|error| (error.text_range.start, error.inner.to_string()))
+                .chain(
+                    parse
+                        .errors
+                        .iter()
+                        .map(|error| (error.primary_span.start, error.message.to_string())),
+                )
+                .map(|(offset, message)| (self.yaml_position(offset), message))
+                .collect();
+        }
+
+        let file = parse.source_file();
+        let diag = std::sync::Mutex::new(Vec::new());
+        let outline = dt_analyzer::new::stage1::analyze_file(&file, &self.source, &diag);
+        let stage2 =
+            dt_analyzer::new::stage2::compute(&SourceId::from("example.dts"), &outline, &[], &diag);
+
+        // Examples are preprocessed into a single file, so every error is in it
+        bindings
+            .validate(&stage2)
+            .into_iter()
+            .map(|(_, diagnostic)| {
+                let position = diagnostic
+                    .span
+                    .primary_spans
+                    .first()
+                    .and_then(|span| self.yaml_position(span.start));
+                // The rest of the message names the schema, which is the one being checked
+                let message = diagnostic.msg.lines().next().unwrap_or_default().to_owned();
+                (position, message)
+            })
+            .collect()
+    }
+
+    /// Returns the 1-based YAML line and column of `offset` in the source.
+    fn yaml_position(&self, offset: usize) -> Option<(usize, usize)> {
+        let before = self.source.get(..offset)?;
+        let line = before.matches('\n').count();
+        let column = before.len() - before.rfind('\n').map_or(0, |newline| newline + 1);
+        let (yaml_line, indent) = (*self.origins.get(line)?)?;
+        Some((yaml_line, indent + column + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "test_data/examples/acme,foo.yaml";
+    const BINDING: &str = include_str!("../test_data/examples/acme,foo.yaml");
+
+    /// Checks the examples of `text`, a variant of the binding at [`PATH`].
+    fn check(text: &str) -> Vec<String> {
+        check_examples(Path::new(PATH), text, &SchemaIndex::default(), &[])
+            .iter()
+            .map(|error| format!("{} {error}", error.pointer))
+            .collect()
+    }
+
+    #[test]
+    fn examples() {
+        assert_eq!(
+            check(BINDING),
+            [
+                r#"/examples/1 21:5: example 1: "reg" is a required property"#,
+                r#"/examples/1 23:21: example 1: "medium" is not one of ["fast","slow"]"#,
+                "/examples/2 25:5: example 2: Expected ‘,’ or ‘;’, but found ‘}’",
+                "/examples/2 25:5: example 2: Expected ‘;’, but found ‘}’",
+            ]
+        );
+    }
+
+    #[test]
+    fn preprocess() {
+        if Command::new("cpp").arg("--version").output().is_err() {
+            eprintln!("skipping, `cpp` isn't installed");
+            return;
+        }
+        let binding = BINDING.replace(
+            "  - |\n    foo@1000 {\n        compatible = \"acme,foo\";\n        reg = <0x1000 0x10>;\n",
+            "  - |\n    #define FOO_SIZE 0x10\n\n    foo@1000 {\n        compatible = \"acme,foo\";\n        reg = <0x1000 FOO_SIZE>;\n        acme,mode = \"medium\";\n",
+        );
+        assert_eq!(
+            check(&binding)[0],
+            r#"/examples/0 21:21: example 0: "medium" is not one of ["fast","slow"]"#
+        );
+    }
+}
@@ -1,104 +1,240 @@
-use std::{collections::HashSet, path::Path};
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
-use anyhow::Context as _;
-use dt_analyzer::DefinitionTreeNode;
-use jsonschema::Validator;
-use metaschemas::SCHEMA_VALIDATOR;
+use dt_analyzer::{new::stage2::Stage2Node, DefinitionTreeNode};
+use jsonschema::{error::ValidationErrorKind, Validator};
+use serde_json::Value as JValue;
 use serde_yaml::{Mapping, Value};
 
+pub use bindings::{BindingLoadError, Bindings};
+pub use cache::default_cache_dir;
+pub use check::{check_binding, SchemaError};
+pub use compatibles::{get_compatible_items, CompatibleIndex};
+pub use docs::{BindingDoc, PropertyDoc};
+pub use examples::check_examples;
+pub use lint::{UndocumentedCompatibles, UNDOCUMENTED_COMPATIBLES};
+pub use retriever::SchemaIndex;
+pub use skeleton::{NodeSkeleton, SkeletonProperty};
+pub use types::{PropertyType, PropertyTypes, Shape};
+pub use zephyr::{ZephyrBinding, ZephyrProperty, ZephyrType};
+
+mod bindings;
+mod cache;
+mod check;
+mod compatibles;
+mod docs;
+mod examples;
 mod fixups;
+mod lint;
 mod metaschemas;
 mod retriever;
+mod skeleton;
+mod types;
+mod zephyr;
+
+/// A binding documenting devicetree nodes, either a dt-schema [`BindingSchema`] or a
+/// [`ZephyrBinding`].
+pub trait Binding: std::fmt::Debug + Send + Sync {
+    /// The path of the YAML file
+    fn path(&self) -> &Path;
+
+    /// Describes the binding for error messages, e.g. `schema http://devicetree.org/schemas/…`
+    fn origin(&self) -> Option<String>;
+
+    /// Validates a node which the binding applies to.
+    ///
+    /// `json` is the node converted to JSON with its values decoded, see [`PropertyTypes::coerce`].
+    fn validate(&self, node: &Stage2Node, json: &JValue) -> Vec<BindingError>;
+}
+
+/// A node failing validation against a [`Binding`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingError {
+    /// JSON pointers relative to the node of the values which failed validation, or an empty
+    /// pointer for the node itself
+    pub pointers: Vec<String>,
+    pub msg: String,
+}
 
 #[derive(Debug)]
 pub struct BindingSchema {
+    /// The path of the YAML file
+    pub path: PathBuf,
+    /// The schema after fixups, including `select` and `maintainers`
     pub raw_schema: Mapping,
-    pub validator: Validator,
-    pub select_validator: Validator,
     pub maintainers: Option<Value>,
+    /// Whether the schema has its own `select` instead of one generated from `compatible`
+    pub custom_select: bool,
+    index: SchemaIndex,
+    validator: OnceLock<Result<Validator, String>>,
+    select_validator: OnceLock<Result<Validator, String>>,
 }
 impl BindingSchema {
-    /// Compiles a binding schema
+    /// Compiles a binding schema, resolving `$ref`s with `index`
     ///
     /// # Errors
     ///
     /// Will return `Err` if any of the following conditions are met:
     ///
-    /// - Loading the YAML file fails
+    /// - Loading the YAML file fails or it isn't a mapping
     /// - Schema is invalid according to [the `dt-schema` validations](https://github.com/devicetree-org/dt-schema)
-    /// - Full JSON Schema compilation fails
+    /// - Full JSON Schema compilation fails, e.g. because a `$ref` isn't in `index`
     /// - Select JSON Schema compilation fails
-    #[expect(clippy::missing_panics_doc, reason = "fixups should add select key")]
-    pub fn compile(path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let mut yaml: Value = serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+    pub fn compile(path: impl AsRef<Path>, index: &SchemaIndex) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        Self::compile_text(path, &std::fs::read_to_string(path)?, index)
+    }
+
+    /// Compiles a binding schema read from `path`, see [`BindingSchema::compile`].
+    pub(crate) fn compile_text(path: &Path, text: &str, index: &SchemaIndex) -> anyhow::Result<Self> {
+        let yaml: Value = serde_yaml::from_str(text)?;
+
+        let errors = check::meta_schema_errors(&serde_json::to_value(&yaml)?, index);
+        if !errors.is_empty() {
+            let errors = errors.iter().fold(String::new(), |mut acc, (pointer, message)| {
+                let _ = write!(acc, "\n* {pointer}: {message}");
+                acc
+            });
+            anyhow::bail!("Invalid schema:{errors}");
+        }
+        Self::compile_unchecked(path, yaml, index)
+    }
+
+    /// Compiles a binding schema without checking it against the meta-schemas.
+    pub(crate) fn compile_unchecked(
+        path: &Path,
+        mut yaml: Value,
+        index: &SchemaIndex,
+    ) -> anyhow::Result<Self> {
         let Some(yaml_map) = yaml.as_mapping_mut() else {
-            todo!()
+            anyhow::bail!("Binding schema must be a mapping");
         };
 
+        let custom_select = yaml_map.contains_key("select");
         yaml_map.entry("type".into()).or_insert("object".into());
         fixups::fixup_node(yaml_map);
         fixups::add_select(yaml_map);
-        let raw_schema = yaml_map.clone();
-        let select = yaml_map
-            .remove("select")
-            .expect("fixed document should always have select");
-        let maintainers = yaml_map.remove("maintainers");
-
-        let json = serde_json::to_value(&yaml)?;
-
-        // FIXME: fix errors
-        let _ = SCHEMA_VALIDATOR;
-        /*SCHEMA_VALIDATOR.validate(&json).map_err(|err| anyhow::anyhow!(err
-            .map(|e| e.to_string())
-            .fold(String::new(), |mut acc, b| {
-                acc.reserve(b.len() + 3);
-                acc.push_str("* ");
-                acc.push_str(&b);
-                acc.push_str("\n");
-                acc
-            }))
-        ).context("Invalid schema!")?;*/
-
-        let validator = jsonschema::options()
-            //.with_dt_meta_schemas()
-            // Jsonschema doesn't follow $schema references. This is declared in
-            // dtschema/meta-schemas/core.yaml
-            //.with_draft(jsonschema::Draft::Draft201909)
-            .with_retriever(retriever::DtJsonSchemaRetriever)
-            .build(&json)
-            .context("Failed to compile schema")?;
-
-        let select = serde_json::to_value(&select)?;
-        let select_validator = jsonschema::options()
-            .build(&select)
-            .context("Failed to compile select schema")?;
-
-        Ok(Self {
+
+        let schema = Self::from_fixed(path.to_owned(), yaml_map.clone(), custom_select, index);
+        schema.validator()?;
+        schema.select_validator()?;
+        Ok(schema)
+    }
+
+    /// Creates a binding schema from a schema which has already been fixed up, e.g. from the cache.
+    ///
+    /// The validators are compiled on first use.
+    pub(crate) fn from_fixed(
+        path: PathBuf,
+        raw_schema: Mapping,
+        custom_select: bool,
+        index: &SchemaIndex,
+    ) -> Self {
+        Self {
+            path,
+            maintainers: raw_schema.get("maintainers").cloned(),
             raw_schema,
-            validator,
-            select_validator,
-            maintainers,
-        })
+            custom_select,
+            index: index.clone(),
+            validator: OnceLock::new(),
+            select_validator: OnceLock::new(),
+        }
+    }
+
+    /// Returns the validator for the whole schema, compiling it on first use.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the schema fails to compile, e.g. because a `$ref` isn't in the index.
+    pub fn validator(&self) -> anyhow::Result<&Validator> {
+        self.validator
+            .get_or_init(|| {
+                let mut schema = self.raw_schema.clone();
+                schema.remove("select");
+                schema.remove("maintainers");
+                let json = serde_json::to_value(&schema).map_err(|err| err.to_string())?;
+
+                jsonschema::options()
+                    // Jsonschema doesn't follow $schema references. This is declared in
+                    // dtschema/meta-schemas/core.yaml
+                    //.with_draft(jsonschema::Draft::Draft201909)
+                    .with_retriever(self.index.clone())
+                    .build(&json)
+                    .map_err(|err| format!("Failed to compile schema: {err}"))
+            })
+            .as_ref()
+            .map_err(|err| anyhow::anyhow!("{err}"))
+    }
+
+    /// Returns the validator for the schema's `select`, compiling it on first use.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the select schema fails to compile.
+    pub fn select_validator(&self) -> anyhow::Result<&Validator> {
+        self.select_validator
+            .get_or_init(|| {
+                let select = self
+                    .raw_schema
+                    .get("select")
+                    .ok_or("Fixed schema should always have select")?;
+                let select = serde_json::to_value(select).map_err(|err| err.to_string())?;
+                // The select schema has no `$schema`, so the draft of dt-schema's meta-schemas must be
+                // given explicitly. The default draft 2020-12 rejects arrays in `items`.
+                jsonschema::options()
+                    .with_draft(jsonschema::Draft::Draft201909)
+                    .build(&select)
+                    .map_err(|err| format!("Failed to compile select schema: {err}"))
+            })
+            .as_ref()
+            .map_err(|err| anyhow::anyhow!("{err}"))
     }
 }
 
-/// TODO: use this in lsp
-pub fn get_compatible_items(map: &Mapping) -> HashSet<String> {
-    // TODO: handle $ref ? this is currently really lazy
-    let mut list = HashSet::new();
-    if let Some(properties) = map.get("properties").and_then(Value::as_mapping) {
-        if let Some(compatible) = properties.get("compatible").and_then(Value::as_mapping) {
-            if let Some(item) = compatible.get("const").and_then(Value::as_str) {
-                list.insert(item.to_owned());
+impl Binding for BindingSchema {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn origin(&self) -> Option<String> {
+        let id = self.raw_schema.get("$id")?.as_str()?;
+        Some(format!("schema {id}"))
+    }
+
+    fn validate(&self, _node: &Stage2Node, json: &JValue) -> Vec<BindingError> {
+        // Cached schemas are compiled on first use, so this can fail if a schema they reference
+        // has changed
+        let validator = match self.validator() {
+            Ok(validator) => validator,
+            Err(err) => {
+                return vec![BindingError {
+                    pointers: vec![String::new()],
+                    msg: format!("{err:#}"),
+                }]
             }
-            if let Some(items) = compatible.get("enum").and_then(Value::as_sequence) {
-                for item in items.iter().filter_map(Value::as_str) {
-                    list.insert(item.to_owned());
+        };
+        validator
+            .iter_errors(json)
+            .map(|error| {
+                let pointer = error.instance_path.to_string();
+                let pointers = match &error.kind {
+                    ValidationErrorKind::AdditionalProperties { unexpected }
+                    | ValidationErrorKind::UnevaluatedProperties { unexpected } => unexpected
+                        .iter()
+                        .map(|name| format!("{pointer}/{}", bindings::escape(name)))
+                        .collect(),
+                    _ => vec![pointer],
+                };
+                BindingError {
+                    pointers,
+                    msg: error.to_string(),
                 }
-            }
-        }
+            })
+            .collect()
     }
-    list
 }
 
 #[must_use]
@@ -121,66 +257,37 @@ mod tests {
     use super::*;
     use dt_parser::ast;
 
-    fn compile_example(example: &str, binding_schema: &BindingSchema) {
-        // Hack for examples that already have root nodes like simple-framebuffer.yaml
-        let example = if example.contains("/ {") {
-            example
-        } else {
-            &format!("/dts-v1/;\n\n/ {{\n{example}\n}};")
-        };
-
-        let parse = ast::SourceFile::parse(example);
+    /// Validates the first example of `binding_schema` against it.
+    fn check_example(binding_schema: &BindingSchema) {
+        let example = binding_schema.raw_schema["examples"][0].as_str().unwrap();
+        // Examples are wrapped in a root node, like dt-schema does
+        let example = format!("/dts-v1/;\n\n/ {{\n{example}\n}};");
 
-        if !parse.lex_errors.is_empty() || !parse.errors.is_empty() {
-            eprintln!("Invalid DTS!");
-            std::process::exit(1);
-        };
+        let parse = ast::SourceFile::parse(&example);
+        assert_eq!(parse.lex_errors, Vec::new());
+        assert_eq!(parse.errors, Vec::new());
 
-        let file = parse.source_file();
-        //eprintln!("Parsed tree: {}", file.syntax().green.print_tree());
-
-        let def = dt_analyzer::analyze_cst(&file, example).unwrap();
-        println!(
-            "tree: {:#?} parsed from {}, json = {}",
-            def.tree,
-            example,
-            serde_json::to_string_pretty(&def.tree.clone().into_json()).unwrap()
-        );
-        let json = find_select(def.tree, &binding_schema.select_validator)
+        let def = dt_analyzer::analyze_cst(&parse.source_file(), &example).unwrap();
+        let json = find_select(def.tree, binding_schema.select_validator().unwrap())
             .expect("Couldn't find selected")
             .into_json();
-        eprintln!("{json:#?}");
-        let errors = binding_schema.validator.iter_errors(&json).collect::<Vec<_>>();
-        if !errors.is_empty() {
-            eprintln!("{errors:#?}");
-        };
+        let validator = binding_schema.validator().unwrap();
+        let errors = validator
+            .iter_errors(&json)
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(errors, Vec::<String>::new(), "{json:#}");
     }
 
     #[test]
-    #[ignore = "Custom meta schemas are not fully implemented: https://github.com/Stranger6667/jsonschema/issues/664"]
     fn test_example() {
-        // https://github.com/mrcjkb/rustaceanvim/discussions/231
-        let schema = BindingSchema::compile(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("example.yaml")).unwrap();
-        compile_example(schema.raw_schema["examples"][0].as_str().unwrap(), &schema);
-    }
-
-    #[test]
-    #[ignore = "Custom meta schemas are not fully implemented: https://github.com/Stranger6667/jsonschema/issues/664"]
-    fn test_leds() {
-        let schema = BindingSchema::compile(
-            "/home/axel/dev/mainlining/linux/Documentation/devicetree/bindings/leds/leds-bcm6328.yaml",
-        )
-        .unwrap();
-        compile_example(schema.raw_schema["examples"][0].as_str().unwrap(), &schema);
-    }
-
-    #[test]
-    #[ignore = "Custom meta schemas are not fully implemented: https://github.com/Stranger6667/jsonschema/issues/664"]
-    fn test_simplefb() {
-        let schema = BindingSchema::compile(
-            "/home/axel/dev/mainlining/linux/Documentation/devicetree/bindings/display/simple-framebuffer.yaml",
-        )
-        .unwrap();
-        compile_example(schema.raw_schema["examples"][0].as_str().unwrap(), &schema);
+        let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let (index, errors) = SchemaIndex::build(&[
+            manifest_dir.join("dt-schema"),
+            manifest_dir.join("test_data/schemas"),
+        ]);
+        assert_eq!(errors.len(), 0, "{errors:?}");
+        let schema = BindingSchema::compile(manifest_dir.join("example.yaml"), &index).unwrap();
+        check_example(&schema);
     }
 }
@@ -0,0 +1,157 @@
+//! Lints which need binding schemas.
+
+use std::sync::Arc;
+
+use dt_analyzer::{new::stage2::Stage2Property, resolved_prop::Value};
+use dt_lint::{LateContext, LateLintPass, Lint, LintGroup, LintLevel, LintRegistry};
+use dt_parser::ast::{AstNodeOrToken as _, PropValue};
+
+use crate::CompatibleIndex;
+
+pub static UNDOCUMENTED_COMPATIBLES: Lint = Lint {
+    name: "undocumented_compatibles",
+    default_level: LintLevel::Warn,
+    desc: "every `compatible` must be documented by a binding schema",
+    group: LintGroup::Correctness,
+    rationale: "Nodes with undocumented compatibles can't be validated, and they are often typos. \
+Like `checkpatch.pl`, this checks the compatibles against every binding schema in the configured \
+`bindings-dirs`, so it does nothing without them.",
+    bad_examples: &[
+        "/dts-v1/;\n\n/ {\n\tserial@1000 {\n\t\tcompatible = \"acme,uart-typo\";\n\t};\n};\n",
+    ],
+    good_examples: &[
+        "/dts-v1/;\n\n/ {\n\tserial@1000 {\n\t\tcompatible = \"acme,uart\";\n\t};\n};\n",
+    ],
+    url: None,
+};
+
+/// Lints compatibles which aren't in a [`CompatibleIndex`], e.g. [`Bindings::compatibles`].
+///
+/// Nothing is linted when the index is empty, i.e. when no bindings are loaded.
+///
+/// [`Bindings::compatibles`]: crate::Bindings::compatibles
+pub struct UndocumentedCompatibles(pub Arc<CompatibleIndex>);
+
+impl UndocumentedCompatibles {
+    /// Registers the lint with a pass using the index returned by `compatibles` for each file.
+    ///
+    /// The index can change between files, e.g. when the language server reloads the bindings.
+    pub fn register(
+        registry: &mut LintRegistry,
+        compatibles: impl Fn() -> Arc<CompatibleIndex> + Send + Sync + 'static,
+    ) {
+        registry.register_lints(&[&UNDOCUMENTED_COMPATIBLES]);
+        registry.register_late_pass(move || Box::new(Self(compatibles())));
+    }
+}
+
+impl LateLintPass for UndocumentedCompatibles {
+    fn check_property(
+        &mut self,
+        cx: &mut LateContext<'_>,
+        _path: &[&str],
+        name: &str,
+        property: &Stage2Property,
+    ) {
+        if name != "compatible" || self.0.is_empty() {
+            return;
+        }
+        // Values are only computed if every value AST is valid, so they line up
+        for (value, ast) in property.values.iter().zip(property.ast.values()) {
+            let (Value::String(compatible), PropValue::String(_)) = (value, &ast) else {
+                continue;
+            };
+            if !self.0.is_documented(compatible) {
+                cx.add_lint(
+                    &property.source_id,
+                    &UNDOCUMENTED_COMPATIBLES,
+                    format!("`{compatible}` isn't documented by any binding"),
+                    ast.syntax().text_range(),
+                )
+                .with_data(compatible.as_str());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use dt_analyzer::new::{
+        stage1::{analyze_file, AnalyzedToplevel},
+        stage2::ResolvedInclude,
+    };
+    use dt_parser::{ast::SourceFile, SourceId};
+
+    use super::*;
+    use crate::SchemaIndex;
+
+    fn registry() -> LintRegistry {
+        let mut index = CompatibleIndex::new();
+        index.insert_schema(
+            Path::new("acme,uart.yaml"),
+            &serde_yaml::from_str("properties:\n  compatible:\n    const: acme,uart\n").unwrap(),
+            &SchemaIndex::default(),
+        );
+        let index = Arc::new(index);
+
+        let mut registry = LintRegistry::new();
+        UndocumentedCompatibles::register(&mut registry, move || index.clone());
+        registry
+    }
+
+    #[test]
+    fn undocumented_compatibles() {
+        let registry = registry();
+        assert_eq!(registry.check_examples(), []);
+
+        let src = "/dts-v1/;\n/ { serial { compatible = \"acme,uart2\", \"acme,uart\", \"ns16550\"; }; };";
+        let file = SourceFile::parse(src).source_file();
+        let lints = registry.lint_file(&file, src, true);
+        assert_eq!(
+            lints.iter().map(|lint| &lint.msg).collect::<Vec<_>>(),
+            [
+                "`acme,uart2` isn't documented by any binding",
+                "`ns16550` isn't documented by any binding",
+            ]
+        );
+        assert_eq!(lints[0].data.as_deref(), Some("acme,uart2"));
+        let span = lints[0].span.primary_spans[0];
+        assert_eq!(&src[span.start..span.end], "\"acme,uart2\"");
+    }
+
+    #[test]
+    fn overridden_compatible() {
+        let soc = r#"/ { uart: serial { compatible = "acme,uart"; }; };"#;
+        let board = "/dts-v1/;\n/include/ \"soc.dtsi\"\n&uart { compatible = \"acme,uart2\"; };";
+        let diag = std::sync::Mutex::new(Vec::new());
+        let soc_outline = analyze_file(&SourceFile::parse(soc).source_file(), soc, &diag);
+        let board_file = SourceFile::parse(board).source_file();
+        let board_outline = analyze_file(&board_file, board, &diag);
+        let includes = [ResolvedInclude {
+            includer: SourceId::from("board.dts"),
+            text_range: board_outline
+                .iter()
+                .find_map(AnalyzedToplevel::as_include)
+                .unwrap()
+                .text_range,
+            source_id: SourceId::from("soc.dtsi"),
+            outline: &soc_outline,
+        }];
+
+        let lints = registry().lint_file_with_includes(
+            &board_file,
+            board,
+            true,
+            &SourceId::from("board.dts"),
+            &includes,
+        );
+        assert_eq!(lints.len(), 1, "{lints:?}");
+        assert_eq!(lints[0].data.as_deref(), Some("acme,uart2"));
+        assert_eq!(
+            lints[0].span.primary_spans[0].text(board),
+            Some("\"acme,uart2\"")
+        );
+    }
+}
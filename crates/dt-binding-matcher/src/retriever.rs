@@ -1,58 +1,303 @@
-use anyhow::{anyhow, Context as _};
-use jsonschema::Retrieve;
+//! Offline resolution of `$ref`s between schemas.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use anyhow::Context as _;
+use jsonschema::{Retrieve, Validator};
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
 use serde_json::Value;
-use std::path::PathBuf;
+use sha2::{Digest as _, Sha256};
+
+use crate::{
+    bindings::collect_yaml_files, cache::hex, metaschemas::META_SCHEMA_FILES, BindingLoadError,
+};
+
+/// The `$schema` of dt-schema's meta-schemas
+const DRAFT_2019_09: &str = "https://json-schema.org/draft/2019-09/schema";
+
+/// An index of schema files by `$id`, for resolving `$ref`s without the network.
+///
+/// `$ref`s resolve to exactly the file declaring the `$id`. Meta-schemas fall back to the bundled
+/// ones when they aren't in the index, e.g. without a dt-schema checkout. Files are parsed the first
+/// time they are referenced, once for all clones of the index.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaIndex {
+    by_id: Arc<FxHashMap<String, IndexedSchema>>,
+    /// Validators for meta-schemas by `$id`, compiled on first use
+    meta_validators: Arc<Mutex<FxHashMap<String, Arc<Validator>>>>,
+}
+
+#[derive(Debug)]
+struct IndexedSchema {
+    path: PathBuf,
+    contents: OnceLock<Result<Value, String>>,
+}
+
+impl IndexedSchema {
+    /// Returns the parsed file, which is read the first time.
+    fn contents(&self) -> &Result<Value, String> {
+        self.contents.get_or_init(|| {
+            let text = std::fs::read_to_string(&self.path)
+                .map_err(|err| format!("{}: {err}", self.path.display()))?;
+            let mut contents: Value = serde_yaml::from_str(&text)
+                .map_err(|err| format!("{}: {err}", self.path.display()))?;
+            // Referenced schemas must declare a draft. dt-schema's meta-schemas are
+            // written in draft 2019-09
+            if let Some(meta_schema) = contents.get_mut("$schema").filter(|meta_schema| {
+                meta_schema.as_str().is_some_and(|id| {
+                    normalize_id(id).starts_with("http://devicetree.org/meta-schemas/")
+                })
+            }) {
+                *meta_schema = DRAFT_2019_09.into();
+            }
+            Ok(contents)
+        })
+    }
+}
+
+impl SchemaIndex {
+    /// Indexes every `.yaml` file in `dirs` and their subdirectories.
+    ///
+    /// Files which can't be read or declare an `$id` which is already taken are returned as errors.
+    #[must_use]
+    pub fn build(dirs: &[impl AsRef<Path>]) -> (Self, Vec<BindingLoadError>) {
+        let mut errors = Vec::new();
+        let mut paths = Vec::new();
+        for dir in dirs {
+            collect_yaml_files(dir.as_ref(), &mut paths, &mut errors);
+        }
+        paths.sort_unstable();
+        let (index, index_errors) = Self::from_paths(&paths);
+        errors.extend(index_errors);
+        (index, errors)
+    }
 
-// TODO: "workspace dirs", read all the $id's and use here
+    /// Indexes the files in `paths`. Earlier files win if `$id`s are duplicated.
+    pub(crate) fn from_paths(paths: &[PathBuf]) -> (Self, Vec<BindingLoadError>) {
+        let ids = paths
+            .par_iter()
+            .map(|path| {
+                let text = std::fs::read_to_string(path).map_err(|err| BindingLoadError {
+                    path: path.clone(),
+                    error: err.into(),
+                })?;
+                Ok(read_id(&text).map(normalize_id))
+            })
+            .collect::<Vec<_>>();
+
+        let mut errors = Vec::new();
+        let mut by_id = FxHashMap::<String, IndexedSchema>::default();
+        for (path, id) in paths.iter().zip(ids) {
+            let id = match id {
+                Ok(Some(id)) => id,
+                Ok(None) => continue,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+            if let Some(other) = by_id.get(&id) {
+                errors.push(BindingLoadError {
+                    path: path.clone(),
+                    error: anyhow::anyhow!(
+                        "`$id: {id}` is already declared in {}",
+                        other.path.display()
+                    ),
+                });
+                continue;
+            }
+            by_id.insert(
+                id,
+                IndexedSchema {
+                    path: path.clone(),
+                    contents: OnceLock::new(),
+                },
+            );
+        }
+        (
+            Self {
+                by_id: Arc::new(by_id),
+                meta_validators: Arc::default(),
+            },
+            errors,
+        )
+    }
 
-pub struct DtJsonSchemaRetriever;
+    /// Returns the path of the file declaring `id`.
+    #[must_use]
+    pub fn path(&self, id: &str) -> Option<&Path> {
+        Some(&self.by_id.get(&normalize_id(id))?.path)
+    }
+
+    /// Returns the parsed schema declaring `id`, or `None` if it's missing or can't be parsed.
+    pub(crate) fn schema(&self, id: &str) -> Option<&Value> {
+        self.by_id.get(&normalize_id(id))?.contents().as_ref().ok()
+    }
+
+    /// Returns a validator for binding schemas declaring `$schema: <id>`, e.g. dt-schema's
+    /// `http://devicetree.org/meta-schemas/core.yaml#`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the meta-schema can't be retrieved or compiled.
+    #[expect(
+        clippy::missing_panics_doc,
+        reason = "the lock is only poisoned by panics"
+    )]
+    pub fn meta_validator(&self, id: &str) -> anyhow::Result<Arc<Validator>> {
+        let id = normalize_id(id);
+        let mut meta_validators = self.meta_validators.lock().expect("lock poisoned");
+        if let Some(validator) = meta_validators.get(&id) {
+            return Ok(validator.clone());
+        }
+        let uri = jsonschema::Uri::parse(id.clone())
+            .map_err(|err| anyhow::anyhow!("invalid `$schema`: {err}"))?;
+        let meta_schema = self
+            .retrieve(&uri)
+            .map_err(|err| anyhow::anyhow!("{err}"))?;
+        // dt-schema's meta-schemas are written in draft 2019-09 but have a custom `$schema`
+        let validator = jsonschema::options()
+            .with_draft(jsonschema::Draft::Draft201909)
+            .with_retriever(self.clone())
+            .build(&meta_schema)
+            .with_context(|| format!("Failed to compile meta-schema {id}"))?;
+        let validator = Arc::new(validator);
+        meta_validators.insert(id, validator.clone());
+        Ok(validator)
+    }
 
-impl Retrieve for DtJsonSchemaRetriever {
+    /// Hashes the contents of the meta-schemas, which decide whether binding schemas are valid.
+    pub(crate) fn meta_schemas_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for (id, text) in META_SCHEMA_FILES {
+            hasher.update(id);
+            hasher.update(text);
+        }
+        let mut meta_schemas = self
+            .by_id
+            .iter()
+            .filter(|(id, _)| id.contains("/meta-schemas/"))
+            .collect::<Vec<_>>();
+        meta_schemas.sort_unstable_by_key(|(id, _)| *id);
+        for (id, schema) in meta_schemas {
+            hasher.update(id);
+            hasher.update(std::fs::read(&schema.path).unwrap_or_default());
+        }
+        hex(&hasher.finalize())
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+}
+
+impl Retrieve for SchemaIndex {
     fn retrieve(
         &self,
         url: &jsonschema::Uri<String>,
     ) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
-        match url.scheme().as_str() {
-            "json-schema" => Err(anyhow!("cannot resolve schema without root schema ID").into()),
-            "http" | "https" => {
-                if url.authority().map(|auth| auth.host()) == Some("devicetree.org") {
-                    let abs_path = url
-                        .path()
-                        .as_str()
-                        .strip_prefix('/')
-                        .ok_or_else(|| anyhow!("devicetree.org url has no path portion"))?;
-                    eprintln!("reading devicetree.org/{abs_path}");
-
-                    let mut path_a =
-                        PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/dt-schema/dtschema"));
-                    path_a.push(abs_path);
-                    eprintln!("path_a: {}", path_a.display());
-
-                    let path_b = if let Some(abs_path) = abs_path.strip_prefix("schemas/") {
-                        let mut path_b = PathBuf::from(
-                            "/home/axel/dev/mainlining/linux/Documentation/devicetree/bindings/",
-                        );
-                        path_b.push(abs_path);
-                        eprintln!("path_b: {}", path_b.display());
-                        Some(path_b)
-                    } else {
-                        None
-                    };
-
-                    let f = std::fs::File::open(path_a)
-                        .or_else(|e| match path_b {
-                            Some(path_b) => std::fs::File::open(path_b),
-                            None => Err(e),
-                        })
-                        .context("could not read devicetee.org schema")?;
-                    let yaml: serde_yaml::Value = serde_yaml::from_reader(f)
-                        .context("could not read devicetee.org schema")?;
-                    Ok(serde_json::to_value(&yaml)?)
-                } else {
-                    Err(anyhow!("cannot resolve online schemas").into())
-                }
-            }
-            _ => Err(anyhow!("scheme is not supported").into()),
+        let id = normalize_id(url.as_str());
+        if let Some(schema) = self.by_id.get(&id) {
+            return Ok(schema.contents().clone()?);
+        }
+        if let Some((_, contents)) = META_SCHEMA_FILES
+            .iter()
+            .find(|(meta_id, _)| normalize_id(meta_id) == id)
+        {
+            return Ok(serde_yaml::from_str(contents)?);
+        }
+        if id.starts_with("http://devicetree.org/") {
+            Err(format!(
+                "no schema declares `$id: {id}#`. Add its directory to `bindings-dirs` or set \
+                `dt-schema-dir` in the workspace config"
+            )
+            .into())
+        } else {
+            Err(
+                format!("`{id}` is not a devicetree.org schema, and schemas are never downloaded")
+                    .into(),
+            )
         }
     }
 }
+
+/// Reads the `$id` of a YAML schema without parsing it.
+pub(crate) fn read_id(text: &str) -> Option<&str> {
+    text.lines().find_map(|line| {
+        let id = line.strip_prefix("$id:")?.trim();
+        Some(id.trim_matches(|c| c == '"' || c == '\''))
+    })
+}
+
+/// Removes the fragment and makes `https` and `http` the same, like dt-schema.
+fn normalize_id(id: &str) -> String {
+    let id = id.split_once('#').map_or(id, |(id, _)| id);
+    match id.strip_prefix("https://") {
+        Some(rest) => format!("http://{rest}"),
+        None => id.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retrieve() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("schemas")).unwrap();
+        std::fs::create_dir_all(dir.join("vendor")).unwrap();
+        std::fs::write(
+            dir.join("schemas/types.yaml"),
+            "$id: \"http://devicetree.org/schemas/types.yaml#\"\ndefinitions: {}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("vendor/copy.yaml"),
+            "$id: https://devicetree.org/schemas/types.yaml\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("no-id.yaml"), "title: No ID\n").unwrap();
+
+        let (index, errors) = SchemaIndex::build(&[dir]);
+        let retrieve = |url: &str| index.retrieve(&jsonschema::Uri::parse(url.to_owned()).unwrap());
+
+        assert_eq!(index.len(), 1);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].path.ends_with("copy.yaml"));
+        assert!(errors[0].error.to_string().contains("already declared"));
+        assert_eq!(
+            index.path("https://devicetree.org/schemas/types.yaml#/definitions/uint32"),
+            Some(dir.join("schemas/types.yaml").as_path())
+        );
+        assert_eq!(
+            retrieve("http://devicetree.org/schemas/types.yaml").unwrap(),
+            serde_json::json!({ "$id": "http://devicetree.org/schemas/types.yaml#", "definitions": {} })
+        );
+        // Bundled meta-schemas
+        assert!(retrieve("http://devicetree.org/meta-schemas/core.yaml").is_ok());
+
+        let missing = retrieve("http://devicetree.org/schemas/missing.yaml")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            missing.contains("`$id: http://devicetree.org/schemas/missing.yaml#`"),
+            "{missing}"
+        );
+        let online = retrieve("https://example.com/schema.json")
+            .unwrap_err()
+            .to_string();
+        assert!(online.contains("never downloaded"), "{online}");
+    }
+}
@@ -0,0 +1,487 @@
+//! Generating devicetree nodes from binding schemas, for adding new devices.
+
+use dt_parser::ast::{self, AstNodeOrToken as _, AstToken as _, HasName as _};
+use rustc_hash::FxHashMap;
+use serde_yaml::{Mapping, Value};
+
+use crate::{BindingSchema, PropertyType, PropertyTypes, Shape};
+
+/// A node with the required properties of a binding schema, see [`NodeSkeleton::from_schema`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSkeleton {
+    /// The node name with its unit address, e.g. `serial@1000`
+    pub name: String,
+    /// The required properties, in the order of the devicetree coding style
+    pub properties: Vec<SkeletonProperty>,
+}
+
+/// A property of a [`NodeSkeleton`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkeletonProperty {
+    pub name: String,
+    /// The value in DTS syntax, e.g. `<0x1000 0x100>`, or `None` for flags
+    pub value: Option<String>,
+}
+
+impl SkeletonProperty {
+    /// Returns the property in DTS syntax, e.g. `reg = <0x1000 0x100>;`.
+    #[must_use]
+    pub fn to_dts(&self) -> String {
+        match &self.value {
+            Some(value) => format!("{} = {value};", self.name),
+            None => format!("{};", self.name),
+        }
+    }
+}
+
+impl NodeSkeleton {
+    /// Creates a node for `compatible` with every property which `schema` requires.
+    ///
+    /// Values are placeholders to be filled in. They are taken from, in order:
+    ///
+    /// - `const`s in the schema, like `#clock-cells: const: 1` or the items of `clock-names`
+    /// - The first example with a node for `compatible`, or any node with a `compatible`
+    /// - The property's type in `types`, repeated for `minItems` or the number of `items`
+    ///
+    /// `compatible` is followed by its fallbacks from the schema, and the node name comes from the
+    /// example or the schema's `$nodename`.
+    #[must_use]
+    pub fn from_schema(schema: &BindingSchema, compatible: &str, types: &PropertyTypes) -> Self {
+        let example = ExampleNode::find(&schema.raw_schema, compatible);
+        let properties = schema
+            .raw_schema
+            .get("properties")
+            .and_then(Value::as_mapping);
+        let property_schema = |name: &str| {
+            properties
+                .and_then(|properties| properties.get(name))
+                .and_then(Value::as_mapping)
+        };
+
+        let mut required = schema
+            .raw_schema
+            .get("required")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .filter(|name| *name != "compatible")
+            .collect::<Vec<_>>();
+        required.sort_by_key(|name| property_order(name));
+
+        let mut skeleton = Self {
+            name: String::new(),
+            properties: vec![SkeletonProperty {
+                name: "compatible".to_owned(),
+                value: Some(compatible_value(&schema.raw_schema, compatible)),
+            }],
+        };
+        for name in required {
+            let property = property_schema(name);
+            let example_value = example
+                .as_ref()
+                .and_then(|example| example.values.get(name));
+            let value = match (property.and_then(const_value), example_value) {
+                (Some(value), _) => Some(value),
+                (None, Some(value)) => value.clone(),
+                (None, None) => default_value(name, types.get(name), property),
+            };
+            skeleton.properties.push(SkeletonProperty {
+                name: name.to_owned(),
+                value,
+            });
+        }
+
+        skeleton.name = if let Some(example) = example {
+            example.name
+        } else {
+            let name = property_schema("$nodename")
+                .and_then(node_name)
+                .or_else(|| {
+                    compatible
+                        .split_once(',')
+                        .map(|(_, device)| device.to_owned())
+                })
+                .unwrap_or_else(|| compatible.to_owned());
+            if skeleton
+                .properties
+                .iter()
+                .any(|property| property.name == "reg")
+            {
+                format!("{name}@0")
+            } else {
+                name
+            }
+        };
+        skeleton
+    }
+
+    /// Returns the node in DTS syntax, indented with tabs like the Linux kernel.
+    #[must_use]
+    pub fn to_dts(&self) -> String {
+        let mut dts = format!("{} {{\n", self.name);
+        for property in &self.properties {
+            dts.push('\t');
+            dts.push_str(&property.to_dts());
+            dts.push('\n');
+        }
+        dts.push_str("};\n");
+        dts
+    }
+}
+
+/// Sorts `compatible`, `reg` and `ranges` first, then standard properties and then vendor
+/// properties, following the devicetree coding style.
+pub(crate) fn property_order(name: &str) -> u8 {
+    match name {
+        "compatible" => 0,
+        "reg" => 1,
+        "ranges" => 2,
+        _ if name.contains(',') => 4,
+        _ => 3,
+    }
+}
+
+/// Returns the values of a schema which are `const`, e.g. `const: 1` or a list of `items` with
+/// `const`s, which fixups make of most `const`s.
+fn const_value(schema: &Mapping) -> Option<String> {
+    let consts = match (schema.get("const"), schema.get("items")) {
+        (Some(value), _) => vec![value],
+        (None, Some(Value::Sequence(items))) => items
+            .iter()
+            .map(|item| item.as_mapping()?.get("const"))
+            .collect::<Option<Vec<_>>>()?,
+        _ => return None,
+    };
+    if consts.is_empty() {
+        return None;
+    }
+    if let Some(strings) = consts
+        .iter()
+        .map(|value| value.as_str())
+        .collect::<Option<Vec<_>>>()
+    {
+        return Some(
+            strings
+                .iter()
+                .map(|string| format!("\"{string}\""))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+    }
+    let numbers = consts
+        .iter()
+        .map(|value| value.as_u64().map(|number| number.to_string()))
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("<{}>", numbers.join(" ")))
+}
+
+/// Returns `compatible` and the fallbacks listed after it in the schema's `compatible`, e.g.
+/// `"acme,foo-v2", "acme,foo"`.
+fn compatible_value(schema: &Mapping, compatible: &str) -> String {
+    let mut list = vec![compatible];
+    let property = schema
+        .get("properties")
+        .and_then(Value::as_mapping)
+        .and_then(|properties| properties.get("compatible"));
+    if let Some(fallbacks) = property.and_then(|property| find_fallbacks(property, compatible)) {
+        list.extend(fallbacks);
+    }
+    list.iter()
+        .map(|compatible| format!("\"{compatible}\""))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Finds a list of `items` containing `compatible` and returns the `const`s after it.
+fn find_fallbacks<'a>(schema: &'a Value, compatible: &str) -> Option<Vec<&'a str>> {
+    let schema = schema.as_mapping()?;
+    if let Some(items) = schema.get("items").and_then(Value::as_sequence) {
+        let accepts = |item: &Value| {
+            item.get("const").and_then(Value::as_str) == Some(compatible)
+                || item
+                    .get("enum")
+                    .and_then(Value::as_sequence)
+                    .is_some_and(|values| {
+                        values
+                            .iter()
+                            .any(|value| value.as_str() == Some(compatible))
+                    })
+        };
+        if let Some(position) = items.iter().position(accepts) {
+            return Some(
+                items[position + 1..]
+                    .iter()
+                    .map_while(|item| item.get("const").and_then(Value::as_str))
+                    .collect(),
+            );
+        }
+    }
+    ["oneOf", "anyOf", "allOf"]
+        .into_iter()
+        .filter_map(|key| schema.get(key)?.as_sequence())
+        .flatten()
+        .find_map(|branch| find_fallbacks(branch, compatible))
+}
+
+/// Returns a placeholder value for a property of type `ty`.
+fn default_value(name: &str, ty: Option<PropertyType>, schema: Option<&Mapping>) -> Option<String> {
+    // The number of entries, e.g. clocks
+    let count = schema
+        .and_then(|schema| {
+            schema.get("minItems").and_then(Value::as_u64).or_else(|| {
+                schema
+                    .get("items")
+                    .and_then(Value::as_sequence)
+                    .map(|items| items.len() as u64)
+            })
+        })
+        .unwrap_or(1)
+        .max(1);
+    let repeat = |value: &str| vec![value; usize::try_from(count).unwrap_or(1)].join(", ");
+    // Enums are wrapped in `items` by fixups
+    let first_enum = schema.and_then(|schema| {
+        schema
+            .get("items")
+            .and_then(Value::as_sequence)
+            .and_then(|items| items.first())
+            .unwrap_or(&Value::Mapping(schema.clone()))
+            .get("enum")?
+            .as_sequence()?
+            .first()
+            .cloned()
+    });
+    if let Some(value) = first_enum {
+        if let Some(string) = value.as_str() {
+            return Some(format!("\"{string}\""));
+        }
+        if let Some(number) = value.as_u64() {
+            return Some(format!("<{number}>"));
+        }
+    }
+
+    Some(match ty {
+        Some(PropertyType::Flag) => return None,
+        Some(PropertyType::String) => "\"\"".to_owned(),
+        Some(PropertyType::StringArray) => repeat("\"\""),
+        Some(PropertyType::Phandle) => "<&node>".to_owned(),
+        Some(PropertyType::PhandleArray) => repeat("<&provider>"),
+        Some(PropertyType::Int { bits, shape, .. }) => {
+            let bits = if bits == 32 {
+                String::new()
+            } else {
+                format!("/bits/ {bits} ")
+            };
+            match shape {
+                _ if name.starts_with('#') && name.ends_with("-cells") => format!("{bits}<1>"),
+                Shape::Matrix if name == "reg" => repeat("<0x0 0x1000>"),
+                Shape::Matrix => repeat(&format!("{bits}<0>")),
+                Shape::Scalar | Shape::Array => format!("{bits}<0>"),
+            }
+        }
+        None => "<0>".to_owned(),
+    })
+}
+
+/// Returns the generic node name from a `$nodename` schema, e.g. `serial` from
+/// `pattern: "^serial(@.*)?$"`.
+fn node_name(schema: &Mapping) -> Option<String> {
+    // Fixups wrap strings in `items`
+    let schema = match schema.get("items").and_then(Value::as_sequence) {
+        Some(items) => items.first()?.as_mapping()?,
+        None => schema,
+    };
+    if let Some(name) = schema.get("const").and_then(Value::as_str) {
+        return Some(name.to_owned());
+    }
+    let pattern = schema.get("pattern")?.as_str()?.strip_prefix('^')?;
+    let name = pattern
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect::<String>();
+    (!name.is_empty()).then_some(name)
+}
+
+/// A node from a schema's `examples:`.
+struct ExampleNode {
+    /// The name with the unit address
+    name: String,
+    /// The DTS text of each property's values, or `None` for flags
+    values: FxHashMap<String, Option<String>>,
+}
+
+impl ExampleNode {
+    /// Finds the first node for `compatible` in the examples, or the first node with a
+    /// `compatible` if there is none.
+    fn find(schema: &Mapping, compatible: &str) -> Option<Self> {
+        let examples = schema
+            .get("examples")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(|example| {
+                // Like dt-schema, examples without a root node get one
+                if example
+                    .lines()
+                    .any(|line| line.trim_start().starts_with("/ {"))
+                {
+                    example.to_owned()
+                } else {
+                    format!("/ {{\n{example}\n}};\n")
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut fallback = None;
+        for src in &examples {
+            let file = ast::SourceFile::parse(src).source_file();
+            let mut stack = file.nodes().collect::<Vec<_>>();
+            while let Some(node) = stack.pop() {
+                stack.extend(node.subnodes());
+                let Some(compatibles) = node
+                    .properties()
+                    .find(|property| property_name(property).as_deref() == Some("compatible"))
+                else {
+                    continue;
+                };
+                let has_compatible = compatibles.values().any(|value| {
+                    matches!(&value, ast::PropValue::String(token) if token.text().trim_matches('"') == compatible)
+                });
+                if has_compatible {
+                    return Some(Self::new(&node, src));
+                }
+                if fallback.is_none() {
+                    fallback = Some(Self::new(&node, src));
+                }
+            }
+        }
+        fallback
+    }
+
+    fn new(node: &ast::DtNode, src: &str) -> Self {
+        let values = node
+            .properties()
+            .filter_map(|property| {
+                let name = property_name(&property)?;
+                let mut ranges = property.values().map(|value| value.syntax().text_range());
+                let value = ranges.next().map(|first| {
+                    let end = ranges.last().unwrap_or(first).end;
+                    src[first.start..end].to_owned()
+                });
+                Some((name, value))
+            })
+            .collect();
+        Self {
+            name: node.text_name(src).unwrap_or_default().into_owned(),
+            values,
+        }
+    }
+}
+
+fn property_name(property: &ast::DtProperty) -> Option<String> {
+    Some(property.name()?.syntax().text().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::SchemaIndex;
+
+    fn skeleton(yaml: &str, compatible: &str) -> String {
+        let schema = BindingSchema::compile_unchecked(
+            Path::new("acme,foo.yaml"),
+            serde_yaml::from_str(yaml).unwrap(),
+            &SchemaIndex::default(),
+        )
+        .unwrap();
+        let mut types = PropertyTypes::new();
+        types.insert_schema(&schema.raw_schema);
+        NodeSkeleton::from_schema(&schema, compatible, &types).to_dts()
+    }
+
+    const BINDING: &str = r#"
+$id: http://devicetree.org/schemas/acme,foo.yaml#
+$schema: http://devicetree.org/meta-schemas/core.yaml#
+title: Acme Foo
+properties:
+  $nodename:
+    pattern: "^foo(@.*)?$"
+  compatible:
+    oneOf:
+      - const: acme,foo
+      - items:
+          - const: acme,foo-v2
+          - const: acme,foo
+  reg:
+    maxItems: 1
+  clocks:
+    minItems: 2
+  clock-names:
+    items:
+      - const: core
+      - const: bus
+  '#clock-cells':
+    const: 1
+  interrupt-controller: true
+  acme,mode:
+    enum: [fast, slow]
+required:
+  - acme,mode
+  - compatible
+  - '#clock-cells'
+  - interrupt-controller
+  - clocks
+  - clock-names
+  - reg
+"#;
+
+    #[test]
+    fn from_schema() {
+        assert_eq!(
+            skeleton(BINDING, "acme,foo-v2"),
+            "foo@0 {
+\tcompatible = \"acme,foo-v2\", \"acme,foo\";
+\treg = <0x0 0x1000>;
+\t#clock-cells = <1>;
+\tinterrupt-controller;
+\tclocks = <&provider>, <&provider>;
+\tclock-names = \"core\", \"bus\";
+\tacme,mode = \"fast\";
+};
+"
+        );
+    }
+
+    #[test]
+    fn from_example() {
+        let binding = format!(
+            "{BINDING}examples:
+  - |
+    #include <dt-bindings/interrupt-controller/irq.h>
+
+    foo@1000 {{
+        compatible = \"acme,foo\";
+        reg = <0x1000 0x100>;
+        #clock-cells = <2>;
+        clocks = <&osc>, <&pll 1>;
+    }};
+"
+        );
+        assert_eq!(
+            skeleton(&binding, "acme,foo"),
+            "foo@1000 {
+\tcompatible = \"acme,foo\";
+\treg = <0x1000 0x100>;
+\t#clock-cells = <1>;
+\tinterrupt-controller;
+\tclocks = <&osc>, <&pll 1>;
+\tclock-names = \"core\", \"bus\";
+\tacme,mode = \"fast\";
+};
+"
+        );
+    }
+}
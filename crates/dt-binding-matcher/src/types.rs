@@ -0,0 +1,859 @@
+//! Property types, for decoding raw values into the shapes schemas expect.
+//!
+//! DTS doesn't know the types of properties, so `clocks = <&a 1>, <&b 2>;` and
+//! `clocks = <&a 1 &b 2>;` compile to the same bytes. dt-schema decodes each property with its type
+//! from `types.yaml` before validating, turning `clocks` into one row per clock. The number of
+//! cells in each row comes from `#clock-cells` of the referenced provider. [`PropertyTypes::coerce`]
+//! does the same for the JSON from [`Stage2Node::to_json_with_spans`].
+
+use dt_analyzer::{
+    new::stage2::{JsonSpans, Stage2Node, Stage2Property, Stage2Tree},
+    resolved_prop::{Cell, PhandleTarget, Value},
+};
+use dt_parser::{
+    ast::{AstNode, AstToken, DtLabel, HasName},
+    TextRange,
+};
+use regex::Regex;
+use rustc_hash::FxHashMap;
+use serde_json::Value as JValue;
+use serde_yaml::{Mapping, Value as YValue};
+
+use crate::bindings::escape;
+
+/// How many values an integer property has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// A single integer, e.g. `uint32`
+    Scalar,
+    /// A list of integers, e.g. `uint32-array`
+    Array,
+    /// A list of lists of integers, e.g. `uint32-matrix`
+    Matrix,
+}
+
+/// The type of a property, from the definitions in dt-schema's `types.yaml`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyType {
+    /// `flag` or `boolean`: a property without a value
+    Flag,
+    /// `string`
+    String,
+    /// `string-array` or `non-unique-string-array`
+    StringArray,
+    /// `uint32`, `int8-array`, `uint64-matrix` and so on
+    Int {
+        bits: u8,
+        signed: bool,
+        shape: Shape,
+    },
+    /// `phandle`: a reference to a node
+    Phandle,
+    /// `phandle-array`: references to providers, each followed by its arguments
+    PhandleArray,
+}
+
+impl PropertyType {
+    /// Parses the name of a definition in `types.yaml`, like `uint32-matrix`.
+    #[must_use]
+    pub fn from_definition(name: &str) -> Option<Self> {
+        Some(match name {
+            "flag" | "boolean" => Self::Flag,
+            "string" => Self::String,
+            "string-array" | "non-unique-string-array" => Self::StringArray,
+            "phandle" => Self::Phandle,
+            "phandle-array" => Self::PhandleArray,
+            _ => {
+                let (signed, rest) = match name.strip_prefix('u') {
+                    Some(rest) => (false, rest),
+                    None => (true, name),
+                };
+                let rest = rest.strip_prefix("int")?;
+                let (bits, shape) = match rest.split_once('-') {
+                    None => (rest, Shape::Scalar),
+                    Some((bits, "item")) => (bits, Shape::Scalar),
+                    Some((bits, "array")) => (bits, Shape::Array),
+                    Some((bits, "matrix")) => (bits, Shape::Matrix),
+                    Some(_) => return None,
+                };
+                let bits = match bits {
+                    "8" => 8,
+                    "16" => 16,
+                    "32" => 32,
+                    "64" => 64,
+                    _ => return None,
+                };
+                Self::Int {
+                    bits,
+                    signed,
+                    shape,
+                }
+            }
+        })
+    }
+}
+
+impl PropertyType {
+    /// Returns the name of the definition in `types.yaml`, e.g. `uint32-matrix`.
+    #[must_use]
+    pub fn name(self) -> String {
+        match self {
+            Self::Flag => "flag".to_owned(),
+            Self::String => "string".to_owned(),
+            Self::StringArray => "string-array".to_owned(),
+            Self::Phandle => "phandle".to_owned(),
+            Self::PhandleArray => "phandle-array".to_owned(),
+            Self::Int {
+                bits,
+                signed,
+                shape,
+            } => {
+                let sign = if signed { "" } else { "u" };
+                let shape = match shape {
+                    Shape::Scalar => "",
+                    Shape::Array => "-array",
+                    Shape::Matrix => "-matrix",
+                };
+                format!("{sign}int{bits}{shape}")
+            }
+        }
+    }
+}
+
+/// Types of the core properties, defined in dt-schema's own schemas.
+const BUILTIN_TYPES: &[(&str, &str)] = &[
+    ("compatible", "string-array"),
+    ("model", "string"),
+    ("status", "string"),
+    ("phandle", "uint32"),
+    ("#address-cells", "uint32"),
+    ("#size-cells", "uint32"),
+    ("reg", "uint32-matrix"),
+    ("ranges", "uint32-matrix"),
+    ("dma-ranges", "uint32-matrix"),
+    ("interrupts", "uint32-matrix"),
+    ("interrupt-parent", "phandle"),
+    ("interrupts-extended", "phandle-array"),
+    ("interrupt-controller", "flag"),
+    ("clocks", "phandle-array"),
+    ("assigned-clocks", "phandle-array"),
+    ("assigned-clock-parents", "phandle-array"),
+    ("assigned-clock-rates", "uint32-array"),
+    ("resets", "phandle-array"),
+    ("power-domains", "phandle-array"),
+    ("dmas", "phandle-array"),
+    ("phys", "phandle-array"),
+    ("pwms", "phandle-array"),
+    ("mboxes", "phandle-array"),
+    ("iommus", "phandle-array"),
+    ("hwlocks", "phandle-array"),
+    ("io-channels", "phandle-array"),
+    ("interconnects", "phandle-array"),
+    ("thermal-sensors", "phandle-array"),
+    ("sound-dai", "phandle-array"),
+    ("gpios", "phandle-array"),
+];
+
+/// Types of the core properties matching patterns.
+const BUILTIN_PATTERN_TYPES: &[(&str, &str)] = &[
+    ("^#.*-cells$", "uint32"),
+    ("-names$", "string-array"),
+    ("-supply$", "phandle"),
+    ("-gpios?$", "phandle-array"),
+    ("^pinctrl-[0-9]+$", "phandle-array"),
+];
+
+/// Where the number of arguments after each phandle of a `phandle-array` comes from.
+enum ArgCells {
+    Fixed(u32),
+    /// A `#*-cells` property of the referenced provider
+    Provider(&'static str),
+}
+
+fn arg_cells(name: &str) -> Option<ArgCells> {
+    Some(ArgCells::Provider(match name {
+        "clocks" | "assigned-clocks" | "assigned-clock-parents" => "#clock-cells",
+        "interrupts-extended" => "#interrupt-cells",
+        "resets" => "#reset-cells",
+        "power-domains" => "#power-domain-cells",
+        "dmas" => "#dma-cells",
+        "phys" => "#phy-cells",
+        "pwms" => "#pwm-cells",
+        "mboxes" => "#mbox-cells",
+        "iommus" => "#iommu-cells",
+        "hwlocks" => "#hwlock-cells",
+        "io-channels" => "#io-channel-cells",
+        "interconnects" => "#interconnect-cells",
+        "thermal-sensors" => "#thermal-sensor-cells",
+        "sound-dai" => "#sound-dai-cells",
+        _ if name == "gpios" || name.ends_with("-gpios") || name.ends_with("-gpio") => {
+            "#gpio-cells"
+        }
+        _ if name.starts_with("pinctrl-") => return Some(ArgCells::Fixed(0)),
+        _ => return None,
+    }))
+}
+
+/// A database of property types, from the `$ref`s to `types.yaml` in binding schemas.
+#[derive(Debug)]
+pub struct PropertyTypes {
+    by_name: FxHashMap<String, PropertyType>,
+    by_pattern: Vec<(Regex, PropertyType)>,
+}
+
+impl Default for PropertyTypes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PropertyTypes {
+    /// Creates a database with the types of the core properties, like `reg` and `clocks`.
+    #[must_use]
+    #[expect(clippy::missing_panics_doc, reason = "builtin types are valid")]
+    pub fn new() -> Self {
+        let definition = |name| PropertyType::from_definition(name).expect("valid definition");
+        Self {
+            by_name: BUILTIN_TYPES
+                .iter()
+                .map(|(name, ty)| ((*name).to_owned(), definition(ty)))
+                .collect(),
+            by_pattern: BUILTIN_PATTERN_TYPES
+                .iter()
+                .map(|(pattern, ty)| (Regex::new(pattern).expect("valid regex"), definition(ty)))
+                .collect(),
+        }
+    }
+
+    /// Returns the type of the property `name`.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<PropertyType> {
+        self.by_name.get(name).copied().or_else(|| {
+            self.by_pattern
+                .iter()
+                .find(|(pattern, _)| pattern.is_match(name))
+                .map(|(_, ty)| *ty)
+        })
+    }
+
+    /// Adds the types of the properties in a binding schema and its subnode schemas.
+    ///
+    /// The type is found by following `$ref`s, including ones in `allOf` and to local
+    /// definitions, until reaching `types.yaml`. Types which are already known aren't replaced.
+    pub fn insert_schema(&mut self, schema: &Mapping) {
+        self.walk_schema(schema, &YValue::Mapping(schema.clone()));
+    }
+
+    fn walk_schema(&mut self, root: &Mapping, value: &YValue) {
+        match value {
+            YValue::Mapping(map) => {
+                for (key, child) in map {
+                    let key = key.as_str();
+                    if let (Some("properties" | "patternProperties"), Some(properties)) =
+                        (key, child.as_mapping())
+                    {
+                        let is_pattern = key == Some("patternProperties");
+                        for (name, property) in properties {
+                            let (Some(name), Some(ty)) =
+                                (name.as_str(), find_type(root, property, 0))
+                            else {
+                                continue;
+                            };
+                            if !is_pattern {
+                                self.by_name.entry(name.to_owned()).or_insert(ty);
+                            } else if let Ok(pattern) = Regex::new(name) {
+                                if !self.by_pattern.iter().any(|(p, _)| p.as_str() == name) {
+                                    self.by_pattern.push((pattern, ty));
+                                }
+                            }
+                        }
+                    }
+                    self.walk_schema(root, child);
+                }
+            }
+            YValue::Sequence(seq) => {
+                for child in seq {
+                    self.walk_schema(root, child);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reshapes the properties in `json`, the output of [`Stage2Node::to_json_with_spans`] for
+    /// `root`, according to their types. `spans` is updated to the new shape.
+    ///
+    /// Integer arrays become a single row, 64-bit integers are joined from pairs of cells and
+    /// `phandle-array`s get a row for each phandle and its arguments. `reg` is split using
+    /// `#address-cells` and `#size-cells` of the parent and `interrupts` using `#interrupt-cells` of
+    /// the interrupt parent.
+    ///
+    /// Properties whose values don't fit their type are left as is, for the schema to reject.
+    pub fn coerce(&self, root: &Stage2Node, json: &mut JValue, spans: &mut JsonSpans) {
+        let mut coercer = Coercer {
+            types: self,
+            root,
+            labels: FxHashMap::default(),
+            phandles: FxHashMap::default(),
+        };
+        coercer.collect_labels(root);
+        coercer.coerce_node(&mut Vec::new(), root, json, &mut String::new(), spans);
+    }
+}
+
+/// Finds the `types.yaml` definition which `schema` refers to.
+fn find_type(root: &Mapping, schema: &YValue, depth: usize) -> Option<PropertyType> {
+    const MAX_DEPTH: usize = 8;
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    let map = schema.as_mapping()?;
+    if let Some(reference) = map.get("$ref").and_then(YValue::as_str) {
+        if let Some((_, definition)) = reference.split_once("types.yaml#/definitions/") {
+            return PropertyType::from_definition(definition);
+        }
+        if let Some(pointer) = reference.strip_prefix('#') {
+            if let Some(target) = resolve_pointer(root, pointer) {
+                return find_type(root, target, depth + 1);
+            }
+        }
+    }
+    ["allOf", "oneOf", "anyOf"]
+        .into_iter()
+        .filter_map(|key| map.get(key)?.as_sequence())
+        .flatten()
+        .find_map(|schema| find_type(root, schema, depth + 1))
+}
+
+/// Resolves a local JSON pointer like `/$defs/foo` in a YAML document.
+pub(crate) fn resolve_pointer<'a>(root: &'a Mapping, pointer: &str) -> Option<&'a YValue> {
+    let mut segments = pointer.split('/').skip(1);
+    let first = segments.next()?.replace("~1", "/").replace("~0", "~");
+    let mut value = root.get(first.as_str())?;
+    for segment in segments {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        value = match value {
+            YValue::Mapping(map) => map.get(segment.as_str())?,
+            YValue::Sequence(seq) => seq.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// A 32-bit cell of a property being coerced.
+#[derive(Clone, Copy)]
+struct Item<'a> {
+    cell: RawCell<'a>,
+    span: TextRange,
+    /// Index of the `<>` or `[]` value the cell is in
+    value_index: usize,
+}
+
+#[derive(Clone, Copy)]
+enum RawCell<'a> {
+    Number(u32),
+    Phandle(&'a PhandleTarget),
+}
+
+struct Coercer<'a> {
+    types: &'a PropertyTypes,
+    root: &'a Stage2Node,
+    labels: FxHashMap<String, &'a Stage2Node>,
+    /// Phandles assigned to referenced nodes without a `phandle` property
+    phandles: FxHashMap<*const Stage2Node, u32>,
+}
+
+impl<'a> Coercer<'a> {
+    fn collect_labels(&mut self, node: &'a Stage2Node) {
+        for (_, ast) in &node.asts {
+            for label in ast.syntax().child_nodes().filter_map(DtLabel::cast) {
+                if let Some(name) = label.name() {
+                    self.labels.insert(name.syntax().text().to_string(), node);
+                }
+            }
+        }
+        for child in node.children.values() {
+            if let Stage2Tree::Node(child) = child {
+                self.collect_labels(child);
+            }
+        }
+    }
+
+    fn resolve(&self, target: &PhandleTarget) -> Option<&'a Stage2Node> {
+        match target {
+            PhandleTarget::Label(label) => self.labels.get(label).copied(),
+            PhandleTarget::Path(path) => path
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .try_fold(self.root, |node, name| node.children.get(name)?.as_node()),
+        }
+    }
+
+    /// Returns the phandle of `node`, like dtc assigns them.
+    ///
+    /// The values don't match dtc's, but no schema depends on them.
+    fn phandle(&mut self, node: &Stage2Node) -> u32 {
+        if let Some(phandle) = prop_u32(node, "phandle") {
+            return phandle;
+        }
+        let next = u32::try_from(self.phandles.len() + 1).unwrap_or(u32::MAX);
+        *self
+            .phandles
+            .entry(std::ptr::from_ref(node))
+            .or_insert(next)
+    }
+
+    fn coerce_node(
+        &mut self,
+        ancestors: &mut Vec<&'a Stage2Node>,
+        node: &'a Stage2Node,
+        json: &mut JValue,
+        pointer: &mut String,
+        spans: &mut JsonSpans,
+    ) {
+        let JValue::Object(map) = json else {
+            return;
+        };
+        for (name, tree) in &node.children {
+            let Some(child_json) = map.get_mut(name) else {
+                continue;
+            };
+            let len = pointer.len();
+            pointer.push('/');
+            pointer.push_str(&escape(name));
+            match tree {
+                Stage2Tree::Node(child) => {
+                    ancestors.push(node);
+                    self.coerce_node(ancestors, child, child_json, pointer, spans);
+                    ancestors.pop();
+                }
+                Stage2Tree::Prop(prop) => {
+                    if let Some(coerced) =
+                        self.coerce_prop(ancestors, node, name, prop, pointer, spans)
+                    {
+                        *child_json = coerced;
+                    }
+                }
+            }
+            pointer.truncate(len);
+        }
+    }
+
+    fn coerce_prop(
+        &mut self,
+        ancestors: &[&'a Stage2Node],
+        node: &'a Stage2Node,
+        name: &str,
+        prop: &'a Stage2Property,
+        pointer: &str,
+        spans: &mut JsonSpans,
+    ) -> Option<JValue> {
+        let ty = self.types.get(name)?;
+        let (bits, signed) = match ty {
+            PropertyType::Int { bits, signed, .. } => (bits, signed),
+            PropertyType::Phandle | PropertyType::PhandleArray => (32, false),
+            PropertyType::Flag | PropertyType::String | PropertyType::StringArray => return None,
+        };
+
+        let items = collect_items(prop, pointer, spans)?;
+
+        let rows = match ty {
+            PropertyType::Int {
+                shape: Shape::Matrix,
+                ..
+            } => {
+                let row_len = match name {
+                    "reg" => ancestors.last().map(|parent| {
+                        prop_u32(parent, "#address-cells").unwrap_or(2)
+                            + prop_u32(parent, "#size-cells").unwrap_or(1)
+                    }),
+                    "interrupts" => self
+                        .interrupt_parent(ancestors, node)
+                        .and_then(|parent| prop_u32(parent, "#interrupt-cells")),
+                    _ => None,
+                };
+                match row_len.and_then(|row_len| usize::try_from(row_len).ok()) {
+                    Some(row_len) if row_len > 0 && items.len() % row_len == 0 => {
+                        items.chunks(row_len).map(<[_]>::to_vec).collect()
+                    }
+                    _ => by_value(&items),
+                }
+            }
+            PropertyType::PhandleArray => self
+                .split_phandle_array(name, &items)
+                .unwrap_or_else(|| by_value(&items)),
+            _ => vec![items],
+        };
+
+        let mut json_rows = Vec::with_capacity(rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            let mut json_row = Vec::with_capacity(row.len());
+            let cells: Vec<(JValue, TextRange)> = if bits == 64 {
+                if row.len() % 2 != 0 {
+                    return None;
+                }
+                row.chunks(2)
+                    .map(|pair| {
+                        let high = u64::from(self.cell_value(pair[0].cell).unwrap_or(u32::MAX));
+                        let low = u64::from(self.cell_value(pair[1].cell).unwrap_or(u32::MAX));
+                        let value = (high << 32) | low;
+                        #[expect(clippy::cast_possible_wrap, reason = "two's complement")]
+                        let json = if signed {
+                            JValue::from(value as i64)
+                        } else {
+                            JValue::from(value)
+                        };
+                        (json, pair[0].span.to(pair[1].span))
+                    })
+                    .collect()
+            } else {
+                row.iter()
+                    .map(|item| {
+                        #[expect(
+                            clippy::cast_possible_wrap,
+                            clippy::cast_possible_truncation,
+                            reason = "two's complement, and sized cells use `/bits/`"
+                        )]
+                        let json = match (self.cell_value(item.cell), signed, bits) {
+                            // Like `Cell::into_json`
+                            (None, ..) => JValue::from(-1),
+                            (Some(value), true, 8) => JValue::from(value as i8),
+                            (Some(value), true, 16) => JValue::from(value as i16),
+                            (Some(value), true, _) => JValue::from(value as i32),
+                            (Some(value), false, _) => JValue::from(value),
+                        };
+                        (json, item.span)
+                    })
+                    .collect()
+            };
+            for (j, (json, span)) in cells.iter().enumerate() {
+                spans.insert(
+                    format!("{pointer}/{i}/{j}"),
+                    (prop.source_id.clone(), *span),
+                );
+                json_row.push(json.clone());
+            }
+            if let (Some(first), Some(last)) = (cells.first(), cells.last()) {
+                spans.insert(
+                    format!("{pointer}/{i}"),
+                    (prop.source_id.clone(), first.1.to(last.1)),
+                );
+            }
+            json_rows.push(JValue::Array(json_row));
+        }
+        Some(JValue::Array(json_rows))
+    }
+
+    /// Returns the value of a cell, or `None` for a reference to a node which doesn't exist.
+    fn cell_value(&mut self, cell: RawCell<'a>) -> Option<u32> {
+        match cell {
+            RawCell::Number(n) => Some(n),
+            RawCell::Phandle(target) => {
+                let node = self.resolve(target)?;
+                Some(self.phandle(node))
+            }
+        }
+    }
+
+    /// Splits the cells of a `phandle-array` into a row for each phandle and its arguments.
+    fn split_phandle_array<'i>(
+        &self,
+        name: &str,
+        items: &[Item<'i>],
+    ) -> Option<Vec<Vec<Item<'i>>>> {
+        let arg_cells = arg_cells(name)?;
+        let mut rows = Vec::new();
+        let mut rest = items;
+        while let Some((first, tail)) = rest.split_first() {
+            let count = match (first.cell, &arg_cells) {
+                (RawCell::Phandle(_), ArgCells::Fixed(count)) => *count,
+                (RawCell::Phandle(target), ArgCells::Provider(cells_name)) => {
+                    prop_u32(self.resolve(target)?, cells_name)?
+                }
+                // A null phandle has no arguments
+                (RawCell::Number(0), _) => 0,
+                (RawCell::Number(_), _) => return None,
+            };
+            let count = usize::try_from(count).ok()?;
+            if tail.len() < count {
+                return None;
+            }
+            rows.push(rest[..=count].to_vec());
+            rest = &tail[count..];
+        }
+        Some(rows)
+    }
+
+    /// Returns the node handling the interrupts of `node`.
+    fn interrupt_parent(
+        &self,
+        ancestors: &[&'a Stage2Node],
+        node: &'a Stage2Node,
+    ) -> Option<&'a Stage2Node> {
+        let mut nodes = std::iter::once(node).chain(ancestors.iter().rev().copied());
+        match nodes.find_map(|node| node.children.get("interrupt-parent")) {
+            Some(Stage2Tree::Prop(prop)) => match prop.values.first()? {
+                Value::CellList(cells) => match cells.first()? {
+                    Cell::Phandle(target) => self.resolve(target),
+                    Cell::U32(_) => None,
+                },
+                _ => None,
+            },
+            Some(Stage2Tree::Node(_)) => None,
+            None => ancestors.last().copied(),
+        }
+    }
+}
+
+/// Flattens the cells of a property, or returns `None` if it has strings or references to paths.
+fn collect_items<'a>(
+    prop: &'a Stage2Property,
+    pointer: &str,
+    spans: &JsonSpans,
+) -> Option<Vec<Item<'a>>> {
+    let mut items = Vec::new();
+    for (i, value) in prop.values.iter().enumerate() {
+        let mut push = |j: usize, cell| {
+            items.push(Item {
+                cell,
+                span: spans
+                    .find(&format!("{pointer}/{i}/{j}"))
+                    .map_or_else(|| prop.ast.syntax().text_range(), |(_, span)| *span),
+                value_index: i,
+            });
+        };
+        match value {
+            Value::CellList(cells) => {
+                for (j, cell) in cells.iter().enumerate() {
+                    push(
+                        j,
+                        match cell {
+                            Cell::U32(n) => RawCell::Number(*n),
+                            Cell::Phandle(target) => RawCell::Phandle(target),
+                        },
+                    );
+                }
+            }
+            Value::Bytestring(bytes) => {
+                for (j, byte) in bytes.iter().enumerate() {
+                    push(j, RawCell::Number(u32::from(*byte)));
+                }
+            }
+            Value::String(_) | Value::Phandle(_) => return None,
+        }
+    }
+    Some(items)
+}
+
+/// Splits cells into a row for each `<>` value, like dtc.
+fn by_value<'i>(items: &[Item<'i>]) -> Vec<Vec<Item<'i>>> {
+    items
+        .chunk_by(|a, b| a.value_index == b.value_index)
+        .map(<[_]>::to_vec)
+        .collect()
+}
+
+/// Returns the first cell of the property `name` of `node`.
+fn prop_u32(node: &Stage2Node, name: &str) -> Option<u32> {
+    match node.children.get(name)?.as_prop()?.values.first()? {
+        Value::CellList(cells) => match cells.first()? {
+            Cell::U32(n) => Some(*n),
+            Cell::Phandle(_) => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dt_parser::{ast::SourceFile, SourceId};
+
+    use super::*;
+
+    fn coerce(types: &PropertyTypes, src: &str, pointer: &str) -> (JValue, Vec<String>) {
+        let file = SourceFile::parse(src).source_file();
+        let diag = std::sync::Mutex::new(Vec::new());
+        let outline = dt_analyzer::new::stage1::analyze_file(&file, src, &diag);
+        let stage2 =
+            dt_analyzer::new::stage2::compute(&SourceId::from("test.dts"), &outline, &[], &diag);
+        let (mut json, mut spans) = stage2.root_node.to_json_with_spans();
+        types.coerce(&stage2.root_node, &mut json, &mut spans);
+
+        let value = json.pointer(pointer).unwrap().clone();
+        let rows = (0..value.as_array().map_or(0, Vec::len))
+            .map(|i| {
+                let (_, span) = spans.get(&format!("{pointer}/{i}")).unwrap();
+                span.text(src).unwrap().to_owned()
+            })
+            .collect();
+        (value, rows)
+    }
+
+    #[test]
+    fn definitions() {
+        assert_eq!(
+            PropertyType::from_definition("uint32-matrix"),
+            Some(PropertyType::Int {
+                bits: 32,
+                signed: false,
+                shape: Shape::Matrix
+            })
+        );
+        assert_eq!(
+            PropertyType::from_definition("int8-item"),
+            Some(PropertyType::Int {
+                bits: 8,
+                signed: true,
+                shape: Shape::Scalar
+            })
+        );
+        assert_eq!(
+            PropertyType::from_definition("phandle-array"),
+            Some(PropertyType::PhandleArray)
+        );
+        assert_eq!(PropertyType::from_definition("uint31"), None);
+        assert_eq!(PropertyType::from_definition("object"), None);
+    }
+
+    #[test]
+    fn schema_types() {
+        let schema: Mapping = serde_yaml::from_str(
+            r"
+properties:
+  acme,rates:
+    $ref: /schemas/types.yaml#/definitions/uint64-array
+  acme,mode:
+    allOf:
+      - $ref: http://devicetree.org/schemas/types.yaml#/definitions/string
+      - enum: [fast, slow]
+  acme,flag:
+    $ref: '#/$defs/flag'
+  clocks:
+    $ref: /schemas/types.yaml#/definitions/uint32
+patternProperties:
+  '^acme,.*-level$':
+    $ref: /schemas/types.yaml#/definitions/int32
+  '^channel@[0-9]$':
+    type: object
+    properties:
+      acme,gain:
+        $ref: /schemas/types.yaml#/definitions/uint8
+$defs:
+  flag:
+    $ref: /schemas/types.yaml#/definitions/flag
+",
+        )
+        .unwrap();
+        let mut types = PropertyTypes::new();
+        types.insert_schema(&schema);
+
+        let int = |bits, signed, shape| PropertyType::Int {
+            bits,
+            signed,
+            shape,
+        };
+        assert_eq!(types.get("acme,rates"), Some(int(64, false, Shape::Array)));
+        assert_eq!(types.get("acme,mode"), Some(PropertyType::String));
+        assert_eq!(types.get("acme,flag"), Some(PropertyType::Flag));
+        assert_eq!(types.get("acme,gain"), Some(int(8, false, Shape::Scalar)));
+        assert_eq!(
+            types.get("acme,low-level"),
+            Some(int(32, true, Shape::Scalar))
+        );
+        // Builtin types aren't replaced
+        assert_eq!(types.get("clocks"), Some(PropertyType::PhandleArray));
+        assert_eq!(types.get("vdd-supply"), Some(PropertyType::Phandle));
+        assert_eq!(types.get("acme,unknown"), None);
+    }
+
+    #[test]
+    fn phandle_arrays() {
+        let types = PropertyTypes::new();
+        let src = r"/dts-v1/;
+/ {
+    osc: osc { #clock-cells = <0>; };
+    cru: cru { #clock-cells = <1>; phandle = <7>; };
+    gpio: gpio { #gpio-cells = <2>; };
+    dev {
+        clocks = <&osc &cru 12>, <&{/cru} 13>;
+        reset-gpios = <&gpio 1 0 &gpio 2 1>;
+        acme,other = <&osc 1>, <&cru>;
+    };
+    broken { clocks = <&osc &missing 1>; };
+};";
+        let (json, rows) = coerce(&types, src, "/dev/clocks");
+        assert_eq!(json, serde_json::json!([[1], [7, 12], [7, 13]]));
+        assert_eq!(rows, ["&osc", "&cru 12", "&{/cru} 13"]);
+
+        let (json, rows) = coerce(&types, src, "/dev/reset-gpios");
+        assert_eq!(json, serde_json::json!([[2, 1, 0], [2, 2, 1]]));
+        assert_eq!(rows, ["&gpio 1 0", "&gpio 2 1"]);
+
+        // Untyped properties and unresolvable providers keep the rows from the source
+        let (json, _) = coerce(&types, src, "/dev/acme,other");
+        assert_eq!(json, serde_json::json!([[-1, 1], [-1]]));
+        let (json, rows) = coerce(&types, src, "/broken/clocks");
+        assert_eq!(json, serde_json::json!([[1, -1, 1]]));
+        assert_eq!(rows, ["&osc &missing 1"]);
+    }
+
+    #[test]
+    fn int_shapes() {
+        let mut types = PropertyTypes::new();
+        types.insert_schema(
+            &serde_yaml::from_str(
+                r"
+properties:
+  acme,array:
+    $ref: /schemas/types.yaml#/definitions/uint32-array
+  acme,offset:
+    $ref: /schemas/types.yaml#/definitions/int32
+  acme,big:
+    $ref: /schemas/types.yaml#/definitions/uint64
+",
+            )
+            .unwrap(),
+        );
+        let src = r"/dts-v1/;
+/ {
+    #address-cells = <1>;
+    #size-cells = <1>;
+    intc: intc { #interrupt-cells = <3>; };
+    soc {
+        #address-cells = <2>;
+        #size-cells = <2>;
+        interrupt-parent = <&intc>;
+        dev {
+            reg = <0 0x1000 0 0x100 0 0x2000 0 0x100>;
+            interrupts = <0 1 4>, <0 2 4>;
+            acme,array = <1 2>, <3>;
+            acme,offset = <0xffffffff>;
+            acme,big = <0x1 0x2>;
+        };
+    };
+    dev { reg = <0x1000 0x100>, <0x2000>; };
+};";
+        let (json, rows) = coerce(&types, src, "/soc/dev/reg");
+        assert_eq!(
+            json,
+            serde_json::json!([[0, 0x1000, 0, 0x100], [0, 0x2000, 0, 0x100]])
+        );
+        assert_eq!(rows, ["0 0x1000 0 0x100", "0 0x2000 0 0x100"]);
+
+        let (json, _) = coerce(&types, src, "/soc/dev/interrupts");
+        assert_eq!(json, serde_json::json!([[0, 1, 4], [0, 2, 4]]));
+        let (json, rows) = coerce(&types, src, "/soc/dev/acme,array");
+        assert_eq!(json, serde_json::json!([[1, 2, 3]]));
+        assert_eq!(rows, ["1 2>, <3"]);
+        let (json, _) = coerce(&types, src, "/soc/dev/acme,offset");
+        assert_eq!(json, serde_json::json!([[-1]]));
+        let (json, rows) = coerce(&types, src, "/soc/dev/acme,big");
+        assert_eq!(json, serde_json::json!([[0x1_0000_0002_u64]]));
+        assert_eq!(rows, ["0x1 0x2"]);
+
+        // Cells that don't fit are left in their rows
+        let (json, _) = coerce(&types, src, "/dev/reg");
+        assert_eq!(json, serde_json::json!([[0x1000, 0x100], [0x2000]]));
+    }
+}
@@ -0,0 +1,670 @@
+//! Bindings in [Zephyr's YAML format][1], which are loaded alongside dt-schema ones.
+//!
+//! Unlike dt-schema, Zephyr bindings aren't JSON schemas: they list the `properties:` of the
+//! nodes with a `compatible:` with their `type:` and whether they're `required:`, and share
+//! them with `include:`. Nodes without a compatible of their own get the `child-binding:` of their
+//! parent's binding.
+//!
+//! [1]: https://docs.zephyrproject.org/latest/build/dts/bindings-syntax.html
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use dt_analyzer::{
+    new::stage2::{Stage2Node, Stage2Property, Stage2Tree},
+    resolved_prop::{Cell, Value},
+};
+use rustc_hash::FxHashMap;
+use serde_json::Value as JValue;
+use serde_yaml::{Mapping, Value as YValue};
+
+use crate::{bindings::escape, Binding, BindingError, BindingLoadError};
+
+/// How many `include:`s are followed in a row, so include cycles terminate
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A binding in Zephyr's format, after its `include:`s have been merged into it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZephyrBinding {
+    /// The path of the YAML file
+    pub path: PathBuf,
+    /// The compatible of the nodes the binding applies to, which child bindings usually don't have
+    pub compatible: Option<String>,
+    pub description: Option<String>,
+    /// The buses provided by the node to its children, from `bus:`
+    pub buses: Vec<String>,
+    /// The bus the node must be on for the binding to apply, from `on-bus:`
+    pub on_bus: Option<String>,
+    pub properties: BTreeMap<String, ZephyrProperty>,
+    /// The names of the specifier cells, e.g. `gpio` to `[pin, flags]` for `gpio-cells:`
+    pub specifier_cells: BTreeMap<String, Vec<String>>,
+    /// The binding of the children without a compatible
+    pub child_binding: Option<Box<ZephyrBinding>>,
+}
+
+/// A property in the `properties:` of a [`ZephyrBinding`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZephyrProperty {
+    pub ty: ZephyrType,
+    pub required: bool,
+    /// The allowed values, which are integers or strings
+    pub enum_values: Option<Vec<JValue>>,
+    pub const_value: Option<JValue>,
+    pub description: Option<String>,
+}
+
+/// The `type:` of a [`ZephyrProperty`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZephyrType {
+    /// `foo;`
+    Boolean,
+    /// `foo = <1>;`
+    Int,
+    /// `foo = <1 2 3>;`
+    Array,
+    /// `foo = [01 02 03];`
+    Uint8Array,
+    /// `foo = "bar";`
+    String,
+    /// `foo = "bar", "baz";`
+    StringArray,
+    /// `foo = <&bar>;`
+    Phandle,
+    /// `foo = <&bar &baz>;`
+    Phandles,
+    /// `foo = <&bar 1 2>, <&baz 3>;`
+    PhandleArray,
+    /// `foo = &bar;` or `foo = "/bar";`
+    Path,
+    /// Anything
+    Compound,
+}
+
+impl ZephyrType {
+    /// Parses the name of a type, e.g. `phandle-array`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "boolean" => Self::Boolean,
+            "int" => Self::Int,
+            "array" => Self::Array,
+            "uint8-array" => Self::Uint8Array,
+            "string" => Self::String,
+            "string-array" => Self::StringArray,
+            "phandle" => Self::Phandle,
+            "phandles" => Self::Phandles,
+            "phandle-array" => Self::PhandleArray,
+            "path" => Self::Path,
+            "compound" => Self::Compound,
+            _ => return None,
+        })
+    }
+
+    /// Returns the name of the type, as written in bindings.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Boolean => "boolean",
+            Self::Int => "int",
+            Self::Array => "array",
+            Self::Uint8Array => "uint8-array",
+            Self::String => "string",
+            Self::StringArray => "string-array",
+            Self::Phandle => "phandle",
+            Self::Phandles => "phandles",
+            Self::PhandleArray => "phandle-array",
+            Self::Path => "path",
+            Self::Compound => "compound",
+        }
+    }
+
+    /// Returns `true` if `values` have the type.
+    ///
+    /// Phandles aren't resolved, so the cell counts of `phandle-array`s aren't checked.
+    fn matches(self, values: &[Value]) -> bool {
+        let all = |f: fn(&Value) -> bool| !values.is_empty() && values.iter().all(f);
+        match self {
+            Self::Compound => true,
+            Self::Boolean => values.is_empty(),
+            Self::Int => {
+                matches!(values, [Value::CellList(cells)] if matches!(cells.as_slice(), [Cell::U32(_)]))
+            }
+            Self::Array => all(|value| matches!(value, Value::CellList(_))),
+            Self::Uint8Array => matches!(values, [Value::Bytestring(_)]),
+            Self::String => matches!(values, [Value::String(_)]),
+            Self::StringArray => all(|value| matches!(value, Value::String(_))),
+            Self::Phandle => {
+                matches!(values, [Value::CellList(cells)] if matches!(cells.as_slice(), [Cell::Phandle(_)]))
+            }
+            Self::Phandles => all(
+                |value| matches!(value, Value::CellList(cells) if cells.iter().all(|cell| matches!(cell, Cell::Phandle(_)))),
+            ),
+            Self::PhandleArray => all(
+                |value| matches!(value, Value::CellList(cells) if matches!(cells.first(), Some(Cell::Phandle(_)))),
+            ),
+            Self::Path => matches!(values, [Value::String(_) | Value::Phandle(_)]),
+        }
+    }
+}
+
+impl ZephyrBinding {
+    /// Returns `true` if the binding applies to a node on a bus of `parent_buses`.
+    fn on_bus_of(&self, parent_buses: &[String]) -> bool {
+        self.on_bus
+            .as_ref()
+            .is_none_or(|bus| parent_buses.contains(bus))
+    }
+
+    /// Checks the type, `enum:` and `const:` of a property of the node.
+    fn check_property(
+        name: &str,
+        property: &ZephyrProperty,
+        prop: &Stage2Property,
+    ) -> Vec<BindingError> {
+        let pointer = format!("/{}", escape(name));
+        if !property.ty.matches(&prop.values) {
+            return vec![BindingError {
+                pointers: vec![pointer],
+                msg: format!("\"{name}\" should have type {}", property.ty.name()),
+            }];
+        }
+        let values = scalars(&prop.values);
+        // Arrays can have a list as `const:`
+        if let Some(expected @ JValue::Array(_)) = &property.const_value {
+            if *expected != JValue::Array(values) {
+                return vec![BindingError {
+                    pointers: vec![pointer],
+                    msg: format!("{expected} was expected"),
+                }];
+            }
+            return Vec::new();
+        }
+        let mut errors = Vec::new();
+        for (i, value) in values.into_iter().enumerate() {
+            let msg = match (&property.enum_values, &property.const_value) {
+                (Some(allowed), _) if !allowed.contains(&value) => {
+                    format!("{value} is not one of {}", JValue::Array(allowed.clone()))
+                }
+                (_, Some(expected)) if *expected != value => format!("{expected} was expected"),
+                _ => continue,
+            };
+            // Integers are the cells of the first value
+            let value_pointer = match prop.values.first() {
+                Some(Value::CellList(_)) => format!("{pointer}/0/{i}"),
+                _ => format!("{pointer}/{i}"),
+            };
+            errors.push(BindingError {
+                pointers: vec![value_pointer],
+                msg,
+            });
+        }
+        errors
+    }
+}
+
+/// Returns the integers and strings in `values`, which `enum:` and `const:` constrain.
+///
+/// Only the integers of the first cell list are returned, like Zephyr only allows `<>` once in
+/// `array`s with an `enum:`.
+fn scalars(values: &[Value]) -> Vec<JValue> {
+    match values {
+        [Value::CellList(cells), ..] => cells
+            .iter()
+            .filter_map(|cell| match cell {
+                Cell::U32(n) => Some(JValue::from(*n)),
+                Cell::Phandle(_) => None,
+            })
+            .collect(),
+        values => values
+            .iter()
+            .filter_map(|value| match value {
+                Value::String(s) => Some(JValue::from(s.as_str())),
+                _ => None,
+            })
+            .collect(),
+    }
+}
+
+impl Binding for ZephyrBinding {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn origin(&self) -> Option<String> {
+        Some(format!("binding {}", self.path.display()))
+    }
+
+    fn validate(&self, node: &Stage2Node, _json: &JValue) -> Vec<BindingError> {
+        let mut errors = Vec::new();
+        for (name, property) in &self.properties {
+            match node.children.get(name) {
+                Some(Stage2Tree::Prop(prop)) => {
+                    errors.extend(Self::check_property(name, property, prop));
+                }
+                None if property.required => errors.push(BindingError {
+                    pointers: vec![String::new()],
+                    msg: format!("\"{name}\" is a required property"),
+                }),
+                Some(Stage2Tree::Node(_)) | None => {}
+            }
+        }
+        for (space, names) in &self.specifier_cells {
+            let cells_name = format!("#{space}-cells");
+            let Some(Stage2Tree::Prop(prop)) = node.children.get(&cells_name) else {
+                continue;
+            };
+            let [Value::CellList(cells)] = prop.values.as_slice() else {
+                continue;
+            };
+            if let [Cell::U32(count)] = cells.as_slice() {
+                if *count as usize != names.len() {
+                    errors.push(BindingError {
+                        pointers: vec![format!("/{cells_name}/0/0")],
+                        msg: format!(
+                            "\"{cells_name}\" should be {} for the specifier cells [{}]",
+                            names.len(),
+                            names.join(", ")
+                        ),
+                    });
+                }
+            }
+        }
+        errors
+    }
+}
+
+/// Zephyr bindings indexed by their compatibles.
+#[derive(Debug, Default)]
+pub(crate) struct ZephyrBindings {
+    bindings: Vec<ZephyrBinding>,
+    by_compatible: FxHashMap<String, Vec<usize>>,
+}
+
+impl ZephyrBindings {
+    pub fn push(&mut self, binding: ZephyrBinding) {
+        if let Some(compatible) = &binding.compatible {
+            self.by_compatible
+                .entry(compatible.clone())
+                .or_default()
+                .push(self.bindings.len());
+        }
+        self.bindings.push(binding);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, ZephyrBinding> {
+        self.bindings.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bindings.len()
+    }
+
+    /// Returns the binding of a node with `compatibles`, which is the child binding of its parent's
+    /// binding `parent` if none of them have a binding.
+    ///
+    /// Like Zephyr, the first compatible with a binding wins, and bindings with an `on-bus:` matching
+    /// a bus of the parent are preferred over those without one.
+    pub fn find<'a>(
+        &'a self,
+        compatibles: impl IntoIterator<Item = &'a str>,
+        parent: Option<&'a ZephyrBinding>,
+    ) -> Option<&'a ZephyrBinding> {
+        let parent_buses = parent.map_or(&[][..], |parent| parent.buses.as_slice());
+        for compatible in compatibles {
+            let Some(indices) = self.by_compatible.get(compatible) else {
+                continue;
+            };
+            let candidates = indices.iter().map(|&index| &self.bindings[index]);
+            let on_bus = candidates
+                .clone()
+                .find(|binding| binding.on_bus.is_some() && binding.on_bus_of(parent_buses));
+            if let Some(binding) =
+                on_bus.or_else(|| candidates.clone().find(|binding| binding.on_bus.is_none()))
+            {
+                return Some(binding);
+            }
+        }
+        parent?.child_binding.as_deref()
+    }
+}
+
+/// Parses `text` if it's a Zephyr binding rather than a dt-schema one, which always has an `$id`.
+pub(crate) fn parse_binding(text: &str) -> Option<Mapping> {
+    if crate::retriever::read_id(text).is_some() {
+        return None;
+    }
+    let map = serde_yaml::from_str::<Mapping>(text).ok()?;
+    (!map.contains_key("$schema")).then_some(map)
+}
+
+/// Loads the Zephyr bindings in `files`, merging their `include:`s.
+///
+/// Files are included by their file name, like in Zephyr, so every binding directory shares one
+/// namespace. Files without a `compatible:` are only used as includes.
+pub(crate) fn load(files: &[(PathBuf, Mapping)]) -> (Vec<ZephyrBinding>, Vec<BindingLoadError>) {
+    let by_name = files
+        .iter()
+        .filter_map(|(path, map)| Some((path.file_name()?.to_str()?, map)))
+        .collect::<FxHashMap<_, _>>();
+    let loader = Loader { by_name };
+
+    let mut bindings = Vec::new();
+    let mut errors = Vec::new();
+    for (path, map) in files {
+        if !map.contains_key("compatible") {
+            continue;
+        }
+        match loader.binding(path, map) {
+            Ok(binding) => bindings.push(binding),
+            Err(error) => errors.push(BindingLoadError {
+                path: path.clone(),
+                error,
+            }),
+        }
+    }
+    (bindings, errors)
+}
+
+struct Loader<'a> {
+    /// Every Zephyr binding by file name, for `include:`s
+    by_name: FxHashMap<&'a str, &'a Mapping>,
+}
+
+impl Loader<'_> {
+    fn binding(&self, path: &Path, map: &Mapping) -> anyhow::Result<ZephyrBinding> {
+        let map = self.merge_includes(map, 0)?;
+        let string = |key: &str| map.get(key).and_then(YValue::as_str).map(str::to_owned);
+
+        let buses = match map.get("bus") {
+            Some(YValue::String(bus)) => vec![bus.clone()],
+            Some(YValue::Sequence(buses)) => buses
+                .iter()
+                .filter_map(YValue::as_str)
+                .map(str::to_owned)
+                .collect(),
+            Some(_) => anyhow::bail!("`bus:` must be a string or a list of strings"),
+            None => Vec::new(),
+        };
+
+        let mut properties = BTreeMap::new();
+        if let Some(props) = map.get("properties") {
+            let Some(props) = props.as_mapping() else {
+                anyhow::bail!("`properties:` must be a mapping");
+            };
+            for (name, property) in props {
+                let Some(name) = name.as_str() else {
+                    anyhow::bail!("property names must be strings");
+                };
+                let property = property_from_yaml(property)
+                    .map_err(|err| err.context(format!("in property `{name}`")))?;
+                properties.insert(name.to_owned(), property);
+            }
+        }
+
+        let specifier_cells = map
+            .iter()
+            .filter_map(|(key, value)| {
+                let space = key.as_str()?.strip_suffix("-cells")?;
+                let names = value.as_sequence()?.iter().filter_map(YValue::as_str);
+                Some((space.to_owned(), names.map(str::to_owned).collect()))
+            })
+            .collect();
+
+        let child_binding = match map.get("child-binding") {
+            Some(YValue::Mapping(child)) => Some(Box::new(
+                self.binding(path, child)
+                    .map_err(|err| err.context("in `child-binding:`"))?,
+            )),
+            Some(_) => anyhow::bail!("`child-binding:` must be a mapping"),
+            None => None,
+        };
+
+        Ok(ZephyrBinding {
+            path: path.to_owned(),
+            compatible: string("compatible"),
+            description: string("description"),
+            buses,
+            on_bus: string("on-bus"),
+            properties,
+            specifier_cells,
+            child_binding,
+        })
+    }
+
+    /// Returns `map` with its `include:`s merged into it, recursively.
+    fn merge_includes(&self, map: &Mapping, depth: usize) -> anyhow::Result<Mapping> {
+        let mut merged = map.clone();
+        let Some(entries) = merged.remove("include") else {
+            return Ok(merged);
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            anyhow::bail!("`include:`s are nested too deeply, they probably form a cycle");
+        }
+        let entries = match entries {
+            YValue::Sequence(entries) => entries,
+            include => vec![include],
+        };
+        for include in &entries {
+            let (name, filter) = match include {
+                YValue::String(name) => (name.as_str(), None),
+                YValue::Mapping(include) => {
+                    let Some(name) = include.get("name").and_then(YValue::as_str) else {
+                        anyhow::bail!("`include:` entries must have a `name:`");
+                    };
+                    (name, Some(include))
+                }
+                _ => anyhow::bail!("`include:` must be file names or mappings with a `name:`"),
+            };
+            let Some(included) = self.by_name.get(name) else {
+                anyhow::bail!("included binding `{name}` wasn't found");
+            };
+            let mut included = self
+                .merge_includes(included, depth + 1)
+                .map_err(|err| err.context(format!("in `{name}`")))?;
+            if let Some(filter) = filter {
+                filter_properties(&mut included, filter);
+            }
+            merge(&mut merged, &included);
+        }
+        Ok(merged)
+    }
+}
+
+/// Applies the `property-allowlist:`, `property-blocklist:` and `child-binding:` of an
+/// `include:` entry to the included binding `map`.
+fn filter_properties(map: &mut Mapping, filter: &Mapping) {
+    let names = |key: &str| {
+        filter
+            .get(key)
+            .and_then(YValue::as_sequence)
+            .map(|names| names.iter().filter_map(YValue::as_str).collect::<Vec<_>>())
+    };
+    let allowlist = names("property-allowlist");
+    let blocklist = names("property-blocklist").unwrap_or_default();
+    if let Some(YValue::Mapping(properties)) = map.get_mut("properties") {
+        properties.retain(|name, _| {
+            let name = name.as_str().unwrap_or_default();
+            allowlist.as_ref().is_none_or(|list| list.contains(&name)) && !blocklist.contains(&name)
+        });
+    }
+    if let (Some(YValue::Mapping(child)), Some(YValue::Mapping(child_filter))) =
+        (map.get_mut("child-binding"), filter.get("child-binding"))
+    {
+        filter_properties(child, child_filter);
+    }
+}
+
+/// Merges an included binding into `to`.
+///
+/// Like in Zephyr, the values of `to` win, except that included properties stay `required:`.
+fn merge(to: &mut Mapping, from: &Mapping) {
+    for (key, value) in from {
+        match (to.get_mut(key), value) {
+            (None, value) => {
+                to.insert(key.clone(), value.clone());
+            }
+            (Some(YValue::Mapping(to)), YValue::Mapping(from)) => merge(to, from),
+            (Some(required @ YValue::Bool(_)), YValue::Bool(true)) if key == "required" => {
+                *required = YValue::Bool(true);
+            }
+            (Some(_), _) => {}
+        }
+    }
+}
+
+fn property_from_yaml(yaml: &YValue) -> anyhow::Result<ZephyrProperty> {
+    let Some(map) = yaml.as_mapping() else {
+        anyhow::bail!("properties must be mappings");
+    };
+    let Some(ty) = map.get("type").and_then(YValue::as_str) else {
+        anyhow::bail!("missing `type:`");
+    };
+    let Some(ty) = ZephyrType::from_name(ty) else {
+        anyhow::bail!("unknown type `{ty}`");
+    };
+    let enum_values = match map.get("enum") {
+        Some(YValue::Sequence(values)) => Some(
+            values
+                .iter()
+                .map(serde_json::to_value)
+                .collect::<Result<_, _>>()?,
+        ),
+        Some(_) => anyhow::bail!("`enum:` must be a list"),
+        None => None,
+    };
+    Ok(ZephyrProperty {
+        ty,
+        required: map
+            .get("required")
+            .and_then(YValue::as_bool)
+            .unwrap_or_default(),
+        enum_values,
+        const_value: map.get("const").map(serde_json::to_value).transpose()?,
+        description: map
+            .get("description")
+            .and_then(YValue::as_str)
+            .map(str::to_owned),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(files: &[(&str, &str)]) -> Vec<(PathBuf, Mapping)> {
+        files
+            .iter()
+            .map(|(name, yaml)| (PathBuf::from(name), serde_yaml::from_str(yaml).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn includes() {
+        let files = files(&[
+            (
+                "base.yaml",
+                r"
+properties:
+  reg:
+    type: array
+  status:
+    type: string
+    enum: [okay, disabled]
+  label:
+    type: string
+",
+            ),
+            (
+                "gpio-controller.yaml",
+                r"
+include: base.yaml
+properties:
+  '#gpio-cells':
+    type: int
+    required: true
+child-binding:
+  properties:
+    gpio-hog:
+      type: boolean
+    line-name:
+      type: string
+",
+            ),
+            (
+                "acme,gpio.yaml",
+                r"
+compatible: acme,gpio
+include:
+  - name: gpio-controller.yaml
+    property-blocklist: [label]
+    child-binding:
+      property-allowlist: [gpio-hog]
+properties:
+  reg:
+    required: true
+  '#gpio-cells':
+    required: false
+gpio-cells:
+  - pin
+  - flags
+",
+            ),
+            (
+                "acme,cycle.yaml",
+                "compatible: acme,cycle\ninclude: acme,cycle.yaml\n",
+            ),
+        ]);
+        let (bindings, errors) = load(&files);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].path.ends_with("acme,cycle.yaml"));
+
+        let [binding] = bindings.as_slice() else {
+            panic!("{bindings:?}");
+        };
+        assert_eq!(binding.compatible.as_deref(), Some("acme,gpio"));
+        assert_eq!(
+            binding.properties.keys().collect::<Vec<_>>(),
+            ["#gpio-cells", "reg", "status"]
+        );
+        assert!(binding.properties["reg"].required);
+        // Included properties stay required
+        assert!(binding.properties["#gpio-cells"].required);
+        assert_eq!(binding.properties["status"].ty, ZephyrType::String);
+        assert_eq!(
+            binding.specifier_cells["gpio"],
+            ["pin".to_owned(), "flags".to_owned()]
+        );
+        let child = binding.child_binding.as_ref().unwrap();
+        assert_eq!(child.properties.keys().collect::<Vec<_>>(), ["gpio-hog"]);
+    }
+
+    #[test]
+    fn invalid() {
+        let files = files(&[
+            ("acme,a.yaml", "compatible: acme,a\ninclude: missing.yaml\n"),
+            (
+                "acme,b.yaml",
+                "compatible: acme,b\nproperties:\n  foo:\n    type: float\n",
+            ),
+            (
+                "acme,c.yaml",
+                "compatible: acme,c\nproperties:\n  foo:\n    required: true\n",
+            ),
+        ]);
+        let (bindings, errors) = load(&files);
+        assert!(bindings.is_empty());
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| format!("{:#}", error.error))
+                .collect::<Vec<_>>(),
+            [
+                "included binding `missing.yaml` wasn't found",
+                "in property `foo`: unknown type `float`",
+                "in property `foo`: missing `type:`",
+            ]
+        );
+    }
+}
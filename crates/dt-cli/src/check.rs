@@ -0,0 +1,171 @@
+//! The `dt check` and `dt check-bindings` commands.
+
+use std::{collections::HashMap, error::Error, path::Path};
+
+use codespan_reporting::{
+    diagnostic::{Diagnostic, Label, Severity},
+    files::SimpleFiles,
+    term::{
+        self,
+        termcolor::{ColorChoice, StandardStream},
+    },
+};
+use dt_analyzer::new::workspace::IncludedFiles;
+use dt_binding_matcher::{check_binding, check_examples, Bindings, SchemaIndex};
+use dt_parser::SourceId;
+use dt_workspace::Workspace;
+
+/// Loads the binding schemas configured in `workspace`, printing the schemas which fail to load.
+pub(crate) fn load_bindings(workspace: &Workspace) -> Bindings {
+    let (bindings, errors) = Bindings::load_workspace(workspace);
+    for error in &errors {
+        tracing::info!("failed to load binding schema {error}");
+    }
+    if !errors.is_empty() {
+        eprintln!(
+            "warning: {} binding schemas failed to load, use --verbose to list them",
+            errors.len()
+        );
+    }
+    bindings
+}
+
+/// Validates `files` against `bindings` and prints the errors.
+///
+/// Each file is merged with the files it includes, which are found in `include_dirs`, and the
+/// whole tree is validated like `make dtbs_check` does. Returns `true` if any errors were found.
+pub(crate) fn check_files(
+    bindings: &Bindings,
+    include_dirs: &[impl AsRef<Path>],
+    paths: &[impl AsRef<Path>],
+) -> Result<bool, Box<dyn Error>> {
+    let writer = StandardStream::stderr(ColorChoice::Auto);
+    let config = term::Config::default();
+    let mut files = SimpleFiles::new();
+    // Included files are added when they have errors
+    let mut file_ids = HashMap::<SourceId, usize>::new();
+    let mut has_errors = false;
+
+    for path in paths {
+        let included = IncludedFiles::load(path.as_ref(), include_dirs)?;
+        let main_file = included.main_file();
+        file_ids.insert(
+            main_file.source_id.clone(),
+            files.add(main_file.source_id.to_string(), main_file.text.clone()),
+        );
+
+        let diag = std::sync::Mutex::new(Vec::new());
+        let stage2 = dt_analyzer::new::stage2::compute(
+            &main_file.source_id,
+            &main_file.outline,
+            &included.resolved_includes(),
+            &diag,
+        );
+
+        for (source_id, diagnostic) in bindings.validate(&stage2) {
+            let file_id = if let Some(file_id) = file_ids.get(&source_id) {
+                *file_id
+            } else {
+                let Some(file) = included.get(&source_id) else {
+                    continue;
+                };
+                let file_id = files.add(source_id.to_string(), file.text.clone());
+                file_ids.insert(source_id, file_id);
+                file_id
+            };
+            let severity = match diagnostic.severity {
+                dt_diagnostic::Severity::Warn => Severity::Warning,
+                dt_diagnostic::Severity::Error => Severity::Error,
+            };
+            has_errors |= severity >= Severity::Error;
+
+            // The first line is the error and the rest are notes, like the schema ID
+            let mut lines = diagnostic.msg.lines();
+            let diagnostic = Diagnostic::new(severity)
+                .with_message(lines.next().unwrap_or_default())
+                .with_code("binding")
+                .with_labels(
+                    diagnostic
+                        .span
+                        .primary_spans
+                        .iter()
+                        .map(|span| Label::primary(file_id, *span))
+                        .chain(diagnostic.span.span_labels.iter().map(|span_label| {
+                            Label::secondary(file_id, span_label.span)
+                                .with_message(span_label.msg.as_ref())
+                        }))
+                        .collect(),
+                )
+                .with_notes(lines.map(str::to_owned).collect());
+            term::emit(&mut writer.lock(), &config, &files, &diagnostic)?;
+        }
+    }
+
+    Ok(has_errors)
+}
+
+/// Checks the binding schemas in `paths` and prints the errors.
+///
+/// `$ref`s are resolved with the schemas configured in `workspace`. With `examples`, the examples
+/// of valid schemas are checked too. Returns `true` if any errors were found.
+pub(crate) fn check_bindings(
+    workspace: &Workspace,
+    paths: &[impl AsRef<Path>],
+    examples: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let (index, errors) = SchemaIndex::build(
+        &workspace
+            .config
+            .bindings_dirs()
+            .iter()
+            .map(AsRef::as_ref)
+            .chain(workspace.config.dt_schema_dir())
+            .collect::<Vec<_>>(),
+    );
+    for error in &errors {
+        tracing::info!("failed to index schema {error}");
+    }
+
+    let writer = StandardStream::stderr(ColorChoice::Auto);
+    let config = term::Config::default();
+    let mut files = SimpleFiles::new();
+    let mut has_errors = false;
+
+    for path in paths {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let file_id = files.add(path.display().to_string(), text.clone());
+
+        let mut errors = check_binding(path, &text, &index);
+        if examples && errors.is_empty() {
+            errors = check_examples(path, &text, &index, workspace.config.include_paths());
+        }
+        for error in errors {
+            has_errors = true;
+            let start = line_column_offset(&text, error.line, error.column);
+            let end = text[start..]
+                .find('\n')
+                .map_or(text.len(), |len| start + len);
+            let diagnostic = Diagnostic::error()
+                .with_message(error.message)
+                .with_code("schema")
+                .with_labels(vec![Label::primary(file_id, start..end)]);
+            term::emit(&mut writer.lock(), &config, &files, &diagnostic)?;
+        }
+    }
+
+    Ok(has_errors)
+}
+
+/// Converts a 1-based line and column in characters to a byte offset in `text`.
+fn line_column_offset(text: &str, line: usize, column: usize) -> usize {
+    let line_start = text
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum::<usize>();
+    text[line_start..]
+        .char_indices()
+        .nth(column.saturating_sub(1))
+        .map_or(text.len(), |(offset, _)| line_start + offset)
+}
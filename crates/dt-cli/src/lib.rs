@@ -0,0 +1,237 @@
+//! The `dt` command line interface.
+//!
+//! This is a library so that the CLI can be built with custom lints. See [`run`].
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use clap::{
+    builder::{
+        styling::{AnsiColor, Style},
+        Styles,
+    },
+    Parser, Subcommand,
+};
+use dt_binding_matcher::{CompatibleIndex, UndocumentedCompatibles};
+use dt_lint::{rules::RuleFile, Applicability, LintRegistry};
+use tracing_subscriber::filter::LevelFilter;
+
+use dt_workspace::{
+    config::{
+        cli_config::CliConfig, env_config::EnvConfig, toml_config::TomlConfig, CombinedConfig,
+    },
+    Workspace, WorkspacePathFindResult,
+};
+
+mod check;
+mod lint;
+
+fn styles() -> Styles {
+    Styles::styled()
+        .header(Style::new().bold())
+        .usage(Style::new().bold())
+        .literal(AnsiColor::Blue.on_default().bold())
+        .placeholder(AnsiColor::White.on_default().dimmed())
+}
+
+// TODO: https://github.com/oven-sh/bun/blob/df49a5a8e4644ce833bd91db0ae14a279bb97bce/src/cli.zig#L963-L989
+const HELP_TEMPLATE: &str = "\
+{before-help}{about-with-newline}
+{usage-heading} {usage}
+
+{all-args}{after-help}\
+    ";
+
+/// dt is a fast Devicetree parser, linter and compiler all in one.
+#[derive(Parser, Debug)]
+#[command(version, author, about, long_about = None, styles = styles(), help_template = HELP_TEMPLATE)]
+struct Cli {
+    /// If provided, displays info log messages
+    ///
+    /// This can be overridden by the `RUST_LOG` environment variable
+    #[arg(short, long)]
+    verbose: bool,
+    #[command(subcommand)]
+    command: Command,
+    #[clap(flatten)]
+    config: CliConfig,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Parse DTS files for debugging dt-tools
+    Parse {
+        /// The path for the DTS file to format
+        #[arg(short, long)]
+        file: PathBuf,
+
+        /// An optional path for the new DTS
+        ///
+        /// By default, `file` gets overwritten
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Lint DTS and DTB files
+    Lint {
+        /// The DTS files to lint
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Apply all machine-applicable fixes in place
+        #[arg(long)]
+        fix: bool,
+
+        /// Also apply fixes which may change the meaning of the source, e.g. renaming nodes
+        #[arg(long, requires = "fix")]
+        unsafe_fixes: bool,
+    },
+    /// Validate DTS files against the binding schemas in the configured `bindings-dirs`
+    Check {
+        /// The DTS files to validate
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Check binding schemas against dt-schema's meta-schemas, like `make dt_binding_check`
+    CheckBindings {
+        /// Also validate each binding's `examples:` against the binding, preprocessing them with
+        /// the include paths
+        #[arg(long)]
+        examples: bool,
+        /// The YAML files to check
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Print a node with the required properties of the binding schema documenting a compatible
+    NewNode {
+        /// The compatible of the new node, e.g. `ns16550a`
+        compatible: String,
+    },
+    /// Show the documentation of a lint
+    Explain {
+        /// The name of the lint, e.g. `kernel_coding_style`
+        lint: String,
+    },
+}
+
+/// Parses the command line arguments and runs the CLI with the lints from `registry`.
+///
+/// # Example
+///
+/// A `main.rs` with custom lints:
+///
+/// ```no_run
+/// let mut registry = dt_lint::LintRegistry::builtin();
+/// // registry.register_lints(...);
+/// // registry.register_early_pass(...);
+/// dt_cli::run(registry).unwrap();
+/// ```
+///
+/// Rules from the workspace's `rule-files` are added to `registry`, along with
+/// [`UndocumentedCompatibles`] for the bindings in `bindings-dirs`.
+///
+/// # Errors
+///
+/// Returns an error when the workspace configuration or rule files can't be loaded or when files
+/// can't be read or written.
+pub fn run(mut registry: LintRegistry) -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(if cli.verbose {
+            LevelFilter::INFO
+        } else {
+            LevelFilter::WARN
+        })
+        .init();
+    let cwd = Path::new(".").canonicalize()?;
+    let (toml_config, workspace_dir) = match Workspace::find_workspace_dir(&cwd) {
+        WorkspacePathFindResult::TomlConfig {
+            toml_file_path,
+            workspace_dir,
+        } => (Some(TomlConfig::load(&toml_file_path)), workspace_dir),
+        WorkspacePathFindResult::ZephyrMarker { workspace_dir } => {
+            (Some(Ok(TomlConfig::zephyr(workspace_dir))), workspace_dir)
+        }
+        WorkspacePathFindResult::LinuxMarker { workspace_dir }
+        | WorkspacePathFindResult::Fallback { workspace_dir } => (None, workspace_dir),
+    };
+
+    let workspace = Workspace {
+        config: CombinedConfig::merge(
+            Some(cli.config),
+            Some(EnvConfig::from_env()?),
+            toml_config.transpose()?,
+        ),
+        path: workspace_dir.to_path_buf(),
+    };
+
+    for rule_file in workspace.config.rule_files() {
+        let rules = RuleFile::load(rule_file)
+            .map_err(|err| format!("{}: {err}", rule_file.display()))?
+            .rules;
+        registry
+            .register_rules(rules)
+            .map_err(|err| format!("{}: {err}", rule_file.display()))?;
+    }
+    // Bindings are only loaded for linting, but the lint is always registered for `dt explain`
+    let compatibles = match cli.command {
+        Command::Lint { .. } if !workspace.config.bindings_dirs().is_empty() => {
+            check::load_bindings(&workspace).compatibles().clone()
+        }
+        _ => Arc::new(CompatibleIndex::new()),
+    };
+    UndocumentedCompatibles::register(&mut registry, move || compatibles.clone());
+
+    match cli.command {
+        Command::Parse { file: _, output: _ } => {}
+        Command::Lint {
+            files,
+            fix,
+            unsafe_fixes,
+        } => {
+            let fix_applicability = match (fix, unsafe_fixes) {
+                (false, _) => None,
+                (true, false) => Some(Applicability::MachineApplicable),
+                (true, true) => Some(Applicability::MaybeIncorrect),
+            };
+            if lint::lint_files(
+                &registry,
+                workspace.config.include_paths(),
+                &files,
+                fix_applicability,
+            )? {
+                std::process::exit(1);
+            }
+        }
+        Command::Check { files } => {
+            let bindings = check::load_bindings(&workspace);
+            if check::check_files(&bindings, workspace.config.include_paths(), &files)? {
+                std::process::exit(1);
+            }
+        }
+        Command::CheckBindings { examples, files } => {
+            if check::check_bindings(&workspace, &files, examples)? {
+                std::process::exit(1);
+            }
+        }
+        Command::NewNode { compatible } => {
+            let bindings = check::load_bindings(&workspace);
+            let Some(skeleton) = bindings.node_skeleton(&compatible) else {
+                eprintln!("error: no binding schema documents `{compatible}`");
+                std::process::exit(1);
+            };
+            print!("{}", skeleton.to_dts());
+        }
+        Command::Explain { lint } => {
+            let Some(lint) = registry.find_lint(&lint) else {
+                eprintln!("error: {}", dt_lint::UnknownLintError(lint));
+                std::process::exit(1);
+            };
+            print!("{}", lint.explanation());
+        }
+    }
+
+    Ok(())
+}
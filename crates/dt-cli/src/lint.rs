@@ -0,0 +1,242 @@
+//! The `dt lint` command.
+
+use std::{error::Error, path::Path};
+
+use codespan_reporting::{
+    diagnostic::{Diagnostic, Label, Severity},
+    files::SimpleFiles,
+    term::{
+        self,
+        termcolor::{ColorChoice, StandardStream},
+    },
+};
+use dt_analyzer::new::workspace::{IncludedFiles, LoadedFile};
+use dt_binding_matcher::UNDOCUMENTED_COMPATIBLES;
+use dt_lint::{Applicability, EmittedLint, LintId, LintRegistry, LintSeverity};
+use dt_parser::ast::SourceFile;
+
+/// How many times the fixes are applied at most. Fixes can uncover other fixable lints.
+const MAX_FIX_PASSES: usize = 10;
+
+/// The result of [`fix_source`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Fixed {
+    pub text: String,
+    /// The number of applied suggestions
+    pub applied: usize,
+}
+
+/// Lints `file`, parsed from `text`, as the file at `path` merged with the files it includes.
+fn lint_file(
+    registry: &LintRegistry,
+    path: &Path,
+    include_dirs: &[impl AsRef<Path>],
+    file: &SourceFile,
+    text: &str,
+) -> Vec<EmittedLint> {
+    let is_main_file = path.extension().is_some_and(|ext| ext == "dts");
+    let included =
+        IncludedFiles::with_main_file(LoadedFile::new(path, text.to_owned()), include_dirs);
+    registry.lint_file_with_includes(
+        file,
+        text,
+        is_main_file,
+        &included.main_file().source_id,
+        &included.resolved_includes(),
+    )
+}
+
+/// Applies all suggestions with at least `applicability` until no more suggestions can be applied.
+///
+/// `src` is the text of the file at `path`. Fixes which would introduce new syntax errors are
+/// discarded.
+pub(crate) fn fix_source(
+    registry: &LintRegistry,
+    path: &Path,
+    include_dirs: &[impl AsRef<Path>],
+    src: &str,
+    applicability: Applicability,
+) -> Fixed {
+    let mut text = src.to_owned();
+    let mut applied = 0;
+
+    for _ in 0..MAX_FIX_PASSES {
+        let parse = SourceFile::parse(&text);
+        let error_count = parse.errors.len() + parse.lex_errors.len();
+        let lints = lint_file(registry, path, include_dirs, &parse.source_file(), &text);
+        let suggestions = lints
+            .iter()
+            .flat_map(|lint| &lint.suggestions)
+            .filter(|suggestion| suggestion.applicability <= applicability)
+            .collect::<Vec<_>>();
+        if suggestions.is_empty() {
+            break;
+        }
+
+        let (new_text, skipped) = dt_diagnostic::apply_suggestions(&text, suggestions.clone());
+        if new_text == text {
+            break;
+        }
+
+        let new_parse = SourceFile::parse(&new_text);
+        if new_parse.errors.len() + new_parse.lex_errors.len() > error_count {
+            eprintln!("warning: discarding fixes which would introduce syntax errors");
+            break;
+        }
+
+        applied += suggestions.len() - skipped.len();
+        text = new_text;
+    }
+
+    Fixed { text, applied }
+}
+
+/// Lints `files`, prints the diagnostics and optionally applies fixes.
+///
+/// Late lints see each file merged with the files it includes, which are found in
+/// `include_dirs`. Returns `true` if any errors were found.
+pub(crate) fn lint_files(
+    registry: &LintRegistry,
+    include_dirs: &[impl AsRef<Path>],
+    paths: &[impl AsRef<Path>],
+    fix_applicability: Option<Applicability>,
+) -> Result<bool, Box<dyn Error>> {
+    let writer = StandardStream::stderr(ColorChoice::Auto);
+    let config = term::Config::default();
+    let mut files = SimpleFiles::new();
+    let mut has_errors = false;
+
+    for path in paths {
+        let path = path.as_ref();
+        let mut text = std::fs::read_to_string(path)?;
+
+        if let Some(applicability) = fix_applicability {
+            let fixed = fix_source(registry, path, include_dirs, &text, applicability);
+            if fixed.applied > 0 {
+                std::fs::write(path, &fixed.text)?;
+                eprintln!("Applied {} fixes to {}", fixed.applied, path.display());
+                text = fixed.text;
+            }
+        }
+
+        let parse = SourceFile::parse(&text);
+        let lints = lint_file(registry, path, include_dirs, &parse.source_file(), &text);
+        let file_id = files.add(path.display().to_string(), text.clone());
+
+        let mut diagnostics = Vec::new();
+        for lex_error in &parse.lex_errors {
+            diagnostics.push(
+                Diagnostic::error()
+                    .with_message(lex_error.inner.to_string())
+                    .with_code("lex-error")
+                    .with_labels(vec![Label::primary(file_id, lex_error.text_range)]),
+            );
+        }
+        for error in &parse.errors {
+            diagnostics.push(
+                Diagnostic::error()
+                    .with_message(error.message.as_ref())
+                    .with_code("syntax-error")
+                    .with_labels(
+                        std::iter::once(Label::primary(file_id, error.primary_span))
+                            .chain(error.span_labels.iter().map(|span_label| {
+                                Label::secondary(file_id, span_label.span)
+                                    .with_message(span_label.msg.as_ref())
+                            }))
+                            .collect(),
+                    ),
+            );
+        }
+        for lint in &lints {
+            diagnostics.push(
+                Diagnostic::new(match lint.severity {
+                    LintSeverity::Warn => Severity::Warning,
+                    LintSeverity::Error => Severity::Error,
+                })
+                .with_message(lint.msg.as_ref())
+                .with_code(lint.id.to_string())
+                .with_labels(
+                    lint.span
+                        .primary_spans
+                        .iter()
+                        .map(|span| Label::primary(file_id, *span))
+                        .chain(lint.span.span_labels.iter().map(|(span, msg)| {
+                            Label::secondary(file_id, *span).with_message(msg.as_ref())
+                        }))
+                        .collect(),
+                )
+                .with_notes(
+                    lint.suggestions
+                        .iter()
+                        .map(|suggestion| format!("help: {}", suggestion.msg))
+                        .collect(),
+                ),
+            );
+        }
+
+        for diagnostic in diagnostics {
+            has_errors |= diagnostic.severity >= Severity::Error;
+            term::emit(&mut writer.lock(), &config, &files, &diagnostic)?;
+        }
+
+        let undocumented = undocumented_compatibles(&lints);
+        if !undocumented.is_empty() {
+            eprintln!(
+                "{}: undocumented compatibles: {}",
+                path.display(),
+                undocumented.join(", ")
+            );
+        }
+    }
+
+    Ok(has_errors)
+}
+
+/// Returns the compatibles linted by [`UNDOCUMENTED_COMPATIBLES`] in `lints`, without duplicates.
+fn undocumented_compatibles(lints: &[EmittedLint]) -> Vec<&str> {
+    let mut compatibles = Vec::new();
+    for lint in lints {
+        if lint.id != LintId::of(&UNDOCUMENTED_COMPATIBLES) {
+            continue;
+        }
+        if let Some(compatible) = lint.data.as_deref() {
+            if !compatibles.contains(&compatible) {
+                compatibles.push(compatible);
+            }
+        }
+    }
+    compatibles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fix_until_fixed_point() {
+        let fixed = fix_source(
+            &LintRegistry::builtin(),
+            Path::new("board.dts"),
+            &[] as &[&Path],
+            "/ {\n\tnode@0x1A { reg = <0x1A>; };\n};\n",
+            Applicability::MaybeIncorrect,
+        );
+        assert_eq!(
+            fixed.text,
+            "/dts-v1/;\n\n/ {\n\tnode@1a { reg = <0x1a>; };\n};\n"
+        );
+        assert_eq!(fixed.applied, 3);
+    }
+
+    #[test]
+    fn only_machine_applicable() {
+        let fixed = fix_source(
+            &LintRegistry::builtin(),
+            Path::new("board.dts"),
+            &[] as &[&Path],
+            "/dts-v1/;\n/ { node@0x1A {}; };\n",
+            Applicability::MachineApplicable,
+        );
+        assert_eq!(fixed.applied, 0);
+    }
+}
@@ -2,8 +2,11 @@ use std::borrow::Cow;
 
 use text_range::TextRange;
 
+mod suggestion;
 pub mod text_range;
 
+pub use suggestion::{apply_suggestions, Applicability, Suggestion, TextEdit};
+
 // TODO: https://doc.rust-lang.org/nightly/nightly-rustc/rustc_error_messages/enum.DiagMessage.html
 pub type DiagnosticMessage = Cow<'static, str>;
 
@@ -36,6 +39,8 @@ pub struct Diagnostic {
     pub span: MultiSpan,
     pub msg: DiagnosticMessage,
     pub severity: Severity,
+    /// Fixes for this diagnostic
+    pub suggestions: Vec<Suggestion>,
 }
 impl Diagnostic {
     #[must_use]
@@ -44,8 +49,16 @@ impl Diagnostic {
             span: MultiSpan::from(primary_span),
             msg,
             severity,
+            suggestions: Vec::new(),
         }
     }
+
+    /// Attaches a fix to this diagnostic.
+    #[must_use]
+    pub fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        self.suggestions.push(suggestion);
+        self
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
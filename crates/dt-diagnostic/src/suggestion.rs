@@ -0,0 +1,207 @@
+//! Machine-applicable fixes attached to diagnostics.
+
+use std::borrow::Cow;
+
+use crate::{text_range::TextRange, DiagnosticMessage};
+
+/// Indicates the confidence in the correctness of a [`Suggestion`].
+///
+/// Just like [`Applicability` from rustc][1].
+///
+/// [1]: https://doc.rust-lang.org/nightly/nightly-rustc/rustc_lint_defs/enum.Applicability.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Applicability {
+    /// The suggestion is definitely what the user intended, or maintains the exact meaning of the
+    /// code. This suggestion should be automatically applied.
+    MachineApplicable,
+    /// The suggestion may be what the user intended, but it is uncertain. The suggestion should
+    /// result in valid Devicetree source if it is applied.
+    MaybeIncorrect,
+    /// The suggestion contains placeholders like `<...>` or `"TODO"` and thus cannot be applied
+    /// automatically.
+    HasPlaceholders,
+    /// The applicability of the suggestion is unknown.
+    Unspecified,
+}
+
+/// A single replacement of source text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TextEdit {
+    /// The range to replace. Empty ranges are insertions.
+    pub range: TextRange,
+    /// The text to replace the range with. Empty text is a deletion.
+    pub replacement: Cow<'static, str>,
+}
+
+impl TextEdit {
+    #[must_use]
+    pub fn new(range: TextRange, replacement: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            range,
+            replacement: replacement.into(),
+        }
+    }
+
+    /// Creates an edit inserting `text` at `offset`.
+    #[must_use]
+    pub fn insert(offset: usize, text: impl Into<Cow<'static, str>>) -> Self {
+        Self::new(TextRange::new(offset, offset), text)
+    }
+
+    /// Creates an edit deleting the text in `range`.
+    #[must_use]
+    pub fn delete(range: TextRange) -> Self {
+        Self::new(range, Cow::Borrowed(""))
+    }
+
+    /// Returns true if the ranges of `self` and `other` overlap.
+    ///
+    /// Two insertions at the same offset also overlap, because their order would be ambiguous.
+    #[must_use]
+    pub fn overlaps(&self, other: &TextEdit) -> bool {
+        if self.range.start == other.range.start {
+            return true;
+        }
+        self.range.start < other.range.end && other.range.start < self.range.end
+    }
+}
+
+/// A fix for a diagnostic, composed of one or more [`TextEdit`]s which must be applied together.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Suggestion {
+    /// A short message describing the fix, e.g. "use lowercase hex".
+    pub msg: DiagnosticMessage,
+    /// The edits. These must not overlap each other.
+    pub edits: Vec<TextEdit>,
+    pub applicability: Applicability,
+}
+
+impl Suggestion {
+    #[must_use]
+    pub fn new(
+        msg: impl Into<DiagnosticMessage>,
+        edits: Vec<TextEdit>,
+        applicability: Applicability,
+    ) -> Self {
+        Self {
+            msg: msg.into(),
+            edits,
+            applicability,
+        }
+    }
+
+    /// Returns true if this suggestion is [`Applicability::MachineApplicable`].
+    #[must_use]
+    pub fn is_machine_applicable(&self) -> bool {
+        self.applicability == Applicability::MachineApplicable
+    }
+}
+
+/// Applies `suggestions` to `src` and returns the new source text.
+///
+/// Suggestions are applied atomically in the given order: a suggestion is skipped when any of its
+/// edits overlaps an edit of a previously accepted suggestion or is out of bounds. The skipped
+/// suggestions are returned so that the caller can try again on the new source text.
+///
+/// # Example
+///
+/// ```
+/// use dt_diagnostic::{text_range::TextRange, Applicability, Suggestion, TextEdit, apply_suggestions};
+///
+/// let src = "reg = <0xABCD>;";
+/// let suggestion = Suggestion::new(
+///     "use lowercase hex",
+///     vec![TextEdit::new(TextRange::new(7, 13), "0xabcd")],
+///     Applicability::MachineApplicable,
+/// );
+///
+/// let (fixed, skipped) = apply_suggestions(src, [&suggestion]);
+/// assert_eq!(fixed, "reg = <0xabcd>;");
+/// assert!(skipped.is_empty());
+/// ```
+#[must_use]
+pub fn apply_suggestions<'s>(
+    src: &str,
+    suggestions: impl IntoIterator<Item = &'s Suggestion>,
+) -> (String, Vec<&'s Suggestion>) {
+    let mut accepted: Vec<&TextEdit> = Vec::new();
+    let mut skipped = Vec::new();
+
+    for suggestion in suggestions {
+        let valid = suggestion.edits.iter().enumerate().all(|(i, edit)| {
+            edit.range.start <= edit.range.end
+                && src.get(edit.range.byte_range()).is_some()
+                && !suggestion.edits[..i]
+                    .iter()
+                    .any(|other| other.overlaps(edit))
+                && !accepted.iter().any(|other| other.overlaps(edit))
+        });
+        if valid {
+            accepted.extend(&suggestion.edits);
+        } else {
+            skipped.push(suggestion);
+        }
+    }
+
+    accepted.sort_unstable_by_key(|edit| edit.range.start);
+
+    let mut out = String::with_capacity(src.len());
+    let mut last_end = 0;
+    for edit in accepted {
+        out.push_str(&src[last_end..edit.range.start]);
+        out.push_str(&edit.replacement);
+        last_end = edit.range.end;
+    }
+    out.push_str(&src[last_end..]);
+
+    (out, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suggestion(edits: Vec<TextEdit>) -> Suggestion {
+        Suggestion::new("test", edits, Applicability::MachineApplicable)
+    }
+
+    #[test]
+    fn apply_multiple() {
+        let src = "aaa bbb ccc";
+        let a = suggestion(vec![TextEdit::new(TextRange::new(0, 3), "x")]);
+        let c = suggestion(vec![
+            TextEdit::delete(TextRange::new(7, 11)),
+            TextEdit::insert(4, "y "),
+        ]);
+
+        let (out, skipped) = apply_suggestions(src, [&a, &c]);
+        assert_eq!(out, "x y bbb");
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn skip_overlapping() {
+        let src = "aaa bbb ccc";
+        let first = suggestion(vec![TextEdit::new(TextRange::new(0, 5), "x")]);
+        let second = suggestion(vec![
+            TextEdit::new(TextRange::new(8, 11), "z"),
+            TextEdit::new(TextRange::new(4, 7), "y"),
+        ]);
+        let insert_at_same_offset = suggestion(vec![TextEdit::insert(0, "w")]);
+
+        let (out, skipped) = apply_suggestions(src, [&first, &second, &insert_at_same_offset]);
+        // `second` must be skipped as a whole
+        assert_eq!(out, "xbb ccc");
+        assert_eq!(skipped, vec![&second, &insert_at_same_offset]);
+    }
+
+    #[test]
+    fn skip_out_of_bounds() {
+        let src = "aaa";
+        let oob = suggestion(vec![TextEdit::new(TextRange::new(2, 4), "x")]);
+
+        let (out, skipped) = apply_suggestions(src, [&oob]);
+        assert_eq!(out, "aaa");
+        assert_eq!(skipped, vec![&oob]);
+    }
+}
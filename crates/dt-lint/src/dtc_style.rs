@@ -1,10 +1,25 @@
 use dt_parser::{
-    ast::{self, AstNode, AstNodeOrToken, HasLabel as _},
+    ast::{self, AstNode, AstNodeOrToken},
     cst::NodeKind,
     lexer::TokenKind,
 };
 
-use crate::{EarlyLintPass, LintId, LintSeverity};
+use crate::{Applicability, EarlyLintPass, Lint, LintGroup, LintLevel, Suggestion, TextEdit};
+
+pub static DTC_STYLE: Lint = Lint {
+    name: "dtc_style",
+    default_level: LintLevel::Deny,
+    desc: "items must be accepted by dtc",
+    group: LintGroup::Correctness,
+    rationale: "`dtc` rejects these sources, so they can't be compiled into a DTB.",
+    bad_examples: &[
+        "/ {};\n",
+        "/dts-v1/;\n\n/ {\n\tnode {};\n\tprop;\n};\n",
+        "/dts-v1/;\n\n/ {\n\tprop@1 = <1>;\n};\n",
+    ],
+    good_examples: &["/dts-v1/;\n\n/ {\n\tprop = <1>;\n\tnode {};\n};\n"],
+    url: Some("https://github.com/dgibson/dtc/blob/main/Documentation/dts-format.txt"),
+};
 
 /// Lint items by [`dtc`](https://github.com/dgibson/dtc)'s rules
 ///
@@ -20,7 +35,7 @@ pub struct DtcStyle;
 // TODO: validate idents
 
 impl EarlyLintPass for DtcStyle {
-    fn check_document(&mut self, cx: &mut crate::EarlyContext<'_>, file: &ast::SourceFile) {
+    fn check_source_file(&mut self, cx: &mut crate::EarlyContext<'_>, file: &ast::SourceFile) {
         if cx.is_main_file {
             if let Some(first) = file.items().next() {
                 let is_v1_directive = first.clone().into_directive().ok().is_some_and(|dir| {
@@ -30,21 +45,39 @@ impl EarlyLintPass for DtcStyle {
                 });
 
                 if !is_v1_directive {
+                    let first_range = first.syntax().text_range();
+                    let mut edits = vec![TextEdit::insert(first_range.start, "/dts-v1/;\n\n")];
+                    // Move the directive instead of duplicating it
+                    if let Some(v1_directive) = file.directives().find(|dir| {
+                        dir.syntax()
+                            .child_tokens()
+                            .any(|tok| tok.green.kind == TokenKind::V1Directive)
+                    }) {
+                        let mut range = v1_directive.syntax().text_range();
+                        if cx.src[range.end..].starts_with('\n') {
+                            range.end += 1;
+                        }
+                        edits.push(TextEdit::delete(range));
+                    }
+
                     cx.add_lint_from_cst(
-                        LintId::DtcStyle,
+                        &DTC_STYLE,
                         "First item must be `/dts-v1/;` directive",
-                        LintSeverity::Error,
-                        first.syntax().text_range(),
-                    );
+                        first_range,
+                    )
+                    .suggest(Suggestion::new(
+                        "add `/dts-v1/;` at the beginning of the file",
+                        edits,
+                        Applicability::MachineApplicable,
+                    ));
                 }
             }
         }
 
         for prop in file.properties() {
             cx.add_lint_from_cst(
-                LintId::DtcStyle,
+                &DTC_STYLE,
                 "Properties must not be defined outside nodes",
-                LintSeverity::Error,
                 prop.syntax().text_range(),
             );
         }
@@ -52,37 +85,20 @@ impl EarlyLintPass for DtcStyle {
         for node in file.nodes() {
             if !node.is_root() && !node.is_extension() {
                 cx.add_lint_from_cst(
-                    LintId::DtcStyle,
+                    &DTC_STYLE,
                     "Subnodes must be defined inside other nodes",
-                    LintSeverity::Error,
                     node.syntax().text_range(),
                 );
             }
-            self.check_node(cx, &node);
         }
     }
     fn check_node(&mut self, cx: &mut crate::EarlyContext<'_>, node: &ast::DtNode) {
-        if let Some(label) = node.label() {
-            self.check_label(cx, &label);
-        }
-
-        let mut last_prop = None;
-        for property in node.properties() {
-            self.check_property(cx, &property);
-            last_prop = Some(property);
-        }
-
-        for node in node.subnodes() {
-            self.check_node(cx, &node);
-        }
-
-        if let Some(last_prop) = last_prop {
+        if let Some(last_prop) = node.properties().last() {
             if let Some(first_node) = node.subnodes().next() {
                 if last_prop.syntax().text_range().end > first_node.syntax().text_range().start {
                     cx.add_lint_from_cst(
-                        LintId::DtcStyle,
+                        &DTC_STYLE,
                         "Properties must not be defined after nodes",
-                        LintSeverity::Error,
                         last_prop.syntax().text_range(),
                     );
                 }
@@ -97,15 +113,15 @@ impl EarlyLintPass for DtcStyle {
             .find(|node| node.green.kind == NodeKind::UnitAddress)
         {
             cx.add_lint_from_cst(
-                LintId::DtcStyle,
+                &DTC_STYLE,
                 "Properties must not have unit addresses",
-                LintSeverity::Error,
                 unit_address.text_range(),
-            );
-        }
-
-        if let Some(label) = property.label() {
-            self.check_label(cx, &label);
+            )
+            .suggest(Suggestion::new(
+                "remove the unit address",
+                vec![TextEdit::delete(unit_address.text_range())],
+                Applicability::MaybeIncorrect,
+            ));
         }
     }
 }
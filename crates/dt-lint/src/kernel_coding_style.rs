@@ -1,6 +1,26 @@
-use dt_parser::ast::{self, AstToken, HasLabel as _, HasName};
+use dt_parser::ast::{self, AstToken, HasName};
 
-use crate::{EarlyLintPass, LintId, LintSeverity};
+use crate::{Applicability, EarlyLintPass, Lint, LintGroup, LintLevel, Suggestion, TextEdit};
+
+pub static KERNEL_CODING_STYLE: Lint = Lint {
+    name: "kernel_coding_style",
+    default_level: LintLevel::Warn,
+    desc: "items must follow the Linux kernel's devicetree coding style",
+    group: LintGroup::Style,
+    rationale:
+        "Consistent naming makes devicetrees easier to read and search. Patches to the Linux \
+kernel which don't follow its devicetree coding style are likely to be rejected in review.",
+    bad_examples: &[
+        "/dts-v1/;\n\n/ {\n\tSerial_Port@1000 {};\n};\n",
+        "/dts-v1/;\n\n/ {\n\tserial@0x1000 {};\n};\n",
+        "/dts-v1/;\n\n/ {\n\tserial@1000 {\n\t\treg = <0x1000 0xFF>;\n\t};\n};\n",
+        "/dts-v1/;\n\n/ {\n\tUART0: serial@1000 {};\n};\n",
+    ],
+    good_examples: &[
+        "/dts-v1/;\n\n/ {\n\tuart0: serial@1000 {\n\t\treg = <0x1000 0xff>;\n\t};\n};\n",
+    ],
+    url: Some("https://docs.kernel.org/devicetree/bindings/dts-coding-style.html"),
+};
 
 /// Lint items to match the Linux kernel's devicetree [coding style](https://docs.kernel.org/devicetree/bindings/dts-coding-style.html).
 ///
@@ -15,12 +35,34 @@ fn valid_node_name(s: &str) -> bool {
 fn valid_node_unit_name(s: &str) -> bool {
     (s.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')) && !s.starts_with('0')) || s == "0"
 }
+/// Returns the unit address `s` as a lowercase hex number without leading zeros, if it's a single
+/// hex number.
+fn normalize_node_unit_name(s: &str) -> Option<String> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let trimmed = digits.trim_start_matches('0');
+    Some(if trimmed.is_empty() {
+        "0".to_owned()
+    } else {
+        trimmed.to_ascii_lowercase()
+    })
+}
 fn valid_prop_name(s: &str) -> bool {
     s.chars().enumerate().all(|(i, c)| {
         c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || (i == 0 && c == '#')
     })
 }
-fn valid_label_name(s: &str) -> bool {
+/// Returns `true` if `s` is a valid label name in the kernel's coding style, i.e. matches
+/// `[a-z0-9_]+`.
+///
+/// This doesn't check that `s` is non-empty.
+#[must_use]
+pub fn valid_label_name(s: &str) -> bool {
     s.chars()
         .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
 }
@@ -32,17 +74,15 @@ impl EarlyLintPass for KernelCodingStyle {
             if node.is_extension() {
                 if !valid_label_name(text) {
                     cx.add_lint_from_cst(
-                        LintId::KernelCodingStyle,
+                        &KERNEL_CODING_STYLE,
                         format!("Label name `{text}` should match `[a-z0-9_]+`"),
-                        LintSeverity::Warn,
                         name.syntax().text_range(),
                     );
                 }
             } else if !valid_node_name(text) {
                 cx.add_lint_from_cst(
-                    LintId::KernelCodingStyle,
+                    &KERNEL_CODING_STYLE,
                     format!("Node name `{text}` should match `[a-z0-9-]+`"),
-                    LintSeverity::Warn,
                     name.syntax().text_range(),
                 );
             }
@@ -51,71 +91,65 @@ impl EarlyLintPass for KernelCodingStyle {
             let text = name.syntax().text();
             // TODO: "Unless a bus defines differently,"
             if !valid_node_unit_name(text) {
-                cx.add_lint_from_cst(
-                    LintId::KernelCodingStyle,
+                let lint = cx.add_lint_from_cst(
+                    &KERNEL_CODING_STYLE,
                     format!(
                         "Node unit name `{text}` should be a lowercase hex number without leading zeros"
                     ),
-                    LintSeverity::Warn,
                     name.syntax().text_range(),
                 );
+                if let Some(fixed) = normalize_node_unit_name(text) {
+                    // Path references to this node, possibly in other files, would break.
+                    lint.suggest(Suggestion::new(
+                        format!("rename to `{fixed}`"),
+                        vec![TextEdit::new(name.syntax().text_range(), fixed)],
+                        Applicability::MaybeIncorrect,
+                    ));
+                }
             }
         }
-
-        if let Some(label) = node.label() {
-            self.check_label(cx, &label);
-        }
-        for property in node.properties() {
-            // TODO: property order, maybe easier after analyzation
-            self.check_property(cx, &property);
-        }
-        for node in node.subnodes() {
-            self.check_node(cx, &node);
-        }
+        // TODO: property order, maybe easier after analyzation
     }
     fn check_property(&mut self, cx: &mut crate::EarlyContext<'_>, property: &ast::DtProperty) {
         if let Some(name) = property.name() {
             let text = name.syntax().text().as_str();
             if text != "device_type" && text != "ddr_device_type" && !valid_prop_name(text) {
                 cx.add_lint_from_cst(
-                    LintId::KernelCodingStyle,
+                    &KERNEL_CODING_STYLE,
                     format!("Property name `{text}` should match `#?[a-z0-9-]+`"),
-                    LintSeverity::Warn,
                     name.syntax().text_range(),
                 );
             }
         }
-
-        for value in property.values() {
-            if let ast::PropValue::CellList(cell_list) = value {
-                for cell in cell_list.cells() {
-                    if let ast::Cell::Number(dt_number) = cell {
-                        let text = dt_number.text();
-                        // Hex values in properties, e.g. "reg", shall use lowercase hex.
-                        if text.contains(|c: char| c.is_ascii_uppercase()) {
-                            cx.add_lint_from_cst(
-                                LintId::KernelCodingStyle,
-                                "Hex values in properties must use lowercase hex",
-                                LintSeverity::Warn,
-                                dt_number.text_range(),
-                            );
-                        }
-                    }
-                }
+    }
+    fn check_cell(&mut self, cx: &mut crate::EarlyContext<'_>, cell: &ast::Cell) {
+        if let ast::Cell::Number(dt_number) = cell {
+            let text = dt_number.text();
+            // Hex values in properties, e.g. "reg", shall use lowercase hex.
+            if text.contains(|c: char| c.is_ascii_uppercase()) {
+                cx.add_lint_from_cst(
+                    &KERNEL_CODING_STYLE,
+                    "Hex values in properties must use lowercase hex",
+                    dt_number.text_range(),
+                )
+                .suggest(Suggestion::new(
+                    "use lowercase hex",
+                    vec![TextEdit::new(
+                        dt_number.text_range(),
+                        text.to_ascii_lowercase(),
+                    )],
+                    Applicability::MachineApplicable,
+                ));
             }
         }
-        if let Some(label) = property.label() {
-            self.check_label(cx, &label);
-        }
     }
-    fn check_label(&mut self, cx: &mut crate::EarlyContext, label: &ast::DtLabel) {
+    fn check_label(&mut self, cx: &mut crate::EarlyContext<'_>, label: &ast::DtLabel) {
         if let Some(name) = label.name() {
             let text = name.syntax().text();
             if !valid_label_name(text) {
                 cx.add_lint_from_cst(
-                    LintId::KernelCodingStyle,
+                    &KERNEL_CODING_STYLE,
                     format!("Label name `{text}` should match `[a-z0-9_]+`"),
-                    LintSeverity::Warn,
                     name.syntax().text_range(),
                 );
             }
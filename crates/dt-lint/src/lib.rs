@@ -4,27 +4,30 @@
 //!
 //! [1]: https://www.devicetree.org/
 
-use dt_parser::{
-    ast::{self, HasLabel},
-    TextRange,
-};
+use dt_analyzer::new::stage2::{Stage2File, Stage2Node, Stage2Property};
+use dt_parser::{ast, SourceId, TextRange};
 use std::borrow::Cow;
 
+pub use dt_diagnostic::{Applicability, Suggestion, TextEdit};
+pub use registry::{
+    EarlyLintPassFactory, ExampleError, LateLintPassFactory, LintRegistry, UnknownLintError,
+};
+
 mod dtc_style;
 mod kernel_coding_style;
+mod registry;
+pub mod rules;
 
 pub mod lints {
     //! The full collection of lints upstream.
     //!
-    //! Currently all lints in here are applied by the [`default_lint`](crate::default_lint) function
-    //! used by the LSP.
-    pub use crate::dtc_style::DtcStyle;
-    pub use crate::kernel_coding_style::KernelCodingStyle;
+    //! All lints in here are registered by [`LintRegistry::builtin`](crate::LintRegistry::builtin).
+    pub use crate::dtc_style::{DtcStyle, DTC_STYLE};
+    pub use crate::kernel_coding_style::{
+        valid_label_name, KernelCodingStyle, KERNEL_CODING_STYLE,
+    };
 }
 
-// TODO: something like this:
-// https://doc.rust-lang.org/nightly/nightly-rustc/rustc_ast/visit/index.html
-
 pub type DiagnosticMessage = Cow<'static, str>;
 
 /// Just like the [MultiSpan from rustc & clippy][1]
@@ -44,102 +47,516 @@ impl From<TextRange> for MultiSpan {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum LintId {
-    DtcStyle,
-    KernelCodingStyle,
+/// The level of a [`Lint`], which can be overridden using [`LintRegistry::set_level`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+    /// The lint is not emitted
+    Allow,
+    /// The lint is emitted as a warning
+    Warn,
+    /// The lint is emitted as an error
+    Deny,
 }
-impl std::fmt::Display for LintId {
+impl LintLevel {
+    /// Returns the severity emitted lints have with this level, or `None` for [`LintLevel::Allow`].
+    #[must_use]
+    pub fn severity(self) -> Option<LintSeverity> {
+        match self {
+            Self::Allow => None,
+            Self::Warn => Some(LintSeverity::Warn),
+            Self::Deny => Some(LintSeverity::Error),
+        }
+    }
+}
+
+/// The category of a [`Lint`], shown in its [explanation](Lint::explanation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintGroup {
+    /// Code that is accepted, but doesn't follow conventions
+    Style,
+    /// Code that is wrong or rejected by other tools
+    Correctness,
+    /// Lints from rule files or other out-of-tree sources
+    Custom,
+}
+impl std::fmt::Display for LintGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Style => "style",
+            Self::Correctness => "correctness",
+            Self::Custom => "custom",
+        })
+    }
+}
+impl std::fmt::Display for LintLevel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
-            Self::DtcStyle => "dtc_style",
-            Self::KernelCodingStyle => "kernel_coding_style",
+            Self::Allow => "allow",
+            Self::Warn => "warn",
+            Self::Deny => "deny",
         })
     }
 }
 
+/// Static metadata of a lint.
+///
+/// Lints are declared as statics and referenced with [`LintId`]s:
+///
+/// ```
+/// use dt_lint::{Lint, LintGroup, LintLevel};
+///
+/// pub static UART_ALIAS: Lint = Lint {
+///     name: "uart_alias",
+///     default_level: LintLevel::Warn,
+///     desc: "every UART must have an alias",
+///     group: LintGroup::Custom,
+///     rationale: "Aliases keep the `ttyS` numbering stable.",
+///     ..Lint::DEFAULT
+/// };
+/// ```
+///
+/// The examples are checked by [`LintRegistry::check_examples`], so they can't go stale.
+#[derive(Debug)]
+pub struct Lint {
+    /// The unique name of the lint in `snake_case`
+    pub name: &'static str,
+    pub default_level: LintLevel,
+    /// A short description of what the lint checks
+    pub desc: &'static str,
+    pub group: LintGroup,
+    /// Why the lint exists, in Markdown
+    pub rationale: &'static str,
+    /// Main files which must trigger the lint
+    pub bad_examples: &'static [&'static str],
+    /// Main files which must not trigger the lint
+    pub good_examples: &'static [&'static str],
+    /// Further documentation, e.g. for the LSP diagnostic's `code_description`
+    pub url: Option<&'static str>,
+}
+impl Lint {
+    /// Default values for the optional fields, for use with struct update syntax.
+    pub const DEFAULT: Self = Self {
+        name: "",
+        default_level: LintLevel::Warn,
+        desc: "",
+        group: LintGroup::Custom,
+        rationale: "",
+        bad_examples: &[],
+        good_examples: &[],
+        url: None,
+    };
+
+    /// Returns the documentation of the lint in Markdown, as shown by `dt explain`.
+    #[must_use]
+    pub fn explanation(&self) -> String {
+        use std::fmt::Write as _;
+
+        let mut out = format!(
+            "# `{}`\n\nGroup: {}, default level: {}\n\n{}\n",
+            self.name, self.group, self.default_level, self.desc
+        );
+        if !self.rationale.is_empty() {
+            let _ = write!(out, "\n## Rationale\n\n{}\n", self.rationale.trim_end());
+        }
+        for (heading, examples) in [
+            ("Bad examples", self.bad_examples),
+            ("Good examples", self.good_examples),
+        ] {
+            if !examples.is_empty() {
+                let _ = write!(out, "\n## {heading}\n");
+                for example in examples {
+                    let _ = write!(out, "\n```dts\n{}\n```\n", example.trim_end());
+                }
+            }
+        }
+        if let Some(url) = self.url {
+            let _ = write!(out, "\nMore information: <{url}>\n");
+        }
+        out
+    }
+}
+
+/// A reference to a [`Lint`]. Lints are identified by their name.
+#[derive(Debug, Clone, Copy)]
+pub struct LintId {
+    lint: &'static Lint,
+}
+impl LintId {
+    #[must_use]
+    pub const fn of(lint: &'static Lint) -> Self {
+        Self { lint }
+    }
+    /// Returns the lint's metadata.
+    #[must_use]
+    pub const fn lint(self) -> &'static Lint {
+        self.lint
+    }
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        self.lint.name
+    }
+}
+impl PartialEq for LintId {
+    fn eq(&self, other: &Self) -> bool {
+        self.lint.name == other.lint.name
+    }
+}
+impl Eq for LintId {}
+impl std::hash::Hash for LintId {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.lint.name.hash(state);
+    }
+}
+impl std::fmt::Display for LintId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.lint.name)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LintSeverity {
     Warn,
     Error,
 }
 
+/// A lint emitted by an [`EarlyLintPass`] or a [`LateLintPass`].
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct EarlyLint {
+pub struct EmittedLint {
     pub id: LintId,
     pub span: MultiSpan,
     pub msg: DiagnosticMessage,
+    /// The severity according to the lint's [level](LintLevel)
     pub severity: LintSeverity,
+    /// Fixes for this lint
+    pub suggestions: Vec<Suggestion>,
+    /// What the lint is about, for tools, e.g. the compatible of `undocumented_compatibles`
+    pub data: Option<String>,
+}
+impl EmittedLint {
+    /// Attaches a fix to this lint.
+    pub fn suggest(&mut self, suggestion: Suggestion) -> &mut Self {
+        self.suggestions.push(suggestion);
+        self
+    }
+
+    /// Attaches data for tools to this lint.
+    pub fn with_data(&mut self, data: impl Into<String>) -> &mut Self {
+        self.data = Some(data.into());
+        self
+    }
+}
+
+/// Pushes a lint with the lint's default severity, which is adjusted by [`LintRegistry`] later.
+fn push_lint<'a>(
+    lints: &'a mut Vec<EmittedLint>,
+    lint: &'static Lint,
+    msg: DiagnosticMessage,
+    span: MultiSpan,
+) -> &'a mut EmittedLint {
+    tracing::debug!("adding lint {}: {msg}", lint.name);
+    let index = lints.len();
+    lints.push(EmittedLint {
+        id: LintId::of(lint),
+        span,
+        msg,
+        severity: lint.default_level.severity().unwrap_or(LintSeverity::Warn),
+        suggestions: Vec::new(),
+        data: None,
+    });
+    &mut lints[index]
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EarlyContext<'i> {
-    pub lints: Vec<EarlyLint>,
+    pub lints: Vec<EmittedLint>,
     pub src: &'i str,
     pub is_main_file: bool,
 }
 impl EarlyContext<'_> {
+    /// Adds a lint and returns it, so that [suggestions](EmittedLint::suggest) can be attached.
     pub fn add_lint_from_cst(
         &mut self,
-        id: LintId,
-        msg: impl Into<DiagnosticMessage> + Clone,
-        severity: LintSeverity,
+        lint: &'static Lint,
+        msg: impl Into<DiagnosticMessage>,
         span: impl Into<MultiSpan>,
-    ) {
-        tracing::debug!("adding lint {}, {:?}", msg.clone().into(), severity);
-        self.lints.push(EarlyLint {
-            id,
-            span: span.into(),
-            msg: msg.into(),
-            severity,
-        });
+    ) -> &mut EmittedLint {
+        push_lint(&mut self.lints, lint, msg.into(), span.into())
     }
-    pub fn add_lint(&mut self, lint: EarlyLint) {
-        tracing::debug!("adding lint {lint:?}");
-        self.lints.push(lint);
+}
+
+/// Context for [`LateLintPass`]es.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LateContext<'i> {
+    pub lints: Vec<EmittedLint>,
+    /// Lints in the files included by the linted file, which aren't reported
+    pub included_lints: Vec<EmittedLint>,
+    /// The text of the linted file
+    pub src: &'i str,
+    /// The linted file, which its includes were merged into
+    pub source_id: &'i SourceId,
+}
+impl LateContext<'_> {
+    /// Adds a lint and returns it, so that [suggestions](EmittedLint::suggest) can be attached.
+    ///
+    /// The spans must point to ASTs in the file `source_id`, e.g. the
+    /// [`source_id`](Stage2Property::source_id) of a property. Lints in included files are
+    /// discarded, since they are reported when the included file is linted.
+    pub fn add_lint(
+        &mut self,
+        source_id: &SourceId,
+        lint: &'static Lint,
+        msg: impl Into<DiagnosticMessage>,
+        span: impl Into<MultiSpan>,
+    ) -> &mut EmittedLint {
+        let lints = if source_id == self.source_id {
+            &mut self.lints
+        } else {
+            &mut self.included_lints
+        };
+        push_lint(lints, lint, msg.into(), span.into())
     }
 }
 
 /// The early lint pass before type information is acquired. This runs on [AST](ast)s.
+///
+/// The hooks don't recurse: the AST is walked only once by [`LintRegistry::lint_early`], which
+/// calls the hooks of all registered passes for every item.
 pub trait EarlyLintPass {
-    /// Lint a source file's [AST](ast::SourceFile)
-    fn check_document(&mut self, cx: &mut EarlyContext<'_>, file: &ast::SourceFile) {
-        for node in file.nodes() {
-            self.check_node(cx, &node);
-        }
-    }
-    /// Lint a node's [AST](ast::DtNode)
-    fn check_node(&mut self, cx: &mut EarlyContext<'_>, node: &ast::DtNode) {
-        if let Some(label) = node.label() {
-            self.check_label(cx, &label);
-        }
-        for property in node.properties() {
-            self.check_property(cx, &property);
-        }
-        for node in node.subnodes() {
-            self.check_node(cx, &node);
-        }
-    }
+    /// Lint a source file's [AST](ast::SourceFile). This is called before any other hook.
+    fn check_source_file(&mut self, _cx: &mut EarlyContext<'_>, _file: &ast::SourceFile) {}
+    /// Lint a DTS directive's [AST](ast::Directive)
+    fn check_directive(&mut self, _cx: &mut EarlyContext<'_>, _directive: &ast::Directive) {}
+    /// Lint a node's [AST](ast::DtNode). This is called before its children are checked.
+    fn check_node(&mut self, _cx: &mut EarlyContext<'_>, _node: &ast::DtNode) {}
+    /// Lint a node's [AST](ast::DtNode) after its children have been checked.
+    fn check_node_post(&mut self, _cx: &mut EarlyContext<'_>, _node: &ast::DtNode) {}
     /// Lint a property's [AST](ast::DtProperty)
-    fn check_property(&mut self, cx: &mut EarlyContext, property: &ast::DtProperty) {
-        if let Some(label) = property.label() {
-            self.check_label(cx, &label);
-        }
+    fn check_property(&mut self, _cx: &mut EarlyContext<'_>, _property: &ast::DtProperty) {}
+    /// Lint a node or property label's [AST](ast::DtLabel)
+    fn check_label(&mut self, _cx: &mut EarlyContext<'_>, _label: &ast::DtLabel) {}
+    /// Lint a [cell](ast::Cell) in a property's cell list
+    fn check_cell(&mut self, _cx: &mut EarlyContext<'_>, _cell: &ast::Cell) {}
+}
+
+/// The late lint pass after macros and references have been resolved. This runs on the
+/// [stage 2 tree](Stage2File).
+///
+/// Like [`EarlyLintPass`], the hooks don't recurse.
+pub trait LateLintPass {
+    /// Lint a whole file. This is called before any other hook.
+    fn check_file(&mut self, _cx: &mut LateContext<'_>, _file: &Stage2File) {}
+    /// Lint a node. `path` contains the names of the node and its parents, excluding the root
+    /// node.
+    fn check_node(&mut self, _cx: &mut LateContext<'_>, _path: &[&str], _node: &Stage2Node) {}
+    /// Lint a property. `path` contains the names of the parent nodes, excluding the root node.
+    fn check_property(
+        &mut self,
+        _cx: &mut LateContext<'_>,
+        _path: &[&str],
+        _name: &str,
+        _property: &Stage2Property,
+    ) {
     }
-    /// Lint a node label's [AST](ast::DtLabel)
-    fn check_label(&mut self, _cx: &mut EarlyContext, _label: &ast::DtLabel) {}
 }
 
+/// Lints `file` with the builtin early lints.
 #[must_use]
-pub fn default_lint(file: &ast::SourceFile, src: &str, is_main_file: bool) -> Vec<EarlyLint> {
-    let mut cx = EarlyContext {
-        lints: Vec::new(),
-        src,
-        is_main_file,
-    };
-    // TODO: go over the tree only once
-    crate::lints::KernelCodingStyle.check_document(&mut cx, file);
-    crate::lints::DtcStyle.check_document(&mut cx, file);
+pub fn default_lint(file: &ast::SourceFile, src: &str, is_main_file: bool) -> Vec<EmittedLint> {
     // TODO: warn for `&LABEL,` (ident eats the comma) in a devicetree cell
-    cx.lints
+    LintRegistry::builtin().lint_early(file, src, is_main_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use dt_parser::{ast::SourceFile, SourceId};
+
+    use super::*;
+
+    /// Applies all fixes with at least the applicability `applicability`.
+    fn fix(src: &str, applicability: Applicability) -> String {
+        let file = SourceFile::parse(src).source_file();
+        let lints = default_lint(&file, src, true);
+        let suggestions = lints
+            .iter()
+            .flat_map(|lint| &lint.suggestions)
+            .filter(|suggestion| suggestion.applicability <= applicability);
+        dt_diagnostic::apply_suggestions(src, suggestions).0
+    }
+
+    #[test]
+    fn fix_lowercase_hex() {
+        assert_eq!(
+            fix(
+                "/dts-v1/;\n/ { reg = <0xABCD 0x12 0xfF>; };",
+                Applicability::MachineApplicable
+            ),
+            "/dts-v1/;\n/ { reg = <0xabcd 0x12 0xff>; };"
+        );
+    }
+
+    #[test]
+    fn fix_unit_address() {
+        let src = "/dts-v1/;\n/ { a@0x1A {}; b@0000 {}; c@00f0 {}; d@1,2 {}; };";
+        assert_eq!(fix(src, Applicability::MachineApplicable), src);
+        assert_eq!(
+            fix(src, Applicability::MaybeIncorrect),
+            "/dts-v1/;\n/ { a@1a {}; b@0 {}; c@f0 {}; d@1,2 {}; };"
+        );
+    }
+
+    #[test]
+    fn fix_v1_directive() {
+        assert_eq!(
+            fix("// comment\n/ {};", Applicability::MachineApplicable),
+            "// comment\n/dts-v1/;\n\n/ {};"
+        );
+        assert_eq!(
+            fix(
+                "/include/ \"a.dtsi\"\n/ {};\n/dts-v1/;\n",
+                Applicability::MachineApplicable
+            ),
+            "/dts-v1/;\n\n/include/ \"a.dtsi\"\n/ {};\n"
+        );
+    }
+
+    #[test]
+    fn fix_property_unit_address() {
+        assert_eq!(
+            fix(
+                "/dts-v1/;\n/ { prop@1 = <1>; };",
+                Applicability::MaybeIncorrect
+            ),
+            "/dts-v1/;\n/ { prop = <1>; };"
+        );
+    }
+
+    static ALWAYS_ON: Lint = Lint {
+        name: "always_on",
+        default_level: LintLevel::Warn,
+        desc: "no `regulator-always-on`",
+        ..Lint::DEFAULT
+    };
+
+    /// Emits `lint` for `regulator-always-on` properties
+    struct AlwaysOnAs(&'static Lint);
+
+    impl LateLintPass for AlwaysOnAs {
+        fn check_property(
+            &mut self,
+            cx: &mut LateContext<'_>,
+            path: &[&str],
+            name: &str,
+            property: &Stage2Property,
+        ) {
+            use dt_parser::ast::AstNode as _;
+
+            if name == "regulator-always-on" {
+                cx.add_lint(
+                    &property.source_id,
+                    self.0,
+                    format!("`/{}` is always on", path.join("/")),
+                    property.ast.syntax().text_range(),
+                );
+            }
+        }
+    }
+
+    fn lint_late(registry: &LintRegistry, src: &str) -> Vec<EmittedLint> {
+        let file = SourceFile::parse(src).source_file();
+        let diag = std::sync::Mutex::new(Vec::new());
+        let outline = dt_analyzer::new::stage1::analyze_file(&file, src, &diag);
+        let stage2 =
+            dt_analyzer::new::stage2::compute(&SourceId::from("test.dts"), &outline, &[], &diag);
+        assert_eq!(diag.into_inner().unwrap(), Vec::new());
+        registry.lint_late(&stage2, src)
+    }
+
+    #[test]
+    fn late_pass() {
+        let mut registry = LintRegistry::new();
+        registry.register_lints(&[&ALWAYS_ON]);
+        registry.register_late_pass(|| Box::new(AlwaysOnAs(&ALWAYS_ON)));
+
+        let src = "/dts-v1/;\n/ { regulators { vdd { regulator-always-on; }; vcc {}; }; };";
+        let lints = lint_late(&registry, src);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].id, LintId::of(&ALWAYS_ON));
+        assert_eq!(lints[0].msg, "`/regulators/vdd` is always on");
+        assert_eq!(lints[0].severity, LintSeverity::Warn);
+
+        registry.set_level("always_on", LintLevel::Deny).unwrap();
+        assert_eq!(lint_late(&registry, src)[0].severity, LintSeverity::Error);
+
+        assert_eq!(
+            registry.set_level("unknown", LintLevel::Deny),
+            Err(UnknownLintError("unknown".to_owned()))
+        );
+    }
+
+    #[test]
+    fn builtin_examples() {
+        let registry = LintRegistry::builtin();
+        assert_eq!(registry.check_examples(), []);
+        for lint in registry.lints() {
+            assert!(
+                !lint.bad_examples.is_empty(),
+                "`{}` has no bad examples",
+                lint.name
+            );
+            assert!(
+                !lint.good_examples.is_empty(),
+                "`{}` has no good examples",
+                lint.name
+            );
+        }
+    }
+
+    #[test]
+    fn check_examples() {
+        static STALE: Lint = Lint {
+            name: "stale",
+            default_level: LintLevel::Allow,
+            desc: "",
+            bad_examples: &["/dts-v1/;\n/ {};"],
+            good_examples: &["/dts-v1/;\n/ { regulator-always-on; };"],
+            ..Lint::DEFAULT
+        };
+        let mut registry = LintRegistry::new();
+        registry.register_lints(&[&STALE]);
+        registry.register_late_pass(|| Box::new(AlwaysOnAs(&STALE)));
+        assert_eq!(
+            registry.check_examples(),
+            [
+                ExampleError::BadNotTriggered {
+                    lint: "stale",
+                    index: 0
+                },
+                ExampleError::GoodTriggered {
+                    lint: "stale",
+                    index: 0,
+                    msg: "`/` is always on".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn explanation() {
+        let explanation = lints::DTC_STYLE.explanation();
+        assert!(explanation.starts_with(
+            "# `dtc_style`\n\nGroup: correctness, default level: deny\n\nitems must be accepted by dtc\n"
+        ));
+        assert!(explanation.contains("\n## Rationale\n"));
+        assert!(explanation.contains("\n```dts\n/ {};\n```\n"));
+        assert!(explanation.ends_with(
+            "More information: <https://github.com/dgibson/dtc/blob/main/Documentation/dts-format.txt>\n"
+        ));
+    }
+
+    #[test]
+    #[should_panic = "duplicate specification of lint `dtc_style`"]
+    fn duplicate_lint() {
+        LintRegistry::builtin().register_lints(&[&lints::DTC_STYLE]);
+    }
 }
@@ -0,0 +1,403 @@
+use dt_analyzer::new::stage2::{ResolvedInclude, Stage2File, Stage2Node, Stage2Tree};
+use dt_parser::{
+    ast::{
+        self,
+        visit::{self, Visitor},
+    },
+    SourceId,
+};
+use rustc_hash::FxHashMap;
+
+use crate::{
+    EarlyContext, EarlyLintPass, EmittedLint, LateContext, LateLintPass, Lint, LintId, LintLevel,
+};
+
+/// Creates a fresh [`EarlyLintPass`] for each linted file, so passes can keep state per file.
+pub type EarlyLintPassFactory = Box<dyn Fn() -> Box<dyn EarlyLintPass> + Send + Sync>;
+/// Creates a fresh [`LateLintPass`] for each linted file, so passes can keep state per file.
+pub type LateLintPassFactory = Box<dyn Fn() -> Box<dyn LateLintPass> + Send + Sync>;
+
+/// Unknown lint `{0}`
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub struct UnknownLintError(pub String);
+
+/// A documentation example of a [`Lint`] which doesn't behave as documented
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum ExampleError {
+    /// bad example {index} of `{lint}` doesn't trigger the lint
+    BadNotTriggered { lint: &'static str, index: usize },
+    /// good example {index} of `{lint}` triggers the lint: {msg}
+    GoodTriggered {
+        lint: &'static str,
+        index: usize,
+        msg: String,
+    },
+}
+
+/// A collection of lints and lint passes to run.
+///
+/// This is the extension point for out-of-tree lints: register your [`Lint`]s and the passes
+/// emitting them, then pass the registry to `dt_cli::run` or `dt_lsp::serve`.
+///
+/// # Example
+///
+/// ```
+/// use dt_lint::{EarlyContext, EarlyLintPass, Lint, LintLevel, LintRegistry};
+/// use dt_parser::ast::{self, AstNode as _};
+///
+/// static EMPTY_NODES: Lint = Lint {
+///     name: "empty_nodes",
+///     default_level: LintLevel::Warn,
+///     desc: "nodes should have properties or subnodes",
+///     bad_examples: &["/dts-v1/;\n/ { node {}; };"],
+///     ..Lint::DEFAULT
+/// };
+///
+/// struct EmptyNodes;
+///
+/// impl EarlyLintPass for EmptyNodes {
+///     fn check_node(&mut self, cx: &mut EarlyContext<'_>, node: &ast::DtNode) {
+///         if node.properties().next().is_none() && node.subnodes().next().is_none() {
+///             cx.add_lint_from_cst(&EMPTY_NODES, "Empty node", node.syntax().text_range());
+///         }
+///     }
+/// }
+///
+/// let mut registry = LintRegistry::builtin();
+/// registry.register_lints(&[&EMPTY_NODES]);
+/// registry.register_early_pass(|| Box::new(EmptyNodes));
+///
+/// let src = "/dts-v1/;\n/ { node {}; };";
+/// let file = ast::SourceFile::parse(src).source_file();
+/// assert_eq!(registry.lint_early(&file, src, true).len(), 1);
+///
+/// assert!(registry.check_examples().is_empty());
+///
+/// registry.set_level("empty_nodes", LintLevel::Allow).unwrap();
+/// assert!(registry.lint_early(&file, src, true).is_empty());
+/// ```
+#[derive(Default)]
+pub struct LintRegistry {
+    lints: Vec<&'static Lint>,
+    levels: FxHashMap<LintId, LintLevel>,
+    early_passes: Vec<EarlyLintPassFactory>,
+    late_passes: Vec<LateLintPassFactory>,
+}
+
+impl std::fmt::Debug for LintRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LintRegistry")
+            .field("lints", &self.lints)
+            .field("levels", &self.levels)
+            .field("early_passes", &self.early_passes.len())
+            .field("late_passes", &self.late_passes.len())
+            .finish()
+    }
+}
+
+impl LintRegistry {
+    /// Creates an empty registry.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry with all [builtin lints](crate::lints).
+    #[must_use]
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register_lints(&[&crate::lints::KERNEL_CODING_STYLE, &crate::lints::DTC_STYLE]);
+        registry.register_early_pass(|| Box::new(crate::lints::KernelCodingStyle));
+        registry.register_early_pass(|| Box::new(crate::lints::DtcStyle));
+        registry
+    }
+
+    /// Registers lints, so that their levels can be configured.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lint with the same name has already been registered.
+    pub fn register_lints(&mut self, lints: &[&'static Lint]) {
+        for lint in lints {
+            assert!(
+                self.find_lint(lint.name).is_none(),
+                "duplicate specification of lint `{}`",
+                lint.name
+            );
+            self.lints.push(lint);
+        }
+    }
+
+    /// Registers an early lint pass. `factory` is called for each linted file.
+    pub fn register_early_pass(
+        &mut self,
+        factory: impl Fn() -> Box<dyn EarlyLintPass> + Send + Sync + 'static,
+    ) {
+        self.early_passes.push(Box::new(factory));
+    }
+
+    /// Registers a late lint pass. `factory` is called for each linted file.
+    pub fn register_late_pass(
+        &mut self,
+        factory: impl Fn() -> Box<dyn LateLintPass> + Send + Sync + 'static,
+    ) {
+        self.late_passes.push(Box::new(factory));
+    }
+
+    /// Returns all registered lints.
+    pub fn lints(&self) -> impl Iterator<Item = &'static Lint> + '_ {
+        self.lints.iter().copied()
+    }
+
+    /// Returns the registered lint named `name`.
+    #[must_use]
+    pub fn find_lint(&self, name: &str) -> Option<&'static Lint> {
+        self.lints().find(|lint| lint.name == name)
+    }
+
+    /// Overrides the level of the lint named `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no lint named `name` is registered.
+    pub fn set_level(&mut self, name: &str, level: LintLevel) -> Result<(), UnknownLintError> {
+        let lint = self
+            .find_lint(name)
+            .ok_or_else(|| UnknownLintError(name.to_owned()))?;
+        self.levels.insert(LintId::of(lint), level);
+        Ok(())
+    }
+
+    /// Returns the effective level of `lint`.
+    #[must_use]
+    pub fn level(&self, lint: LintId) -> LintLevel {
+        self.levels
+            .get(&lint)
+            .copied()
+            .unwrap_or(lint.lint().default_level)
+    }
+
+    /// Applies the configured levels and sorts the lints by position.
+    fn finish(&self, lints: Vec<EmittedLint>) -> Vec<EmittedLint> {
+        let mut lints = lints
+            .into_iter()
+            .filter_map(|mut lint| {
+                lint.severity = self.level(lint.id).severity()?;
+                Some(lint)
+            })
+            .collect::<Vec<_>>();
+        lints.sort_by_key(|lint| lint.span.primary_spans.first().map(|span| span.start));
+        lints
+    }
+
+    /// Runs all registered early lint passes on `file` in a single traversal.
+    #[must_use]
+    pub fn lint_early(
+        &self,
+        file: &ast::SourceFile,
+        src: &str,
+        is_main_file: bool,
+    ) -> Vec<EmittedLint> {
+        self.finish(self.run_early(file, src, is_main_file))
+    }
+
+    /// Runs all registered late lint passes on `file` in a single traversal.
+    ///
+    /// `src` is the text of the file `file` was computed from. Passes see the nodes and
+    /// properties of the files it includes too, but only lints in `src` are returned.
+    #[must_use]
+    pub fn lint_late(&self, file: &Stage2File, src: &str) -> Vec<EmittedLint> {
+        self.finish(self.run_late(file, src))
+    }
+
+    /// Runs the early and late lint passes on `file`, without its includes.
+    ///
+    /// Diagnostics from the analyzer are ignored.
+    #[must_use]
+    pub fn lint_file(
+        &self,
+        file: &ast::SourceFile,
+        src: &str,
+        is_main_file: bool,
+    ) -> Vec<EmittedLint> {
+        self.lint_file_with_includes(file, src, is_main_file, &SourceId::from(""), &[])
+    }
+
+    /// Runs the early and late lint passes on `file`, whose ID is `source_id`.
+    ///
+    /// Like with [`lint_late`](Self::lint_late), late passes see the tree merged with `includes`,
+    /// but only lints in `file` are returned. Diagnostics from the analyzer are ignored.
+    #[must_use]
+    pub fn lint_file_with_includes(
+        &self,
+        file: &ast::SourceFile,
+        src: &str,
+        is_main_file: bool,
+        source_id: &SourceId,
+        includes: &[ResolvedInclude<'_>],
+    ) -> Vec<EmittedLint> {
+        self.finish(self.run_file(file, src, is_main_file, source_id, includes))
+    }
+
+    fn run_early(&self, file: &ast::SourceFile, src: &str, is_main_file: bool) -> Vec<EmittedLint> {
+        let mut cx = EarlyContext {
+            lints: Vec::new(),
+            src,
+            is_main_file,
+        };
+        let mut combined = CombinedEarlyLintPass {
+            cx: &mut cx,
+            passes: self.early_passes.iter().map(|factory| factory()).collect(),
+        };
+        combined.visit_source_file(file);
+        cx.lints
+    }
+
+    fn run_late(&self, file: &Stage2File, src: &str) -> Vec<EmittedLint> {
+        let mut cx = LateContext {
+            lints: Vec::new(),
+            included_lints: Vec::new(),
+            src,
+            source_id: &file.source_id,
+        };
+        let mut passes = self
+            .late_passes
+            .iter()
+            .map(|factory| factory())
+            .collect::<Vec<_>>();
+
+        for pass in &mut passes {
+            pass.check_file(&mut cx, file);
+        }
+        walk_late(&mut cx, &mut passes, &mut Vec::new(), &file.root_node);
+        cx.lints
+    }
+
+    fn run_file(
+        &self,
+        file: &ast::SourceFile,
+        src: &str,
+        is_main_file: bool,
+        source_id: &SourceId,
+        includes: &[ResolvedInclude<'_>],
+    ) -> Vec<EmittedLint> {
+        let mut lints = self.run_early(file, src, is_main_file);
+
+        let diag = std::sync::Mutex::new(Vec::new());
+        let outline = dt_analyzer::new::stage1::analyze_file(file, src, &diag);
+        let stage2 = dt_analyzer::new::stage2::compute(source_id, &outline, includes, &diag);
+        lints.extend(self.run_late(&stage2, src));
+        lints
+    }
+
+    /// Lints the [examples](Lint::bad_examples) of all registered lints as main files and returns
+    /// the ones which don't behave as documented.
+    ///
+    /// Levels are ignored, so examples of allowed lints are checked too.
+    #[must_use]
+    pub fn check_examples(&self) -> Vec<ExampleError> {
+        let lint_example = |lint: &'static Lint, src: &str| {
+            let file = ast::SourceFile::parse(src).source_file();
+            let mut lints = self.run_file(&file, src, true, &SourceId::from(""), &[]);
+            lints.retain(|emitted| emitted.id == LintId::of(lint));
+            lints
+        };
+
+        let mut errors = Vec::new();
+        for lint in self.lints() {
+            for (index, src) in lint.bad_examples.iter().enumerate() {
+                if lint_example(lint, src).is_empty() {
+                    errors.push(ExampleError::BadNotTriggered {
+                        lint: lint.name,
+                        index,
+                    });
+                }
+            }
+            for (index, src) in lint.good_examples.iter().enumerate() {
+                if let Some(emitted) = lint_example(lint, src).first() {
+                    errors.push(ExampleError::GoodTriggered {
+                        lint: lint.name,
+                        index,
+                        msg: emitted.msg.to_string(),
+                    });
+                }
+            }
+        }
+        errors
+    }
+}
+
+fn walk_late<'a>(
+    cx: &mut LateContext<'_>,
+    passes: &mut [Box<dyn LateLintPass>],
+    path: &mut Vec<&'a str>,
+    node: &'a Stage2Node,
+) {
+    for pass in passes.iter_mut() {
+        pass.check_node(cx, path, node);
+    }
+    // Sort for deterministic order
+    let mut children = node.children.iter().collect::<Vec<_>>();
+    children.sort_unstable_by_key(|(name, _)| *name);
+
+    for (name, child) in children {
+        match child {
+            Stage2Tree::Prop(property) => {
+                for pass in passes.iter_mut() {
+                    pass.check_property(cx, path, name, property);
+                }
+            }
+            Stage2Tree::Node(child) => {
+                path.push(name);
+                walk_late(cx, passes, path, child);
+                path.pop();
+            }
+        }
+    }
+}
+
+/// Dispatches every visited item to all passes.
+struct CombinedEarlyLintPass<'a, 'i> {
+    cx: &'a mut EarlyContext<'i>,
+    passes: Vec<Box<dyn EarlyLintPass>>,
+}
+
+impl Visitor for CombinedEarlyLintPass<'_, '_> {
+    fn visit_source_file(&mut self, file: &ast::SourceFile) {
+        for pass in &mut self.passes {
+            pass.check_source_file(self.cx, file);
+        }
+        visit::walk_source_file(self, file);
+    }
+    fn visit_directive(&mut self, directive: &ast::Directive) {
+        for pass in &mut self.passes {
+            pass.check_directive(self.cx, directive);
+        }
+    }
+    fn visit_node(&mut self, node: &ast::DtNode) {
+        for pass in &mut self.passes {
+            pass.check_node(self.cx, node);
+        }
+        visit::walk_node(self, node);
+        for pass in &mut self.passes {
+            pass.check_node_post(self.cx, node);
+        }
+    }
+    fn visit_property(&mut self, property: &ast::DtProperty) {
+        for pass in &mut self.passes {
+            pass.check_property(self.cx, property);
+        }
+        visit::walk_property(self, property);
+    }
+    fn visit_label(&mut self, label: &ast::DtLabel) {
+        for pass in &mut self.passes {
+            pass.check_label(self.cx, label);
+        }
+    }
+    fn visit_cell(&mut self, cell: &ast::Cell) {
+        for pass in &mut self.passes {
+            pass.check_cell(self.cx, cell);
+        }
+        visit::walk_cell(self, cell);
+    }
+}
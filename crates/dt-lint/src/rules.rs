@@ -0,0 +1,446 @@
+//! Declarative lint rules loaded from TOML files.
+//!
+//! Rule files are listed in the workspace configuration's `rule-files`:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "acme_foo_power_domains"
+//! message = "`acme,foo` nodes must have power domains"
+//! # Optional, "warn" by default
+//! level = "deny"
+//! # All given selectors must match
+//! select = { compatible = "acme,foo", path = "/soc/*" }
+//! required-properties = ["power-domains"]
+//! forbidden-properties = ["regulator-always-on"]
+//! # Optional documentation, shown by `dt explain`
+//! rationale = "The power domain must be enabled before probing."
+//! bad-examples = ["/dts-v1/;\n/ { foo { compatible = \"acme,foo\"; }; };"]
+//! good-examples = []
+//! url = "https://wiki.example.com/acme-foo"
+//! ```
+//!
+//! Paths may contain `*` wildcards, which match any characters in a single node name.
+
+use std::{path::Path, sync::Arc};
+
+use dt_analyzer::{
+    new::stage2::{Stage2Node, Stage2Tree},
+    resolved_prop::Value,
+};
+use dt_parser::{
+    ast::{AstNode as _, AstToken as _, HasName as _},
+    SourceId,
+};
+use serde::Deserialize;
+
+use crate::{LateContext, LateLintPass, Lint, LintGroup, LintLevel, LintRegistry, MultiSpan};
+
+/// A declarative lint rule.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Rule {
+    /// The unique lint name of the rule
+    pub name: String,
+    /// The message of emitted lints
+    pub message: String,
+    #[serde(default = "default_level")]
+    pub level: LintLevel,
+    pub select: Selector,
+    /// Properties which selected nodes must have
+    #[serde(default)]
+    pub required_properties: Vec<String>,
+    /// Properties which selected nodes must not have
+    #[serde(default)]
+    pub forbidden_properties: Vec<String>,
+    /// Why the rule exists, shown by `dt explain`
+    #[serde(default)]
+    pub rationale: String,
+    /// Main files which must trigger the rule
+    #[serde(default)]
+    pub bad_examples: Vec<String>,
+    /// Main files which must not trigger the rule
+    #[serde(default)]
+    pub good_examples: Vec<String>,
+    /// Further documentation, e.g. a wiki page
+    pub url: Option<String>,
+}
+
+fn default_level() -> LintLevel {
+    LintLevel::Warn
+}
+
+/// Selects the nodes a [`Rule`] applies to. All given fields must match.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Selector {
+    /// Matches nodes which have this string in their `compatible` property
+    pub compatible: Option<String>,
+    /// Matches nodes by their full path, e.g. `/soc/serial@*`
+    pub path: Option<String>,
+}
+
+/// The contents of a rule file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleFile {
+    #[serde(rename = "rule", default)]
+    pub rules: Vec<Rule>,
+}
+
+impl RuleFile {
+    /// Parses a rule file from TOML.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `text` isn't a valid rule file.
+    pub fn parse(text: &str) -> Result<Self, RuleError> {
+        let file: Self = toml::from_str(text)?;
+        for rule in &file.rules {
+            if rule.select.compatible.is_none() && rule.select.path.is_none() {
+                return Err(RuleError::EmptySelector(rule.name.clone()));
+            }
+        }
+        Ok(file)
+    }
+
+    /// Reads and parses a rule file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or isn't a valid rule file.
+    pub fn load(path: &Path) -> Result<Self, RuleError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Errors encountered when loading rule files
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum RuleError {
+    /// failed to read rule file: {0}
+    Io(#[from] std::io::Error),
+    /// failed to parse rule file: {0}
+    Toml(#[from] toml::de::Error),
+    /// rule `{0}` must select nodes by `compatible` or `path`
+    EmptySelector(String),
+    /// a lint named `{0}` already exists
+    DuplicateLint(String),
+}
+
+/// A rule with its leaked [`Lint`]
+#[derive(Debug)]
+struct CompiledRule {
+    lint: &'static Lint,
+    rule: Rule,
+}
+
+impl LintRegistry {
+    /// Registers the rules as lints and adds a late lint pass evaluating them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a rule's name is already used by another lint. No rules are registered
+    /// in that case.
+    pub fn register_rules(&mut self, rules: Vec<Rule>) -> Result<(), RuleError> {
+        for (i, rule) in rules.iter().enumerate() {
+            if self.find_lint(&rule.name).is_some()
+                || rules[..i].iter().any(|other| other.name == rule.name)
+            {
+                return Err(RuleError::DuplicateLint(rule.name.clone()));
+            }
+        }
+
+        let rules: Arc<[CompiledRule]> = rules
+            .into_iter()
+            .map(|rule| CompiledRule {
+                // Rules are loaded once per workspace, so leaking is fine
+                lint: Box::leak(Box::new(Lint {
+                    name: leak_str(&rule.name),
+                    default_level: rule.level,
+                    desc: leak_str(&rule.message),
+                    group: LintGroup::Custom,
+                    rationale: leak_str(&rule.rationale),
+                    bad_examples: leak_strs(&rule.bad_examples),
+                    good_examples: leak_strs(&rule.good_examples),
+                    url: rule.url.as_deref().map(leak_str),
+                })),
+                rule,
+            })
+            .collect();
+
+        self.register_lints(&rules.iter().map(|rule| rule.lint).collect::<Vec<_>>());
+        self.register_late_pass(move || Box::new(RulePass(rules.clone())));
+        Ok(())
+    }
+}
+
+fn leak_str(s: &str) -> &'static str {
+    Box::leak(s.into())
+}
+
+fn leak_strs(strs: &[String]) -> &'static [&'static str] {
+    Box::leak(strs.iter().map(|s| leak_str(s)).collect())
+}
+
+/// Evaluates [`Rule`]s on every node.
+struct RulePass(Arc<[CompiledRule]>);
+
+/// Returns true if `path` matches `pattern`, where `*` matches any characters except `/`.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == path,
+        Some((prefix, rest)) => {
+            let Some(path) = path.strip_prefix(prefix) else {
+                return false;
+            };
+            // Try every split point within the current path segment
+            let segment_end = path.find('/').unwrap_or(path.len());
+            (0..=segment_end).any(|i| path_matches(rest, &path[i..]))
+        }
+    }
+}
+
+fn has_compatible(node: &Stage2Node, compatible: &str) -> bool {
+    matches!(
+        node.children.get("compatible"),
+        Some(Stage2Tree::Prop(prop)) if prop.values.iter().any(|value| matches!(value, Value::String(s) if s == compatible))
+    )
+}
+
+impl LateLintPass for RulePass {
+    fn check_node(&mut self, cx: &mut LateContext<'_>, path: &[&str], node: &Stage2Node) {
+        let full_path = format!("/{}", path.join("/"));
+
+        for CompiledRule { lint, rule } in self.0.iter() {
+            if rule
+                .select
+                .compatible
+                .as_ref()
+                .is_some_and(|compatible| !has_compatible(node, compatible))
+                || rule
+                    .select
+                    .path
+                    .as_ref()
+                    .is_some_and(|pattern| !path_matches(pattern, &full_path))
+            {
+                continue;
+            }
+
+            // The last AST, e.g. a `&label { ... }` override in the linted file
+            let Some((node_source_id, ast)) = node.asts.last() else {
+                continue;
+            };
+            let node_span = ast.name().map_or_else(
+                || ast.syntax().text_range(),
+                |name| name.syntax().text_range(),
+            );
+            let selected_by = |source_id: &SourceId| match node.children.get("compatible") {
+                // A lint's spans must all be in one file
+                Some(Stage2Tree::Prop(prop))
+                    if rule.select.compatible.is_some() && prop.source_id == *source_id =>
+                {
+                    vec![(
+                        prop.ast.syntax().text_range(),
+                        "selected by this `compatible`".into(),
+                    )]
+                }
+                _ => Vec::new(),
+            };
+
+            for required in &rule.required_properties {
+                if !matches!(node.children.get(required), Some(Stage2Tree::Prop(_))) {
+                    cx.add_lint(
+                        node_source_id,
+                        lint,
+                        format!("{}: missing `{required}`", rule.message),
+                        MultiSpan {
+                            primary_spans: vec![node_span],
+                            span_labels: selected_by(node_source_id),
+                        },
+                    );
+                }
+            }
+            for forbidden in &rule.forbidden_properties {
+                if let Some(Stage2Tree::Prop(prop)) = node.children.get(forbidden) {
+                    cx.add_lint(
+                        &prop.source_id,
+                        lint,
+                        format!("{}: `{forbidden}` is not allowed", rule.message),
+                        MultiSpan {
+                            primary_spans: vec![prop.ast.syntax().text_range()],
+                            span_labels: selected_by(&prop.source_id),
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dt_analyzer::new::{stage1::AnalyzedToplevel, stage2::ResolvedInclude};
+    use dt_parser::ast::SourceFile;
+
+    use super::*;
+    use crate::{EmittedLint, LintSeverity};
+
+    const RULES: &str = r#"
+[[rule]]
+name = "acme_foo_power_domains"
+message = "`acme,foo` nodes must have power domains"
+level = "deny"
+select = { compatible = "acme,foo" }
+required-properties = ["power-domains"]
+bad-examples = ['/dts-v1/; / { foo { compatible = "acme,foo"; }; };']
+good-examples = ['/dts-v1/; / { foo { compatible = "acme,foo"; power-domains = <1>; }; };']
+
+[[rule]]
+name = "no_always_on"
+message = "no always-on regulators"
+select = { path = "/regulators/*" }
+forbidden-properties = ["regulator-always-on"]
+"#;
+
+    fn lint(src: &str) -> Vec<EmittedLint> {
+        let mut registry = LintRegistry::new();
+        registry
+            .register_rules(RuleFile::parse(RULES).unwrap().rules)
+            .unwrap();
+
+        let file = SourceFile::parse(src).source_file();
+        let diag = std::sync::Mutex::new(Vec::new());
+        let outline = dt_analyzer::new::stage1::analyze_file(&file, src, &diag);
+        let stage2 =
+            dt_analyzer::new::stage2::compute(&SourceId::from("test.dts"), &outline, &[], &diag);
+        registry.lint_late(&stage2, src)
+    }
+
+    #[test]
+    fn examples() {
+        let mut registry = LintRegistry::new();
+        registry
+            .register_rules(RuleFile::parse(RULES).unwrap().rules)
+            .unwrap();
+        assert_eq!(registry.check_examples(), []);
+
+        let lint = registry.find_lint("acme_foo_power_domains").unwrap();
+        assert_eq!(lint.bad_examples.len(), 1);
+        assert!(lint.explanation().contains("## Bad examples"));
+    }
+
+    #[test]
+    fn path_glob() {
+        assert!(path_matches("/", "/"));
+        assert!(path_matches("/soc/serial@*", "/soc/serial@1000"));
+        assert!(path_matches("/*/serial@*", "/soc/serial@1000"));
+        assert!(path_matches("/soc/*", "/soc/serial@1000"));
+        assert!(!path_matches("/soc/*", "/soc/serial@1000/child"));
+        assert!(!path_matches("/soc/*", "/soc"));
+    }
+
+    #[test]
+    fn required_properties() {
+        let src = r#"/ { foo@0 { compatible = "acme,foo"; }; foo@1 { compatible = "acme,foo"; power-domains = <1>; }; };"#;
+        let lints = lint(src);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].id.name(), "acme_foo_power_domains");
+        assert_eq!(lints[0].severity, LintSeverity::Error);
+        assert_eq!(
+            lints[0].msg,
+            "`acme,foo` nodes must have power domains: missing `power-domains`"
+        );
+        assert_eq!(
+            lints[0].span.primary_spans[0].text(src),
+            Some("foo"),
+            "must point to the node name"
+        );
+        assert_eq!(
+            lints[0].span.span_labels[0].0.text(src),
+            Some(r#"compatible = "acme,foo";"#)
+        );
+    }
+
+    #[test]
+    fn forbidden_properties() {
+        let src =
+            "/ { regulators { vdd { regulator-always-on; }; }; vcc { regulator-always-on; }; };";
+        let lints = lint(src);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].severity, LintSeverity::Warn);
+        assert_eq!(
+            lints[0].span.primary_spans[0].text(src),
+            Some("regulator-always-on;")
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        let soc = r#"/ { foo: foo { compatible = "acme,foo"; }; regulators { vdd: vdd {}; }; };"#;
+        let board = "/include/ \"soc.dtsi\"\n&foo { power-domains = <1>; };\n&vdd { regulator-always-on; };";
+        let diag = std::sync::Mutex::new(Vec::new());
+        let soc_outline = dt_analyzer::new::stage1::analyze_file(
+            &SourceFile::parse(soc).source_file(),
+            soc,
+            &diag,
+        );
+        let board_file = SourceFile::parse(board).source_file();
+        let board_outline = dt_analyzer::new::stage1::analyze_file(&board_file, board, &diag);
+        let includes = [ResolvedInclude {
+            includer: SourceId::from("board.dts"),
+            text_range: board_outline
+                .iter()
+                .find_map(AnalyzedToplevel::as_include)
+                .unwrap()
+                .text_range,
+            source_id: SourceId::from("soc.dtsi"),
+            outline: &soc_outline,
+        }];
+
+        let mut registry = LintRegistry::new();
+        registry
+            .register_rules(RuleFile::parse(RULES).unwrap().rules)
+            .unwrap();
+        let lints = registry.lint_file_with_includes(
+            &board_file,
+            board,
+            true,
+            &SourceId::from("board.dts"),
+            &includes,
+        );
+        // `power-domains` is added by the override, and the regulator is linted in the board
+        assert_eq!(
+            lints
+                .iter()
+                .map(|lint| (lint.id.name(), lint.span.primary_spans[0].text(board)))
+                .collect::<Vec<_>>(),
+            [("no_always_on", Some("regulator-always-on;"))]
+        );
+        // The included file is linted on its own
+        let lints = lint(soc);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].id.name(), "acme_foo_power_domains");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            RuleFile::parse("[[rule]]\nname = \"a\"\nmessage = \"\"\nselect = {}"),
+            Err(RuleError::EmptySelector(name)) if name == "a"
+        ));
+        assert!(matches!(
+            RuleFile::parse("[[rule]]\nname = \"a\"\nmessage = \"\"\nselect = { compatible = \"a\" }\nunknown = 1"),
+            Err(RuleError::Toml(_))
+        ));
+
+        let mut registry = LintRegistry::builtin();
+        let rules = RuleFile::parse(
+            "[[rule]]\nname = \"dtc_style\"\nmessage = \"\"\nselect = { path = \"/\" }",
+        )
+        .unwrap()
+        .rules;
+        assert!(matches!(
+            registry.register_rules(rules),
+            Err(RuleError::DuplicateLint(name)) if name == "dtc_style"
+        ));
+    }
+}
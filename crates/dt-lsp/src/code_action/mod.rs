@@ -0,0 +1,538 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    position_to_byte_offset, range_to_lsp,
+    references::{NodeIndex, ReferenceKind},
+    Document,
+};
+use dt_binding_matcher::Bindings;
+use dt_diagnostic::{Applicability, Suggestion, TextEdit};
+use dt_parser::{
+    ast::{self, AstNode, AstToken, HasName},
+    cst::{NodeKind, RedItem, RedNode},
+    lexer::TokenKind,
+    SourceId, TextRange,
+};
+use tower_lsp::lsp_types::{
+    self, CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeActionResponse,
+    Url, WorkspaceEdit,
+};
+
+/// Returns true if `a` and `b` overlap or touch.
+///
+/// Empty ranges, e.g. the cursor, must match the diagnostics around them.
+fn touches(a: TextRange, b: TextRange) -> bool {
+    a.start <= b.end && b.start <= a.end
+}
+
+pub fn code_action(state: &crate::Backend, params: CodeActionParams) -> Option<CodeActionResponse> {
+    let uri = params.text_document.uri;
+    let source_id = SourceId::from(uri.as_str());
+    let document = state.state.document_map.get(&source_id)?;
+    let rope = &document.text;
+
+    let range = TextRange::new(
+        position_to_byte_offset(params.range.start, rope)?,
+        position_to_byte_offset(params.range.end, rope)?,
+    );
+
+    let suggestions = document
+        .lints
+        .iter()
+        .filter(|lint| {
+            lint.span
+                .primary_spans
+                .iter()
+                .any(|span| touches(*span, range))
+        })
+        .flat_map(|lint| &lint.suggestions)
+        .chain(
+            document
+                .diagnostics
+                .iter()
+                .filter(|diag| {
+                    diag.span
+                        .primary_spans
+                        .iter()
+                        .any(|span| touches(*span, range))
+                })
+                .flat_map(|diag| &diag.suggestions),
+        );
+
+    let mut actions = suggestions
+        .filter_map(|suggestion| to_code_action(suggestion, CodeActionKind::QUICKFIX, &uri, rope))
+        .collect::<CodeActionResponse>();
+
+    let bindings = state.state.bindings.read();
+    let mut refactors = Vec::new();
+    refactors.extend(
+        add_required_properties(&document, &bindings, range)
+            .map(|suggestion| (suggestion, CodeActionKind::REFACTOR)),
+    );
+    refactors.extend(
+        sort_properties(&document, range)
+            .map(|suggestion| (suggestion, CodeActionKind::REFACTOR_REWRITE)),
+    );
+    let files = state.reachable_files(&uri);
+    if let Some(file) = files.iter().position(|file| file.uri == uri) {
+        let index = NodeIndex::new(&files);
+        refactors.extend(
+            path_to_label_reference(&index, file, range)
+                .map(|suggestion| (suggestion, CodeActionKind::REFACTOR_REWRITE)),
+        );
+        refactors.extend(
+            extract_to_override(&index, file, range)
+                .map(|suggestion| (suggestion, CodeActionKind::REFACTOR_EXTRACT)),
+        );
+    }
+    actions.extend(
+        refactors
+            .iter()
+            .filter_map(|(suggestion, kind)| to_code_action(suggestion, kind.clone(), &uri, rope)),
+    );
+    Some(actions)
+}
+
+fn to_code_action(
+    suggestion: &Suggestion,
+    kind: CodeActionKind,
+    uri: &Url,
+    rope: &ropey::Rope,
+) -> Option<CodeActionOrCommand> {
+    let edits = suggestion
+        .edits
+        .iter()
+        .map(|edit| {
+            Some(lsp_types::TextEdit {
+                range: range_to_lsp(edit.range, rope)?,
+                new_text: edit.replacement.clone().into_owned(),
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let is_preferred = kind == CodeActionKind::QUICKFIX && suggestion.is_machine_applicable();
+    Some(CodeActionOrCommand::CodeAction(CodeAction {
+        title: suggestion.msg.clone().into_owned(),
+        kind: Some(kind),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), edits)])),
+            ..Default::default()
+        }),
+        is_preferred: is_preferred.then_some(true),
+        ..Default::default()
+    }))
+}
+
+/// Returns the start of the line containing `offset`.
+fn line_start(src: &str, offset: usize) -> usize {
+    src[..offset].rfind('\n').map_or(0, |newline| newline + 1)
+}
+
+/// Returns the whitespace before `offset` on its line.
+fn indent_at(src: &str, offset: usize) -> &str {
+    let line = &src[line_start(src, offset)..offset];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Returns the innermost node in `file` containing `range`.
+fn innermost_node(file: &ast::SourceFile, range: TextRange) -> Option<ast::DtNode> {
+    let contains = |node: &ast::DtNode| {
+        let node_range = node.syntax().text_range();
+        node_range.start <= range.start && range.end <= node_range.end
+    };
+    let mut node = file.nodes().find(contains)?;
+    loop {
+        let Some(subnode) = node.subnodes().find(contains) else {
+            break;
+        };
+        node = subnode;
+    }
+    Some(node)
+}
+
+/// Returns an edit adding the required properties from the binding of the `compatible` at `range`
+/// which its node doesn't have yet, from the [node skeleton](dt_binding_matcher::NodeSkeleton).
+///
+/// The properties are added at the end of the node, indented by one tab more than the node.
+fn add_required_properties(
+    document: &Document,
+    bindings: &Bindings,
+    range: TextRange,
+) -> Option<Suggestion> {
+    let file = document.file.as_ref()?;
+    let src = document.text.to_string();
+    let name_of = |property: &ast::DtProperty| Some(property.name()?.syntax().text().to_string());
+
+    let mut stack = file.nodes().collect::<Vec<_>>();
+    let (node, compatible) = loop {
+        let node = stack.pop()?;
+        stack.extend(node.subnodes());
+        let compatible = node.properties().find(|property| {
+            name_of(property).as_deref() == Some("compatible")
+                && touches(property.syntax().text_range(), range)
+        });
+        if let Some(compatible) = compatible {
+            break (node, compatible);
+        }
+    };
+
+    let (compatible, skeleton) = compatible.values().find_map(|value| {
+        let ast::PropValue::String(token) = value else {
+            return None;
+        };
+        let compatible = token.text().trim_matches('"').to_owned();
+        let skeleton = bindings.node_skeleton(&compatible)?;
+        Some((compatible, skeleton))
+    })?;
+    let existing = node
+        .properties()
+        .filter_map(|property| name_of(&property))
+        .collect::<Vec<_>>();
+    let missing = skeleton
+        .properties
+        .iter()
+        .filter(|property| !existing.contains(&property.name))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return None;
+    }
+
+    let closing_brace = node
+        .syntax()
+        .child_tokens()
+        .find(|token| token.green.kind == TokenKind::RCurly)?
+        .text_range()
+        .start;
+    let node_indent = indent_at(&src, node.syntax().text_range().start);
+
+    let brace_line_start = line_start(&src, closing_brace);
+    let own_line = src[brace_line_start..closing_brace].trim().is_empty();
+    let (range, mut text) = if own_line {
+        (
+            TextRange::new(brace_line_start, brace_line_start),
+            String::new(),
+        )
+    } else {
+        // The closing brace is on the same line as other items, e.g. `foo { compatible = "x"; };`
+        let content_end = src[..closing_brace].trim_end().len();
+        (TextRange::new(content_end, closing_brace), "\n".to_owned())
+    };
+    for property in &missing {
+        text.push_str(node_indent);
+        text.push('\t');
+        text.push_str(&property.to_dts());
+        text.push('\n');
+    }
+    if !own_line {
+        text.push_str(node_indent);
+    }
+
+    Some(Suggestion::new(
+        format!("Add required properties of `{compatible}`"),
+        vec![TextEdit::new(range, text)],
+        Applicability::HasPlaceholders,
+    ))
+}
+
+/// Returns the group of a property in the order of the kernel's DTS coding style: `compatible`,
+/// `reg`, `ranges`, standard properties, vendor-specific properties and `status`.
+fn property_group(name: &str) -> u8 {
+    match name {
+        "compatible" => 0,
+        "reg" => 1,
+        "ranges" => 2,
+        "status" => 5,
+        _ if name.contains(',') => 4,
+        _ => 3,
+    }
+}
+
+/// Returns an edit sorting the properties of the innermost node at `range` like the kernel's DTS
+/// coding style, keeping the order within each group.
+///
+/// Nodes with preprocessor directives between their items are left alone, because moving a
+/// property across a directive could change its meaning.
+fn sort_properties(document: &Document, range: TextRange) -> Option<Suggestion> {
+    let file = document.file.as_ref()?;
+    let src = document.text.to_string();
+    let node = innermost_node(file, range)?;
+    if has_directives(node.syntax()) {
+        return None;
+    }
+
+    let properties = node
+        .properties()
+        .map(|property| {
+            let name = property.name()?.syntax().text().to_string();
+            Some((property_group(&name), property))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let mut sorted = properties.clone();
+    sorted.sort_by_key(|(group, _)| *group);
+    let edits = properties
+        .iter()
+        .zip(&sorted)
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((_, old), (_, new))| {
+            TextEdit::new(
+                old.syntax().text_range(),
+                src[new.syntax().text_range().byte_range()].to_owned(),
+            )
+        })
+        .collect::<Vec<_>>();
+    if edits.is_empty() {
+        return None;
+    }
+    Some(Suggestion::new(
+        "Sort properties",
+        edits,
+        Applicability::MachineApplicable,
+    ))
+}
+
+/// Returns true if there are preprocessor directives in `node`, not counting its subnodes.
+fn has_directives(node: &Arc<RedNode>) -> bool {
+    node.children().any(|child| match child {
+        RedItem::Node(child) => child.green.kind != NodeKind::DtNode && has_directives(&child),
+        RedItem::Token(token) => token.green.kind.is_preprocessor_directive(),
+    })
+}
+
+/// Returns an edit replacing the `&{/path}` reference at `range` with a `&label` reference to the
+/// same node.
+fn path_to_label_reference(index: &NodeIndex, file: usize, range: TextRange) -> Option<Suggestion> {
+    let src = &index.files[file].text;
+    let (phandle, path) = index.references.iter().find_map(|reference| {
+        if reference.file != file || !matches!(reference.kind, ReferenceKind::Path(_)) {
+            return None;
+        }
+        // Include `{` and `}` around the path
+        let start = src[..reference.range.start].rfind('{')?;
+        let end = reference.range.end + src[reference.range.end..].find('}')? + 1;
+        let phandle = TextRange::new(start, end);
+        touches(phandle, range).then(|| Some((phandle, index.resolve(&reference.kind)?)))?
+    })?;
+    let label = index
+        .definitions_of(&path)
+        .find_map(|definition| definition.labels.first())?
+        .0
+        .clone();
+    Some(Suggestion::new(
+        format!("Convert to label reference `&{label}`"),
+        vec![TextEdit::new(phandle, label)],
+        Applicability::MachineApplicable,
+    ))
+}
+
+/// Returns an edit moving the node whose name or label is at `range` to a `&label { ... };`
+/// override at the end of its file, e.g. to keep a board file flat.
+///
+/// The label must come from another definition of the node, as the override doesn't create the
+/// node and would drop the node's own labels.
+fn extract_to_override(index: &NodeIndex, file: usize, range: TextRange) -> Option<Suggestion> {
+    let src = &index.files[file].text;
+    let definition = index
+        .nodes
+        .iter()
+        .find(|node| node.file == file && !node.ast.is_root() && touches(node.name_range, range))?;
+    if !definition.labels.is_empty() {
+        return None;
+    }
+    let label = index
+        .definitions_of(&definition.path)
+        .filter(|other| other.file != file || other.name_range != definition.name_range)
+        .find_map(|other| other.labels.first())?
+        .0
+        .clone();
+
+    let syntax = definition.ast.syntax();
+    let brace = |kind: TokenKind| {
+        syntax
+            .child_tokens()
+            .find(|token| token.green.kind == kind)
+            .map(|token| token.text_range())
+    };
+    let body = src.get(brace(TokenKind::LCurly)?.end..brace(TokenKind::RCurly)?.start)?;
+    let node_range = syntax.text_range();
+    let node_indent = indent_at(src, node_range.start);
+
+    let separator = if src.ends_with('\n') { "\n" } else { "\n\n" };
+    let mut text = format!("{separator}&{label} {{");
+    let body = body.trim_end();
+    if body.contains('\n') {
+        let mut lines = body.split('\n');
+        text.push_str(lines.next().unwrap_or_default());
+        for line in lines {
+            text.push('\n');
+            text.push_str(line.strip_prefix(node_indent).unwrap_or(line));
+        }
+        text.push_str("\n};\n");
+    } else {
+        text.push_str(body);
+        text.push_str(" };\n");
+    }
+
+    // Remove the whole lines of the node if nothing else is on them
+    let mut removed = node_range;
+    if src[line_start(src, removed.start)..removed.start]
+        .trim()
+        .is_empty()
+    {
+        removed.start = line_start(src, removed.start);
+        let rest = &src[removed.end..];
+        let line_end = rest.find('\n').map_or(rest.len(), |newline| newline + 1);
+        if rest[..line_end].trim().is_empty() {
+            removed.end += line_end;
+        }
+    }
+    Some(Suggestion::new(
+        format!("Extract to `&{label}` override"),
+        vec![
+            TextEdit::new(removed, ""),
+            TextEdit::new(TextRange::new(src.len(), src.len()), text),
+        ],
+        Applicability::MaybeIncorrect,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dt_diagnostic::apply_suggestions;
+
+    use crate::tests::{bindings, cursor, snapshots_at_cursor};
+
+    fn document(src: &str) -> Document {
+        Document {
+            text: ropey::Rope::from_str(src),
+            version: None,
+            file: Some(ast::SourceFile::parse(src).source_file()),
+            analyzed: None,
+            lints: Vec::new(),
+            diagnostics: Vec::new(),
+            parse: None,
+            lsp_diagnostics: Vec::new(),
+        }
+    }
+
+    fn apply(src: &str, suggestion: &Suggestion) -> String {
+        let (fixed, skipped) = apply_suggestions(src, [suggestion]);
+        assert!(skipped.is_empty());
+        fixed
+    }
+
+    /// Applies the edit for the cursor at the first `|` in `src`.
+    fn add(bindings: &Bindings, src: &str) -> Option<String> {
+        let (src, cursor) = cursor(src);
+        let suggestion =
+            add_required_properties(&document(&src), bindings, TextRange::new(cursor, cursor))?;
+        Some(apply(&src, &suggestion))
+    }
+
+    fn sort(src: &str) -> Option<String> {
+        let (src, cursor) = cursor(src);
+        let suggestion = sort_properties(&document(&src), TextRange::new(cursor, cursor))?;
+        Some(apply(&src, &suggestion))
+    }
+
+    /// Applies `refactor` for the cursor at the first `|` in the first of `files`.
+    fn refactor(
+        files: &[(&str, &str)],
+        refactor: fn(&NodeIndex, usize, TextRange) -> Option<Suggestion>,
+    ) -> Option<String> {
+        let (files, cursor) = snapshots_at_cursor(files);
+        let suggestion = refactor(&NodeIndex::new(&files), 0, TextRange::new(cursor, cursor))?;
+        Some(apply(&files[0].text, &suggestion))
+    }
+
+    #[test]
+    fn required_properties() {
+        let bindings = bindings();
+        assert_eq!(
+            add(
+                &bindings,
+                "/ {\n\tfoo@0 {\n\t\tcompatible = \"acme,|foo\";\n\t\treg = <0 1>;\n\t};\n};\n"
+            )
+            .unwrap(),
+            "/ {\n\tfoo@0 {\n\t\tcompatible = \"acme,foo\";\n\t\treg = <0 1>;\n\t\t#clock-cells = <1>;\n\t};\n};\n"
+        );
+        assert_eq!(
+            add(&bindings, "/ {\n\tfoo { compatible = |\"acme,foo\"; };\n};\n").unwrap(),
+            "/ {\n\tfoo { compatible = \"acme,foo\";\n\t\treg = <0x0 0x1000>;\n\t\t#clock-cells = <1>;\n\t};\n};\n"
+        );
+        assert_eq!(
+            add(
+                &bindings,
+                "/ {\n\tfoo { |compatible = \"acme,bar\"; };\n};\n"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn sorting() {
+        assert_eq!(
+            sort("/ {\n\tfoo@0 {\n\t\tstatus = \"okay\";\n\t\tti,x;\n\t\tclocks = <1>;\n\t\treg = <0>;|\n\t\tcompatible = \"acme,foo\";\n\t\tbar {};\n\t};\n};\n").unwrap(),
+            "/ {\n\tfoo@0 {\n\t\tcompatible = \"acme,foo\";\n\t\treg = <0>;\n\t\tclocks = <1>;\n\t\tti,x;\n\t\tstatus = \"okay\";\n\t\tbar {};\n\t};\n};\n"
+        );
+        // Already sorted
+        assert_eq!(
+            sort("/ { foo { compatible = \"a\"; |reg = <0>; b; }; };"),
+            None
+        );
+        assert_eq!(
+            sort("/ { foo { |b;\n#ifdef X\nreg = <0>;\n#endif\n}; };"),
+            None
+        );
+    }
+
+    #[test]
+    fn label_reference() {
+        let files = [
+            ("board.dts", "/ { x = <&{/soc/|uart@1000}>; };\n"),
+            (
+                "soc.dtsi",
+                "/ { soc { uart0: uart@1000 {}; uart@2000 {}; }; };\n",
+            ),
+        ];
+        assert_eq!(
+            refactor(&files, path_to_label_reference).unwrap(),
+            "/ { x = <&uart0>; };\n"
+        );
+        let files = [
+            ("board.dts", "/ { x = <&{/soc/|uart@2000}>; };\n"),
+            files[1],
+        ];
+        assert_eq!(refactor(&files, path_to_label_reference), None);
+    }
+
+    #[test]
+    fn override_extraction() {
+        let dtsi = ("soc.dtsi", "/ { soc { uart0: uart@1000 {}; }; };\n");
+        let files = [
+            (
+                "board.dts",
+                "/ {\n\tsoc {\n\t\t|uart@1000 {\n\t\t\tstatus = \"okay\";\n\n\t\t\tbt {};\n\t\t};\n\t};\n};\n",
+            ),
+            dtsi,
+        ];
+        assert_eq!(
+            refactor(&files, extract_to_override).unwrap(),
+            "/ {\n\tsoc {\n\t};\n};\n\n&uart0 {\n\tstatus = \"okay\";\n\n\tbt {};\n};\n"
+        );
+        let files = [
+            (
+                "board.dts",
+                "/ { soc { |uart@1000 { status = \"okay\"; }; }; };",
+            ),
+            dtsi,
+        ];
+        assert_eq!(
+            refactor(&files, extract_to_override).unwrap(),
+            "/ { soc {  }; };\n\n&uart0 { status = \"okay\"; };\n"
+        );
+        // The only definition can't be extracted
+        let files = [("board.dts", "/ { soc { uart0: |uart@1000 {}; }; };")];
+        assert_eq!(refactor(&files, extract_to_override), None);
+    }
+}
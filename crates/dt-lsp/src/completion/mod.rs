@@ -0,0 +1,503 @@
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{position_to_byte_offset, range_to_lsp, references::NodeIndex, Snapshot};
+use dt_analyzer::new::stage1::AnalyzedToplevel;
+use dt_binding_matcher::{BindingDoc, Bindings, PropertyDoc};
+use dt_parser::{
+    ast::{self, AstNode, AstToken, HasName},
+    cst::RedNode,
+    lexer::TokenKind,
+    TextRange,
+};
+use ropey::Rope;
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionParams, CompletionResponse, CompletionTextEdit,
+    Documentation, MarkupContent, MarkupKind, TextEdit,
+};
+
+/// Characters after which clients should ask for completions
+pub const TRIGGER_CHARACTERS: &[&str] = &["&", "<", "\"", "/"];
+
+/// Where the cursor is, which decides what to complete
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Context {
+    /// After `&`
+    Label,
+    /// In the path of an `#include` or `/include/`, in the directory `dir`
+    Include { dir: String, quoted: bool },
+    /// In the value of the property `property`
+    Value { property: String },
+    /// Where a property or subnode name can be
+    Name,
+}
+
+/// Returns the range of the word being typed before `offset` and what is being completed.
+fn context(src: &str, offset: usize) -> Option<(TextRange, Context)> {
+    let before = src.get(..offset)?;
+    let line = &before[before.rfind('\n').map_or(0, |newline| newline + 1)..];
+
+    let directive = line.trim_start();
+    if directive.starts_with("#include") || directive.starts_with("/include/") {
+        let open = line.rfind(['<', '"'])?;
+        let path = &line[open + 1..];
+        if path.contains(['>', '"']) {
+            return None;
+        }
+        let dir = path.rfind('/').map_or("", |slash| &path[..=slash]);
+        let start = offset - (path.len() - dir.len());
+        return Some((
+            TextRange::new(start, offset),
+            Context::Include {
+                dir: dir.to_owned(),
+                quoted: line.as_bytes()[open] == b'"',
+            },
+        ));
+    }
+
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || ",._+#?-".contains(c);
+    let word_start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_name_char(*c))
+        .last()
+        .map_or(offset, |(index, _)| index);
+    let range = TextRange::new(word_start, offset);
+    let word = &before[word_start..];
+    if before[..word_start].ends_with('&') {
+        return (!word.contains(['.', ',', '#', '?', '+', '-'])).then_some((range, Context::Label));
+    }
+
+    let statement = &before[before.rfind([';', '{', '}']).map_or(0, |end| end + 1)..];
+    if let Some((name, _)) = statement.split_once('=') {
+        // Labels of the property, e.g. `l: status = "okay"`
+        let property = name.rsplit(':').next().unwrap_or(name).trim();
+        // Values are completed with their quotes or brackets
+        let start = if before[..word_start].ends_with(['"', '<']) {
+            word_start - 1
+        } else {
+            word_start
+        };
+        return Some((
+            TextRange::new(start, offset),
+            Context::Value {
+                property: property.to_owned(),
+            },
+        ));
+    }
+    (statement.trim_start() == word).then_some((range, Context::Name))
+}
+
+/// Returns the innermost node whose body contains `offset`.
+fn node_at(syntax: &Arc<RedNode>, offset: usize) -> Option<ast::DtNode> {
+    syntax
+        .child_nodes()
+        .filter(|child| {
+            let range = child.text_range();
+            range.start < offset && offset <= range.end
+        })
+        .find_map(|child| {
+            node_at(&child, offset).or_else(|| {
+                let node = ast::DtNode::cast(child.clone())?;
+                let body_start = child
+                    .child_tokens()
+                    .find(|token| token.green.kind == TokenKind::LCurly)?
+                    .text_range()
+                    .end;
+                // Until the closing brace
+                let body_end = child
+                    .child_tokens()
+                    .find(|token| token.green.kind == TokenKind::RCurly)
+                    .map_or(usize::MAX, |token| token.text_range().start);
+                (body_start <= offset && offset <= body_end).then_some(node)
+            })
+        })
+}
+
+fn markdown(value: String) -> Documentation {
+    Documentation::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value,
+    })
+}
+
+/// Returns the documentation of a property, e.g. for hover.
+pub(crate) fn property_documentation(binding: &BindingDoc, property: &PropertyDoc) -> String {
+    let mut paragraphs = Vec::new();
+    paragraphs.extend(property.description.clone());
+    if !property.values.is_empty() {
+        paragraphs.push(format!("Values: `{}`", property.values.join("`, `")));
+    }
+    let binding_name = binding
+        .title
+        .clone()
+        .unwrap_or_else(|| binding.path.display().to_string());
+    paragraphs.push(format!("From _{binding_name}_"));
+    paragraphs.join("\n\n")
+}
+
+/// Returns the completions at `offset` in `files[file]`.
+///
+/// `files` are the files whose labels and macros can be used, see
+/// [`Backend::reachable_files`](crate::Backend::reachable_files).
+pub(crate) fn completions(
+    files: &[Snapshot],
+    file: usize,
+    offset: usize,
+    bindings: &Bindings,
+    include_paths: &[PathBuf],
+) -> Vec<CompletionItem> {
+    let snapshot = &files[file];
+    let Some((range, context)) = context(&snapshot.text, offset) else {
+        return Vec::new();
+    };
+    tracing::debug!(?context, "Completing");
+    let index = NodeIndex::new(files);
+    let binding_of = |path: &str| index.binding_doc(path, bindings);
+    let node = node_at(snapshot.file.syntax(), offset);
+    let node_path = node.as_ref().and_then(|node| index.path_of(file, node));
+    let node_binding = node_path.as_deref().and_then(binding_of);
+
+    let mut items = match context {
+        Context::Label => index
+            .labels()
+            .map(|(label, path)| CompletionItem {
+                label: label.to_owned(),
+                kind: Some(CompletionItemKind::REFERENCE),
+                detail: Some(path.to_owned()),
+                documentation: binding_of(path)
+                    .and_then(|binding| binding.title.or(binding.description))
+                    .map(markdown),
+                ..Default::default()
+            })
+            .collect(),
+        Context::Include { dir, quoted } => {
+            let local_dir = quoted
+                .then(|| {
+                    snapshot
+                        .uri
+                        .to_file_path()
+                        .ok()?
+                        .parent()
+                        .map(PathBuf::from)
+                })
+                .flatten();
+            include_items(local_dir.iter().chain(include_paths), &dir)
+        }
+        Context::Value { property } => {
+            let mut items = Vec::new();
+            if let Some(binding) = &node_binding {
+                if let Some(doc) = binding.property(&property) {
+                    items.extend(doc.values.iter().map(|value| CompletionItem {
+                        label: value.clone(),
+                        kind: Some(CompletionItemKind::ENUM_MEMBER),
+                        documentation: Some(markdown(property_documentation(binding, doc))),
+                        ..Default::default()
+                    }));
+                }
+            }
+            items.extend(macro_items(files));
+            items
+        }
+        Context::Name => {
+            let Some(node) = &node else {
+                return Vec::new();
+            };
+            let existing = node
+                .properties()
+                .filter_map(|property| Some(property.name()?.syntax().text().to_string()))
+                .collect::<Vec<_>>();
+            let mut items = Vec::new();
+            if let Some(binding) = &node_binding {
+                items.extend(
+                    binding
+                        .properties
+                        .iter()
+                        .filter(|property| !existing.contains(&property.name))
+                        .map(|property| property_item(binding, property)),
+                );
+            }
+            if let Some(path) = &node_path {
+                items.extend(subnode_items(&index, path, node, &snapshot.text));
+            }
+            items
+        }
+    };
+
+    let rope = Rope::from_str(&snapshot.text);
+    if let Some(range) = range_to_lsp(range, &rope) {
+        for item in &mut items {
+            let new_text = item
+                .insert_text
+                .take()
+                .unwrap_or_else(|| item.label.clone());
+            item.text_edit = Some(CompletionTextEdit::Edit(TextEdit { range, new_text }));
+        }
+    }
+    items
+}
+
+fn property_item(binding: &BindingDoc, property: &PropertyDoc) -> CompletionItem {
+    let mut detail = property.type_name.clone().unwrap_or_default();
+    if property.required {
+        detail.push_str(" (required)");
+    }
+    CompletionItem {
+        label: property.name.clone(),
+        kind: Some(CompletionItemKind::PROPERTY),
+        detail: Some(detail.trim().to_owned()).filter(|detail| !detail.is_empty()),
+        documentation: Some(markdown(property_documentation(binding, property))),
+        deprecated: Some(property.deprecated),
+        // Required properties first
+        sort_text: Some(format!("{}{}", u8::from(!property.required), property.name)),
+        ..Default::default()
+    }
+}
+
+/// Returns the subnodes of the node at `path` in other files which `node` doesn't have yet, e.g.
+/// for overriding them in a node extension.
+fn subnode_items(
+    index: &NodeIndex<'_>,
+    path: &str,
+    node: &ast::DtNode,
+    src: &str,
+) -> Vec<CompletionItem> {
+    let existing = node
+        .subnodes()
+        .filter_map(|subnode| subnode.text_name(src))
+        .collect::<Vec<_>>();
+    let prefix = format!("{}/", path.trim_end_matches('/'));
+    let mut names = index
+        .nodes
+        .iter()
+        .filter_map(|definition| definition.path.strip_prefix(&prefix))
+        .filter(|name| !name.contains('/') && !existing.iter().any(|existing| existing == name))
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| CompletionItem {
+            label: name.to_owned(),
+            kind: Some(CompletionItemKind::MODULE),
+            detail: Some(format!("{prefix}{name}")),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Returns the macros defined in `files`, documented with their definitions.
+fn macro_items(files: &[Snapshot]) -> Vec<CompletionItem> {
+    let mut items = Vec::new();
+    for file in files {
+        for toplevel in &file.analyzed {
+            let AnalyzedToplevel::MacroDefinition { text_range, parsed } = toplevel else {
+                continue;
+            };
+            let definition = file.text.get(text_range.byte_range()).unwrap_or_default();
+            items.push(CompletionItem {
+                label: parsed.name.clone(),
+                kind: Some(CompletionItemKind::CONSTANT),
+                detail: file
+                    .uri
+                    .path_segments()
+                    .and_then(Iterator::last)
+                    .map(str::to_owned),
+                documentation: Some(markdown(format!("```c\n{}\n```", definition.trim()))),
+                ..Default::default()
+            });
+        }
+    }
+    items
+}
+
+/// Returns the directories and includable files in `dir` below each of `bases`.
+fn include_items<'a>(
+    bases: impl IntoIterator<Item = &'a PathBuf>,
+    dir: &str,
+) -> Vec<CompletionItem> {
+    let mut items = Vec::<CompletionItem>::new();
+    for base in bases {
+        let Ok(entries) = std::fs::read_dir(base.join(dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = entry.path();
+            let item = if path.is_dir() {
+                CompletionItem {
+                    label: format!("{name}/"),
+                    kind: Some(CompletionItemKind::FOLDER),
+                    ..Default::default()
+                }
+            } else if path
+                .extension()
+                .is_some_and(|ext| ext == "h" || ext == "dtsi" || ext == "dts")
+            {
+                CompletionItem {
+                    label: name,
+                    kind: Some(CompletionItemKind::FILE),
+                    detail: Some(path.display().to_string()),
+                    ..Default::default()
+                }
+            } else {
+                continue;
+            };
+            // Earlier include paths win, like the preprocessor
+            if !items.iter().any(|existing| existing.label == item.label) {
+                items.push(item);
+            }
+        }
+    }
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+    items
+}
+
+pub fn completion(state: &crate::Backend, params: &CompletionParams) -> Option<CompletionResponse> {
+    let position = &params.text_document_position;
+    let uri = &position.text_document.uri;
+    let files = state.reachable_files(uri);
+    let file = files.iter().position(|file| file.uri == *uri)?;
+    let offset = position_to_byte_offset(position.position, &Rope::from_str(&files[file].text))?;
+    let bindings = state.state.bindings.read();
+    let include_paths = state.state.include_paths.read().clone();
+    Some(CompletionResponse::Array(completions(
+        &files,
+        file,
+        offset,
+        &bindings,
+        &include_paths,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{bindings, cursor, snapshots_at_cursor};
+
+    /// Returns the labels of the completions at the first `|` in the first file.
+    fn complete(
+        bindings: &Bindings,
+        include_paths: &[PathBuf],
+        files: &[(&str, &str)],
+    ) -> Vec<String> {
+        let (files, cursor) = snapshots_at_cursor(files);
+        completions(&files, 0, cursor, bindings, include_paths)
+            .into_iter()
+            .map(|item| item.label)
+            .collect()
+    }
+
+    #[test]
+    fn contexts() {
+        let check = |src: &str| {
+            let (src, cursor) = cursor(src);
+            context(&src, cursor)
+                .map(|(range, context)| (src[range.byte_range()].to_owned(), context))
+        };
+        assert_eq!(
+            check("#include <dt-bindings/gp|"),
+            Some((
+                "gp".to_owned(),
+                Context::Include {
+                    dir: "dt-bindings/".to_owned(),
+                    quoted: false
+                }
+            ))
+        );
+        assert_eq!(check("#include <a.h>|"), None);
+        assert_eq!(
+            check("/ { x = <&ua|"),
+            Some(("ua".to_owned(), Context::Label))
+        );
+        assert_eq!(
+            check("/ { n { status = \"o|"),
+            Some((
+                "\"o".to_owned(),
+                Context::Value {
+                    property: "status".to_owned()
+                }
+            ))
+        );
+        assert_eq!(
+            check("/ { n { x; acme,p|"),
+            Some(("acme,p".to_owned(), Context::Name))
+        );
+        assert_eq!(
+            check("/ { n { x = <1 2|"),
+            Some((
+                "2".to_owned(),
+                Context::Value {
+                    property: "x".to_owned()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let files = [
+            ("board.dts", "/include/ \"soc.dtsi\"\n/ { x = <&|>; };"),
+            ("soc.dtsi", "/ { uart0: serial {}; i2c0: i2c {}; };"),
+        ];
+        let mut labels = complete(&Bindings::new(), &[], &files);
+        labels.sort();
+        assert_eq!(labels, ["i2c0", "uart0"]);
+    }
+
+    #[test]
+    fn properties() {
+        let bindings = bindings();
+        let files = [
+            ("board.dts", "&uart0 { reg = <1>; | };"),
+            (
+                "soc.dtsi",
+                "/ { uart0: serial { compatible = \"acme,uart\"; sub {}; }; };",
+            ),
+        ];
+        let items = complete(&bindings, &[], &files);
+        assert!(items.contains(&"acme,parity".to_owned()), "{items:?}");
+        assert!(items.contains(&"status".to_owned()), "{items:?}");
+        assert!(items.contains(&"sub".to_owned()), "{items:?}");
+        assert!(!items.contains(&"reg".to_owned()), "{items:?}");
+    }
+
+    #[test]
+    fn values() {
+        let bindings = bindings();
+        let files = [
+            (
+                "board.dts",
+                "#include \"gpio.h\"\n/ { serial { compatible = \"acme,uart\"; acme,parity = |",
+            ),
+            ("gpio.h", "#define GPIO_ACTIVE_LOW 1\n"),
+        ];
+        assert_eq!(
+            complete(&bindings, &[], &files),
+            ["\"even\"", "\"odd\"", "GPIO_ACTIVE_LOW"]
+        );
+    }
+
+    #[test]
+    fn includes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("dt-bindings/gpio")).unwrap();
+        std::fs::write(dir.join("dt-bindings/gpio/gpio.h"), "").unwrap();
+        std::fs::write(dir.join("dt-bindings/README"), "").unwrap();
+        std::fs::write(dir.join("soc.dtsi"), "").unwrap();
+
+        let include_paths = [dir.to_owned()];
+        let items = complete(
+            &Bindings::new(),
+            &include_paths,
+            &[("board.dts", "#include <|")],
+        );
+        assert_eq!(items, ["dt-bindings/", "soc.dtsi"]);
+        let items = complete(
+            &Bindings::new(),
+            &include_paths,
+            &[("board.dts", "#include <dt-bindings/gpio/|")],
+        );
+        assert_eq!(items, ["gpio.h"]);
+    }
+}
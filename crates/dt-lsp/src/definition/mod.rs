@@ -0,0 +1,248 @@
+use crate::{
+    position_to_byte_offset, range_to_lsp,
+    references::{NodeIndex, ReferenceKind},
+    Snapshot,
+};
+use dt_analyzer::new::stage1::{AnalyzedInclude, AnalyzedToplevel};
+use dt_parser::{
+    ast::{self, AstNode, AstToken, HasName},
+    lexer::TokenKind,
+    TextRange,
+};
+use ropey::Rope;
+use tower_lsp::lsp_types::{
+    GotoDefinitionParams, GotoDefinitionResponse, Location, Position, Range, Url,
+};
+
+/// What can be looked up with go-to-definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// `&label`
+    Label(String),
+    /// `&{/path/to/node}`, without the curly braces
+    Path(String),
+    /// A macro identifier, e.g. `GPIO_ACTIVE_HIGH`
+    Macro(String),
+    /// An `#include` or `/include/`
+    Include(AnalyzedInclude),
+}
+
+impl Target {
+    /// Returns the target at `offset` in `snapshot` if there is one.
+    pub fn at(snapshot: &Snapshot, offset: usize) -> Option<Self> {
+        if let Some(include) = snapshot
+            .analyzed
+            .iter()
+            .filter_map(AnalyzedToplevel::as_include)
+            .find(|include| include.text_range.byte_range().contains(&offset))
+        {
+            return Some(Self::Include(include.clone()));
+        }
+
+        let token = snapshot.file.syntax().token_at_offset(offset)?;
+        if token.green.kind == TokenKind::Ident {
+            return Some(Self::Macro(token.text().to_string()));
+        }
+        let phandle = token.parent_ancestors().find_map(ast::DtPhandle::cast)?;
+        if phandle.is_path() {
+            let syntax = phandle.syntax();
+            let start = syntax
+                .child_tokens()
+                .find(|token| token.green.kind == TokenKind::LCurly)?
+                .text_range()
+                .end;
+            let end = syntax
+                .child_tokens()
+                .find(|token| token.green.kind == TokenKind::RCurly)
+                .map_or(syntax.text_range().end, |token| token.text_range().start);
+            Some(Self::Path(snapshot.text.get(start..end)?.trim().to_owned()))
+        } else {
+            Some(Self::Label(phandle.name()?.syntax().text().to_string()))
+        }
+    }
+}
+
+pub fn goto_definition(
+    state: &crate::Backend,
+    params: GotoDefinitionParams,
+) -> Option<GotoDefinitionResponse> {
+    let params = params.text_document_position_params;
+    let uri = params.text_document.uri;
+    let snapshot = state.snapshot(&uri)?;
+    let offset = position_to_byte_offset(params.position, &Rope::from_str(&snapshot.text))?;
+
+    let locations = match Target::at(&snapshot, offset)? {
+        Target::Include(include) => {
+            let path = state.resolve_include(&uri, &include)?;
+            vec![Location::new(
+                Url::from_file_path(path).ok()?,
+                Range::new(Position::new(0, 0), Position::new(0, 0)),
+            )]
+        }
+        target => definitions(&state.reachable_files(&uri), &target),
+    };
+    tracing::debug!(?locations, "Found definitions");
+
+    (!locations.is_empty()).then_some(GotoDefinitionResponse::Array(locations))
+}
+
+/// Returns the locations where `target` is defined in `files`.
+///
+/// Labels and macros are looked up in every file, paths in the nodes of every file and the node
+/// extensions of known nodes.
+pub(crate) fn definitions(files: &[Snapshot], target: &Target) -> Vec<Location> {
+    let mut locations = Vec::new();
+    for file in files {
+        let rope = Rope::from_str(&file.text);
+        let mut push = |range: TextRange| {
+            if let Some(range) = range_to_lsp(range, &rope) {
+                locations.push(Location::new(file.uri.clone(), range));
+            }
+        };
+        for toplevel in &file.analyzed {
+            match (toplevel, target) {
+                (AnalyzedToplevel::Node(node), Target::Label(label)) => {
+                    if let Some(def) = node.labels.get(label) {
+                        push(def.label_ast.name().map_or_else(
+                            || def.label_ast.syntax().text_range(),
+                            |name| name.syntax().text_range(),
+                        ));
+                    }
+                }
+                (AnalyzedToplevel::MacroDefinition { text_range, parsed }, Target::Macro(name))
+                    if parsed.name == *name =>
+                {
+                    push(macro_name_range(&file.text, *text_range, name));
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Target::Path(path) = target {
+        let index = NodeIndex::new(files);
+        if let Some(path) = index.resolve(&ReferenceKind::Path(path.clone())) {
+            locations.extend(
+                index
+                    .definitions_of(&path)
+                    .filter_map(|node| index.location(node.file, node.name_range)),
+            );
+        }
+    }
+    locations
+}
+
+/// Returns the range of the macro name in the `#define` directive at `range`, or the whole
+/// directive if it can't be found.
+pub(crate) fn macro_name_range(src: &str, range: TextRange, name: &str) -> TextRange {
+    let Some(directive) = src.get(range.byte_range()) else {
+        return range;
+    };
+    directive
+        .find("define")
+        .and_then(|keyword| {
+            let start = keyword + "define".len();
+            directive[start..].find(name).map(|offset| start + offset)
+        })
+        .map_or(range, |offset| {
+            TextRange::new(range.start + offset, range.start + offset + name.len())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{snapshot, snapshots_at_cursor};
+
+    /// Returns the definitions of the target at the first `|` in the first file, as the text at
+    /// each location and the file name.
+    fn goto(files: &[(&str, &str)]) -> Vec<(String, String)> {
+        let (snapshots, cursor) = snapshots_at_cursor(files);
+
+        let target = Target::at(&snapshots[0], cursor).unwrap();
+        definitions(&snapshots, &target)
+            .into_iter()
+            .map(|location| {
+                let file = snapshots
+                    .iter()
+                    .find(|snapshot| snapshot.uri == location.uri)
+                    .unwrap();
+                let rope = Rope::from_str(&file.text);
+                let start = position_to_byte_offset(location.range.start, &rope).unwrap();
+                let end = position_to_byte_offset(location.range.end, &rope).unwrap();
+                (
+                    file.text[start..end].to_owned(),
+                    location.uri.path().trim_start_matches('/').to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn label() {
+        assert_eq!(
+            goto(&[
+                (
+                    "board.dts",
+                    "/include/ \"soc.dtsi\"\n&ua|rt0 { status = \"okay\"; };"
+                ),
+                ("soc.dtsi", "/ { soc { uart0: serial@1000 {}; }; };"),
+            ]),
+            [("uart0".to_owned(), "soc.dtsi".to_owned())]
+        );
+        assert_eq!(
+            goto(&[("board.dts", "/ { l: node {}; other { prop = <&|l>; }; };")]),
+            [("l".to_owned(), "board.dts".to_owned())]
+        );
+    }
+
+    #[test]
+    fn path() {
+        let files = [
+            ("board.dts", "&{/soc/se|rial@1000} { status = \"okay\"; };"),
+            (
+                "soc.dtsi",
+                "/ { soc { serial@1000 {}; serial@2000 {}; }; };",
+            ),
+        ];
+        assert_eq!(
+            goto(&files),
+            [("serial@1000".to_owned(), "soc.dtsi".to_owned())]
+        );
+        let files = [(
+            "board.dts",
+            "/ { soc { node@1 {}; }; x = <&{|/soc/node}>; };",
+        )];
+        assert_eq!(
+            goto(&files),
+            [("node@1".to_owned(), "board.dts".to_owned())]
+        );
+        let files = [("board.dts", "/ { x = <&{|/missing}>; };")];
+        assert_eq!(goto(&files), []);
+    }
+
+    #[test]
+    fn macro_definition() {
+        assert_eq!(
+            goto(&[
+                (
+                    "board.dts",
+                    "#include \"gpio.h\"\n/ { x = <&gpio 1 GPIO_|ACTIVE_LOW>; };"
+                ),
+                (
+                    "gpio.h",
+                    "#define GPIO_ACTIVE_HIGH 0\n#define GPIO_ACTIVE_LOW 1\n"
+                ),
+            ]),
+            [("GPIO_ACTIVE_LOW".to_owned(), "gpio.h".to_owned())]
+        );
+    }
+
+    #[test]
+    fn include() {
+        let snapshot = snapshot("board.dts", "#include <dt-bindings/gpio/gpio.h>\n/ {};");
+        let Some(Target::Include(include)) = Target::at(&snapshot, 3) else {
+            panic!("expected an include");
+        };
+        assert_eq!(include.path, "dt-bindings/gpio/gpio.h");
+    }
+}
@@ -1,139 +1,364 @@
-use crate::{offset_to_position, position_to_offset};
+use std::sync::Arc;
+
+use crate::{
+    completion::property_documentation,
+    position_to_byte_offset, range_to_lsp,
+    references::{self, NodeIndex},
+    Snapshot,
+};
+use dt_analyzer::{
+    eval::{evaluate, expand_macros},
+    new::stage1::AnalyzedToplevel,
+    resolved_prop::MacroResolver,
+};
+use dt_binding_matcher::Bindings;
 use dt_parser::{
-    ast::{self, AstNode},
-    cst::NodeKind,
+    ast::{self, AstNode, AstNodeOrToken, AstToken, HasName},
+    cst::{NodeKind, RedItem, RedToken},
     lexer::TokenKind,
-    match_ast, SourceId,
-};
-use itertools::Itertools;
-use tower_lsp::lsp_types::{
-    Hover, HoverContents, HoverParams, MarkupContent, MarkupKind, MessageType, Range,
+    TextRange,
 };
+use ropey::Rope;
+use rustc_hash::FxHashMap;
+use tower_lsp::lsp_types::{Hover, HoverContents, HoverParams, MarkupContent, MarkupKind};
 
-// ## DynMap:
-// Key<ast::Fn, FunctionId>
-//
-// ## rust markup
-// {if mod path}
-// ```rust
-// {mod path}
-// ```
-//
-// {fi}
-// ```rust
-// {sema desc e.g. fn main()}
-// ```
-// {if doc}
-// ___
-// {doc}
-// {fi}
-
-fn _node_definition(node: &ast::DtNode, src: &str) -> String {
-    let path = node.path(src).join("/");
-
-    // TODO: get documentation by `compatible`
-    format!("node `/{path}`")
+/// What the cursor is on
+enum Hovered {
+    /// The name of a node or a node extension, with the node's full path
+    Node(String),
+    /// A `&label` or `&{/path}` in a value, with the full path of the node it points to
+    Reference(String),
+    Property(ast::DtProperty),
 }
 
-pub async fn hover(state: &crate::Backend, params: HoverParams) -> Option<Hover> {
-    let params = params.text_document_position_params;
-    let uri = params.text_document.uri.to_string();
-    tracing::info!(?uri);
-    let source_id = SourceId::from(uri.clone());
-    let document = state.state.document_map.get(&source_id)?;
+impl Hovered {
+    /// Returns what is at `offset` in `files[file]` and the range it covers.
+    fn at(index: &NodeIndex<'_>, file: usize, offset: usize) -> Option<(TextRange, Self)> {
+        let syntax = index.files[file].file.syntax();
+        std::iter::once(offset)
+            .chain(offset.checked_sub(1))
+            .filter_map(|offset| syntax.token_at_offset(offset))
+            .filter(|token| !token.green.kind.is_trivia())
+            .find_map(|token| Self::at_token(index, file, &token))
+    }
 
-    let cst = document.file.as_ref()?.syntax();
-    let offset = position_to_offset(params.position, &document.text)?;
+    fn at_token(
+        index: &NodeIndex<'_>,
+        file: usize,
+        token: &Arc<RedToken>,
+    ) -> Option<(TextRange, Self)> {
+        if let Some(phandle) = token.parent_ancestors().find_map(ast::DtPhandle::cast) {
+            let range = phandle.syntax().text_range();
+            let path = index.node_at(file, token.text_range().start)?;
+            let parent = phandle.syntax().parent.clone()?;
+            return Some(if parent.green.kind == NodeKind::DtNode {
+                (range, Self::Node(path))
+            } else {
+                (range, Self::Reference(path))
+            });
+        }
+        if let Some(property) = token.parent_ancestors().find_map(ast::DtProperty::cast) {
+            return Some((property.syntax().text_range(), Self::Property(property)));
+        }
+        let node = token.parent_ancestors().find_map(ast::DtNode::cast)?;
+        let range = references::name_range(&node)?;
+        let token_range = token.text_range();
+        if token_range.start < range.start || range.end < token_range.end {
+            return None;
+        }
+        Some((range, Self::Node(index.path_of(file, &node)?)))
+    }
+}
 
-    // TODO: try prev offset?
-    // TODO: Check for nodes above e.g. quit the search directly on an error node
-    let token = cst.token_at_offset(offset)?.clone();
-    tracing::debug!(kind = ?token.green.kind, "Found token");
+/// Returns the hover text at `offset` in `files[file]` with the range it is about.
+pub(crate) fn hover_markdown(
+    files: &[Snapshot],
+    file: usize,
+    offset: usize,
+    bindings: &Bindings,
+) -> Option<(String, TextRange)> {
+    let index = NodeIndex::new(files);
+    let (range, hovered) = Hovered::at(&index, file, offset)?;
+    let markdown = match hovered {
+        Hovered::Node(path) => node_markdown(&index, &path, bindings),
+        Hovered::Reference(path) => reference_markdown(&index, &path, bindings),
+        Hovered::Property(property) => property_markdown(&index, file, &property, bindings)?,
+    };
+    Some((markdown, range))
+}
 
-    if token.green.kind.is_trivia() {
-        return None;
+/// Shows the full path of a node, the binding matching its compatible and its properties after
+/// merging all its definitions.
+fn node_markdown(index: &NodeIndex<'_>, path: &str, bindings: &Bindings) -> String {
+    let mut paragraphs = vec![format!("node `{path}`")];
+    if let Some(binding) = index.binding_doc(path, bindings) {
+        paragraphs.extend(binding.title.map(|title| format!("**{title}**")));
+        paragraphs.extend(binding.description);
+        if !binding.maintainers.is_empty() {
+            paragraphs.push(format!("Maintainers: {}", binding.maintainers.join(", ")));
+        }
     }
-    if token.parent.green.kind == NodeKind::ParseError {
-        state
-            .client
-            .show_message(MessageType::ERROR, "parent node is error")
-            .await;
-        return None;
+    let properties = index
+        .properties_of(path)
+        .into_iter()
+        .filter_map(|(file, property)| property_source(&index.files[file].text, &property))
+        .collect::<Vec<_>>();
+    if !properties.is_empty() {
+        paragraphs.push(format!("```dts\n{}\n```", properties.join("\n")));
     }
-    if token.green.kind == TokenKind::Unrecognized {
-        state
-            .client
-            .show_message(MessageType::ERROR, "token is unrecognized")
-            .await;
-        return None;
+    paragraphs.join("\n\n")
+}
+
+/// Shows the full path and compatible of the node a `&label` or `&{/path}` points to.
+fn reference_markdown(index: &NodeIndex<'_>, path: &str, bindings: &Bindings) -> String {
+    let mut paragraphs = vec![format!("node `{path}`")];
+    let compatibles = index.compatibles(path);
+    if !compatibles.is_empty() {
+        paragraphs.push(format!("compatible: `{}`", compatibles.join("`, `")));
+    }
+    if let Some(binding) = index.binding_doc(path, bindings) {
+        paragraphs.extend(binding.title.map(|title| format!("**{title}**")));
     }
+    paragraphs.join("\n\n")
+}
 
-    let rope = &document.text;
+/// Shows the binding's documentation of a property and its value with macros expanded and cells
+/// evaluated.
+fn property_markdown(
+    index: &NodeIndex<'_>,
+    file: usize,
+    property: &ast::DtProperty,
+    bindings: &Bindings,
+) -> Option<String> {
+    let src = &index.files[file].text;
+    let name = property.name()?.syntax().text().to_string();
+    let mut paragraphs = vec![format!("property `{name}`")];
 
-    // definition
+    let node = property
+        .syntax()
+        .parent_ancestors()
+        .find_map(ast::DtNode::cast)?;
+    let binding = index
+        .path_of(file, &node)
+        .and_then(|path| index.binding_doc(&path, bindings));
+    if let Some((binding, doc)) = binding
+        .as_ref()
+        .and_then(|binding| Some((binding, binding.property(&name)?)))
     {
-        let parent = token.parent.clone();
-        tracing::debug!("analyzed, direct parent #1 kind = {:?}", parent.green.kind);
-
-        // FIXME: NameRef, NameDef
-        // TODO: search for node at offset directly
-        if let Some(name) = ast::NameRef::cast(parent) {
-            let parent = name.syntax().parent.clone()?;
-            match_ast! {
-                match parent {
-                    ast::DtProperty(_it) => {
-                        // call doc and sema db
-                        Some(("property".to_owned(), None))
-                    },
-                    ast::DtNode(_node) => {
-                        // call doc and sema db
-                        Some(("node".into(), None))
-                    },
-                    ast::DtLabel(_it) => {
-                        Some(("label".into(), None))
-                    },
-                    ast::DtPhandle(phandle) => {
-                        tracing::debug!("Is phandle");
-
-                        // TODO: Something like Either<&RedNode, Arc<RedNode>> so I don't have to
-                        // clone here
-
-                        let node = phandle.syntax().parent.as_ref().unwrap().clone();
-                        if let Some(_node) = ast::DtNode::cast(node) {}
-
-                        if phandle.is_path() {
-                            let _path = token.text();
-                            None // TODO
-                        } else {
-                            let label_name = token.text();
-                            // TODO: to_string is a HACK
-                            //let label_defs = analyzed.exported_labels.get(&label_name.to_string())?;
-                            //let label = label_defs.last()?; // TODO: show all label defs
-                            //Some((format!("{}\n---\nlabel `{label_name}`", node_definition(&label.node_ast, &src)), None))
-                            Some((format!("aaa\n---\nlabel `{label_name}`"), None))
-                        }
-                    },
-                    _ => None
-                }
+        if let Some(type_name) = &doc.type_name {
+            let required = if doc.required { ", required" } else { "" };
+            paragraphs.push(format!("_{type_name}{required}_"));
+        }
+        paragraphs.push(property_documentation(binding, doc));
+    }
+
+    let macros = macro_resolver(index.files);
+    let values = property
+        .values()
+        .map(|value| evaluate_value(&value, src, &macros))
+        .collect::<Vec<_>>();
+    if !values.is_empty() {
+        let hex = values
+            .iter()
+            .map(|value| value.to_dts(|number| format!("{number:#x}")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let decimal = values
+            .iter()
+            .map(|value| value.to_dts(|number| number.to_string()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut lines = vec![format!("{name} = {hex};")];
+        if decimal != hex {
+            lines.push(format!("{name} = {decimal};"));
+        }
+        paragraphs.push(format!("```dts\n{}\n```", lines.join("\n")));
+    }
+    Some(paragraphs.join("\n\n"))
+}
+
+/// Returns the source of `property` with its whitespace collapsed, e.g. `reg = <0x1000 0x10>;`.
+fn property_source(src: &str, property: &ast::DtProperty) -> Option<String> {
+    let text = src.get(property.syntax().text_range().byte_range())?;
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    Some(if text.ends_with(';') {
+        text
+    } else {
+        format!("{text};")
+    })
+}
+
+/// A property value after expanding macros
+enum EvaluatedValue {
+    Cells(Vec<EvaluatedCell>),
+    /// A value which isn't evaluated further, e.g. a string
+    Text(String),
+}
+
+pub(crate) enum EvaluatedCell {
+    Number(u32),
+    /// A cell which couldn't be evaluated, e.g. a phandle, as it is written after expanding macros
+    Text(String),
+}
+
+impl EvaluatedValue {
+    /// Formats the value in DTS syntax with `number` formatting the cells.
+    fn to_dts(&self, number: impl Fn(u32) -> String) -> String {
+        match self {
+            Self::Cells(cells) => {
+                let cells = cells
+                    .iter()
+                    .map(|cell| match cell {
+                        EvaluatedCell::Number(value) => number(*value),
+                        EvaluatedCell::Text(text) => text.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                format!("<{}>", cells.join(" "))
             }
-        } else {
-            None
+            Self::Text(text) => text.clone(),
         }
     }
-    // TODO: literals
-    .map(|(markdown, span)| {
-        Hover {
-            contents: HoverContents::Markup(MarkupContent {
-                kind: MarkupKind::Markdown,
-                value: markdown,
-            }),
-            range: span.and_then(|span: &dt_parser::TextRange| {
-                // TODO: proper Span type with file IDs and more
-                Some(Range::new(
-                    offset_to_position(span.start, rope)?,
-                    offset_to_position(span.end, rope)?,
-                ))
-            }),
+}
+
+/// Returns the macros defined in `files` for expanding them.
+pub(crate) fn macro_resolver(files: &[Snapshot]) -> impl MacroResolver + '_ {
+    files
+        .iter()
+        .flat_map(|file| &file.analyzed)
+        .filter_map(AnalyzedToplevel::as_macro_definition)
+        .map(|(range, definition)| (definition.name.clone(), (*range, definition)))
+        .collect::<FxHashMap<_, _>>()
+}
+
+/// Evaluates an item of a cell list, or returns `None` if it isn't a cell, e.g. `<`.
+pub(crate) fn evaluate_cell(
+    item: &RedItem,
+    src: &str,
+    macros: &impl MacroResolver,
+) -> Option<EvaluatedCell> {
+    let written = src.get(item.text_range().byte_range())?.to_owned();
+    let expression = match item {
+        RedItem::Node(node) => match node.green.kind {
+            NodeKind::DtPhandle => return Some(EvaluatedCell::Text(written)),
+            NodeKind::MacroInvocation | NodeKind::DtExpr => expand_macros(&written, macros),
+            _ => return None,
+        },
+        RedItem::Token(token)
+            if matches!(token.green.kind, TokenKind::Number | TokenKind::Char) =>
+        {
+            written
         }
+        RedItem::Token(_) => return None,
+    };
+    // Cells are 32 bits wide, like dtc truncates them
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "cells are truncated to 32 bits"
+    )]
+    Some(match evaluate(&expression) {
+        Ok(number) => EvaluatedCell::Number(number as u32),
+        Err(_) => EvaluatedCell::Text(expression),
     })
 }
+
+fn evaluate_value(
+    value: &ast::PropValue,
+    src: &str,
+    macros: &impl MacroResolver,
+) -> EvaluatedValue {
+    let text = |range: TextRange| src.get(range.byte_range()).unwrap_or_default().to_owned();
+    match value {
+        ast::PropValue::CellList(cell_list) => EvaluatedValue::Cells(
+            cell_list
+                .syntax()
+                .children()
+                .filter_map(|item| evaluate_cell(&item, src, macros))
+                .collect(),
+        ),
+        ast::PropValue::Macro(invocation) => EvaluatedValue::Text(expand_macros(
+            &text(invocation.syntax().text_range()),
+            macros,
+        )),
+        _ => EvaluatedValue::Text(text(value.syntax().text_range())),
+    }
+}
+
+pub fn hover(state: &crate::Backend, params: &HoverParams) -> Option<Hover> {
+    let params = &params.text_document_position_params;
+    let uri = &params.text_document.uri;
+    let files = state.reachable_files(uri);
+    let file = files.iter().position(|file| file.uri == *uri)?;
+    let rope = Rope::from_str(&files[file].text);
+    let offset = position_to_byte_offset(params.position, &rope)?;
+    let bindings = state.state.bindings.read();
+    let (markdown, range) = hover_markdown(&files, file, offset, &bindings)?;
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: markdown,
+        }),
+        range: range_to_lsp(range, &rope),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{bindings, snapshots_at_cursor};
+
+    /// Returns the hover text at the `|` in the first file.
+    fn hover(files: &[(&str, &str)]) -> String {
+        let (files, cursor) = snapshots_at_cursor(files);
+        hover_markdown(&files, 0, cursor, &bindings()).unwrap().0
+    }
+
+    #[test]
+    fn node() {
+        let markdown = hover(&[
+            ("board.dts", "/include/ \"soc.dtsi\"\n&ua|rt0 { status = \"okay\"; };"),
+            (
+                "soc.dtsi",
+                "/ { soc { uart0: serial@1000 { compatible = \"acme,uart\"; status = \"disabled\"; }; }; };",
+            ),
+        ]);
+        assert_eq!(
+            markdown,
+            "node `/soc/serial@1000`\n\n**Acme UART**\n\nA UART with a FIFO.\n\n\
+             Maintainers: Jane Doe <jane@example.com>\n\n\
+             ```dts\ncompatible = \"acme,uart\";\nstatus = \"okay\";\n```"
+        );
+    }
+
+    #[test]
+    fn property() {
+        let markdown = hover(&[(
+            "board.dts",
+            "#define BASE 0x1000\n#define SIZE(n) ((n) * 0x10)\n\
+             / { serial { compatible = \"acme,uart\"; r|eg = <BASE SIZE(2)>, <0 (BASE | 1)>; }; };",
+        )]);
+        assert!(
+            markdown.starts_with(
+                "property `reg`\n\n_uint32-matrix, required_\n\nThe registers and the FIFO."
+            ),
+            "{markdown}"
+        );
+        assert!(
+            markdown.ends_with(
+                "```dts\nreg = <0x1000 0x20>, <0x0 0x1001>;\nreg = <4096 32>, <0 4097>;\n```"
+            ),
+            "{markdown}"
+        );
+    }
+
+    #[test]
+    fn reference() {
+        let markdown = hover(&[(
+            "board.dts",
+            "/ { uart0: serial { compatible = \"acme,uart\"; }; chosen { stdout = <&uar|t0>; }; };",
+        )]);
+        assert_eq!(
+            markdown,
+            "node `/serial`\n\ncompatible: `acme,uart`\n\n**Acme UART**"
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use std::sync::Arc;
+
+use crate::{
+    hover::{evaluate_cell, macro_resolver, EvaluatedCell},
+    offset_to_position, position_to_byte_offset,
+    references::NodeIndex,
+    Snapshot,
+};
+use dt_analyzer::{eval::expand_macros, resolved_prop::MacroResolver};
+use dt_parser::{
+    ast::{self, AstNode, AstToken, HasName},
+    cst::{NodeKind, RedItem, RedNode},
+    lexer::TokenKind,
+    TextRange,
+};
+use ropey::Rope;
+use tower_lsp::lsp_types::{InlayHint, InlayHintKind, InlayHintLabel, InlayHintParams};
+
+/// Properties with phandle and specifier lists, with the `#*-cells` property of the providers
+const SPECIFIER_LISTS: &[(&str, &str)] = &[
+    ("clocks", "#clock-cells"),
+    ("dmas", "#dma-cells"),
+    ("interrupts-extended", "#interrupt-cells"),
+    ("io-channels", "#io-channel-cells"),
+    ("iommus", "#iommu-cells"),
+    ("mboxes", "#mbox-cells"),
+    ("phys", "#phy-cells"),
+    ("power-domains", "#power-domain-cells"),
+    ("pwms", "#pwm-cells"),
+    ("resets", "#reset-cells"),
+    ("thermal-sensors", "#thermal-sensor-cells"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hint {
+    offset: usize,
+    label: String,
+    /// [`InlayHintKind::PARAMETER`] hints go before what they describe, others after it
+    kind: Option<InlayHintKind>,
+}
+
+impl Hint {
+    fn parameter(offset: usize, label: &str) -> Self {
+        Self {
+            offset,
+            label: format!("{label}:"),
+            kind: Some(InlayHintKind::PARAMETER),
+        }
+    }
+}
+
+/// An item of a cell list
+struct Cell {
+    range: TextRange,
+    syntax: RedItem,
+    value: EvaluatedCell,
+}
+
+/// Returns the hints for the properties in `files[file]` which overlap `range`.
+fn hints(files: &[Snapshot], file: usize, range: TextRange) -> Vec<Hint> {
+    let index = NodeIndex::new(files);
+    let macros = macro_resolver(files);
+    let mut hints = Vec::new();
+    let mut stack = files[file].file.nodes().collect::<Vec<_>>();
+    while let Some(node) = stack.pop() {
+        stack.extend(node.subnodes());
+        let Some(path) = index.path_of(file, &node) else {
+            continue;
+        };
+        for property in node.properties() {
+            let property_range = property.syntax().text_range();
+            if property_range.end < range.start || range.end < property_range.start {
+                continue;
+            }
+            property_hints(&index, &macros, file, &path, &property, &mut hints);
+        }
+    }
+    hints.sort_by_key(|hint| hint.offset);
+    hints
+}
+
+fn property_hints(
+    index: &NodeIndex,
+    macros: &impl MacroResolver,
+    file: usize,
+    path: &str,
+    property: &ast::DtProperty,
+    hints: &mut Vec<Hint>,
+) {
+    let src = &index.files[file].text;
+    let Some(name) = property.name() else {
+        return;
+    };
+    let name = name.syntax().text().as_str();
+    let cells = property
+        .values()
+        .filter_map(|value| match value {
+            ast::PropValue::CellList(cell_list) => Some(cell_list),
+            _ => None,
+        })
+        .flat_map(|cell_list| {
+            cell_list
+                .syntax()
+                .children()
+                .filter_map(|item| {
+                    Some(Cell {
+                        range: item.text_range(),
+                        value: evaluate_cell(&item, src, macros)?,
+                        syntax: item,
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    for cell in &cells {
+        if let (RedItem::Node(syntax), EvaluatedCell::Number(value)) = (&cell.syntax, &cell.value) {
+            if !contains_macro(syntax) {
+                continue;
+            }
+            let expanded = expand_macros(&src[cell.range.byte_range()], macros);
+            let value = if expanded.contains("0x") || expanded.contains("<<") {
+                format!("{value:#x}")
+            } else {
+                value.to_string()
+            };
+            hints.push(Hint {
+                offset: cell.range.end,
+                label: format!("= {value}"),
+                kind: None,
+            });
+        }
+    }
+
+    if name == "reg" {
+        reg_hints(index, macros, path, &cells, hints);
+    } else if name == "interrupts" {
+        interrupt_hints(index, macros, path, &cells, hints);
+    } else if let Some(cells_name) = SPECIFIER_LISTS
+        .iter()
+        .find(|(list, _)| *list == name)
+        .map(|(_, cells_name)| *cells_name)
+        .or_else(|| (name == "gpios" || name.ends_with("-gpios")).then_some("#gpio-cells"))
+    {
+        specifier_hints(index, macros, file, cells_name, &cells, hints);
+    }
+}
+
+/// Returns true if `node`, a macro invocation or an expression, uses a macro.
+fn contains_macro(node: &Arc<RedNode>) -> bool {
+    node.green.kind == NodeKind::MacroInvocation
+        || node.children().any(|child| match child {
+            RedItem::Node(child) => contains_macro(&child),
+            RedItem::Token(token) => token.green.kind == TokenKind::Ident,
+        })
+}
+
+/// Marks the addresses and sizes of `reg` by the `#address-cells` and `#size-cells` of the parent.
+fn reg_hints(
+    index: &NodeIndex,
+    macros: &impl MacroResolver,
+    path: &str,
+    cells: &[Cell],
+    hints: &mut Vec<Hint>,
+) {
+    let Some(parent) = parent_path(path) else {
+        return;
+    };
+    let address_cells = cell_count(index, macros, parent, "#address-cells").unwrap_or(2) as usize;
+    let size_cells = cell_count(index, macros, parent, "#size-cells").unwrap_or(1) as usize;
+    if address_cells + size_cells == 0 {
+        return;
+    }
+    for entry in cells.chunks(address_cells + size_cells) {
+        if let Some(address) = entry.first().filter(|_| address_cells > 0) {
+            hints.push(Hint::parameter(address.range.start, "addr"));
+        }
+        if let Some(size) = entry.get(address_cells).filter(|_| size_cells > 0) {
+            hints.push(Hint::parameter(size.range.start, "size"));
+        }
+    }
+}
+
+/// Splits `interrupts` into specifiers by the `#interrupt-cells` of the interrupt parent.
+fn interrupt_hints(
+    index: &NodeIndex,
+    macros: &impl MacroResolver,
+    path: &str,
+    cells: &[Cell],
+    hints: &mut Vec<Hint>,
+) {
+    let Some(parent) = interrupt_parent(index, path) else {
+        return;
+    };
+    let Some(count) = cell_count(index, macros, &parent, "#interrupt-cells") else {
+        return;
+    };
+    if count == 0 {
+        return;
+    }
+    let name = provider_name(index, &parent);
+    for specifier in cells.chunks(count as usize) {
+        hints.push(Hint::parameter(specifier[0].range.start, &name));
+    }
+}
+
+/// Splits a list of phandles with arguments into specifiers by the `cells_name` of each provider.
+///
+/// Specifiers are marked with the provider's label if their phandle doesn't already show it, e.g.
+/// for `&{/path}`s.
+fn specifier_hints(
+    index: &NodeIndex,
+    macros: &impl MacroResolver,
+    file: usize,
+    cells_name: &str,
+    cells: &[Cell],
+    hints: &mut Vec<Hint>,
+) {
+    let src = &index.files[file].text;
+    let mut rest = cells;
+    while let Some((phandle, args)) = rest.split_first() {
+        let RedItem::Node(syntax) = &phandle.syntax else {
+            return;
+        };
+        if syntax.green.kind != NodeKind::DtPhandle {
+            return;
+        }
+        let Some(provider) = phandle_target(index, file, phandle.range) else {
+            return;
+        };
+        let Some(count) = cell_count(index, macros, &provider, cells_name) else {
+            return;
+        };
+        let name = provider_name(index, &provider);
+        if src[phandle.range.byte_range()] != format!("&{name}") {
+            hints.push(Hint::parameter(phandle.range.start, &name));
+        }
+        rest = args.get(count as usize..).unwrap_or_default();
+    }
+}
+
+/// Returns the path of the parent of the node at `path`, or `None` for the root node.
+fn parent_path(path: &str) -> Option<&str> {
+    if path == "/" {
+        return None;
+    }
+    let (parent, _) = path.rsplit_once('/')?;
+    Some(if parent.is_empty() { "/" } else { parent })
+}
+
+/// Returns the property `name` of the node at `path` with its file.
+fn find_property(index: &NodeIndex, path: &str, name: &str) -> Option<(usize, ast::DtProperty)> {
+    index.properties_of(path).into_iter().find(|(_, property)| {
+        property
+            .name()
+            .is_some_and(|property| property.syntax().text().as_str() == name)
+    })
+}
+
+/// Returns the first cell of the property `name`, e.g. `#address-cells`, of the node at `path`.
+fn cell_count(
+    index: &NodeIndex,
+    macros: &impl MacroResolver,
+    path: &str,
+    name: &str,
+) -> Option<u32> {
+    let (file, property) = find_property(index, path, name)?;
+    let src = &index.files[file].text;
+    let count = property.values().find_map(|value| {
+        let ast::PropValue::CellList(cell_list) = value else {
+            return None;
+        };
+        let cell = cell_list
+            .syntax()
+            .children()
+            .find_map(|item| evaluate_cell(&item, src, macros))?;
+        match cell {
+            EvaluatedCell::Number(count) => Some(count),
+            EvaluatedCell::Text(_) => None,
+        }
+    });
+    count
+}
+
+/// Returns the full path of the node the phandle at `range` in `file` points to.
+fn phandle_target(index: &NodeIndex, file: usize, range: TextRange) -> Option<String> {
+    let reference = index.references.iter().find(|reference| {
+        reference.file == file
+            && range.start <= reference.range.start
+            && reference.range.end <= range.end
+    })?;
+    index.resolve(&reference.kind)
+}
+
+/// Returns the interrupt parent of the node at `path` from the `interrupt-parent` of the node or
+/// its closest ancestor.
+fn interrupt_parent(index: &NodeIndex, path: &str) -> Option<String> {
+    let mut current = Some(path);
+    while let Some(path) = current {
+        if let Some((file, property)) = find_property(index, path, "interrupt-parent") {
+            let phandle = property.values().find_map(|value| {
+                let ast::PropValue::CellList(cell_list) = value else {
+                    return None;
+                };
+                let phandle = cell_list.cells().find_map(|cell| match cell {
+                    ast::Cell::Phandle(phandle) => Some(phandle.syntax().text_range()),
+                    _ => None,
+                });
+                phandle
+            })?;
+            return phandle_target(index, file, phandle);
+        }
+        current = parent_path(path);
+    }
+    None
+}
+
+/// Returns the first label of the node at `path`, or its name if it has none.
+fn provider_name(index: &NodeIndex, path: &str) -> String {
+    index
+        .definitions_of(path)
+        .find_map(|definition| definition.labels.first())
+        .map_or_else(
+            || path.rsplit('/').next().unwrap_or(path).to_owned(),
+            |(label, _)| label.clone(),
+        )
+}
+
+pub fn inlay_hint(state: &crate::Backend, params: &InlayHintParams) -> Option<Vec<InlayHint>> {
+    let uri = &params.text_document.uri;
+    let files = state.reachable_files(uri);
+    let file = files.iter().position(|file| file.uri == *uri)?;
+    let rope = Rope::from_str(&files[file].text);
+    let range = TextRange::new(
+        position_to_byte_offset(params.range.start, &rope)?,
+        position_to_byte_offset(params.range.end, &rope)?,
+    );
+    Some(
+        hints(&files, file, range)
+            .into_iter()
+            .filter_map(|hint| {
+                let is_parameter = hint.kind == Some(InlayHintKind::PARAMETER);
+                Some(InlayHint {
+                    position: offset_to_position(hint.offset, &rope)?,
+                    label: InlayHintLabel::String(hint.label),
+                    kind: hint.kind,
+                    text_edits: None,
+                    tooltip: None,
+                    padding_left: Some(!is_parameter),
+                    padding_right: Some(is_parameter),
+                    data: None,
+                })
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::snapshots;
+
+    /// Renders the hints of the first file as comments, e.g. `<BIT(1)/*= 2*/>`.
+    fn render(files: &[(&str, &str)]) -> String {
+        let files = snapshots(files);
+        let mut rendered = files[0].text.clone();
+        let range = TextRange::new(0, rendered.len());
+        for hint in hints(&files, 0, range).into_iter().rev() {
+            rendered.insert_str(hint.offset, &format!("/*{}*/", hint.label));
+        }
+        rendered
+    }
+
+    #[test]
+    fn macros() {
+        let src = "#define IRQ_TYPE_LEVEL_HIGH 4\n#define BIT(n) (1 << (n))\n\
+                   / { a = <IRQ_TYPE_LEVEL_HIGH (BIT(4) | 1) (2 + 2) 3 UNKNOWN>; };";
+        assert_eq!(
+            render(&[("board.dts", src)]),
+            "#define IRQ_TYPE_LEVEL_HIGH 4\n#define BIT(n) (1 << (n))\n\
+             / { a = <IRQ_TYPE_LEVEL_HIGH/*= 4*/ (BIT(4) | 1)/*= 0x11*/ (2 + 2) 3 UNKNOWN>; };"
+        );
+    }
+
+    #[test]
+    fn reg() {
+        let src = "/ {\n\t#address-cells = <1>;\n\tsoc {\n\t\t#address-cells = <2>;\n\t\t\
+                   #size-cells = <0>;\n\t\tfoo@0 { reg = <0 0x1000>, <1 0x2000>; };\n\t};\n\t\
+                   bar@0 { reg = <0x1000 0x10 0x2000 0x10>; };\n};";
+        assert_eq!(
+            render(&[("board.dts", src)]),
+            "/ {\n\t#address-cells = <1>;\n\tsoc {\n\t\t#address-cells = <2>;\n\t\t\
+             #size-cells = <0>;\n\t\tfoo@0 { reg = </*addr:*/0 0x1000>, </*addr:*/1 0x2000>; };\
+             \n\t};\n\tbar@0 { reg = </*addr:*/0x1000 /*size:*/0x10 /*addr:*/0x2000 \
+             /*size:*/0x10>; };\n};"
+        );
+    }
+
+    #[test]
+    fn specifiers() {
+        let dtsi = "/ {\n\tinterrupt-parent = <&gic>;\n\tgic: interrupt-controller { \
+                    #interrupt-cells = <3>; };\n\tclk: clock-controller { #clock-cells = <1>; };\n\t\
+                    fixed { #clock-cells = <0>; };\n\tgpio0: gpio { #gpio-cells = <2>; };\n};";
+        let board = "/ { uart {\n\tinterrupts = <0 1 4>, <0 2 4>;\n\t\
+                     clocks = <&clk 1 &{/fixed} &clk 2>;\n\treset-gpios = <&gpio0 5 0>;\n}; };";
+        assert_eq!(
+            render(&[("board.dts", board), ("soc.dtsi", dtsi)]),
+            "/ { uart {\n\tinterrupts = </*gic:*/0 1 4>, </*gic:*/0 2 4>;\n\t\
+             clocks = <&clk 1 /*fixed:*/&{/fixed} &clk 2>;\n\treset-gpios = <&gpio0 5 0>;\n}; };"
+        );
+    }
+}
@@ -0,0 +1,1406 @@
+//! The Devicetree language server.
+//!
+//! This is a library so that the server can be built with custom lints. See [`serve`].
+
+use axka_rcu::{triomphe, Rcu};
+use dt_analyzer::new::{
+    stage1::{AnalyzedInclude, AnalyzedToplevel},
+    stage2::ResolvedInclude,
+};
+use dt_binding_matcher::{Bindings, UndocumentedCompatibles};
+use dt_diagnostic::DiagnosticCollector;
+use dt_lint::{rules::RuleFile, LintRegistry};
+use dt_parser::{
+    ast::{self, AstNode},
+    cst::RedNode,
+    parser::{parse, Parse},
+    SourceId, TextRange,
+};
+use dt_workspace::{
+    config::{env_config::EnvConfig, toml_config::TomlConfig},
+    Workspace, WorkspacePathFindResult,
+};
+use ropey::Rope;
+use std::{borrow::Cow, sync::Arc};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+use tokio::{net::TcpListener, sync::Mutex};
+use tower_lsp::lsp_types::{
+    CodeActionParams, CodeActionProviderCapability, CodeActionResponse, CodeDescription,
+    CompletionOptions, CompletionParams, CompletionResponse, Diagnostic,
+    DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidChangeWorkspaceFoldersParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams, DocumentHighlight, DocumentHighlightParams, DocumentSymbolParams,
+    DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverParams,
+    HoverProviderCapability, InitializeParams, InitializeResult, InitializedParams, InlayHint,
+    InlayHintParams, Location, MessageType, NumberOrString, OneOf, Position, PrepareRenameResponse,
+    Range, ReferenceParams, RenameOptions, RenameParams, SemanticTokensFullOptions,
+    SemanticTokensOptions, SemanticTokensParams, SemanticTokensRangeParams,
+    SemanticTokensRangeResult, SemanticTokensResult, SemanticTokensServerCapabilities,
+    ServerCapabilities, ServerInfo, SymbolInformation, TextDocumentContentChangeEvent,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+    WorkDoneProgressOptions, WorkspaceEdit, WorkspaceFolder, WorkspaceFoldersServerCapabilities,
+    WorkspaceServerCapabilities, WorkspaceSymbolParams,
+};
+use tower_lsp::{Client, LanguageServer, LspService, Server};
+use tracing::{debug, info, level_filters::LevelFilter, warn};
+use tracing_subscriber::EnvFilter;
+
+mod code_action;
+mod completion;
+mod definition;
+mod hover;
+mod inlay_hints;
+mod references;
+mod rename;
+mod semantic_tokens;
+mod symbols;
+
+/// How many `#include`s are followed in a row, so include cycles terminate
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// A fast map that can be sent between threads
+pub type FxDashMap<K, V> =
+    dashmap::DashMap<K, V, std::hash::BuildHasherDefault<rustc_hash::FxHasher>>;
+
+#[derive(Debug)]
+pub struct Document {
+    pub text: Rope,
+    /// The version from the client, or [`None`] if the file isn't open and was read from disk
+    pub version: Option<i32>,
+    pub file: Option<ast::SourceFile>,
+    pub analyzed: Option<Vec<AnalyzedToplevel>>,
+    pub lints: Vec<dt_lint::EmittedLint>,
+    /// Diagnostics from the analyzer
+    pub diagnostics: Vec<dt_diagnostic::Diagnostic>,
+    /// The syntax tree and errors if there were no lex errors, for [reparsing](Parse::reparse)
+    /// after an edit
+    pub parse: Option<Parse<'static>>,
+    /// The published diagnostics, without the binding errors found when checking files which
+    /// include this one
+    pub lsp_diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug)]
+struct SharedState {
+    document_map: FxDashMap<SourceId, Document>,
+    workspace_folders: Mutex<Vec<WorkspaceFolder>>,
+    /// The file where evaluation will start from
+    ///
+    /// Files not (recursively) included in this file must not be analyzed
+    main_file: Rcu<Option<SourceId>>,
+    /// Rules from the workspace config are registered on initialization
+    lint_registry: parking_lot::RwLock<LintRegistry>,
+    /// Directories searched for `#include <...>`s, from the workspace config
+    include_paths: parking_lot::RwLock<Vec<PathBuf>>,
+    /// Binding schemas from the workspace config, loaded in the background after initialization.
+    ///
+    /// This is shared with the [`UndocumentedCompatibles`] lint pass.
+    bindings: Arc<Rcu<Bindings>>,
+    /// Binding errors in included files, by the included file and then by the checked file which
+    /// includes it
+    included_diagnostics: FxDashMap<SourceId, rustc_hash::FxHashMap<SourceId, Vec<Diagnostic>>>,
+}
+
+impl SharedState {
+    fn new(lint_registry: LintRegistry, bindings: Arc<Rcu<Bindings>>) -> Self {
+        Self {
+            document_map: FxDashMap::default(),
+            workspace_folders: Mutex::new(Vec::new()),
+            main_file: Rcu::new(triomphe::Arc::new(None)),
+            lint_registry: parking_lot::RwLock::new(lint_registry),
+            include_paths: parking_lot::RwLock::new(Vec::new()),
+            bindings,
+            included_diagnostics: FxDashMap::default(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Backend {
+    client: Client,
+    state: Arc<SharedState>,
+}
+
+type Result<T, E = tower_lsp::jsonrpc::Error> = std::result::Result<T, E>;
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        if let Some(client_info) = params.client_info {
+            if let Some(version) = client_info.version {
+                tracing::info!("{} {} connected!", client_info.name, version);
+            } else {
+                tracing::info!("{} connected!", client_info.name);
+            }
+        }
+
+        // TODO: support legacy clients using root_uri
+        if let Some(workspace_folders) = params.workspace_folders {
+            tracing::info!("Workspace folders: {workspace_folders:?}");
+            if let Some(path) = workspace_folders
+                .first()
+                .and_then(|folder| folder.uri.to_file_path().ok())
+            {
+                if let Err(err) = self.load_rule_files(&path) {
+                    self.client
+                        .show_message(
+                            MessageType::ERROR,
+                            format!("Failed to load lint rules: {err}"),
+                        )
+                        .await;
+                }
+                self.load_include_paths(&path);
+                self.load_bindings(&path, tokio::runtime::Handle::current());
+            }
+            *self.state.workspace_folders.lock().await = workspace_folders;
+        } else {
+            tracing::info!("Connected in single-file mode");
+        }
+        Ok(InitializeResult {
+            server_info: Some(ServerInfo {
+                name: "dt-lsp".to_owned(),
+                version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            }),
+            capabilities: ServerCapabilities {
+                //position_encoding: (),
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::INCREMENTAL,
+                )),
+                //selection_range_provider: (),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(
+                        completion::TRIGGER_CHARACTERS
+                            .iter()
+                            .map(|&c| c.to_owned())
+                            .collect(),
+                    ),
+                    ..Default::default()
+                }),
+                //signature_help_provider: (),
+                definition_provider: Some(OneOf::Left(true)),
+                // implementation_provider: (), // TODO: for labels
+                references_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                //code_lens_provider: (),
+                //document_formatting_provider: (),
+                //document_on_type_formatting_provider: (),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        SemanticTokensOptions {
+                            work_done_progress_options: WorkDoneProgressOptions::default(),
+                            legend: semantic_tokens::legend(),
+                            range: Some(true),
+                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                        },
+                    ),
+                ),
+                //document_link_provider: (),
+                //color_provider: (),
+                //folding_range_provider: (),
+                //declaration_provider: (),
+                //execute_command_provider: (),
+                //linked_editing_range_provider: (),
+                //inline_value_provider: (),
+                //inlay_hint_provider: (),
+                //diagnostic_provider: (),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        // TODO: recompute includes etc.
+        let mut folders = self.state.workspace_folders.lock().await;
+        for removed in params.event.removed {
+            let pos = folders.iter().position(|x| *x == removed).unwrap();
+            folders.swap_remove(pos);
+        }
+        folders.extend_from_slice(&params.event.added);
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        info!("Server initialized");
+        self.client
+            .log_message(MessageType::INFO, "server initialized!")
+            .await;
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        debug!("file opened");
+        self.client
+            .log_message(MessageType::INFO, "file opened!")
+            .await;
+
+        let source_id = SourceId::from(params.text_document.uri.as_str());
+
+        if self.state.main_file.read().is_none() {
+            self.state
+                .main_file
+                .write(triomphe::Arc::new(Some(source_id)));
+        }
+
+        // TODO: warn files that aren't included by main_file
+
+        self.on_change(
+            params.text_document.uri,
+            params.text_document.text,
+            None,
+            Some(params.text_document.version),
+            tokio::runtime::Handle::current(),
+        );
+    }
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri;
+        let Some((text, previous)) = self.apply_changes(&uri, params.content_changes) else {
+            warn!("Couldn't apply changes to `{uri}`, the ranges are out of bounds");
+            return;
+        };
+        self.on_change(
+            uri,
+            text,
+            previous,
+            Some(params.text_document.version),
+            tokio::runtime::Handle::current(),
+        );
+    }
+    async fn did_save(&self, _: DidSaveTextDocumentParams) {
+        self.client
+            .log_message(MessageType::INFO, "file saved!")
+            .await;
+    }
+    async fn did_close(&self, _: DidCloseTextDocumentParams) {
+        debug!("file closed");
+
+        // TODO: remove main file?
+
+        self.client
+            .log_message(MessageType::INFO, "file closed!")
+            .await;
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        Ok(hover::hover(self, &params))
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        Ok(completion::completion(self, &params))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        Ok(code_action::code_action(self, params))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        Ok(definition::goto_definition(self, params))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        Ok(references::references(self, params))
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        Ok(references::document_highlight(self, params))
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        Ok(symbols::document_symbol(self, &params))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        Ok(Some(symbols::symbol_search(self, &params)))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        Ok(inlay_hints::inlay_hint(self, &params))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        Ok(semantic_tokens::semantic_tokens_full(self, &params).map(Into::into))
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        Ok(semantic_tokens::semantic_tokens_range(self, &params).map(Into::into))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        Ok(rename::prepare_rename(self, &params))
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let mut dirs = self
+            .state
+            .workspace_folders
+            .lock()
+            .await
+            .iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect::<Vec<_>>();
+        dirs.extend(self.state.include_paths.read().iter().cloned());
+        rename::rename(self, params, &dirs)
+            .map_err(|err| tower_lsp::jsonrpc::Error::invalid_params(err.to_string()))
+    }
+}
+
+impl Backend {
+    /// Registers the lint rules from the config of the workspace containing `folder`.
+    fn load_rule_files(&self, folder: &Path) -> Result<(), String> {
+        let WorkspacePathFindResult::TomlConfig { toml_file_path, .. } =
+            Workspace::find_workspace_dir(folder)
+        else {
+            return Ok(());
+        };
+        // Rule files can only be configured in the TOML config
+        let rule_files = TomlConfig::load(&toml_file_path)
+            .map_err(|err| format!("{}: {err}", toml_file_path.display()))?
+            .rule_files
+            .unwrap_or_default();
+
+        let mut lint_registry = self.state.lint_registry.write();
+        for rule_file in &rule_files {
+            let rules = RuleFile::load(rule_file)
+                .map_err(|err| format!("{}: {err}", rule_file.display()))?
+                .rules;
+            lint_registry
+                .register_rules(rules)
+                .map_err(|err| format!("{}: {err}", rule_file.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Uses the include paths from the environment or the config of the workspace containing
+    /// `folder` for resolving `#include`s, like `dt` does.
+    fn load_include_paths(&self, folder: &Path) {
+        let toml_include_paths = || {
+            let WorkspacePathFindResult::TomlConfig { toml_file_path, .. } =
+                Workspace::find_workspace_dir(folder)
+            else {
+                return None;
+            };
+            // Errors are already reported by `load_rule_files`
+            TomlConfig::load(&toml_file_path).ok()?.include_paths
+        };
+        let include_paths = EnvConfig::from_env()
+            .ok()
+            .and_then(|env| env.include_paths)
+            .or_else(toml_include_paths)
+            .unwrap_or_default();
+        *self.state.include_paths.write() = include_paths;
+    }
+
+    /// Returns the path of the file included by `include` in the file at `uri`.
+    fn resolve_include(&self, uri: &Url, include: &AnalyzedInclude) -> Option<PathBuf> {
+        let path = uri.to_file_path().ok()?;
+        include.find_file(path.parent()?, &self.state.include_paths.read())
+    }
+
+    /// Returns the includes of the file at `uri`, whose stage 1 output is `analyzed`, and of the
+    /// `files` it includes for [`compute`](dt_analyzer::new::stage2::compute).
+    fn resolved_includes<'a>(
+        &self,
+        uri: &Url,
+        analyzed: &[AnalyzedToplevel],
+        files: &'a [Snapshot],
+    ) -> Vec<ResolvedInclude<'a>> {
+        let files_with_includes = std::iter::once((uri, analyzed)).chain(
+            files
+                .iter()
+                .filter(|file| file.uri != *uri)
+                .map(|file| (&file.uri, file.analyzed.as_slice())),
+        );
+        let mut includes = Vec::new();
+        for (includer, analyzed) in files_with_includes {
+            for include in analyzed.iter().filter_map(AnalyzedToplevel::as_include) {
+                let Some(included_uri) = self
+                    .resolve_include(includer, include)
+                    .and_then(|path| Url::from_file_path(path).ok())
+                else {
+                    continue;
+                };
+                if let Some(file) = files.iter().find(|file| file.uri == included_uri) {
+                    includes.push(ResolvedInclude {
+                        includer: SourceId::from(includer.as_str()),
+                        text_range: include.text_range,
+                        source_id: SourceId::from(included_uri.as_str()),
+                        outline: &file.analyzed,
+                    });
+                }
+            }
+        }
+        includes
+    }
+
+    /// Returns a snapshot of the file at `uri`, which is read from disk if it isn't open.
+    fn snapshot(&self, uri: &Url) -> Option<Snapshot> {
+        if let Some(document) = self.state.document_map.get(&SourceId::from(uri.as_str())) {
+            if let (Some(file), Some(analyzed)) = (&document.file, &document.analyzed) {
+                return Some(Snapshot {
+                    uri: uri.clone(),
+                    text: document.text.to_string(),
+                    file: file.clone(),
+                    analyzed: analyzed.clone(),
+                });
+            }
+            // Still being analyzed
+            return Some(Snapshot::parse(uri.clone(), document.text.to_string()));
+        }
+        let text = std::fs::read_to_string(uri.to_file_path().ok()?).ok()?;
+        Some(Snapshot::parse(uri.clone(), text))
+    }
+
+    /// Returns snapshots of the file at `uri` and the files it includes, recursively, followed by
+    /// the main file and its includes. Each file is only returned once.
+    ///
+    /// Labels and macros are global, so these are the files they can be defined in.
+    fn reachable_files(&self, uri: &Url) -> Vec<Snapshot> {
+        let main_file = self
+            .state
+            .main_file
+            .read()
+            .as_deref()
+            .and_then(|source_id| Url::parse(source_id).ok());
+        self.collect_files_from(std::iter::once(uri.clone()).chain(main_file))
+    }
+
+    /// Returns the [reachable files](Self::reachable_files) of `uri` followed by every open
+    /// document and its includes.
+    ///
+    /// Files including `uri` are only found if they are open, which is enough for finding the
+    /// references to its nodes.
+    fn include_graph(&self, uri: &Url) -> Vec<Snapshot> {
+        let main_file = self
+            .state
+            .main_file
+            .read()
+            .as_deref()
+            .and_then(|source_id| Url::parse(source_id).ok());
+        self.collect_files_from(
+            std::iter::once(uri.clone())
+                .chain(main_file)
+                .chain(self.open_documents()),
+        )
+    }
+
+    /// Returns snapshots of the main file, every open document and the files they include.
+    fn analyzed_files(&self) -> Vec<Snapshot> {
+        let main_file = self
+            .state
+            .main_file
+            .read()
+            .as_deref()
+            .and_then(|source_id| Url::parse(source_id).ok());
+        self.collect_files_from(main_file.into_iter().chain(self.open_documents()))
+    }
+
+    fn open_documents(&self) -> Vec<Url> {
+        // Collected first so no document map guard is held while taking snapshots
+        self.state
+            .document_map
+            .iter()
+            .filter_map(|entry| Url::parse(entry.key()).ok())
+            .collect()
+    }
+
+    fn collect_files_from(&self, starts: impl IntoIterator<Item = Url>) -> Vec<Snapshot> {
+        let mut files = Vec::new();
+        let mut seen = rustc_hash::FxHashSet::default();
+        for start in starts {
+            self.collect_files(&start, 0, &mut seen, &mut files);
+        }
+        files
+    }
+
+    fn collect_files(
+        &self,
+        uri: &Url,
+        depth: usize,
+        seen: &mut rustc_hash::FxHashSet<Url>,
+        files: &mut Vec<Snapshot>,
+    ) {
+        if depth > MAX_INCLUDE_DEPTH || !seen.insert(uri.clone()) {
+            return;
+        }
+        let Some(snapshot) = self.snapshot(uri) else {
+            return;
+        };
+        let includes = snapshot
+            .analyzed
+            .iter()
+            .filter_map(AnalyzedToplevel::as_include)
+            .filter_map(|include| self.resolve_include(uri, include))
+            .filter_map(|path| Url::from_file_path(path).ok())
+            .collect::<Vec<_>>();
+        files.push(snapshot);
+        for include in includes {
+            self.collect_files(&include, depth + 1, seen, files);
+        }
+    }
+
+    /// Loads the binding schemas from the config of the workspace containing `folder`, or the
+    /// bindings of the Zephyr tree containing it, in the background, then re-checks the open
+    /// documents.
+    fn load_bindings(&self, folder: &Path, tokio_handle: tokio::runtime::Handle) {
+        let config = match Workspace::find_workspace_dir(folder) {
+            // Errors are already reported by `load_rule_files`
+            WorkspacePathFindResult::TomlConfig { toml_file_path, .. } => {
+                let Ok(config) = TomlConfig::load(&toml_file_path) else {
+                    return;
+                };
+                config
+            }
+            WorkspacePathFindResult::ZephyrMarker { workspace_dir } => {
+                TomlConfig::zephyr(workspace_dir)
+            }
+            WorkspacePathFindResult::LinuxMarker { .. }
+            | WorkspacePathFindResult::Fallback { .. } => return,
+        };
+        let Some(bindings_dirs) = config.bindings_dirs else {
+            return;
+        };
+        let dt_schema_dir = config.dt_schema_dir;
+        let cache_dir = config
+            .cache_dir
+            .or_else(dt_binding_matcher::default_cache_dir);
+
+        let this = self.clone();
+        rayon::spawn(move || {
+            let (bindings, errors) = match cache_dir {
+                Some(cache_dir) => {
+                    Bindings::load_dirs_cached(&bindings_dirs, dt_schema_dir.as_deref(), &cache_dir)
+                }
+                None => Bindings::load_dirs(&bindings_dirs, dt_schema_dir.as_deref()),
+            };
+            for error in &errors {
+                tracing::info!("failed to load binding schema {error}");
+            }
+            info!(
+                "Loaded {} binding schemas, {} failed",
+                bindings.len(),
+                errors.len()
+            );
+            this.state.bindings.write(triomphe::Arc::new(bindings));
+
+            this.recheck_documents(&tokio_handle);
+        });
+    }
+
+    /// Checks every document again with its current text, e.g. after the bindings change.
+    fn recheck_documents(&self, tokio_handle: &tokio::runtime::Handle) {
+        // Collected first so no document map guard is held while checking
+        let source_ids = self
+            .state
+            .document_map
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        for source_id in source_ids {
+            // Read right before checking, so edits made meanwhile aren't lost
+            let Some((text, version)) = self
+                .state
+                .document_map
+                .get(&source_id)
+                .map(|document| (document.text.to_string(), document.version))
+            else {
+                continue;
+            };
+            if let Ok(uri) = Url::parse(source_id.as_ref()) {
+                self.on_change(uri, text, None, version, tokio_handle.clone());
+            }
+        }
+    }
+
+    /// Stores `document` unless the document map has a newer version of it.
+    ///
+    /// Returns false if `document` is outdated, so its diagnostics mustn't be published. Open
+    /// documents are always newer than text read from disk.
+    fn store_document(&self, source_id: SourceId, document: Document) -> bool {
+        match self.state.document_map.entry(source_id) {
+            dashmap::Entry::Occupied(mut entry) => {
+                if entry.get().version > document.version {
+                    return false;
+                }
+                entry.insert(document);
+            }
+            dashmap::Entry::Vacant(entry) => {
+                entry.insert(document);
+            }
+        }
+        true
+    }
+
+    /// Applies `changes` to the text of the document at `uri`.
+    ///
+    /// Returns the new text, with the document's parse and the byte range of the edit in the old
+    /// text if the changes are a single edit which can be reparsed incrementally.
+    fn apply_changes(
+        &self,
+        uri: &Url,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Option<(String, Option<(Parse<'static>, TextRange)>)> {
+        let (mut rope, parse) = self
+            .state
+            .document_map
+            .get(&SourceId::from(uri.as_str()))
+            .map(|document| (document.text.clone(), document.parse.clone()))
+            .unwrap_or_default();
+        let mut previous = parse.filter(|_| changes.len() == 1);
+        let mut edit = None;
+        for change in changes {
+            let Some(range) = change.range else {
+                rope = Rope::from_str(&change.text);
+                previous = None;
+                continue;
+            };
+            let start = position_to_byte_offset(range.start, &rope)?;
+            let end = position_to_byte_offset(range.end, &rope)?;
+            edit = Some(TextRange::new(start, end));
+            let start = rope.try_byte_to_char(start).ok()?;
+            let end = rope.try_byte_to_char(end).ok()?;
+            rope.try_remove(start..end).ok()?;
+            rope.try_insert(start, &change.text).ok()?;
+        }
+        Some((rope.to_string(), previous.zip(edit)))
+    }
+
+    /// Parses, lints and analyzes the new `text` of the document at `uri` and publishes its
+    /// diagnostics.
+    ///
+    /// `previous` is the document's last parse with the byte range of the edit since, which lets
+    /// only the edited node be parsed again.
+    ///
+    /// Nothing is stored or published if the document map already has a newer `version`, e.g.
+    /// when the document was edited while a background task was checking it.
+    #[tracing::instrument(skip_all, fields(uri = %uri))]
+    fn on_change(
+        &self,
+        uri: Url,
+        text: String,
+        previous: Option<(Parse<'static>, TextRange)>,
+        version: Option<i32>,
+        tokio_handle: tokio::runtime::Handle,
+    ) {
+        debug!(version, "File with URI `{uri}` was changed");
+        let rope = ropey::Rope::from_str(&text);
+        let source_id = SourceId::from(uri.as_str());
+        let is_newest = self.store_document(
+            source_id.clone(),
+            Document {
+                text: rope.clone(),
+                version,
+                file: None,
+                analyzed: None,
+                lints: Vec::new(),
+                diagnostics: Vec::new(),
+                parse: None,
+                lsp_diagnostics: Vec::new(),
+            },
+        );
+        if !is_newest {
+            debug!(version, "Skipped outdated version of `{uri}`");
+            return;
+        }
+        // TODO: Check if it exists already, with equal text
+        // It may be an included file or a reopened file
+
+        let is_main_file = self.state.main_file.read().as_deref() == Some(&source_id);
+
+        let parse = match previous {
+            Some((previous, range)) => previous.reparse(range, &text),
+            None => parse(&text),
+        };
+        let reusable_parse = parse.lex_errors.is_empty().then(|| Parse {
+            green_node: parse.green_node.clone(),
+            lex_errors: Vec::new(),
+            errors: parse.errors.clone(),
+        });
+        let Parse {
+            green_node,
+            lex_errors,
+            errors,
+        } = parse;
+
+        let mut diagnostics = Vec::new();
+
+        let earliest_lex_error_range = lex_errors.first().map(|e| e.text_range);
+        for lex_error in lex_errors {
+            diagnostics.push(Diagnostic::new_simple(
+                range_to_lsp(lex_error.text_range, &rope).expect("range should be in the rope"),
+                format!("{} [lex error]", lex_error.inner),
+            ));
+        }
+
+        for error in errors {
+            if let Some(earliest_lex_error_range) = earliest_lex_error_range {
+                if error.primary_span.start >= earliest_lex_error_range.start {
+                    break;
+                }
+            }
+
+            diagnostics.push(Diagnostic {
+                range: range_to_lsp(error.primary_span, &rope)
+                    .expect("range should be in the rope"),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("dt-tools(syntax-error)".to_owned()),
+                message: error.message.into_owned(),
+                related_information: Some(
+                    error
+                        .span_labels
+                        .iter()
+                        .map(|span_label| DiagnosticRelatedInformation {
+                            location: Location {
+                                uri: uri.clone(),
+                                range: range_to_lsp(span_label.span, &rope)
+                                    .expect("range should be in the rope"),
+                            },
+                            message: span_label.msg.clone().into_owned(),
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            });
+            for span_label in error.span_labels {
+                diagnostics.push(Diagnostic {
+                    range: range_to_lsp(span_label.span, &rope)
+                        .expect("range should be in the rope"),
+                    severity: Some(DiagnosticSeverity::HINT),
+                    source: Some("dt-tools(syntax-error)".to_owned()),
+                    message: span_label.msg.into_owned(),
+                    related_information: Some(vec![DiagnosticRelatedInformation {
+                        location: Location {
+                            uri: uri.clone(),
+                            range: range_to_lsp(error.primary_span, &rope)
+                                .expect("range should be in the rope"),
+                        },
+                        message: "original diagnostic".to_owned(),
+                    }]),
+                    ..Default::default()
+                });
+            }
+        }
+
+        let cst = RedNode::new(Arc::new(green_node));
+
+        // TODO: use Parse::source_file
+        let file = ast::SourceFile::cast(cst).expect("parser should always return SourceFile");
+
+        let lint_registry = self.state.lint_registry.read();
+        let mut lints = lint_registry.lint_early(&file, &text, is_main_file);
+
+        let mut new_diagnostics = Vec::new();
+        let diag = parking_lot::Mutex::new(&mut new_diagnostics);
+
+        let analyzed = dt_analyzer::new::stage1::analyze_file(&file, &text, &diag);
+        let included_files = self.collect_files_from(
+            analyzed
+                .iter()
+                .filter_map(AnalyzedToplevel::as_include)
+                .filter_map(|include| self.resolve_include(&uri, include))
+                .filter_map(|path| Url::from_file_path(path).ok()),
+        );
+        // Late lints and bindings see the tree merged with the includes, like `dt check`
+        let merged = dt_analyzer::new::stage2::compute(
+            &source_id,
+            &analyzed,
+            &self.resolved_includes(&uri, &analyzed, &included_files),
+            &diag,
+        );
+        lints.extend(lint_registry.lint_late(&merged, &text));
+        diagnostics.extend(lints.iter().flat_map(|lint| {
+            lint.span
+                .primary_spans
+                .iter()
+                .enumerate()
+                .map(|(i, span)| Diagnostic {
+                    range: range_to_lsp(*span, &rope).expect("range should be in the rope"),
+                    severity: Some(match lint.severity {
+                        dt_lint::LintSeverity::Warn => DiagnosticSeverity::WARNING,
+                        dt_lint::LintSeverity::Error => DiagnosticSeverity::ERROR,
+                    }),
+                    code: Some(NumberOrString::String(lint.id.to_string())),
+                    code_description: lint
+                        .id
+                        .lint()
+                        .url
+                        .and_then(|url| Url::parse(url).ok())
+                        .map(|href| CodeDescription { href }),
+                    source: Some(format!("dt-tools(lint {})", lint.id)),
+                    message: lint.msg.clone().into_owned(),
+                    related_information: Some(
+                        lint.span
+                            .primary_spans
+                            .iter()
+                            .enumerate()
+                            .filter(|(j, _)| *j != i)
+                            .map(|(_, span)| DiagnosticRelatedInformation {
+                                location: Location {
+                                    uri: uri.clone(),
+                                    range: range_to_lsp(*span, &rope)
+                                        .expect("range should be in the rope"),
+                                },
+                                message: "see also".to_owned(),
+                            })
+                            .chain(lint.span.span_labels.iter().map(|span| {
+                                DiagnosticRelatedInformation {
+                                    location: Location {
+                                        uri: uri.clone(),
+                                        range: range_to_lsp(span.0, &rope)
+                                            .expect("range should be in the rope"),
+                                    },
+                                    message: span.1.clone().into_owned(),
+                                }
+                            }))
+                            .collect(),
+                    ),
+                    ..Default::default()
+                })
+                .chain(lint.span.span_labels.iter().map(|(span, hint)| {
+                    Diagnostic {
+                        range: range_to_lsp(*span, &rope).expect("range should be in the rope"),
+                        severity: Some(DiagnosticSeverity::HINT),
+                        source: Some(format!("dt-tools(lint {})", lint.id)),
+                        message: hint.clone().into_owned(),
+                        related_information: Some(
+                            lint.span
+                                .primary_spans
+                                .iter()
+                                .map(|span| DiagnosticRelatedInformation {
+                                    location: Location {
+                                        uri: uri.clone(),
+                                        range: range_to_lsp(*span, &rope)
+                                            .expect("range should be in the rope"),
+                                    },
+                                    message: "original diagnostic".to_owned(),
+                                })
+                                .collect(),
+                        ),
+                        ..Default::default()
+                    }
+                }))
+        }));
+
+        let mut included_diagnostics = rustc_hash::FxHashMap::default();
+        for (error_source_id, binding_error) in self.state.bindings.read().validate(&merged) {
+            let (included_rope, target) = if error_source_id == source_id {
+                (None, &mut diagnostics)
+            } else {
+                let Some(included_rope) = Url::parse(&error_source_id)
+                    .ok()
+                    .and_then(|uri| self.rope(&uri))
+                else {
+                    continue;
+                };
+                (
+                    Some(included_rope),
+                    included_diagnostics
+                        .entry(error_source_id)
+                        .or_insert_with(Vec::new),
+                )
+            };
+            let error_rope = included_rope.as_ref().unwrap_or(&rope);
+            target.extend(
+                binding_error
+                    .span
+                    .primary_spans
+                    .iter()
+                    .filter_map(|span| range_to_lsp(*span, error_rope))
+                    .map(|range| Diagnostic {
+                        range,
+                        severity: Some(DiagnosticSeverity::ERROR),
+                        code: Some(NumberOrString::String("binding".to_owned())),
+                        source: Some("dt-tools(binding)".to_owned()),
+                        message: binding_error.msg.clone().into_owned(),
+                        ..Default::default()
+                    }),
+            );
+        }
+
+        //if is_main_file {
+        if false {
+            use std::io::Write;
+            let mut f = std::fs::File::create("analyzed2.dbg").unwrap();
+            write!(f, "analyzed2={merged:#?}").unwrap();
+            let mut f = std::fs::File::create("analyzed1.dbg").unwrap();
+            write!(f, "analyzed1={analyzed:#?}").unwrap();
+        }
+
+        // Linux kernel DTC include path, to be set in the workspace config:
+        // - include (for #define's in header files)
+        // - scripts/dtc/include-prefixes
+
+        // TODO: only re-check when includes are updated or include config is changed
+        for include in analyzed.iter().filter_map(AnalyzedToplevel::as_include) {
+            let include_path = self.resolve_include(&uri, include);
+
+            let Some(include_path) = include_path else {
+                diag.emit(dt_diagnostic::Diagnostic::new(
+                    include.text_range,
+                    Cow::Borrowed("Couldn't find file to include"),
+                    dt_diagnostic::Severity::Error,
+                ));
+                continue;
+            };
+
+            let new_uri = Url::from_file_path(include_path.clone()).unwrap();
+
+            tracing::info!("dot {:?} -> {:?}", uri.to_string(), new_uri.to_string());
+
+            if !self
+                .state
+                .document_map
+                .contains_key(&SourceId::from(new_uri.to_string()))
+            {
+                let this = self.clone();
+                let tokio_handle = tokio_handle.clone();
+                rayon::spawn(move || {
+                    // AnalyzedInclude::find_file made sure it exists
+                    if let Ok(text) = std::fs::read_to_string(include_path) {
+                        // Without a version, so a document opened meanwhile isn't overwritten
+                        this.on_change(new_uri, text, None, None, tokio_handle);
+                    }
+                });
+            }
+        }
+
+        for new_diagnostic in new_diagnostics.clone() {
+            // TODO: level
+            for primary_span in new_diagnostic.span.primary_spans {
+                diagnostics.push(Diagnostic::new_simple(
+                    range_to_lsp(primary_span, &rope).expect("range should be in the rope"),
+                    new_diagnostic.msg.clone().into_owned(),
+                ));
+            }
+
+            for span_label in new_diagnostic.span.span_labels {
+                diagnostics.push(Diagnostic::new(
+                    range_to_lsp(span_label.span, &rope).expect("range should be in the rope"),
+                    Some(DiagnosticSeverity::HINT),
+                    None,
+                    None,
+                    span_label.msg.into_owned(),
+                    None,
+                    None,
+                ));
+            }
+        }
+
+        diagnostics.dedup();
+        let is_newest = self.store_document(
+            source_id.clone(),
+            Document {
+                text: rope,
+                version,
+                file: Some(file),
+                analyzed: Some(analyzed),
+                lints,
+                diagnostics: new_diagnostics,
+                parse: reusable_parse,
+                lsp_diagnostics: diagnostics,
+            },
+        );
+        if !is_newest {
+            debug!(version, "`{uri}` was changed while it was checked");
+            return;
+        }
+
+        for included in self.set_included_diagnostics(&source_id, included_diagnostics) {
+            if let Ok(included) = Url::parse(&included) {
+                self.publish_diagnostics(included, &tokio_handle);
+            }
+        }
+        self.publish_diagnostics(uri, &tokio_handle);
+    }
+
+    /// Returns the text of the file at `uri`, which is read from disk if it isn't open.
+    fn rope(&self, uri: &Url) -> Option<Rope> {
+        if let Some(document) = self.state.document_map.get(&SourceId::from(uri.as_str())) {
+            return Some(document.text.clone());
+        }
+        let text = std::fs::read_to_string(uri.to_file_path().ok()?).ok()?;
+        Some(Rope::from_str(&text))
+    }
+
+    /// Replaces the binding errors in the files included by `includer` with `diagnostics`.
+    ///
+    /// Returns the included files whose diagnostics changed.
+    fn set_included_diagnostics(
+        &self,
+        includer: &SourceId,
+        mut diagnostics: rustc_hash::FxHashMap<SourceId, Vec<Diagnostic>>,
+    ) -> Vec<SourceId> {
+        let mut changed = Vec::new();
+        for mut entry in self.state.included_diagnostics.iter_mut() {
+            let new = diagnostics.remove(entry.key());
+            if entry.value().get(includer) != new.as_ref() {
+                changed.push(entry.key().clone());
+                match new {
+                    Some(new) => entry.value_mut().insert(includer.clone(), new),
+                    None => entry.value_mut().remove(includer),
+                };
+            }
+        }
+        for (included, new) in diagnostics {
+            changed.push(included.clone());
+            self.state
+                .included_diagnostics
+                .entry(included)
+                .or_default()
+                .insert(includer.clone(), new);
+        }
+        changed
+    }
+
+    /// Publishes the diagnostics of the file at `uri` with the binding errors found in it when
+    /// checking the files including it.
+    fn publish_diagnostics(&self, uri: Url, tokio_handle: &tokio::runtime::Handle) {
+        let source_id = SourceId::from(uri.as_str());
+        let (mut diagnostics, version) = self
+            .state
+            .document_map
+            .get(&source_id)
+            .map(|document| (document.lsp_diagnostics.clone(), document.version))
+            .unwrap_or_default();
+        if let Some(included) = self.state.included_diagnostics.get(&source_id) {
+            diagnostics.extend(included.values().flatten().cloned());
+        }
+        diagnostics.dedup();
+        let client = self.client.clone();
+        tokio_handle.spawn(async move {
+            client.publish_diagnostics(uri, diagnostics, version).await;
+        });
+    }
+}
+
+/// Runs the language server with the lints from `registry`.
+///
+/// [`UndocumentedCompatibles`] is added to `registry` for the bindings in the workspace config.
+///
+/// The server communicates over stdio, or over TCP on port 9257 when the first argument is `tcp`.
+///
+/// # Example
+///
+/// A `main.rs` with custom lints:
+///
+/// ```no_run
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut registry = dt_lint::LintRegistry::builtin();
+///     // registry.register_lints(...);
+///     // registry.register_late_pass(...);
+///     dt_lsp::serve(registry).await
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the TCP listener can't be bound or accept a connection.
+pub async fn serve(mut lint_registry: LintRegistry) -> Result<(), Box<dyn std::error::Error>> {
+    let first_arg = std::env::args().nth(1);
+
+    let bindings = Arc::new(Rcu::new(triomphe::Arc::new(Bindings::new())));
+    let lint_bindings = Arc::clone(&bindings);
+    UndocumentedCompatibles::register(&mut lint_registry, move || {
+        lint_bindings.read().compatibles().clone()
+    });
+
+    let (service, socket) = LspService::new(|client| Backend {
+        client,
+        state: Arc::new(SharedState::new(lint_registry, bindings)),
+    });
+
+    let tracing_env_filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    #[cfg(debug_assertions)]
+    let tracing_env_filter = tracing_env_filter
+        .add_directive("dt_lsp=trace".parse()?)
+        .add_directive("dt_parser=trace".parse()?);
+
+    if first_arg == Some("tcp".to_owned()) {
+        tracing_subscriber::fmt()
+            .with_env_filter(tracing_env_filter)
+            .init();
+
+        info!("Using tcp");
+        let listener =
+            TcpListener::bind(SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 9257)).await?;
+        let (stream, _) = listener.accept().await?;
+        let (read, write) = tokio::io::split(stream);
+        Server::new(read, write, socket).serve(service).await;
+    } else {
+        tracing_subscriber::fmt()
+            .with_env_filter(tracing_env_filter)
+            .with_writer(std::io::stderr)
+            .with_ansi(false)
+            .init();
+
+        info!("Using stdio");
+        let read = tokio::io::stdin();
+        let write = tokio::io::stdout();
+        Server::new(read, write, socket).serve(service).await;
+    }
+    Ok(())
+}
+
+/// A snapshot of a parsed file, which may not be open
+#[derive(Debug, Clone)]
+pub(crate) struct Snapshot {
+    pub uri: Url,
+    pub text: String,
+    pub file: ast::SourceFile,
+    pub analyzed: Vec<AnalyzedToplevel>,
+}
+
+impl Snapshot {
+    /// Parses and analyzes `text`, ignoring any errors.
+    pub fn parse(uri: Url, text: String) -> Self {
+        let file = ast::SourceFile::parse(&text).source_file();
+        let analyzed = dt_analyzer::new::stage1::analyze_file(
+            &file,
+            &text,
+            &parking_lot::Mutex::new(Vec::new()),
+        );
+        Self {
+            uri,
+            text,
+            file,
+            analyzed,
+        }
+    }
+}
+
+/// Converts an LSP position, whose column is in UTF-16 code units, to a byte offset in `rope`.
+fn position_to_byte_offset(position: Position, rope: &Rope) -> Option<usize> {
+    let line_start = rope.try_line_to_char(position.line as usize).ok()?;
+    let line_start_cu = rope.try_char_to_utf16_cu(line_start).ok()?;
+    let char_idx = rope
+        .try_utf16_cu_to_char(line_start_cu + position.character as usize)
+        .ok()?;
+    rope.try_char_to_byte(char_idx).ok()
+}
+
+/// Converts a byte offset in `rope` to an LSP position, whose column is in UTF-16 code units.
+fn offset_to_position(offset: usize, rope: &Rope) -> Option<Position> {
+    let line = rope.try_byte_to_line(offset).ok()?;
+    let line_start = rope.try_line_to_char(line).ok()?;
+    let char_idx = rope.try_byte_to_char(offset).ok()?;
+    let column =
+        rope.try_char_to_utf16_cu(char_idx).ok()? - rope.try_char_to_utf16_cu(line_start).ok()?;
+    Some(Position::new(
+        u32::try_from(line).ok()?,
+        u32::try_from(column).ok()?,
+    ))
+}
+
+fn range_to_lsp(text_range: TextRange, rope: &Rope) -> Option<Range> {
+    Some(Range {
+        start: offset_to_position(text_range.start, rope)?,
+        end: offset_to_position(text_range.end, rope)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_range_to_lsp_trailing_newline() {
+        let rope = ropey::Rope::from_str("\n");
+        assert_eq!(
+            range_to_lsp(TextRange { start: 0, end: 1 }, &rope),
+            Some(Range {
+                start: Position {
+                    line: 0,
+                    character: 0
+                },
+                end: Position {
+                    line: 1,
+                    character: 0
+                },
+            })
+        );
+        assert_eq!(range_to_lsp(TextRange { start: 0, end: 2 }, &rope), None);
+
+        // Let's hope fully empty files (e.g. created through VS Code or Windows) don't get
+        // diagnostics with nonzero length (invalid according to LSP spec? or?). Not sure how rust-analyzer handles this.
+        let rope = ropey::Rope::from_str("");
+        assert_eq!(range_to_lsp(TextRange { start: 0, end: 1 }, &rope), None);
+    }
+
+    #[test]
+    fn test_position_to_byte_offset_multibyte() {
+        // `ä` is 2 bytes and 1 UTF-16 code unit, `𝄞` is 4 bytes and 2 UTF-16 code units
+        let rope = ropey::Rope::from_str("// ä𝄞\n/ { ä; };\n");
+        let position = |line, character| Position { line, character };
+
+        assert_eq!(position_to_byte_offset(position(0, 4), &rope), Some(5));
+        assert_eq!(position_to_byte_offset(position(0, 6), &rope), Some(9));
+        assert_eq!(position_to_byte_offset(position(1, 4), &rope), Some(14));
+        assert_eq!(position_to_byte_offset(position(5, 0), &rope), None);
+
+        assert_eq!(offset_to_position(9, &rope), Some(position(0, 6)));
+        assert_eq!(offset_to_position(14, &rope), Some(position(1, 4)));
+    }
+
+    /// Returns the binding schemas in `test_data/bindings`.
+    pub(crate) fn bindings() -> Bindings {
+        let (bindings, errors) = Bindings::load_dirs(&["test_data/bindings"], None);
+        assert!(errors.is_empty(), "{errors:?}");
+        bindings
+    }
+
+    /// Returns a backend with the builtin lints and no bindings, whose client isn't connected.
+    pub(crate) fn backend() -> Backend {
+        let (service, _socket) = LspService::new(|client| Backend {
+            client,
+            state: Arc::new(SharedState::new(
+                LintRegistry::builtin(),
+                Arc::new(Rcu::new(triomphe::Arc::new(Bindings::new()))),
+            )),
+        });
+        service.inner().clone()
+    }
+
+    /// Returns a snapshot of the file `name`, e.g. `board.dts`, with the text `src`.
+    pub(crate) fn snapshot(name: &str, src: &str) -> Snapshot {
+        Snapshot::parse(
+            Url::parse(&format!("file:///{name}")).unwrap(),
+            src.to_owned(),
+        )
+    }
+
+    /// Returns snapshots of `files`, given as names and texts.
+    pub(crate) fn snapshots(files: &[(&str, &str)]) -> Vec<Snapshot> {
+        files
+            .iter()
+            .map(|(name, src)| snapshot(name, src))
+            .collect()
+    }
+
+    /// Removes the cursor, the first `|`, from `src` and returns its offset.
+    pub(crate) fn cursor(src: &str) -> (String, usize) {
+        let cursor = src.find('|').unwrap();
+        (src.replacen('|', "", 1), cursor)
+    }
+
+    /// Returns snapshots of `files` and the offset of the cursor, the first `|` in the first file.
+    pub(crate) fn snapshots_at_cursor(files: &[(&str, &str)]) -> (Vec<Snapshot>, usize) {
+        let (first, cursor) = cursor(files[0].1);
+        let mut files = files.to_vec();
+        files[0].1 = &first;
+        (snapshots(&files), cursor)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn edits_during_recheck_are_kept() {
+        use tower_lsp::lsp_types::{TextDocumentItem, VersionedTextDocumentIdentifier};
+
+        let backend = backend();
+        let uri = Url::parse("file:///board.dts").unwrap();
+        backend
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    "devicetree".to_owned(),
+                    1,
+                    "/ {\n};\n".to_owned(),
+                ),
+            })
+            .await;
+
+        // Like after the bindings are loaded
+        let tokio_handle = tokio::runtime::Handle::current();
+        let recheck = {
+            let backend = backend.clone();
+            let tokio_handle = tokio_handle.clone();
+            std::thread::spawn(move || {
+                for _ in 0..20 {
+                    backend.recheck_documents(&tokio_handle);
+                }
+            })
+        };
+        for version in 2..50 {
+            backend
+                .did_change(DidChangeTextDocumentParams {
+                    text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+                    content_changes: vec![TextDocumentContentChangeEvent {
+                        range: Some(Range::new(Position::new(1, 0), Position::new(1, 0))),
+                        range_length: None,
+                        text: "\ta;\n".to_owned(),
+                    }],
+                })
+                .await;
+        }
+        recheck.join().unwrap();
+
+        // A check of an older version finishing late, or the file's text on disk
+        backend.on_change(
+            uri.clone(),
+            "/ {\n};\n".to_owned(),
+            None,
+            Some(1),
+            tokio_handle.clone(),
+        );
+        backend.on_change(
+            uri.clone(),
+            "/ {\n};\n".to_owned(),
+            None,
+            None,
+            tokio_handle,
+        );
+
+        let document = backend
+            .state
+            .document_map
+            .get(&SourceId::from(uri.as_str()))
+            .unwrap();
+        assert_eq!(document.version, Some(49));
+        assert_eq!(
+            document.text.to_string(),
+            format!("/ {{\n{}}};\n", "\ta;\n".repeat(48))
+        );
+        assert!(document.file.is_some());
+    }
+}
@@ -0,0 +1,637 @@
+use std::sync::Arc;
+
+use crate::{position_to_byte_offset, range_to_lsp, Snapshot};
+use dt_analyzer::new::stage1::{AnalyzedInclude, AnalyzedToplevel};
+use dt_binding_matcher::{BindingDoc, Bindings};
+use dt_parser::{
+    ast::{self, AstNode, AstToken, HasName},
+    cst::{NodeKind, RedNode},
+    lexer::TokenKind,
+    TextRange,
+};
+use ropey::Rope;
+use rustc_hash::FxHashMap;
+use tower_lsp::lsp_types::{
+    DocumentHighlight, DocumentHighlightKind, DocumentHighlightParams, Location, ReferenceParams,
+    Url,
+};
+
+/// What a [`Reference`] points to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ReferenceKind {
+    /// `&label`
+    Label(String),
+    /// `&{/path/to/node}`
+    Path(String),
+}
+
+/// A `&label` or `&{/path}`, in a value or as a node extension
+#[derive(Debug, Clone)]
+pub(crate) struct Reference {
+    /// The index of the file in [`NodeIndex::files`]
+    pub file: usize,
+    /// The range of the label name or the path
+    pub range: TextRange,
+    pub kind: ReferenceKind,
+    /// Whether this is a node extension, e.g. `&label { ... };`
+    pub is_extension: bool,
+}
+
+/// A node with its full path, e.g. `/soc/serial@1000`
+///
+/// A node can be defined several times, e.g. in a `.dtsi` and again in a `.dts`.
+#[derive(Debug, Clone)]
+pub(crate) struct NodeDefinition {
+    /// The index of the file in [`NodeIndex::files`]
+    pub file: usize,
+    pub path: String,
+    /// The range of the name and unit address, or `/` for the root node
+    pub name_range: TextRange,
+    /// The label names with their ranges
+    pub labels: Vec<(String, TextRange)>,
+    pub ast: ast::DtNode,
+}
+
+/// A node extension, e.g. `&label { ... };`, with the full path of the node it extends
+#[derive(Debug, Clone)]
+pub(crate) struct NodeExtension {
+    /// The index of the file in [`NodeIndex::files`]
+    pub file: usize,
+    pub path: String,
+    pub ast: ast::DtNode,
+}
+
+/// The nodes defined in a set of files and the references to them
+#[derive(Debug)]
+pub(crate) struct NodeIndex<'a> {
+    pub files: &'a [Snapshot],
+    ropes: Vec<Rope>,
+    /// The full path of each label
+    labels: FxHashMap<String, String>,
+    pub nodes: Vec<NodeDefinition>,
+    pub references: Vec<Reference>,
+    pub extensions: Vec<NodeExtension>,
+}
+
+impl<'a> NodeIndex<'a> {
+    /// Indexes the nodes and references in `files`.
+    ///
+    /// Node extensions are resolved once the node they extend is known, so their order doesn't
+    /// matter.
+    pub fn new(files: &'a [Snapshot]) -> Self {
+        let mut index = Self {
+            files,
+            ropes: files
+                .iter()
+                .map(|file| Rope::from_str(&file.text))
+                .collect(),
+            labels: FxHashMap::default(),
+            nodes: Vec::new(),
+            references: Vec::new(),
+            extensions: Vec::new(),
+        };
+
+        let mut pending = files
+            .iter()
+            .enumerate()
+            .flat_map(|(file, snapshot)| {
+                snapshot
+                    .analyzed
+                    .iter()
+                    .filter_map(move |toplevel| match toplevel {
+                        AnalyzedToplevel::Node(node) => Some((file, node.ast.clone())),
+                        _ => None,
+                    })
+            })
+            .collect::<Vec<_>>();
+        loop {
+            let count = pending.len();
+            pending.retain(|(file, node)| {
+                let Some(path) = index.toplevel_path(*file, node) else {
+                    return true;
+                };
+                index.add_node(*file, node, &path);
+                false
+            });
+            if pending.is_empty() || pending.len() == count {
+                break;
+            }
+        }
+
+        for (file, snapshot) in files.iter().enumerate() {
+            index.add_references(file, snapshot.file.syntax());
+        }
+        index
+    }
+
+    /// Returns the path of a top-level node, if it's the root node or extends a known node.
+    fn toplevel_path(&self, file: usize, node: &ast::DtNode) -> Option<String> {
+        match node.extension_name() {
+            Some(phandle) => self.resolve(&reference_kind(&phandle, &self.files[file].text)?.1),
+            None => node.is_root().then(|| "/".to_owned()),
+        }
+    }
+
+    fn add_node(&mut self, file: usize, node: &ast::DtNode, path: &str) {
+        let src = &self.files[file].text;
+        if node.is_extension() {
+            self.extensions.push(NodeExtension {
+                file,
+                path: path.to_owned(),
+                ast: node.clone(),
+            });
+        } else {
+            let labels = node
+                .syntax()
+                .child_nodes()
+                .filter_map(ast::DtLabel::cast)
+                .filter_map(|label| label.name())
+                .map(|name| (name.syntax().text().to_string(), name.syntax().text_range()))
+                .collect::<Vec<_>>();
+            for (label, _) in &labels {
+                self.labels
+                    .entry(label.clone())
+                    .or_insert_with(|| path.to_owned());
+            }
+            if let Some(name_range) = name_range(node) {
+                self.nodes.push(NodeDefinition {
+                    file,
+                    path: path.to_owned(),
+                    name_range,
+                    labels,
+                    ast: node.clone(),
+                });
+            }
+        }
+        for subnode in node.subnodes() {
+            if let Some(name) = subnode.text_name(src) {
+                let subpath = format!("{}/{name}", path.trim_end_matches('/'));
+                self.add_node(file, &subnode, &subpath);
+            }
+        }
+    }
+
+    fn add_references(&mut self, file: usize, syntax: &Arc<RedNode>) {
+        for child in syntax.child_nodes() {
+            if let Some(phandle) = ast::DtPhandle::cast(child.clone()) {
+                if let Some((range, kind)) = reference_kind(&phandle, &self.files[file].text) {
+                    self.references.push(Reference {
+                        file,
+                        range,
+                        kind,
+                        is_extension: syntax.green.kind == NodeKind::DtNode,
+                    });
+                }
+            } else {
+                self.add_references(file, &child);
+            }
+        }
+    }
+
+    /// Returns the full path of the node `kind` points to if it's known.
+    ///
+    /// Like dtc, a path segment without a unit address matches a node with any unit address.
+    pub fn resolve(&self, kind: &ReferenceKind) -> Option<String> {
+        match kind {
+            ReferenceKind::Label(label) => self.labels.get(label).cloned(),
+            ReferenceKind::Path(path) => self
+                .nodes
+                .iter()
+                .find(|node| path_matches(path, &node.path))
+                .map(|node| node.path.clone()),
+        }
+    }
+
+    /// Returns the full path of the node referenced or defined at `offset` in `file`.
+    pub fn node_at(&self, file: usize, offset: usize) -> Option<String> {
+        let contains = |range: &TextRange| range.start <= offset && offset <= range.end;
+        if let Some(reference) = self
+            .references
+            .iter()
+            .find(|reference| reference.file == file && contains(&reference.range))
+        {
+            return self.resolve(&reference.kind);
+        }
+        self.nodes
+            .iter()
+            .filter(|node| node.file == file)
+            .find(|node| {
+                contains(&node.name_range) || node.labels.iter().any(|(_, range)| contains(range))
+            })
+            .map(|node| node.path.clone())
+    }
+
+    /// Returns the references to the node at `path`.
+    pub fn references_to<'s>(&'s self, path: &'s str) -> impl Iterator<Item = &'s Reference> {
+        self.references
+            .iter()
+            .filter(move |reference| self.resolve(&reference.kind).as_deref() == Some(path))
+    }
+
+    /// Returns the definitions of the node at `path`.
+    pub fn definitions_of<'s>(&'s self, path: &'s str) -> impl Iterator<Item = &'s NodeDefinition> {
+        self.nodes.iter().filter(move |node| node.path == path)
+    }
+
+    /// Returns the labels with the full paths of their nodes.
+    pub fn labels(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.labels
+            .iter()
+            .map(|(label, path)| (label.as_str(), path.as_str()))
+    }
+
+    /// Returns the full path of `node` in `file`, which may be a node extension.
+    pub fn path_of(&self, file: usize, node: &ast::DtNode) -> Option<String> {
+        if let Some(phandle) = node.extension_name() {
+            return self.resolve(&reference_kind(&phandle, &self.files[file].text)?.1);
+        }
+        let range = node.syntax().text_range();
+        self.nodes
+            .iter()
+            .find(|definition| {
+                definition.file == file && definition.ast.syntax().text_range() == range
+            })
+            .map(|definition| definition.path.clone())
+    }
+
+    /// Returns the compatibles of the node at `path`, from its effective `compatible` property.
+    pub fn compatibles(&self, path: &str) -> Vec<String> {
+        self.properties_of(path)
+            .into_iter()
+            .find(|(_, property)| {
+                property
+                    .name()
+                    .is_some_and(|name| name.syntax().text().as_str() == "compatible")
+            })
+            .map(|(_, compatible)| {
+                compatible
+                    .values()
+                    .filter_map(|value| match value {
+                        ast::PropValue::String(token) => {
+                            Some(token.text().trim_matches('"').to_owned())
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the documentation of the first binding matching a compatible of the node at `path`.
+    pub fn binding_doc(&self, path: &str, bindings: &Bindings) -> Option<BindingDoc> {
+        self.compatibles(path)
+            .iter()
+            .find_map(|compatible| bindings.binding_doc(compatible))
+    }
+
+    /// Returns the definitions and extensions of the node at `path` with their files, in the
+    /// order the preprocessor sees them.
+    pub fn bodies_in_order(&self, path: &str) -> Vec<(usize, &ast::DtNode)> {
+        let order = self.preprocessing_order();
+        let rank = |file: usize, offset: usize| {
+            order
+                .iter()
+                .rposition(|&(item_file, start)| item_file == file && start <= offset)
+        };
+        let mut bodies = self
+            .nodes
+            .iter()
+            .filter(|node| node.path == path)
+            .map(|node| (node.file, &node.ast))
+            .chain(
+                self.extensions
+                    .iter()
+                    .filter(|extension| extension.path == path)
+                    .map(|extension| (extension.file, &extension.ast)),
+            )
+            .collect::<Vec<_>>();
+        bodies.sort_by_key(|(file, body)| {
+            let start = body.syntax().text_range().start;
+            (rank(*file, start), start)
+        });
+        bodies
+    }
+
+    /// Returns the properties of the node at `path` with their files after merging its
+    /// definitions and extensions, where later properties replace earlier ones like in dtc.
+    pub fn properties_of(&self, path: &str) -> Vec<(usize, ast::DtProperty)> {
+        let mut properties = Vec::<(usize, ast::DtProperty)>::new();
+        for (file, body) in self.bodies_in_order(path) {
+            for property in body.properties() {
+                let Some(name) = property.name() else {
+                    continue;
+                };
+                let existing = properties.iter_mut().find(|(_, existing)| {
+                    existing
+                        .name()
+                        .is_some_and(|existing| existing.syntax().text() == name.syntax().text())
+                });
+                match existing {
+                    Some(existing) => *existing = (file, property),
+                    None => properties.push((file, property)),
+                }
+            }
+        }
+        properties
+    }
+
+    /// Returns the start of the top-level items of every file in the order the preprocessor
+    /// sees them, where the items of an included file follow its `#include`.
+    ///
+    /// Includes are matched to files by the end of their path.
+    fn preprocessing_order(&self) -> Vec<(usize, usize)> {
+        let included_file = |include: &AnalyzedInclude| {
+            let suffix = format!("/{}", include.path);
+            self.files
+                .iter()
+                .position(|file| file.uri.path().ends_with(&suffix))
+        };
+        let mut is_included = vec![false; self.files.len()];
+        for file in self.files {
+            for include in file
+                .analyzed
+                .iter()
+                .filter_map(AnalyzedToplevel::as_include)
+            {
+                if let Some(included) = included_file(include) {
+                    is_included[included] = true;
+                }
+            }
+        }
+
+        let mut seen = vec![false; self.files.len()];
+        let mut order = Vec::new();
+        let mut stack = (0..self.files.len())
+            .rev()
+            .chain(
+                (0..self.files.len())
+                    .rev()
+                    .filter(|file| !is_included[*file]),
+            )
+            .map(|file| (file, 0))
+            .collect::<Vec<_>>();
+        // Depth-first without recursion, as (file, index of the next top-level item)
+        while let Some((file, item)) = stack.pop() {
+            if item == 0 && std::mem::replace(&mut seen[file], true) {
+                continue;
+            }
+            let Some(toplevel) = self.files[file].analyzed.get(item) else {
+                continue;
+            };
+            order.push((file, toplevel.text_range().start));
+            stack.push((file, item + 1));
+            if let Some(included) = toplevel.as_include().and_then(included_file) {
+                stack.push((included, 0));
+            }
+        }
+        order
+    }
+
+    /// Converts `range` in `file` to an LSP location.
+    pub fn location(&self, file: usize, range: TextRange) -> Option<Location> {
+        Some(Location::new(
+            self.files[file].uri.clone(),
+            range_to_lsp(range, &self.ropes[file])?,
+        ))
+    }
+
+    /// Returns the index of the file at `uri`.
+    pub fn file(&self, uri: &Url) -> Option<usize> {
+        self.files.iter().position(|file| file.uri == *uri)
+    }
+}
+
+/// Returns the range of the label name or path of `phandle` with what it points to.
+fn reference_kind(phandle: &ast::DtPhandle, src: &str) -> Option<(TextRange, ReferenceKind)> {
+    if phandle.is_path() {
+        let syntax = phandle.syntax();
+        let start = syntax
+            .child_tokens()
+            .find(|token| token.green.kind == TokenKind::LCurly)?
+            .text_range()
+            .end;
+        let end = syntax
+            .child_tokens()
+            .find(|token| token.green.kind == TokenKind::RCurly)
+            .map_or(syntax.text_range().end, |token| token.text_range().start);
+        let path = src.get(start..end)?;
+        let trimmed = path.trim_start();
+        let start = start + path.len() - trimmed.len();
+        let trimmed = trimmed.trim_end();
+        Some((
+            TextRange::new(start, start + trimmed.len()),
+            ReferenceKind::Path(trimmed.to_owned()),
+        ))
+    } else {
+        let name = phandle.name()?;
+        Some((
+            name.syntax().text_range(),
+            ReferenceKind::Label(name.syntax().text().to_string()),
+        ))
+    }
+}
+
+/// Returns the range of the name and unit address of `node`, or of `/` for the root node.
+pub(crate) fn name_range(node: &ast::DtNode) -> Option<TextRange> {
+    if node.is_root() {
+        return node
+            .syntax()
+            .child_tokens()
+            .find(|token| token.green.kind == TokenKind::Slash)
+            .map(|token| token.text_range());
+    }
+    let start = node.name()?.syntax().text_range().start;
+    let end = node
+        .unit_address()
+        .map_or_else(|| node.name(), Some)?
+        .syntax()
+        .text_range()
+        .end;
+    Some(TextRange::new(start, end))
+}
+
+/// Returns `true` if the full path `path` matches `pattern`, where segments without a unit address
+/// match any unit address.
+pub(crate) fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern = pattern.split('/').filter(|segment| !segment.is_empty());
+    let mut path = path.split('/').filter(|segment| !segment.is_empty());
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(name)) => {
+                let matches = expected == name
+                    || (!expected.contains('@')
+                        && name
+                            .split_once('@')
+                            .is_some_and(|(base, _)| base == expected));
+                if !matches {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+pub fn references(state: &crate::Backend, params: ReferenceParams) -> Option<Vec<Location>> {
+    let params_position = params.text_document_position;
+    let uri = params_position.text_document.uri;
+    let files = state.include_graph(&uri);
+    let index = NodeIndex::new(&files);
+    let file = index.file(&uri)?;
+    let offset = position_to_byte_offset(params_position.position, &index.ropes[file])?;
+    let path = index.node_at(file, offset)?;
+    tracing::debug!(?path, "Finding references");
+
+    let mut locations = Vec::new();
+    if params.context.include_declaration {
+        for node in index.definitions_of(&path) {
+            locations.extend(index.location(node.file, node.name_range));
+            for (_, range) in &node.labels {
+                locations.extend(index.location(node.file, *range));
+            }
+        }
+    }
+    for reference in index.references_to(&path) {
+        locations.extend(index.location(reference.file, reference.range));
+    }
+    Some(locations)
+}
+
+pub fn document_highlight(
+    state: &crate::Backend,
+    params: DocumentHighlightParams,
+) -> Option<Vec<DocumentHighlight>> {
+    let params = params.text_document_position_params;
+    let uri = params.text_document.uri;
+    let files = state.include_graph(&uri);
+    let index = NodeIndex::new(&files);
+    let file = index.file(&uri)?;
+    let rope = &index.ropes[file];
+    let offset = position_to_byte_offset(params.position, rope)?;
+    let path = index.node_at(file, offset)?;
+
+    let highlight = |range: TextRange, kind: DocumentHighlightKind| {
+        Some(DocumentHighlight {
+            range: range_to_lsp(range, rope)?,
+            kind: Some(kind),
+        })
+    };
+    let mut highlights = Vec::new();
+    for node in index.definitions_of(&path).filter(|node| node.file == file) {
+        highlights.extend(highlight(node.name_range, DocumentHighlightKind::WRITE));
+        for (_, range) in &node.labels {
+            highlights.extend(highlight(*range, DocumentHighlightKind::WRITE));
+        }
+    }
+    for reference in index
+        .references_to(&path)
+        .filter(|reference| reference.file == file)
+    {
+        // Extensions change the node, like its definitions
+        let kind = if reference.is_extension {
+            DocumentHighlightKind::WRITE
+        } else {
+            DocumentHighlightKind::READ
+        };
+        highlights.extend(highlight(reference.range, kind));
+    }
+    Some(highlights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::snapshots_at_cursor;
+
+    /// File names with the text at a range
+    type Found = Vec<(String, String)>;
+
+    /// Returns the definitions and references of the node at the first `|` in the first file, as
+    /// the file name and text at each range.
+    fn find(files: &[(&str, &str)]) -> (Found, Found) {
+        let (files, cursor) = snapshots_at_cursor(files);
+        let index = NodeIndex::new(&files);
+        let path = index.node_at(0, cursor).unwrap();
+
+        let text = |file: usize, range: TextRange| {
+            (
+                files[file].uri.path().trim_start_matches('/').to_owned(),
+                files[file].text[range.byte_range()].to_owned(),
+            )
+        };
+        let definitions = index
+            .definitions_of(&path)
+            .flat_map(|node| {
+                std::iter::once(node.name_range)
+                    .chain(node.labels.iter().map(|(_, range)| *range))
+                    .map(|range| text(node.file, range))
+            })
+            .collect();
+        let references = index
+            .references_to(&path)
+            .map(|reference| text(reference.file, reference.range))
+            .collect();
+        (definitions, references)
+    }
+
+    fn pairs(list: &[(&str, &str)]) -> Found {
+        list.iter()
+            .map(|(a, b)| ((*a).to_owned(), (*b).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn across_files() {
+        let files = [
+            (
+                "board.dts",
+                "/include/ \"soc.dtsi\"\n&ua|rt0 { status = \"okay\"; };\n/ { x = <&{/soc/serial}>; };",
+            ),
+            (
+                "soc.dtsi",
+                "/ { soc { uart0: serial@1000 {}; serial@2000 {}; }; y = <&uart0>; };",
+            ),
+        ];
+        let (definitions, references) = find(&files);
+        assert_eq!(
+            definitions,
+            pairs(&[("soc.dtsi", "serial@1000"), ("soc.dtsi", "uart0")])
+        );
+        assert_eq!(
+            references,
+            pairs(&[
+                ("board.dts", "uart0"),
+                ("board.dts", "/soc/serial"),
+                ("soc.dtsi", "uart0"),
+            ])
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        // The extension of `soc` comes before its definition and defines another label
+        let files = [(
+            "board.dts",
+            "&soc { l2: sub {}; };\n/ { soc: soc {}; x = <&l2>, <&{/soc/s|ub}>; };",
+        )];
+        let (definitions, references) = find(&files);
+        assert_eq!(
+            definitions,
+            pairs(&[("board.dts", "sub"), ("board.dts", "l2")])
+        );
+        assert_eq!(
+            references,
+            pairs(&[("board.dts", "l2"), ("board.dts", "/soc/sub")])
+        );
+    }
+
+    #[test]
+    fn matching_paths() {
+        assert!(path_matches("/soc/serial", "/soc/serial@1000"));
+        assert!(path_matches("/soc/serial@1000/", "/soc/serial@1000"));
+        assert!(!path_matches("/soc/serial@2000", "/soc/serial@1000"));
+        assert!(!path_matches("/soc", "/soc/serial@1000"));
+        assert!(path_matches("/", "/"));
+    }
+}
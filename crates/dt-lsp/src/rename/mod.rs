@@ -0,0 +1,516 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{definition::macro_name_range, position_to_byte_offset, range_to_lsp, Snapshot};
+use dt_analyzer::new::stage1::AnalyzedToplevel;
+use dt_lint::lints::valid_label_name;
+use dt_parser::{
+    ast::{self, AstNode, AstToken, HasName},
+    cst::{RedNode, RedToken},
+    lexer::TokenKind,
+    TextRange,
+};
+use ropey::Rope;
+use tower_lsp::lsp_types::{
+    PrepareRenameResponse, RenameParams, TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit,
+};
+
+/// Something which can be renamed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Symbol {
+    Label(String),
+    Macro(String),
+}
+
+/// Why a rename was refused
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub(crate) enum RenameError {
+    /// only labels and macros can be renamed
+    NotRenameable,
+    /// `{0}` is not a valid label name, it should match `[a-z0-9_]+`
+    InvalidLabel(String),
+    /// `{0}` is not a valid macro name
+    InvalidMacro(String),
+    /// the label `{0}` already exists
+    LabelExists(String),
+    /// the macro `{0}` already exists
+    MacroExists(String),
+}
+
+impl Symbol {
+    /// Returns the symbol at `offset` in `snapshot` with the range of its name.
+    ///
+    /// The cursor may also be right after the name.
+    pub fn at(snapshot: &Snapshot, offset: usize) -> Option<(TextRange, Self)> {
+        let syntax = snapshot.file.syntax();
+        std::iter::once(offset)
+            .chain(offset.checked_sub(1))
+            .filter_map(|offset| Some((offset, syntax.token_at_offset(offset)?)))
+            .find_map(|(offset, token)| Self::at_token(snapshot, offset, &token))
+    }
+
+    fn at_token(
+        snapshot: &Snapshot,
+        offset: usize,
+        token: &Arc<RedToken>,
+    ) -> Option<(TextRange, Self)> {
+        match token.green.kind {
+            TokenKind::Ident => Some((token.text_range(), Self::Macro(token.text().to_string()))),
+            TokenKind::DefineDirective => {
+                snapshot
+                    .analyzed
+                    .iter()
+                    .find_map(|toplevel| match toplevel {
+                        AnalyzedToplevel::MacroDefinition { text_range, parsed }
+                            if *text_range == token.text_range() =>
+                        {
+                            let range = macro_name_range(&snapshot.text, *text_range, &parsed.name);
+                            (range != *text_range && range.start <= offset && offset <= range.end)
+                                .then(|| (range, Self::Macro(parsed.name.clone())))
+                        }
+                        _ => None,
+                    })
+            }
+            TokenKind::Name => {
+                let parent = token.parent.clone();
+                let is_label = ast::DtLabel::cast(parent.clone()).is_some()
+                    || ast::DtPhandle::cast(parent).is_some_and(|phandle| phandle.is_label());
+                is_label.then(|| (token.text_range(), Self::Label(token.text().to_string())))
+            }
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::Label(name) | Self::Macro(name) => name,
+        }
+    }
+
+    /// Returns true if `file` has a definition of the symbol.
+    fn is_defined_in(&self, file: &Snapshot) -> bool {
+        file.analyzed.iter().any(|toplevel| match (self, toplevel) {
+            (Self::Label(name), AnalyzedToplevel::Node(node)) => node.labels.contains_key(name),
+            (Self::Macro(name), AnalyzedToplevel::MacroDefinition { parsed, .. }) => {
+                parsed.name == *name
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Returns the files in which `symbol` is renamed: the include graph of `uri` and the files in
+/// `dirs` which include a file defining `symbol`, even if they aren't open.
+fn rename_files(
+    state: &crate::Backend,
+    uri: &Url,
+    symbol: &Symbol,
+    dirs: &[PathBuf],
+) -> Vec<Snapshot> {
+    let mut files = state.include_graph(uri);
+    let defining = files
+        .iter()
+        .filter(|file| symbol.is_defined_in(file))
+        .map(|file| file.uri.clone())
+        .collect::<Vec<_>>();
+    if defining.is_empty() {
+        return files;
+    }
+
+    let mut paths = Vec::new();
+    for dir in dirs {
+        collect_source_files(dir, &mut paths);
+    }
+    let mut seen: rustc_hash::FxHashSet<_> = files.iter().map(|file| file.uri.clone()).collect();
+    for path in paths {
+        let Some(uri) = path
+            .canonicalize()
+            .ok()
+            .and_then(|path| Url::from_file_path(path).ok())
+        else {
+            continue;
+        };
+        // Most files don't mention the symbol, so they aren't analyzed
+        if seen.contains(&uri)
+            || !std::fs::read_to_string(&path)
+                .is_ok_and(|text| words(&text, symbol.name()).next().is_some())
+        {
+            continue;
+        }
+        let graph = state.collect_files_from([uri]);
+        // Files defining a symbol of the same name on their own are left alone
+        if graph.iter().any(|file| defining.contains(&file.uri)) {
+            files.extend(
+                graph
+                    .into_iter()
+                    .filter(|file| seen.insert(file.uri.clone())),
+            );
+        }
+    }
+    files
+}
+
+/// Collects the devicetree sources and headers in `dir` and its subdirectories, without following
+/// symlinks to directories.
+fn collect_source_files(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            collect_source_files(&path, paths);
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "h" || ext == "dtsi" || ext == "dts")
+        {
+            paths.push(path);
+        }
+    }
+}
+
+/// Returns the edits renaming `symbol` to `new_name` in every file.
+pub(crate) fn rename_edits(
+    files: &[Snapshot],
+    symbol: &Symbol,
+    new_name: &str,
+) -> Result<HashMap<Url, Vec<TextEdit>>, RenameError> {
+    match symbol {
+        Symbol::Label(_) => {
+            if new_name.is_empty()
+                || new_name.starts_with(|c: char| c.is_ascii_digit())
+                || !valid_label_name(new_name)
+            {
+                return Err(RenameError::InvalidLabel(new_name.to_owned()));
+            }
+            let exists = files.iter().any(|file| {
+                let mut ranges = Vec::new();
+                find_labels(file.file.syntax(), new_name, &mut ranges);
+                !ranges.is_empty()
+            });
+            if exists {
+                return Err(RenameError::LabelExists(new_name.to_owned()));
+            }
+        }
+        Symbol::Macro(_) => {
+            let mut chars = new_name.chars();
+            let valid = chars
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(RenameError::InvalidMacro(new_name.to_owned()));
+            }
+            let exists = files.iter().flat_map(|file| &file.analyzed).any(|toplevel| {
+                matches!(toplevel, AnalyzedToplevel::MacroDefinition { parsed, .. } if parsed.name == new_name)
+            });
+            if exists {
+                return Err(RenameError::MacroExists(new_name.to_owned()));
+            }
+        }
+    }
+
+    let mut changes = HashMap::new();
+    for file in files {
+        let mut ranges = Vec::new();
+        match symbol {
+            Symbol::Label(name) => find_labels(file.file.syntax(), name, &mut ranges),
+            Symbol::Macro(name) => find_macros(file.file.syntax(), &file.text, name, &mut ranges),
+        }
+        if ranges.is_empty() {
+            continue;
+        }
+        let rope = Rope::from_str(&file.text);
+        let edits = ranges
+            .into_iter()
+            .filter_map(|range| {
+                Some(TextEdit::new(
+                    range_to_lsp(range, &rope)?,
+                    new_name.to_owned(),
+                ))
+            })
+            .collect();
+        changes.insert(file.uri.clone(), edits);
+    }
+    Ok(changes)
+}
+
+/// Finds the label definitions and `&label` references named `name`.
+fn find_labels(syntax: &Arc<RedNode>, name: &str, ranges: &mut Vec<TextRange>) {
+    for child in syntax.child_nodes() {
+        let label_name = if let Some(label) = ast::DtLabel::cast(child.clone()) {
+            label.name()
+        } else if let Some(phandle) = ast::DtPhandle::cast(child.clone()) {
+            phandle.is_label().then(|| phandle.name()).flatten()
+        } else {
+            find_labels(&child, name, ranges);
+            continue;
+        };
+        if let Some(label_name) = label_name.filter(|label| label.syntax().text().as_str() == name)
+        {
+            ranges.push(label_name.syntax().text_range());
+        }
+    }
+}
+
+/// Finds the macro identifiers named `name`, including in `#define`s, e.g. in their bodies.
+fn find_macros(syntax: &Arc<RedNode>, src: &str, name: &str, ranges: &mut Vec<TextRange>) {
+    for token in syntax.child_tokens() {
+        match token.green.kind {
+            TokenKind::Ident if token.text().as_str() == name => ranges.push(token.text_range()),
+            TokenKind::DefineDirective => {
+                let range = token.text_range();
+                let text = token.text_from_source(src);
+                ranges.extend(words(text, name).map(|start| {
+                    TextRange::new(range.start + start, range.start + start + name.len())
+                }));
+            }
+            _ => {}
+        }
+    }
+    for child in syntax.child_nodes() {
+        find_macros(&child, src, name, ranges);
+    }
+}
+
+/// Returns the offsets of `word` in `text` where it isn't part of a longer identifier.
+fn words<'a>(text: &'a str, word: &'a str) -> impl Iterator<Item = usize> + 'a {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    text.match_indices(word)
+        .map(|(start, _)| start)
+        .filter(move |&start| {
+            !text[..start].ends_with(is_ident) && !text[start + word.len()..].starts_with(is_ident)
+        })
+}
+
+pub fn prepare_rename(
+    state: &crate::Backend,
+    params: &TextDocumentPositionParams,
+) -> Option<PrepareRenameResponse> {
+    let snapshot = state.snapshot(&params.text_document.uri)?;
+    let rope = Rope::from_str(&snapshot.text);
+    let offset = position_to_byte_offset(params.position, &rope)?;
+    let (range, symbol) = Symbol::at(&snapshot, offset)?;
+    Some(PrepareRenameResponse::RangeWithPlaceholder {
+        range: range_to_lsp(range, &rope)?,
+        placeholder: symbol.name().to_owned(),
+    })
+}
+
+/// Renames the symbol at the position in `params` in the files which can see its definition,
+/// including the files in `dirs` which aren't open.
+pub fn rename(
+    state: &crate::Backend,
+    params: RenameParams,
+    dirs: &[PathBuf],
+) -> Result<Option<WorkspaceEdit>, RenameError> {
+    let params_position = params.text_document_position;
+    let uri = params_position.text_document.uri;
+    let Some(snapshot) = state.snapshot(&uri) else {
+        return Ok(None);
+    };
+    let symbol = position_to_byte_offset(params_position.position, &Rope::from_str(&snapshot.text))
+        .and_then(|offset| Symbol::at(&snapshot, offset))
+        .ok_or(RenameError::NotRenameable)?
+        .1;
+    tracing::debug!(?symbol, new_name = params.new_name, "Renaming");
+
+    let files = rename_files(state, &uri, &symbol, dirs);
+    let changes = rename_edits(&files, &symbol, &params.new_name)?;
+    Ok(Some(WorkspaceEdit {
+        changes: Some(changes),
+        ..Default::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{position_to_byte_offset, tests::snapshots_at_cursor};
+
+    /// Renames the symbol at the first `|` in the first file and returns the changed files.
+    fn rename(
+        files: &[(&str, &str)],
+        new_name: &str,
+    ) -> Result<Vec<(String, String)>, RenameError> {
+        let (files, cursor) = snapshots_at_cursor(files);
+        let (_, symbol) = Symbol::at(&files[0], cursor).unwrap();
+
+        let changes = rename_edits(&files, &symbol, new_name)?;
+        Ok(files
+            .iter()
+            .filter_map(|file| {
+                let mut edits = changes.get(&file.uri)?.clone();
+                let rope = Rope::from_str(&file.text);
+                edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+                let mut text = file.text.clone();
+                for edit in edits {
+                    let start = position_to_byte_offset(edit.range.start, &rope).unwrap();
+                    let end = position_to_byte_offset(edit.range.end, &rope).unwrap();
+                    text.replace_range(start..end, &edit.new_text);
+                }
+                Some((file.uri.path().trim_start_matches('/').to_owned(), text))
+            })
+            .collect())
+    }
+
+    #[test]
+    fn label() {
+        let files = [
+            (
+                "board.dts",
+                "/include/ \"soc.dtsi\"\n&vcc|_3v3 { x = <&{/vcc}>; };",
+            ),
+            (
+                "soc.dtsi",
+                "/ { vcc_3v3: vcc {}; reg: r { supply = <&vcc_3v3>; }; };",
+            ),
+        ];
+        assert_eq!(
+            rename(&files, "vdd_3v3").unwrap(),
+            [
+                (
+                    "board.dts".to_owned(),
+                    "/include/ \"soc.dtsi\"\n&vdd_3v3 { x = <&{/vcc}>; };".to_owned()
+                ),
+                (
+                    "soc.dtsi".to_owned(),
+                    "/ { vdd_3v3: vcc {}; reg: r { supply = <&vdd_3v3>; }; };".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(
+            rename(&files, "reg"),
+            Err(RenameError::LabelExists("reg".to_owned()))
+        );
+        assert_eq!(
+            rename(&files, "VDD"),
+            Err(RenameError::InvalidLabel("VDD".to_owned()))
+        );
+        assert_eq!(
+            rename(&files, ""),
+            Err(RenameError::InvalidLabel(String::new()))
+        );
+    }
+
+    #[test]
+    fn label_definition() {
+        let files = [("board.dts", "/ { l1|: node {}; x = <&l1>; };")];
+        assert_eq!(
+            rename(&files, "l2").unwrap(),
+            [(
+                "board.dts".to_owned(),
+                "/ { l2: node {}; x = <&l2>; };".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn macros() {
+        let files = [
+            (
+                "board.dts",
+                "#include \"gpio.h\"\n/ { x = <&gpio 1 GPIO_LOW|>, <GPIO_LOWER>; };",
+            ),
+            (
+                "gpio.h",
+                "#define GPIO_LOW 1\n#define GPIO_LOWER 2\n#define GPIO_INV(x) ((x) ^ GPIO_LOW)\n",
+            ),
+        ];
+        assert_eq!(
+            rename(&files, "GPIO_ACTIVE_LOW").unwrap(),
+            [
+                (
+                    "board.dts".to_owned(),
+                    "#include \"gpio.h\"\n/ { x = <&gpio 1 GPIO_ACTIVE_LOW>, <GPIO_LOWER>; };"
+                        .to_owned()
+                ),
+                (
+                    "gpio.h".to_owned(),
+                    "#define GPIO_ACTIVE_LOW 1\n#define GPIO_LOWER 2\n#define GPIO_INV(x) ((x) ^ GPIO_ACTIVE_LOW)\n"
+                        .to_owned()
+                ),
+            ]
+        );
+        assert_eq!(
+            rename(&files, "GPIO_LOWER"),
+            Err(RenameError::MacroExists("GPIO_LOWER".to_owned()))
+        );
+        assert_eq!(
+            rename(&files, "1GPIO"),
+            Err(RenameError::InvalidMacro("1GPIO".to_owned()))
+        );
+    }
+
+    #[test]
+    fn closed_includers() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("soc.dtsi", "/ { vcc_3v3: vcc {}; };"),
+            ("board.dts", "#include \"soc.dtsi\"\n&vcc_3v3 { x; };"),
+            (
+                "boards/other.dts",
+                "#include \"../soc.dtsi\"\n/ { y = <&vcc_3v3>; };",
+            ),
+            // Defines a label with the same name without including `soc.dtsi`
+            ("unrelated.dts", "/ { vcc_3v3: vcc {}; z = <&vcc_3v3>; };"),
+            ("notes.txt", "vcc_3v3"),
+        ];
+        std::fs::create_dir(dir.path().join("boards")).unwrap();
+        for (name, text) in files {
+            std::fs::write(dir.path().join(name), text).unwrap();
+        }
+        let uri = Url::from_file_path(dir.path().join("soc.dtsi").canonicalize().unwrap()).unwrap();
+
+        let edit = super::rename(
+            &crate::tests::backend(),
+            RenameParams {
+                text_document_position: TextDocumentPositionParams::new(
+                    tower_lsp::lsp_types::TextDocumentIdentifier::new(uri),
+                    tower_lsp::lsp_types::Position::new(0, 6),
+                ),
+                new_name: "vdd_3v3".to_owned(),
+                work_done_progress_params: tower_lsp::lsp_types::WorkDoneProgressParams::default(),
+            },
+            &[dir.path().to_owned()],
+        )
+        .unwrap()
+        .unwrap();
+        let mut changed = edit
+            .changes
+            .unwrap()
+            .into_iter()
+            .map(|(uri, edits)| {
+                let path = uri.to_file_path().unwrap();
+                let path = path
+                    .strip_prefix(dir.path().canonicalize().unwrap())
+                    .unwrap();
+                (path.to_str().unwrap().to_owned(), edits.len())
+            })
+            .collect::<Vec<_>>();
+        changed.sort();
+        assert_eq!(
+            changed,
+            [
+                ("board.dts".to_owned(), 1),
+                ("boards/other.dts".to_owned(), 1),
+                ("soc.dtsi".to_owned(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn macro_definition() {
+        let files = [("gpio.h", "#define GPIO|_LOW 1\n")];
+        assert_eq!(
+            rename(&files, "LOW").unwrap(),
+            [("gpio.h".to_owned(), "#define LOW 1\n".to_owned())]
+        );
+        let files = [("gpio.h", "#defi|ne GPIO_LOW 1\n")];
+        let (files, cursor) = snapshots_at_cursor(&files);
+        assert_eq!(Symbol::at(&files[0], cursor), None);
+    }
+}
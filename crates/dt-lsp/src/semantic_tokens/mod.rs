@@ -0,0 +1,597 @@
+use std::sync::Arc;
+
+use crate::{definition::macro_name_range, offset_to_position, position_to_byte_offset, Snapshot};
+use dt_analyzer::{
+    eval::{evaluate, expand_macros},
+    new::stage1::AnalyzedToplevel,
+    resolved_prop::MacroResolver,
+};
+use dt_parser::{
+    ast::{self, AstNode},
+    cst::{NodeKind, RedItem, RedNode, RedToken},
+    lexer::TokenKind,
+    TextRange,
+};
+use ropey::Rope;
+use rustc_hash::FxHashMap;
+use tower_lsp::lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensLegend,
+    SemanticTokensParams, SemanticTokensRangeParams,
+};
+
+/// What a token is, indexing [`TOKEN_TYPES`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenType {
+    /// A node name, also in `&{/path}`s
+    NodeName,
+    UnitAddress,
+    /// A label definition or a `&label` reference, told apart by [`Modifier::Declaration`]
+    Label,
+    Property,
+    Macro,
+    String,
+    /// A number, character or bytestring
+    Number,
+    /// A devicetree directive like `/dts-v1/` or `/delete-node/`
+    Keyword,
+    /// A line in a preprocessor conditional which is skipped
+    Inactive,
+}
+
+const TOKEN_TYPES: &[TokenType] = &[
+    TokenType::NodeName,
+    TokenType::UnitAddress,
+    TokenType::Label,
+    TokenType::Property,
+    TokenType::Macro,
+    TokenType::String,
+    TokenType::Number,
+    TokenType::Keyword,
+    TokenType::Inactive,
+];
+
+impl TokenType {
+    fn lsp(self) -> SemanticTokenType {
+        match self {
+            Self::NodeName => SemanticTokenType::STRUCT,
+            Self::UnitAddress => SemanticTokenType::new("unitAddress"),
+            Self::Label => SemanticTokenType::VARIABLE,
+            Self::Property => SemanticTokenType::PROPERTY,
+            Self::Macro => SemanticTokenType::MACRO,
+            Self::String => SemanticTokenType::STRING,
+            Self::Number => SemanticTokenType::NUMBER,
+            Self::Keyword => SemanticTokenType::KEYWORD,
+            Self::Inactive => SemanticTokenType::new("inactive"),
+        }
+    }
+}
+
+/// A token modifier, whose bit is its index in [`MODIFIERS`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Modifier {
+    /// A label or macro definition
+    Declaration,
+    /// A property without a vendor prefix, e.g. `reg`
+    Standard,
+    /// A property with a vendor prefix, e.g. `ti,hwmods`
+    Vendor,
+    /// A `#*-cells` property, e.g. `#address-cells`
+    Cells,
+}
+
+const MODIFIERS: &[Modifier] = &[
+    Modifier::Declaration,
+    Modifier::Standard,
+    Modifier::Vendor,
+    Modifier::Cells,
+];
+
+impl Modifier {
+    fn lsp(self) -> SemanticTokenModifier {
+        match self {
+            Self::Declaration => SemanticTokenModifier::DECLARATION,
+            Self::Standard => SemanticTokenModifier::new("standard"),
+            Self::Vendor => SemanticTokenModifier::new("vendor"),
+            Self::Cells => SemanticTokenModifier::new("cells"),
+        }
+    }
+
+    fn bit(self) -> u32 {
+        let index = MODIFIERS
+            .iter()
+            .position(|modifier| *modifier == self)
+            .unwrap_or_default();
+        1 << index
+    }
+}
+
+/// Returns the token types and modifiers the server uses.
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.iter().map(|ty| ty.lsp()).collect(),
+        token_modifiers: MODIFIERS.iter().map(|modifier| modifier.lsp()).collect(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Token {
+    range: TextRange,
+    ty: TokenType,
+    modifier: Option<Modifier>,
+}
+
+/// Returns the tokens of `files[file]` sorted by position.
+///
+/// `files` are the files whose macros are defined for `#ifdef`s and `#if`s.
+fn tokens(files: &[Snapshot], file: usize) -> Vec<Token> {
+    let snapshot = &files[file];
+    let mut tokens = Vec::new();
+    collect_tokens(snapshot.file.syntax(), &mut tokens);
+    for (range, definition) in snapshot
+        .analyzed
+        .iter()
+        .filter_map(AnalyzedToplevel::as_macro_definition)
+    {
+        let name_range = macro_name_range(&snapshot.text, *range, &definition.name);
+        if name_range != *range {
+            tokens.push(Token {
+                range: name_range,
+                ty: TokenType::Macro,
+                modifier: Some(Modifier::Declaration),
+            });
+        }
+    }
+
+    let inactive = inactive_regions(files, file);
+    tokens.retain(|token| {
+        !inactive
+            .iter()
+            .any(|region| region.start <= token.range.start && token.range.end <= region.end)
+    });
+    for region in inactive {
+        let text = snapshot.text.get(region.byte_range()).unwrap_or_default();
+        tokens.extend(lines(text, region.start).map(|range| Token {
+            range,
+            ty: TokenType::Inactive,
+            modifier: None,
+        }));
+    }
+    tokens.sort_by_key(|token| token.range.start);
+    tokens
+}
+
+fn collect_tokens(node: &Arc<RedNode>, tokens: &mut Vec<Token>) {
+    for child in node.children() {
+        match child {
+            RedItem::Node(child) => collect_tokens(&child, tokens),
+            RedItem::Token(token) => {
+                let Some((ty, modifier)) = classify(&token) else {
+                    continue;
+                };
+                for range in lines(token.text().as_str(), token.text_range().start) {
+                    tokens.push(Token {
+                        range,
+                        ty,
+                        modifier,
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn classify(token: &Arc<RedToken>) -> Option<(TokenType, Option<Modifier>)> {
+    let parent = &token.parent;
+    Some(match token.green.kind {
+        TokenKind::Name => match parent.green.kind {
+            NodeKind::DtNode => (TokenType::NodeName, None),
+            NodeKind::UnitAddress => (TokenType::UnitAddress, None),
+            NodeKind::DtLabel => (TokenType::Label, Some(Modifier::Declaration)),
+            NodeKind::DtPhandle => {
+                let is_path = ast::DtPhandle::cast(parent.clone())?.is_path();
+                let ty = if is_path {
+                    TokenType::NodeName
+                } else {
+                    TokenType::Label
+                };
+                (ty, None)
+            }
+            NodeKind::DtProperty => (
+                TokenType::Property,
+                Some(property_modifier(token.text().as_str())),
+            ),
+            _ => return None,
+        },
+        TokenKind::Ident => (TokenType::Macro, None),
+        TokenKind::String => (TokenType::String, None),
+        TokenKind::Number | TokenKind::Char | TokenKind::DtBytestring => (TokenType::Number, None),
+        TokenKind::V1Directive
+        | TokenKind::PluginDirective
+        | TokenKind::DtIncludeDirective
+        | TokenKind::MemreserveDirective
+        | TokenKind::DeleteNodeDirective
+        | TokenKind::DeletePropertyDirective
+        | TokenKind::BitsDirective => (TokenType::Keyword, None),
+        _ => return None,
+    })
+}
+
+/// Tells standard properties apart from vendor-specific and `#*-cells` ones.
+fn property_modifier(name: &str) -> Modifier {
+    if name.starts_with('#') && name.ends_with("-cells") {
+        Modifier::Cells
+    } else if name.contains(',') {
+        Modifier::Vendor
+    } else {
+        Modifier::Standard
+    }
+}
+
+/// Splits `text` starting at `start` into the ranges of its lines without the surrounding
+/// whitespace, since clients don't necessarily support tokens spanning lines.
+fn lines(text: &str, start: usize) -> impl Iterator<Item = TextRange> + '_ {
+    text.split('\n')
+        .scan(start, |start, line| {
+            let line_start = *start;
+            *start += line.len() + 1;
+            let trimmed = line.trim_start();
+            let leading = line.len() - trimmed.len();
+            let trimmed = trimmed.trim_end();
+            Some(TextRange::new(
+                line_start + leading,
+                line_start + leading + trimmed.len(),
+            ))
+        })
+        .filter(|range| range.start < range.end)
+}
+
+/// A `#if`, `#ifdef` or `#ifndef` being processed
+struct Conditional {
+    /// Whether the code around the conditional is active
+    outer_active: bool,
+    /// Whether a branch was taken already, so `#else` is inactive
+    taken: bool,
+    active: bool,
+}
+
+/// Returns the ranges between preprocessor conditionals which the preprocessor skips.
+///
+/// Macros are defined if any of `files` defines them, except for those defined later in
+/// `files[file]`. `#undef`s aren't followed. Conditions which can't be evaluated are active.
+fn inactive_regions(files: &[Snapshot], file: usize) -> Vec<TextRange> {
+    let snapshot = &files[file];
+    // Other files count as defining their macros before this file
+    let mut defined_at = FxHashMap::default();
+    let mut macros = FxHashMap::default();
+    for (index, other) in files.iter().enumerate() {
+        for (range, definition) in other
+            .analyzed
+            .iter()
+            .filter_map(AnalyzedToplevel::as_macro_definition)
+        {
+            let start = if index == file { range.start } else { 0 };
+            defined_at.entry(definition.name.clone()).or_insert(start);
+            macros
+                .entry(definition.name.clone())
+                .or_insert((*range, definition));
+        }
+    }
+
+    let mut directives = Vec::new();
+    collect_directives(snapshot.file.syntax(), &mut directives);
+
+    let mut stack = Vec::<Conditional>::new();
+    let mut regions = Vec::new();
+    let mut inactive_start = None;
+    for token in directives {
+        let range = token.text_range();
+        let defined = |name: &str| {
+            defined_at
+                .get(name)
+                .is_some_and(|start| *start < range.start)
+        };
+        let active = stack.last().is_none_or(|conditional| conditional.active);
+        let text = token.text().as_str();
+        let argument = text
+            .trim_start_matches('#')
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, argument)| argument.trim());
+        match token.green.kind {
+            TokenKind::IfdefDirective | TokenKind::IfndefDirective | TokenKind::IfDirective => {
+                let condition = match token.green.kind {
+                    TokenKind::IfdefDirective => defined(argument),
+                    TokenKind::IfndefDirective => !defined(argument),
+                    _ => evaluate_condition(argument, &defined, &macros).unwrap_or(true),
+                };
+                stack.push(Conditional {
+                    outer_active: active,
+                    taken: condition,
+                    active: active && condition,
+                });
+            }
+            TokenKind::ElseDirective => {
+                if let Some(conditional) = stack.last_mut() {
+                    conditional.active = conditional.outer_active && !conditional.taken;
+                    conditional.taken = true;
+                }
+            }
+            TokenKind::EndifDirective => {
+                stack.pop();
+            }
+            _ => continue,
+        }
+
+        let now_active = stack.last().is_none_or(|conditional| conditional.active);
+        if active && !now_active {
+            inactive_start = Some(range.end);
+        } else if !active && now_active {
+            if let Some(start) = inactive_start.take() {
+                regions.push(TextRange::new(start, range.start));
+            }
+        }
+    }
+    if let Some(start) = inactive_start {
+        regions.push(TextRange::new(start, snapshot.text.len()));
+    }
+    regions
+}
+
+fn collect_directives(node: &Arc<RedNode>, directives: &mut Vec<Arc<RedToken>>) {
+    for child in node.children() {
+        match child {
+            RedItem::Node(child) => collect_directives(&child, directives),
+            RedItem::Token(token) if token.green.kind.is_preprocessor_directive() => {
+                directives.push(token);
+            }
+            RedItem::Token(_) => {}
+        }
+    }
+}
+
+/// Evaluates the condition of an `#if`, where `defined(NAME)` checks if a macro is defined and
+/// other identifiers are 0 like in the C preprocessor.
+fn evaluate_condition(
+    condition: &str,
+    defined: &impl Fn(&str) -> bool,
+    macros: &impl MacroResolver,
+) -> Option<bool> {
+    // `defined` is replaced before expanding macros, so the names aren't expanded
+    let mut replaced = String::new();
+    let mut rest = condition;
+    while let Some(start) = rest.find("defined") {
+        replaced.push_str(&rest[..start]);
+        let after = rest[start + "defined".len()..].trim_start();
+        let (name, after) = if let Some(inner) = after.strip_prefix('(') {
+            let (name, after) = inner.split_once(')')?;
+            (name.trim(), after)
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            after.split_at(end)
+        };
+        replaced.push_str(if defined(name) { " 1 " } else { " 0 " });
+        rest = after;
+    }
+    replaced.push_str(rest);
+
+    let expanded = expand_macros(&replaced, macros);
+    let mut zeroed = String::new();
+    let mut previous = ' ';
+    let mut chars = expanded.chars().peekable();
+    while let Some(c) = chars.next() {
+        let starts_identifier = (c.is_ascii_alphabetic() || c == '_')
+            && !(previous.is_ascii_alphanumeric() || previous == '_');
+        if starts_identifier {
+            while chars
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_')
+            {
+                chars.next();
+            }
+            zeroed.push('0');
+            previous = '0';
+        } else {
+            zeroed.push(c);
+            previous = c;
+        }
+    }
+    evaluate(&zeroed).ok().map(|value| value != 0)
+}
+
+/// Returns the length of `range` in UTF-16 code units, like LSP positions.
+fn utf16_len(range: TextRange, rope: &Rope) -> Option<u32> {
+    let start = rope.try_byte_to_char(range.start).ok()?;
+    let end = rope.try_byte_to_char(range.end).ok()?;
+    let len = rope.try_char_to_utf16_cu(end).ok()? - rope.try_char_to_utf16_cu(start).ok()?;
+    u32::try_from(len).ok()
+}
+
+/// Encodes `tokens` relative to each other like LSP requires.
+fn encode(tokens: &[Token], rope: &Rope) -> Vec<SemanticToken> {
+    let mut encoded = Vec::new();
+    let (mut line, mut character) = (0, 0);
+    for token in tokens {
+        let Some(start) = offset_to_position(token.range.start, rope) else {
+            continue;
+        };
+        let Some(length) = utf16_len(token.range, rope) else {
+            continue;
+        };
+        let delta_start = if start.line == line {
+            start.character - character
+        } else {
+            start.character
+        };
+        encoded.push(SemanticToken {
+            delta_line: start.line - line,
+            delta_start,
+            length,
+            token_type: TOKEN_TYPES
+                .iter()
+                .position(|ty| *ty == token.ty)
+                .and_then(|index| u32::try_from(index).ok())
+                .unwrap_or_default(),
+            token_modifiers_bitset: token.modifier.map_or(0, Modifier::bit),
+        });
+        (line, character) = (start.line, start.character);
+    }
+    encoded
+}
+
+pub fn semantic_tokens_full(
+    state: &crate::Backend,
+    params: &SemanticTokensParams,
+) -> Option<SemanticTokens> {
+    let uri = &params.text_document.uri;
+    let files = state.reachable_files(uri);
+    let file = files.iter().position(|file| file.uri == *uri)?;
+    let rope = Rope::from_str(&files[file].text);
+    Some(SemanticTokens {
+        result_id: None,
+        data: encode(&tokens(&files, file), &rope),
+    })
+}
+
+pub fn semantic_tokens_range(
+    state: &crate::Backend,
+    params: &SemanticTokensRangeParams,
+) -> Option<SemanticTokens> {
+    let uri = &params.text_document.uri;
+    let files = state.reachable_files(uri);
+    let file = files.iter().position(|file| file.uri == *uri)?;
+    let rope = Rope::from_str(&files[file].text);
+    let start = position_to_byte_offset(params.range.start, &rope)?;
+    let end = position_to_byte_offset(params.range.end, &rope)?;
+    let tokens = tokens(&files, file)
+        .into_iter()
+        .filter(|token| token.range.start < end && start < token.range.end)
+        .collect::<Vec<_>>();
+    Some(SemanticTokens {
+        result_id: None,
+        data: encode(&tokens, &rope),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::snapshot;
+
+    /// Formats the tokens of the first file as `text:type[.modifier]`.
+    fn highlight(files: &[Snapshot]) -> Vec<String> {
+        tokens(files, 0)
+            .into_iter()
+            .map(|token| {
+                let text = &files[0].text[token.range.byte_range()];
+                match token.modifier {
+                    Some(modifier) => format!("{text}:{:?}.{modifier:?}", token.ty),
+                    None => format!("{text}:{:?}", token.ty),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kinds() {
+        let src = "/dts-v1/;\n#define IRQ(n) (n)\n/ {\n\tuart0: serial@1000 {\n\t\t#interrupt-cells = <IRQ(1) 0x2>;\n\t\tti,hwmods = \"uart1\";\n\t\tclocks = <&clk>, <&{/soc/clk@1}>;\n\t};\n};\n";
+        assert_eq!(
+            highlight(&[snapshot("board.dts", src)]),
+            [
+                "/dts-v1/:Keyword",
+                "IRQ:Macro.Declaration",
+                "uart0:Label.Declaration",
+                "serial:NodeName",
+                "1000:UnitAddress",
+                "#interrupt-cells:Property.Cells",
+                "IRQ:Macro",
+                "1:Number",
+                "0x2:Number",
+                "ti,hwmods:Property.Vendor",
+                "\"uart1\":String",
+                "clocks:Property.Standard",
+                "clk:Label",
+                "soc:NodeName",
+                "clk:NodeName",
+                "1:UnitAddress",
+            ]
+        );
+    }
+
+    #[test]
+    fn inactive() {
+        let src = "#ifdef FOO\n/ { a; };\n#else\n/ { b; };\n#endif\n\
+                   #if defined(BAR) && BAR > 1\n/ { c; };\n#endif\n\
+                   #ifndef BAR\n/ { d; };\n#endif\n";
+        let files = [
+            snapshot("board.dts", src),
+            snapshot("config.h", "#define BAR (1 + 1)\n"),
+        ];
+        assert_eq!(
+            highlight(&files),
+            [
+                "/ { a; };:Inactive",
+                "b:Property.Standard",
+                "c:Property.Standard",
+                "/ { d; };:Inactive",
+            ]
+        );
+        // Macros of the same file are only defined after their definition
+        let files = [snapshot(
+            "board.dts",
+            "#ifdef FOO\n/ { a; };\n#endif\n#define FOO\n",
+        )];
+        assert_eq!(
+            highlight(&files),
+            ["/ { a; };:Inactive", "FOO:Macro.Declaration"]
+        );
+    }
+
+    #[test]
+    fn encoding() {
+        let src = "/ {\n\tlabel: node {};\n};";
+        let snapshot = snapshot("board.dts", src);
+        let encoded = encode(&tokens(&[snapshot], 0), &Rope::from_str(src));
+        let label = TOKEN_TYPES
+            .iter()
+            .position(|ty| *ty == TokenType::Label)
+            .and_then(|index| u32::try_from(index).ok())
+            .unwrap();
+        let node = TOKEN_TYPES
+            .iter()
+            .position(|ty| *ty == TokenType::NodeName)
+            .and_then(|index| u32::try_from(index).ok())
+            .unwrap();
+        assert_eq!(
+            encoded,
+            [
+                SemanticToken {
+                    delta_line: 1,
+                    delta_start: 1,
+                    length: 5,
+                    token_type: label,
+                    token_modifiers_bitset: 1,
+                },
+                SemanticToken {
+                    delta_line: 0,
+                    delta_start: 7,
+                    length: 4,
+                    token_type: node,
+                    token_modifiers_bitset: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn encoding_utf16() {
+        // Columns and lengths are in UTF-16 code units
+        let src = "/ {\n\t/* ä𝄞 */ label: node {};\n};";
+        let encoded = encode(
+            &tokens(&[snapshot("board.dts", src)], 0),
+            &Rope::from_str(src),
+        );
+        assert_eq!((encoded[0].delta_start, encoded[0].length), (11, 5));
+    }
+}
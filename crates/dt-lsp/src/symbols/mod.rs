@@ -0,0 +1,261 @@
+#![expect(
+    deprecated,
+    reason = "`deprecated` is a field of the symbol types which must still be set"
+)]
+
+use std::sync::Arc;
+
+use crate::{
+    definition::macro_name_range,
+    range_to_lsp,
+    references::{self, NodeIndex},
+    Snapshot,
+};
+use dt_analyzer::new::stage1::AnalyzedToplevel;
+use dt_parser::{
+    ast::{self, AstNode, AstToken, HasName},
+    cst::RedNode,
+    TextRange,
+};
+use ropey::Rope;
+use tower_lsp::lsp_types::{
+    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, Location, SymbolInformation,
+    SymbolKind, WorkspaceSymbolParams,
+};
+
+/// Returns the outline of a file: its nodes with their labels, properties and subnodes, and its
+/// macros.
+pub(crate) fn document_symbols(snapshot: &Snapshot) -> Vec<DocumentSymbol> {
+    let rope = Rope::from_str(&snapshot.text);
+    let mut symbols = snapshot
+        .file
+        .syntax()
+        .child_nodes()
+        .filter_map(ast::DtNode::cast)
+        .filter_map(|node| node_symbol(&node, &snapshot.text, &rope))
+        .collect::<Vec<_>>();
+    for toplevel in &snapshot.analyzed {
+        if let AnalyzedToplevel::MacroDefinition { text_range, parsed } = toplevel {
+            symbols.extend(symbol(
+                parsed.name.clone(),
+                SymbolKind::CONSTANT,
+                *text_range,
+                macro_name_range(&snapshot.text, *text_range, &parsed.name),
+                None,
+                &rope,
+            ));
+        }
+    }
+    symbols.sort_by_key(|symbol| symbol.range.start);
+    symbols
+}
+
+fn symbol(
+    name: String,
+    kind: SymbolKind,
+    range: TextRange,
+    selection_range: TextRange,
+    children: Option<Vec<DocumentSymbol>>,
+    rope: &Rope,
+) -> Option<DocumentSymbol> {
+    Some(DocumentSymbol {
+        name,
+        detail: None,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: range_to_lsp(range, rope)?,
+        selection_range: range_to_lsp(selection_range, rope)?,
+        children,
+    })
+}
+
+/// Returns the symbol of `node`, named `/`, `&label`, `&{/path}` or `name@unit`.
+fn node_symbol(node: &ast::DtNode, src: &str, rope: &Rope) -> Option<DocumentSymbol> {
+    let (name, selection_range) = if let Some(phandle) = node.extension_name() {
+        let range = phandle.syntax().text_range();
+        (src.get(range.byte_range())?.to_owned(), range)
+    } else {
+        let range = references::name_range(node)?;
+        (src.get(range.byte_range())?.to_owned(), range)
+    };
+
+    let mut children = Vec::new();
+    for child in node.syntax().child_nodes() {
+        children.extend(child_symbol(&child, src, rope));
+    }
+    symbol(
+        name,
+        SymbolKind::OBJECT,
+        node.syntax().text_range(),
+        selection_range,
+        Some(children),
+        rope,
+    )
+}
+
+fn child_symbol(child: &Arc<RedNode>, src: &str, rope: &Rope) -> Option<DocumentSymbol> {
+    if let Some(node) = ast::DtNode::cast(child.clone()) {
+        node_symbol(&node, src, rope)
+    } else if let Some(label) = ast::DtLabel::cast(child.clone()) {
+        let name = label.name()?;
+        symbol(
+            name.syntax().text().to_string(),
+            SymbolKind::KEY,
+            label.syntax().text_range(),
+            name.syntax().text_range(),
+            None,
+            rope,
+        )
+    } else if let Some(property) = ast::DtProperty::cast(child.clone()) {
+        let name = property.name()?;
+        symbol(
+            name.syntax().text().to_string(),
+            SymbolKind::PROPERTY,
+            property.syntax().text_range(),
+            name.syntax().text_range(),
+            None,
+            rope,
+        )
+    } else {
+        None
+    }
+}
+
+/// Returns the nodes, labels and macros in `files` whose name contains `query`, ignoring case.
+///
+/// Nodes are named by their full path.
+pub(crate) fn workspace_symbols(files: &[Snapshot], query: &str) -> Vec<SymbolInformation> {
+    let query = query.to_lowercase();
+    let matches = |name: &str| name.to_lowercase().contains(&query);
+    let information =
+        |name: &str, kind, location, container_name: Option<&str>| SymbolInformation {
+            name: name.to_owned(),
+            kind,
+            tags: None,
+            deprecated: None,
+            location,
+            container_name: container_name.map(str::to_owned),
+        };
+
+    let index = NodeIndex::new(files);
+    let mut symbols = Vec::new();
+    for node in &index.nodes {
+        if matches(&node.path) {
+            if let Some(location) = index.location(node.file, node.name_range) {
+                symbols.push(information(&node.path, SymbolKind::OBJECT, location, None));
+            }
+        }
+        for (label, range) in &node.labels {
+            if matches(label) {
+                if let Some(location) = index.location(node.file, *range) {
+                    let container = Some(node.path.as_str());
+                    symbols.push(information(label, SymbolKind::KEY, location, container));
+                }
+            }
+        }
+    }
+    for file in files {
+        let rope = Rope::from_str(&file.text);
+        for toplevel in &file.analyzed {
+            let AnalyzedToplevel::MacroDefinition { text_range, parsed } = toplevel else {
+                continue;
+            };
+            if !matches(&parsed.name) {
+                continue;
+            }
+            let range = macro_name_range(&file.text, *text_range, &parsed.name);
+            if let Some(range) = range_to_lsp(range, &rope) {
+                let location = Location::new(file.uri.clone(), range);
+                symbols.push(information(
+                    &parsed.name,
+                    SymbolKind::CONSTANT,
+                    location,
+                    None,
+                ));
+            }
+        }
+    }
+    symbols
+}
+
+pub fn document_symbol(
+    state: &crate::Backend,
+    params: &DocumentSymbolParams,
+) -> Option<DocumentSymbolResponse> {
+    let snapshot = state.snapshot(&params.text_document.uri)?;
+    Some(DocumentSymbolResponse::Nested(document_symbols(&snapshot)))
+}
+
+pub fn symbol_search(
+    state: &crate::Backend,
+    params: &WorkspaceSymbolParams,
+) -> Vec<SymbolInformation> {
+    workspace_symbols(&state.analyzed_files(), &params.query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{position_to_byte_offset, tests::snapshot};
+
+    /// Formats symbols as `kind name [selection] { children }`.
+    fn outline(symbols: &[DocumentSymbol], src: &str) -> String {
+        let rope = Rope::from_str(src);
+        symbols
+            .iter()
+            .map(|symbol| {
+                let start = position_to_byte_offset(symbol.selection_range.start, &rope).unwrap();
+                let end = position_to_byte_offset(symbol.selection_range.end, &rope).unwrap();
+                let mut text = format!("{:?} {} [{}]", symbol.kind, symbol.name, &src[start..end]);
+                if let Some(children) = symbol.children.as_ref().filter(|c| !c.is_empty()) {
+                    text = format!("{text} {{ {} }}", outline(children, src));
+                }
+                text
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    #[test]
+    fn document() {
+        let src = "#define FOO 1\n/ {\n\tmodel = \"x\";\n\tuart0: serial@1000 { status = \"okay\"; };\n};\n&uart0 { sub {}; };\n";
+        let symbols = document_symbols(&snapshot("board.dts", src));
+        assert_eq!(
+            outline(&symbols, src),
+            "Constant FOO [FOO], \
+             Object / [/] { Property model [model], Object serial@1000 [serial@1000] { \
+             Key uart0 [uart0], Property status [status] } }, \
+             Object &uart0 [&uart0] { Object sub [sub] }"
+        );
+    }
+
+    #[test]
+    fn workspace() {
+        let files = [
+            snapshot(
+                "soc.dtsi",
+                "/ { soc { uart0: serial@1000 {}; i2c0: i2c@2000 {}; }; };",
+            ),
+            snapshot("gpio.h", "#define GPIO_UART 1\n"),
+        ];
+        let names = |query| {
+            workspace_symbols(&files, query)
+                .into_iter()
+                .map(|symbol| {
+                    format!(
+                        "{:?} {} {}",
+                        symbol.kind,
+                        symbol.name,
+                        symbol.container_name.unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names("uart"),
+            ["Key uart0 /soc/serial@1000", "Constant GPIO_UART ",]
+        );
+        assert_eq!(names("SERIAL"), ["Object /soc/serial@1000 "]);
+    }
+}
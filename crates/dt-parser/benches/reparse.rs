@@ -0,0 +1,85 @@
+//! Compares parsing a large system-on-chip `.dtsi` from scratch with reparsing it after typing in one node
+
+use std::fmt::Write as _;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dt_parser::{parser::parse, TextRange};
+
+/// Generates a system-on-chip `.dtsi` with `peripherals` nodes of about 14 lines each.
+fn soc_dtsi(peripherals: usize) -> String {
+    let mut dtsi = String::from(
+        "// SPDX-License-Identifier: GPL-2.0
+/dts-v1/;
+
+#include <dt-bindings/interrupt-controller/arm-gic.h>
+#include <dt-bindings/gpio/gpio.h>
+
+/ {
+\t#address-cells = <2>;
+\t#size-cells = <2>;
+\tinterrupt-parent = <&gic>;
+
+\tsoc: soc@0 {
+\t\tcompatible = \"simple-bus\";
+\t\t#address-cells = <1>;
+\t\t#size-cells = <1>;
+\t\tranges = <0x0 0x0 0x0 0x40000000>;
+
+\t\tgic: interrupt-controller@1000000 {
+\t\t\tcompatible = \"arm,gic-400\";
+\t\t\treg = <0x1000000 0x1000>, <0x1002000 0x2000>;
+\t\t\t#interrupt-cells = <3>;
+\t\t\tinterrupt-controller;
+\t\t};
+",
+    );
+    for i in 0..peripherals {
+        let address = 0x0200_0000 + i * 0x1000;
+        write!(
+            dtsi,
+            "
+\t\tuart{i}: serial@{address:x} {{
+\t\t\tcompatible = \"acme,soc-uart\", \"ns16550a\";
+\t\t\treg = <{address:#x} 0x400>;
+\t\t\tinterrupts = <GIC_SPI {i} IRQ_TYPE_LEVEL_HIGH>;
+\t\t\tclocks = <&clk {i}>, <&clk {}>;
+\t\t\tclock-names = \"baud\", \"bus\";
+\t\t\tpinctrl-names = \"default\";
+\t\t\tpinctrl-0 = <&uart{i}_pins>;
+\t\t\treset-gpios = <&gpio0 {} GPIO_ACTIVE_LOW>;
+\t\t\tstatus = \"disabled\";
+
+\t\t\t/* The FIFO is configured by the bootloader */
+\t\t\tfifo-size = <64>;
+\t\t}};
+",
+            i + 1,
+            i % 32,
+        )
+        .unwrap();
+    }
+    dtsi.push_str("\t};\n};\n");
+    dtsi
+}
+
+fn bench(c: &mut Criterion) {
+    let dtsi = soc_dtsi(360);
+    assert!(dtsi.lines().count() > 5000);
+
+    // Typing `0` after the FIFO size of a node in the middle
+    let offset = dtsi.match_indices("fifo-size = <64").nth(180).unwrap().0 + 15;
+    let mut new_text = dtsi.clone();
+    new_text.insert(offset, '0');
+    let range = TextRange::new(offset, offset);
+    let old = parse(&dtsi);
+
+    let mut group = c.benchmark_group("soc-dtsi");
+    group.bench_function("parse", |b| b.iter(|| parse(black_box(&new_text))));
+    group.bench_function("reparse", |b| {
+        b.iter(|| old.reparse(black_box(range), black_box(&new_text)));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
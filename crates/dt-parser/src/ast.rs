@@ -53,6 +53,8 @@ use crate::{
     parser::Parse,
 };
 
+pub mod visit;
+
 /// Trait used for downcasting from [`RedNode`]s to AST nodes.
 pub trait AstNode: Sized {
     /// Try to cast a [`RedNode`] to an AST node.
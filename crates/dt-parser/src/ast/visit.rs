@@ -0,0 +1,195 @@
+//! AST walker. Each overridden visit method has full control over what happens with its node: it
+//! can do its own traversal of the node's children, call `visit::walk_*` to apply the default
+//! traversal algorithm, or prevent deeper traversal by doing nothing.
+//!
+//! Just like [`rustc_ast::visit`][1].
+//!
+//! # Example
+//!
+//! ```
+//! use dt_parser::ast::{self, visit::{self, Visitor}};
+//!
+//! #[derive(Default)]
+//! struct CountProperties(usize);
+//!
+//! impl Visitor for CountProperties {
+//!     fn visit_property(&mut self, property: &ast::DtProperty) {
+//!         self.0 += 1;
+//!         visit::walk_property(self, property);
+//!     }
+//! }
+//!
+//! let file = ast::SourceFile::parse("/ { a; b { c; }; };").source_file();
+//! let mut visitor = CountProperties::default();
+//! visitor.visit_source_file(&file);
+//! assert_eq!(visitor.0, 2);
+//! ```
+//!
+//! [1]: https://doc.rust-lang.org/nightly/nightly-rustc/rustc_ast/visit/index.html
+
+use crate::cst::TreeItem;
+
+use super::{
+    AstNode, AstToken, Cell, Directive, DtLabel, DtNode, DtPhandle, DtProperty, MacroInvocation,
+    PreprocessorDirective, PropValue, SourceFile,
+};
+
+/// Each method of the `Visitor` trait is a hook to be potentially overridden. Each method's
+/// default implementation recursively visits the substructure of the input via the corresponding
+/// `walk` function; e.g., the [`Visitor::visit_node`] method by default calls [`walk_node`].
+///
+/// Items are visited in source order.
+pub trait Visitor: Sized {
+    fn visit_source_file(&mut self, file: &SourceFile) {
+        walk_source_file(self, file);
+    }
+    fn visit_directive(&mut self, _directive: &Directive) {}
+    fn visit_preprocessor_directive(&mut self, _directive: &PreprocessorDirective) {}
+    fn visit_node(&mut self, node: &DtNode) {
+        walk_node(self, node);
+    }
+    fn visit_property(&mut self, property: &DtProperty) {
+        walk_property(self, property);
+    }
+    fn visit_label(&mut self, _label: &DtLabel) {}
+    fn visit_prop_value(&mut self, value: &PropValue) {
+        walk_prop_value(self, value);
+    }
+    fn visit_cell(&mut self, cell: &Cell) {
+        walk_cell(self, cell);
+    }
+    fn visit_phandle(&mut self, _phandle: &DtPhandle) {}
+    fn visit_macro_invocation(&mut self, _invocation: &MacroInvocation) {}
+}
+
+/// Visits the top-level items of `file`.
+///
+/// Note that this includes properties, even though they must not be at the top level.
+pub fn walk_source_file<V: Visitor>(visitor: &mut V, file: &SourceFile) {
+    for child in file.syntax().children() {
+        match child {
+            TreeItem::Node(syntax) => {
+                if let Some(node) = DtNode::cast(syntax.clone()) {
+                    visitor.visit_node(&node);
+                } else if let Some(property) = DtProperty::cast(syntax.clone()) {
+                    visitor.visit_property(&property);
+                } else if let Some(directive) = Directive::cast(syntax) {
+                    visitor.visit_directive(&directive);
+                }
+            }
+            TreeItem::Token(syntax) => {
+                if let Some(directive) = PreprocessorDirective::cast(syntax) {
+                    visitor.visit_preprocessor_directive(&directive);
+                }
+            }
+        }
+    }
+}
+
+/// Visits the labels, properties and subnodes of `node`.
+pub fn walk_node<V: Visitor>(visitor: &mut V, node: &DtNode) {
+    for syntax in node.syntax().child_nodes() {
+        if let Some(label) = DtLabel::cast(syntax.clone()) {
+            visitor.visit_label(&label);
+        } else if let Some(property) = DtProperty::cast(syntax.clone()) {
+            visitor.visit_property(&property);
+        } else if let Some(subnode) = DtNode::cast(syntax) {
+            visitor.visit_node(&subnode);
+        }
+    }
+}
+
+/// Visits the labels and values of `property`.
+pub fn walk_property<V: Visitor>(visitor: &mut V, property: &DtProperty) {
+    for label in property.syntax().child_nodes().filter_map(DtLabel::cast) {
+        visitor.visit_label(&label);
+    }
+    for value in property.values() {
+        visitor.visit_prop_value(&value);
+    }
+}
+
+/// Visits the cells, phandles and macro invocations in `value`.
+pub fn walk_prop_value<V: Visitor>(visitor: &mut V, value: &PropValue) {
+    match value {
+        PropValue::CellList(cell_list) => {
+            for cell in cell_list.cells() {
+                visitor.visit_cell(&cell);
+            }
+        }
+        PropValue::Phandle(phandle) => visitor.visit_phandle(phandle),
+        PropValue::Macro(invocation) => visitor.visit_macro_invocation(invocation),
+        PropValue::String(_) | PropValue::Bytestring(_) => {}
+    }
+}
+
+/// Visits the phandle or macro invocation in `cell`.
+pub fn walk_cell<V: Visitor>(visitor: &mut V, cell: &Cell) {
+    match cell {
+        Cell::Phandle(phandle) => visitor.visit_phandle(phandle),
+        Cell::Macro(invocation) => visitor.visit_macro_invocation(invocation),
+        Cell::Number(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::HasName as _;
+
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl Visitor for Recorder {
+        fn visit_directive(&mut self, _directive: &Directive) {
+            self.0.push("directive".to_owned());
+        }
+        fn visit_node(&mut self, node: &DtNode) {
+            let name = node.name().map(|name| name.syntax().text().to_string());
+            self.0.push(format!("node {}", name.unwrap_or_default()));
+            walk_node(self, node);
+        }
+        fn visit_property(&mut self, property: &DtProperty) {
+            let name = property.name().map(|name| name.syntax().text().to_string());
+            self.0.push(format!("prop {}", name.unwrap_or_default()));
+            walk_property(self, property);
+        }
+        fn visit_label(&mut self, _label: &DtLabel) {
+            self.0.push("label".to_owned());
+        }
+        fn visit_cell(&mut self, cell: &Cell) {
+            self.0.push("cell".to_owned());
+            walk_cell(self, cell);
+        }
+        fn visit_phandle(&mut self, _phandle: &DtPhandle) {
+            self.0.push("phandle".to_owned());
+        }
+    }
+
+    #[test]
+    fn source_order() {
+        let file =
+            SourceFile::parse("/dts-v1/;\n/ { a = <1 &b>; lbl: b { c = &a; }; d; };\n&b { e; };")
+                .source_file();
+        let mut recorder = Recorder::default();
+        recorder.visit_source_file(&file);
+        assert_eq!(
+            recorder.0,
+            [
+                "directive",
+                "node ",
+                "prop a",
+                "cell",
+                "cell",
+                "phandle",
+                "node b",
+                "label",
+                "prop c",
+                "phandle",
+                "prop d",
+                "node ",
+                "prop e",
+            ]
+        );
+    }
+}
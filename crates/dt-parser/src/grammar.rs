@@ -143,7 +143,17 @@ pub(super) fn reference_noamp(p: &mut Parser) {
             // TODO: better recovery
             p.expect(TokenKind::Slash);
             if !p.eat_name() {
-                p.error().msg_expected().emit();
+                // Bump the unexpected token so that the loop always progresses
+                p.error().msg_expected().bump_wrap_err().emit();
+                continue;
+            }
+            if p.at(TokenKind::AtSign) {
+                let m = p.start();
+                p.bump();
+                if !p.eat_name() {
+                    p.error().msg_expected().emit();
+                }
+                m.complete(p, NodeKind::UnitAddress);
             }
         }
         p.expect(TokenKind::RCurly);
@@ -725,6 +735,36 @@ pub(super) mod tests {
         );
     }
 
+    #[test]
+    fn path_references() {
+        check_ep(
+            Entrypoint::PropValues,
+            "&{/soc/uart@1000}",
+            &[node(
+                NodeKind::DtPhandle,
+                vec![
+                    static_token(TokenKind::Ampersand),
+                    static_token(TokenKind::LCurly),
+                    static_token(TokenKind::Slash),
+                    dynamic_token(TokenKind::Name, "soc"),
+                    static_token(TokenKind::Slash),
+                    dynamic_token(TokenKind::Name, "uart"),
+                    node(
+                        NodeKind::UnitAddress,
+                        vec![
+                            static_token(TokenKind::AtSign),
+                            dynamic_token(TokenKind::Name, "1000"),
+                        ],
+                    ),
+                    static_token(TokenKind::RCurly),
+                ],
+            )],
+            &[],
+        );
+        // Doesn't get stuck
+        assert_eq!(Entrypoint::PropValues.parse("&{/soc/=}").errors.len(), 1);
+    }
+
     #[test]
     fn macro_positions() {
         let macro_invoc_bar = node(
@@ -23,6 +23,7 @@ use tracing::debug;
 mod errors;
 mod event;
 mod marker;
+mod reparse;
 mod sink;
 mod source;
 
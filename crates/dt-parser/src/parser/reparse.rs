@@ -0,0 +1,351 @@
+//! Incremental reparsing: after an edit inside the braces of a node, only that node is parsed again
+//! and spliced into the old tree, whose other [`GreenNode`]s are shared with the new tree.
+//!
+//! - <https://github.com/rust-lang/rust-analyzer/blob/master/crates/syntax/src/parsing/reparsing.rs>
+
+use std::sync::Arc;
+
+use crate::{
+    cst::{GreenItem, GreenNode, NodeKind},
+    lexer::TokenKind,
+    TextRange,
+};
+
+use super::{parse, Parse, ParseError, WrappedLexError};
+
+impl Parse<'_> {
+    /// Returns the parse of `new_text`, which is the parsed text with `range` replaced.
+    ///
+    /// If the edit is inside the braces of a [`NodeKind::DtNode`], only the innermost such node
+    /// which still parses as a whole node is parsed again. Otherwise, or if this parse has lex
+    /// errors, which may span beyond a node, all of `new_text` is parsed.
+    #[must_use]
+    pub fn reparse<'new>(&self, range: TextRange, new_text: &'new str) -> Parse<'new> {
+        self.reparse_node(range, new_text)
+            .unwrap_or_else(|| parse(new_text))
+    }
+
+    fn reparse_node<'new>(&self, range: TextRange, new_text: &'new str) -> Option<Parse<'new>> {
+        let old_len = self.green_node.width;
+        if !self.lex_errors.is_empty() || range.start > range.end || range.end > old_len {
+            return None;
+        }
+        let removed = range.end - range.start;
+        let inserted = (new_text.len() + removed).checked_sub(old_len)?;
+
+        // The nodes whose body contains the edit, outermost first, as (path of child indices,
+        // offset, node)
+        let mut candidates = Vec::new();
+        let mut path = Vec::new();
+        let (mut node, mut offset) = (&self.green_node, 0);
+        loop {
+            if node.kind == NodeKind::DtNode && body_contains(node, offset, range) {
+                candidates.push((path.clone(), offset, node));
+            }
+            let mut child_offset = offset;
+            let mut next = None;
+            for (i, child) in node.children.iter().enumerate() {
+                let child_end = child_offset + child.length();
+                if child_offset <= range.start && range.end <= child_end {
+                    if let GreenItem::Node(child) = child {
+                        next = Some((i, child, child_offset));
+                    }
+                    break;
+                }
+                child_offset = child_end;
+            }
+            let Some((i, child, child_offset)) = next else {
+                break;
+            };
+            path.push(i);
+            (node, offset) = (child, child_offset);
+        }
+
+        candidates
+            .into_iter()
+            .rev()
+            .find_map(|(path, start, node)| {
+                let old_end = start + node.width;
+                let new_end = old_end + inserted - removed;
+                let new_node = parse_node(new_text.get(start..new_end)?)?;
+                let (errors, lex_errors) = self.splice_errors(
+                    start,
+                    old_end,
+                    new_end,
+                    new_node.errors,
+                    new_node.lex_errors,
+                    new_text,
+                )?;
+                Some(Parse {
+                    green_node: replace(&self.green_node, &path, new_node.green_node),
+                    lex_errors,
+                    errors,
+                })
+            })
+    }
+
+    /// Replaces the errors of the node at `start..old_end` with `errors` and `lex_errors`, which
+    /// are relative to `start`, and moves the errors after the node to its new end.
+    ///
+    /// Returns `None` if an error spans both the node and the text around it.
+    fn splice_errors<'new>(
+        &self,
+        start: usize,
+        old_end: usize,
+        new_end: usize,
+        errors: Vec<ParseError>,
+        lex_errors: Vec<WrappedLexError<'_>>,
+        new_text: &'new str,
+    ) -> Option<(Vec<ParseError>, Vec<WrappedLexError<'new>>)> {
+        let move_range = |range: TextRange| -> Option<TextRange> {
+            if range.end <= start {
+                Some(range)
+            } else if range.start >= old_end {
+                Some(TextRange::new(
+                    range.start - old_end + new_end,
+                    range.end - old_end + new_end,
+                ))
+            } else {
+                None
+            }
+        };
+        let is_inside = |range: TextRange| start <= range.start && range.end <= old_end;
+
+        let mut before = Vec::new();
+        let mut after = Vec::new();
+        for error in &self.errors {
+            let ranges = std::iter::once(error.primary_span)
+                .chain(error.span_labels.iter().map(|label| label.span));
+            if ranges.clone().all(is_inside) {
+                continue;
+            }
+            let mut error = error.clone();
+            error.primary_span = move_range(error.primary_span)?;
+            for label in &mut error.span_labels {
+                label.span = move_range(label.span)?;
+            }
+            if error.primary_span.start < start {
+                before.push(error);
+            } else {
+                after.push(error);
+            }
+        }
+
+        let offset = |range: TextRange| TextRange::new(range.start + start, range.end + start);
+        let errors = before
+            .into_iter()
+            .chain(errors.into_iter().map(|mut error| {
+                error.primary_span = offset(error.primary_span);
+                for label in &mut error.span_labels {
+                    label.span = offset(label.span);
+                }
+                error
+            }))
+            .chain(after)
+            .collect();
+        let lex_errors = lex_errors
+            .into_iter()
+            .map(|error| {
+                let text_range = offset(error.text_range);
+                Some(WrappedLexError {
+                    inner: error.inner,
+                    text_range,
+                    text: new_text.get(text_range.byte_range())?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some((errors, lex_errors))
+    }
+}
+
+/// A node parsed on its own
+struct ParsedNode<'input> {
+    green_node: Arc<GreenNode>,
+    errors: Vec<ParseError>,
+    lex_errors: Vec<WrappedLexError<'input>>,
+}
+
+/// Parses `text` as a single node ending with `};`, which makes sure that the node ends where it
+/// ended before and doesn't consume what follows it, e.g. after removing a `}`.
+fn parse_node(text: &str) -> Option<ParsedNode<'_>> {
+    let parse = parse(text);
+    let [GreenItem::Node(node)] = parse.green_node.children.as_slice() else {
+        return None;
+    };
+    let mut tokens = node
+        .children
+        .iter()
+        .rev()
+        .filter_map(GreenItem::as_token)
+        .map(|token| token.kind)
+        .filter(|kind| !kind.is_trivia());
+    let ends_with_semicolon =
+        tokens.next() == Some(TokenKind::Semicolon) && tokens.next() == Some(TokenKind::RCurly);
+    (node.kind == NodeKind::DtNode && ends_with_semicolon).then(|| ParsedNode {
+        green_node: node.clone(),
+        errors: parse.errors,
+        lex_errors: parse.lex_errors,
+    })
+}
+
+/// Returns true if `range` is between the braces of `node`, which starts at `offset`.
+fn body_contains(node: &GreenNode, offset: usize, range: TextRange) -> bool {
+    let mut lcurly_end = None;
+    let mut rcurly_start = None;
+    let mut child_offset = offset;
+    for child in &node.children {
+        if let GreenItem::Token(token) = child {
+            match token.kind {
+                TokenKind::LCurly if lcurly_end.is_none() => {
+                    lcurly_end = Some(child_offset + token.length());
+                }
+                TokenKind::RCurly => rcurly_start = Some(child_offset),
+                _ => {}
+            }
+        }
+        child_offset += child.length();
+    }
+    lcurly_end.is_some_and(|lcurly_end| lcurly_end <= range.start)
+        && rcurly_start.is_some_and(|rcurly_start| range.end <= rcurly_start)
+}
+
+/// Returns a copy of `node` with the node at `path` replaced with `new`.
+fn replace(node: &GreenNode, path: &[usize], new: Arc<GreenNode>) -> GreenNode {
+    let mut children = node.children.clone();
+    if let Some((&index, rest)) = path.split_first() {
+        children[index] = match (&node.children[index], rest) {
+            (_, []) => GreenItem::Node(new),
+            (GreenItem::Node(child), rest) => GreenItem::Node(Arc::new(replace(child, rest, new))),
+            (GreenItem::Token(_), _) => unreachable!("paths only go through nodes"),
+        };
+    }
+    GreenNode {
+        kind: node.kind,
+        width: children.iter().map(GreenItem::length).sum(),
+        children,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const SRC: &str = "/dts-v1/;
+#include <dt-bindings/gpio/gpio.h>
+
+/ {
+\t#address-cells = <1>;
+\tsoc {
+\t\tuart0: serial@1000 {
+\t\t\tcompatible = \"acme,uart\";
+\t\t\treg = <0x1000 0x100>;
+\t\t};
+
+\t\tgpio0: gpio@2000 {
+\t\t\treg = <0x2000 0x100>;
+\t\t\t#gpio-cells = <2>;
+\t\t};
+\t};
+};
+
+&uart0 {
+\tstatus = \"okay\";
+};
+";
+
+    /// Replaces the first `find` in `SRC` and checks that reparsing gives the same result as
+    /// parsing from scratch.
+    fn check(find: &str, replacement: &str) -> (Parse<'static>, Parse<'static>) {
+        let start = SRC.find(find).unwrap();
+        let range = TextRange::new(start, start + find.len());
+        let new_text = format!("{}{replacement}{}", &SRC[..start], &SRC[range.end..]).leak();
+
+        let old = parse(SRC);
+        let reparsed = old.reparse(range, new_text);
+        let expected = parse(new_text);
+        assert_eq!(reparsed.green_node, expected.green_node);
+        assert_eq!(reparsed.errors, expected.errors);
+        assert_eq!(reparsed.lex_errors, expected.lex_errors);
+        (old, reparsed)
+    }
+
+    /// Returns the top-level nodes of `parse`.
+    fn nodes(parse: &Parse) -> Vec<Arc<GreenNode>> {
+        parse.green_node.child_nodes().cloned().collect()
+    }
+
+    #[test]
+    fn reuses_untouched_nodes() {
+        let (old, new) = check("0x2000 0x100", "0x3000 0x200");
+        let (old, new) = (nodes(&old), nodes(&new));
+        // `/dts-v1/;` and `&uart0 { ... };`
+        assert!(Arc::ptr_eq(&old[0], &new[0]));
+        assert!(Arc::ptr_eq(old.last().unwrap(), new.last().unwrap()));
+        // The root node is copied, but `soc` keeps `serial@1000`
+        let soc = |root: &Arc<GreenNode>| root.child_nodes().nth(1).unwrap().clone();
+        let (old_soc, new_soc) = (soc(&old[1]), soc(&new[1]));
+        assert!(!Arc::ptr_eq(&old_soc, &new_soc));
+        assert!(Arc::ptr_eq(
+            old_soc.child_nodes().next().unwrap(),
+            new_soc.child_nodes().next().unwrap()
+        ));
+    }
+
+    #[test]
+    fn edits() {
+        check("\t\t\t#gpio-cells = <2>;\n", "");
+        check(
+            "reg = <0x1000 0x100>;",
+            "reg = <0x1000 0x100>;\n\t\t\tstatus = \"okay\";",
+        );
+        check(
+            "\t\t};\n\n\t\tgpio0",
+            "\t\t\tchild {};\n\t\t};\n\n\t\tgpio0",
+        );
+        check("status = \"okay\";", "status = ;");
+        check("\t\t\tcompatible", "\t\t\tbroken {\n\t\t\tcompatible");
+        // Unclosed strings and comments, which must swallow the rest of the file
+        check("\"okay\"", "\"okay");
+        check("\t\t\t#gpio", "/*\t\t\t#gpio");
+        // Removing a closing brace changes the structure
+        check("\t\t};\n\n\t\tgpio0", "\n\n\t\tgpio0");
+        // Edits outside of bodies
+        check("serial@1000", "serial@1001");
+        check("/dts-v1/;\n", "");
+        check("\n&uart0", "\n&gpio0 {};\n&uart0");
+    }
+
+    #[test]
+    fn errors_around_edit() {
+        const SRC: &str = "/ { a { b = ; }; c { d = <1>; }; e = ; };";
+        let start = SRC.find("<1>").unwrap();
+        let range = TextRange::new(start, start + 3);
+        let new_text = SRC.replace("<1>", "<2 3>");
+        let old = parse(SRC);
+        let reparsed = old.reparse(range, &new_text);
+        assert_eq!(reparsed, parse(&new_text));
+        // Both `= ;`s have errors, in `a` before the edit and in the root node after `c`
+        assert_eq!(reparsed.errors.len(), 4);
+        let a = |parse: &Parse| {
+            let root = parse.green_node.child_nodes().next().unwrap();
+            root.child_nodes().next().unwrap().clone()
+        };
+        assert!(Arc::ptr_eq(&a(&old), &a(&reparsed)));
+    }
+
+    #[test]
+    fn every_position() {
+        for offset in 0..SRC.len() {
+            for (removed, inserted) in [(0, "x"), (0, "}"), (0, "\""), (1, ""), (1, ";")] {
+                let range = TextRange::new(offset, (offset + removed).min(SRC.len()));
+                let new_text = format!("{}{inserted}{}", &SRC[..range.start], &SRC[range.end..]);
+                assert_eq!(
+                    parse(SRC).reparse(range, &new_text),
+                    parse(&new_text),
+                    "replacing {range:?} with {inserted:?}"
+                );
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[cfg(feature = "cli")]
 use cli_config::CliConfig;
@@ -23,9 +23,9 @@ macro_rules! config_field {
     }
   };
   (@without_cli $start:ident$(, $fallback:ident)*; $field:ident) => {
-      $start.and_then(|value| value.$field)
+      $start.as_ref().and_then(|value| value.$field.clone())
         $(
-        .or_else(|| $fallback.and_then(|value| value.$field))
+        .or_else(|| $fallback.as_ref().and_then(|value| value.$field.clone()))
         )*
   };
 }
@@ -38,6 +38,10 @@ macro_rules! config_field {
 #[derive(Debug, Default, PartialEq)]
 pub struct CombinedConfig {
     include_paths: Vec<PathBuf>,
+    rule_files: Vec<PathBuf>,
+    bindings_dirs: Vec<PathBuf>,
+    dt_schema_dir: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
 }
 
 impl CombinedConfig {
@@ -50,8 +54,42 @@ impl CombinedConfig {
     ) -> Self {
         Self {
             include_paths: config_field!(cli, env, toml; include_paths).unwrap_or_default(),
+            rule_files: config_field!(@without_cli toml; rule_files).unwrap_or_default(),
+            bindings_dirs: config_field!(@without_cli toml; bindings_dirs).unwrap_or_default(),
+            dt_schema_dir: config_field!(@without_cli toml; dt_schema_dir),
+            cache_dir: config_field!(@without_cli toml; cache_dir),
         }
     }
+
+    /// Paths to search for included files and C macros
+    #[must_use]
+    pub fn include_paths(&self) -> &[PathBuf] {
+        &self.include_paths
+    }
+
+    /// Paths to declarative lint rule files
+    #[must_use]
+    pub fn rule_files(&self) -> &[PathBuf] {
+        &self.rule_files
+    }
+
+    /// Directories containing dt-schema or Zephyr binding YAML files
+    #[must_use]
+    pub fn bindings_dirs(&self) -> &[PathBuf] {
+        &self.bindings_dirs
+    }
+
+    /// Path to a dt-schema checkout
+    #[must_use]
+    pub fn dt_schema_dir(&self) -> Option<&Path> {
+        self.dt_schema_dir.as_deref()
+    }
+
+    /// Directory for caches, if it isn't the default `$XDG_CACHE_HOME/dt-tools`
+    #[must_use]
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
 }
 
 #[cfg(test)]
@@ -69,11 +107,19 @@ mod tests {
         };
         let toml = TomlConfig {
             include_paths: Some(vec!["toml".into()]),
+            rule_files: Some(vec!["rules.toml".into()]),
+            bindings_dirs: Some(vec!["bindings".into()]),
+            dt_schema_dir: Some("dt-schema".into()),
+            cache_dir: Some("cache".into()),
         };
 
         assert_eq!(
             CombinedConfig {
                 include_paths: vec![if cfg!(feature = "cli") { "cli" } else { "env" }.into()],
+                rule_files: vec!["rules.toml".into()],
+                bindings_dirs: vec!["bindings".into()],
+                dt_schema_dir: Some("dt-schema".into()),
+                cache_dir: Some("cache".into()),
             },
             CombinedConfig::merge(
                 #[cfg(feature = "cli")]
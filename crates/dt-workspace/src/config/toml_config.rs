@@ -12,6 +12,15 @@ pub const CONFIG_FILENAME: &str = ".dt-tools.toml";
 #[serde(rename_all = "kebab-case")]
 pub struct TomlConfig {
     pub include_paths: Option<Vec<PathBuf>>,
+    /// Paths to declarative lint rule files
+    pub rule_files: Option<Vec<PathBuf>>,
+    /// Directories containing dt-schema or Zephyr binding YAML files
+    pub bindings_dirs: Option<Vec<PathBuf>>,
+    /// Path to a checkout of [dt-schema](https://github.com/devicetree-org/dt-schema), for the
+    /// core schemas and meta-schemas referenced by bindings
+    pub dt_schema_dir: Option<PathBuf>,
+    /// Directory for caches like the compiled binding schemas, instead of `$XDG_CACHE_HOME/dt-tools`
+    pub cache_dir: Option<PathBuf>,
 }
 
 impl TomlConfig {
@@ -20,21 +29,41 @@ impl TomlConfig {
         let mut config: Self = toml::from_str(&fs_err::read_to_string(path)?)?;
 
         // Make paths relative
-        if let Some(include_paths) = config.include_paths {
-            let config_parent = path.parent().ok_or(ConfigError::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "parent not found",
-            )))?;
-            config.include_paths = Some(
-                include_paths
-                    .into_iter()
-                    .map(|include_path| config_parent.join(include_path))
-                    .collect(),
-            );
+        let config_parent = path.parent().ok_or(ConfigError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "parent not found",
+        )))?;
+        for paths in [
+            &mut config.include_paths,
+            &mut config.rule_files,
+            &mut config.bindings_dirs,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            for path in paths {
+                *path = config_parent.join(&*path);
+            }
+        }
+        for path in [&mut config.dt_schema_dir, &mut config.cache_dir]
+            .into_iter()
+            .flatten()
+        {
+            *path = config_parent.join(&*path);
         }
 
         Ok(config)
     }
+
+    /// The config of a Zephyr tree or module at `workspace_dir` without a config file, which uses
+    /// its bindings in [`ZEPHYR_BINDINGS_DIR`](crate::ZEPHYR_BINDINGS_DIR).
+    #[must_use]
+    pub fn zephyr(workspace_dir: &Path) -> Self {
+        Self {
+            bindings_dirs: Some(vec![workspace_dir.join(crate::ZEPHYR_BINDINGS_DIR)]),
+            ..Self::default()
+        }
+    }
 }
 
 /// Configuration errors encountered when loading the TOML config
@@ -56,6 +85,10 @@ mod tests {
         // load using filename
         let config = TomlConfig::load(Path::new(CONFIG_FILENAME)).unwrap();
         assert_eq!(config.include_paths, Some(vec![PathBuf::from("test_data")]));
+        assert_eq!(
+            config.rule_files,
+            Some(vec![PathBuf::from("test_data/rules.toml")])
+        );
 
         // try load
         assert!(TomlConfig::load(&Path::new("test_data").join(CONFIG_FILENAME)).is_err());
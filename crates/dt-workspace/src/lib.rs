@@ -9,6 +9,12 @@ mod search;
 /// A marker to determine the Linux kernel tree root
 const LINUX_MARKER: &str = "dt-bindings/interrupt-controller/arm-gic.h";
 
+/// A marker to determine the root of a Zephyr tree or module
+const ZEPHYR_MARKER: &str = "zephyr/module.yml";
+
+/// The directory of the bindings of a Zephyr tree or module, which also marks its root
+pub const ZEPHYR_BINDINGS_DIR: &str = "dts/bindings";
+
 #[derive(Debug)]
 pub struct Workspace {
     pub path: PathBuf,
@@ -37,6 +43,13 @@ impl Workspace {
                     WorkspacePathFindResult::LinuxMarker { workspace_dir }
                 })
             })
+            .or_else(|| {
+                search(start, ZEPHYR_MARKER, |f| f.is_file())
+                    .or_else(|| search(start, ZEPHYR_BINDINGS_DIR, |f| f.is_dir()))
+                    .map(|(workspace_dir, _)| WorkspacePathFindResult::ZephyrMarker {
+                        workspace_dir,
+                    })
+            })
             .unwrap_or(WorkspacePathFindResult::Fallback {
                 workspace_dir: start,
             })
@@ -49,7 +62,8 @@ impl Workspace {
 ///
 /// 1. `TomlConfig`
 /// 2. `LinuxMarker`
-/// 3. `Fallback`
+/// 3. `ZephyrMarker`
+/// 4. `Fallback`
 #[derive(Debug)]
 pub enum WorkspacePathFindResult<'start> {
     /// TOML config file ([`toml_config::CONFIG_FILENAME`]) is a sibling of the start directory or its ancestors.
@@ -59,6 +73,13 @@ pub enum WorkspacePathFindResult<'start> {
     },
     /// Linux kernel (Using the marker file [`LINUX_MARKER`]) is a sibling of the start directory or its ancestors.
     LinuxMarker { workspace_dir: &'start Path },
+    /// Zephyr tree or module (Using the marker file [`ZEPHYR_MARKER`] or the directory
+    /// [`ZEPHYR_BINDINGS_DIR`]) is a sibling of the start directory or its ancestors.
+    ///
+    /// Its bindings are used without a TOML config, see [`TomlConfig::zephyr`].
+    ///
+    /// [`TomlConfig::zephyr`]: toml_config::TomlConfig::zephyr
+    ZephyrMarker { workspace_dir: &'start Path },
     /// No workspace directory found using the other heuristics. Use the start directory.
     Fallback { workspace_dir: &'start Path },
 }
@@ -90,4 +111,19 @@ mod tests {
             (target.join(toml_config::CONFIG_FILENAME), target.as_path())
         );
     }
+
+    #[test]
+    fn find_zephyr() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let start = root.join("boards/acme");
+        std::fs::create_dir_all(&start).unwrap();
+        std::fs::create_dir_all(root.join(ZEPHYR_BINDINGS_DIR)).unwrap();
+
+        let result = Workspace::find_workspace_dir(&start);
+        assert!(
+            matches!(result, WorkspacePathFindResult::ZephyrMarker { workspace_dir } if workspace_dir == root),
+            "{result:?}"
+        );
+    }
 }